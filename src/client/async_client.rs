@@ -0,0 +1,491 @@
+//! An asynchronous counterpart of `KafkaClient` built on top of
+//! tokio.  Available only with the `async` feature enabled.
+//!
+//! `AsyncKafkaClient` speaks the very same protocol as `KafkaClient`
+//! and shares its request and response codecs; only the i/o with the
+//! brokers is carried out asynchronously.  It currently supports
+//! loading metadata, producing and fetching messages over plain tcp
+//! connections.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use super::{
+    __encode_request, __fetch_requests, default_conn_rw_timeout, fetch, metadata, state,
+    ClientConfig, Compression, FetchPartition, ProduceConfirm, ProduceMessage, RequiredAcks,
+    DEFAULT_MAX_RESPONSE_SIZE,
+};
+use crate::codecs::FromByte;
+use crate::error::{Error, KafkaCode, Result};
+use crate::protocol::{self, ResponseParser, SliceResponseParser};
+
+/// An asynchronous client keeping track of brokers and topic
+/// metadata.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use kafka::client::{AsyncKafkaClient, ProduceMessage, RequiredAcks};
+///
+/// # async fn run() -> kafka::Result<()> {
+/// let mut client = AsyncKafkaClient::new(vec!["localhost:9092".to_owned()]);
+/// client.load_metadata_all().await?;
+/// let req = vec![ProduceMessage::new("my-topic", 0, None, Some("a".as_bytes()))];
+/// client
+///     .produce_messages(RequiredAcks::One, Duration::from_millis(100), req)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AsyncKafkaClient {
+    config: ClientConfig,
+    conns: AsyncConnections,
+    state: state::ClientState,
+}
+
+#[derive(Debug)]
+struct AsyncConnections {
+    conns: HashMap<String, TcpStream>,
+    rw_timeout: Option<Duration>,
+    max_response_size: usize,
+}
+
+impl AsyncConnections {
+    /// Sends the given (size prefixed) request to the specified host
+    /// and reads the broker's response unless `expect_response` is
+    /// `false`.  The connection to the host is dropped on any error
+    /// such that a subsequent request will re-establish it.
+    async fn send_receive(
+        &mut self,
+        host: &str,
+        req: &[u8],
+        expect_response: bool,
+    ) -> Result<Vec<u8>> {
+        let r = match self.rw_timeout {
+            None => self.do_send_receive(host, req, expect_response).await,
+            Some(t) => {
+                match time::timeout(t, self.do_send_receive(host, req, expect_response)).await {
+                    Ok(r) => r,
+                    Err(_) => Err(Error::Kafka(KafkaCode::RequestTimedOut)),
+                }
+            }
+        };
+        if r.is_err() {
+            debug!("send_receive: dropping connection to {}", host);
+            self.conns.remove(host);
+        }
+        r
+    }
+
+    async fn do_send_receive(
+        &mut self,
+        host: &str,
+        req: &[u8],
+        expect_response: bool,
+    ) -> Result<Vec<u8>> {
+        if !self.conns.contains_key(host) {
+            debug!("send_receive: connecting to {}", host);
+            let stream = TcpStream::connect(host).await?;
+            stream.set_nodelay(true)?;
+            self.conns.insert(host.to_owned(), stream);
+        }
+        let stream = self
+            .conns
+            .get_mut(host)
+            .expect("connection just established");
+
+        trace!("send_receive: sending bytes: {:?}", req);
+        stream.write_all(req).await?;
+        if !expect_response {
+            return Ok(Vec::new());
+        }
+        let size = stream.read_i32().await?;
+        let size = match usize::try_from(size) {
+            Ok(size) if size <= self.max_response_size => size,
+            _ => {
+                warn!(
+                    "send_receive: invalid response size from {}: {}",
+                    host, size
+                );
+                return Err(Error::CodecError);
+            }
+        };
+        let mut resp = vec![0; size];
+        stream.read_exact(&mut resp).await?;
+
+        trace!("send_receive: received bytes: {:?}", &resp);
+        Ok(resp)
+    }
+}
+
+impl AsyncKafkaClient {
+    /// Creates a new instance of `AsyncKafkaClient`.  Before being
+    /// able to successfully use the new client, you'll have to load
+    /// metadata.  See `KafkaClient::new`.
+    pub fn new(hosts: Vec<String>) -> AsyncKafkaClient {
+        AsyncKafkaClient {
+            config: ClientConfig::new(hosts),
+            conns: AsyncConnections {
+                conns: HashMap::new(),
+                rw_timeout: default_conn_rw_timeout(),
+                max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            },
+            state: state::ClientState::new(),
+        }
+    }
+
+    /// Exposes the hosts used for discovery of the target kafka
+    /// cluster.  This set of hosts corresponds to the values supplied
+    /// to `AsyncKafkaClient::new`.
+    #[inline]
+    pub fn hosts(&self) -> &[String] {
+        &self.config.hosts
+    }
+
+    /// Sets the client_id to be sent along every request to the
+    /// remote Kafka brokers.  By default, this value is the empty
+    /// string.
+    #[inline]
+    pub fn set_client_id(&mut self, client_id: String) {
+        self.config.client_id = client_id;
+    }
+
+    /// Retrieves the current client id setting.
+    #[inline]
+    pub fn client_id(&self) -> &str {
+        &self.config.client_id
+    }
+
+    /// Sets the compression algorithm to use when sending out
+    /// messages.  See `KafkaClient::set_compression`.
+    #[inline]
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.config.compression = compression;
+    }
+
+    /// Retrieves the current compression algorithm setting.
+    #[inline]
+    pub fn compression(&self) -> Compression {
+        self.config.compression
+    }
+
+    /// Sets the maximum time the brokers are asked to block waiting
+    /// for data when fetching messages.  See
+    /// `KafkaClient::set_fetch_max_wait_time`.
+    #[inline]
+    pub fn set_fetch_max_wait_time(&mut self, max_wait_time: Duration) -> Result<()> {
+        self.config.fetch_max_wait_time = protocol::to_millis_i32(max_wait_time)?;
+        Ok(())
+    }
+
+    /// Retrieves the current fetch max wait time setting.
+    #[inline]
+    pub fn fetch_max_wait_time(&self) -> Duration {
+        Duration::from_millis(self.config.fetch_max_wait_time as u64)
+    }
+
+    /// Sets the minimum number of bytes of available data to wait for
+    /// as long as specified by `AsyncKafkaClient::set_fetch_max_wait_time`
+    /// when fetching messages.  See `KafkaClient::set_fetch_min_bytes`.
+    #[inline]
+    pub fn set_fetch_min_bytes(&mut self, min_bytes: i32) {
+        self.config.fetch_min_bytes = min_bytes;
+    }
+
+    /// Retrieves the current fetch min bytes setting.
+    #[inline]
+    pub fn fetch_min_bytes(&self) -> i32 {
+        self.config.fetch_min_bytes
+    }
+
+    /// Sets the default maximum number of bytes to obtain from a
+    /// single kafka partition when fetching messages.  See
+    /// `KafkaClient::set_fetch_max_bytes_per_partition`.
+    #[inline]
+    pub fn set_fetch_max_bytes_per_partition(&mut self, max_bytes: i32) {
+        self.config.fetch_max_bytes_per_partition = max_bytes;
+    }
+
+    /// Retrieves the current fetch max bytes per partition setting.
+    #[inline]
+    pub fn fetch_max_bytes_per_partition(&self) -> i32 {
+        self.config.fetch_max_bytes_per_partition
+    }
+
+    /// Specifies whether to validate the crc of fetched messages.
+    #[inline]
+    pub fn set_fetch_crc_validation(&mut self, validate_crc: bool) {
+        self.config.fetch_crc_validation = validate_crc;
+    }
+
+    /// Retrieves the current fetch crc validation setting.
+    #[inline]
+    pub fn fetch_crc_validation(&self) -> bool {
+        self.config.fetch_crc_validation
+    }
+
+    /// Specifies the timeout after which a single request to a
+    /// broker (i.e. sending it and awaiting its response) is given
+    /// up with `KafkaCode::RequestTimedOut`.  `None` disables the
+    /// timeout.
+    #[inline]
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.conns.rw_timeout = timeout;
+    }
+
+    /// Retrieves the current request timeout setting.
+    #[inline]
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.conns.rw_timeout
+    }
+
+    /// Sets the maximum size of a response accepted from a broker.
+    /// See `KafkaClient::set_max_response_size`.
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_response_size: usize) {
+        self.conns.max_response_size = max_response_size;
+    }
+
+    /// Retrieves the current max response size setting.
+    #[inline]
+    pub fn max_response_size(&self) -> usize {
+        self.conns.max_response_size
+    }
+
+    /// Provides a view onto the currently loaded metadata of known
+    /// topics.
+    #[inline]
+    pub fn topics(&self) -> metadata::Topics<'_> {
+        metadata::Topics::from_state(&self.state)
+    }
+
+    /// Resets and loads metadata for all topics from the underlying
+    /// brokers.  See `KafkaClient::load_metadata_all`.
+    pub async fn load_metadata_all(&mut self) -> Result<()> {
+        self.state.clear_metadata();
+        self.load_metadata::<&str>(&[]).await
+    }
+
+    /// Reloads metadata for a list of supplied topics.  See
+    /// `KafkaClient::load_metadata`.
+    pub async fn load_metadata<T: AsRef<str>>(&mut self, topics: &[T]) -> Result<()> {
+        let correlation = self.state.next_correlation_id();
+        let version = self.config.metadata_version;
+        let req = __encode_request(protocol::MetadataRequest::new(
+            version,
+            correlation,
+            &self.config.client_id,
+            topics,
+        ))?;
+
+        let hosts = &self.config.hosts;
+        let start = if self.config.randomize_bootstrap_order && !hosts.is_empty() {
+            self.config.bootstrap_offset % hosts.len()
+        } else {
+            0
+        };
+        for host in hosts[start..].iter().chain(&hosts[..start]) {
+            debug!("load_metadata: requesting metadata from {}", host);
+            match self.conns.send_receive(host, &req, true).await {
+                Ok(resp) => {
+                    let p = protocol::metadata::ResponseParser { version };
+                    return self.state.update_metadata(p.parse_slice(&resp)?);
+                }
+                Err(e) => debug!(
+                    "load_metadata: failed to request metadata from {}: {}",
+                    host, e
+                ),
+            }
+        }
+        Err(Error::NoHostReachable)
+    }
+
+    /// Sends a message or a batch of messages to the brokers leading
+    /// the addressed topic partitions.  See
+    /// `KafkaClient::produce_messages`.
+    pub async fn produce_messages<'a, 'b, I, J>(
+        &mut self,
+        acks: RequiredAcks,
+        ack_timeout: Duration,
+        messages: I,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        J: AsRef<ProduceMessage<'a, 'b>>,
+        I: IntoIterator<Item = J>,
+    {
+        let required_acks = acks as i16;
+        let ack_timeout = protocol::to_millis_i32(ack_timeout)?;
+        let correlation = self.state.next_correlation_id();
+
+        // ~ map topic and partition to the corresponding brokers
+        let config = &self.config;
+        let mut reqs: HashMap<&str, protocol::ProduceRequest<'_, '_>> = HashMap::new();
+        for msg in messages {
+            let msg = msg.as_ref();
+            match self.state.find_broker(msg.topic, msg.partition) {
+                None => return Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)),
+                Some(broker) => reqs
+                    .entry(broker)
+                    .or_insert_with(|| {
+                        protocol::ProduceRequest::new(
+                            required_acks,
+                            ack_timeout,
+                            correlation,
+                            &config.client_id,
+                            config.compression,
+                            config.compression_min_size,
+                        )
+                    })
+                    .add(msg.topic, msg.partition, msg.key, msg.value),
+            }
+        }
+        // ~ render the requests up front to release the borrow on
+        // the loaded metadata
+        let reqs = reqs
+            .into_iter()
+            .map(|(host, req)| Ok((host.to_owned(), __encode_request(req)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut confirms = Vec::new();
+        for (host, req) in reqs {
+            let resp = self
+                .conns
+                .send_receive(&host, &req, required_acks != 0)
+                .await?;
+            if required_acks != 0 {
+                let resp = protocol::ProduceResponse::decode_new(&mut Cursor::new(resp))?;
+                confirms.extend(resp.get_response());
+            }
+        }
+        // ~ forget about topics the brokers do not know (anymore)
+        // such that subsequent metadata lookups refresh them
+        for confirm in &confirms {
+            if confirm
+                .partition_confirms
+                .iter()
+                .any(|p| p.offset == Err(KafkaCode::UnknownTopicOrPartition))
+            {
+                debug!(
+                    "produce_messages: evicting metadata for unknown topic '{}'",
+                    confirm.topic
+                );
+                self.state.remove_topic(&confirm.topic);
+            }
+        }
+        Ok(confirms)
+    }
+
+    /// Fetches messages from the brokers leading the specified topic
+    /// partitions.  Partitions without a known leader are silently
+    /// skipped.  See `KafkaClient::fetch_messages`.
+    pub async fn fetch_messages<'a, I, J>(&mut self, input: I) -> Result<Vec<fetch::Response>>
+    where
+        J: AsRef<FetchPartition<'a>>,
+        I: IntoIterator<Item = J>,
+    {
+        let reqs = __fetch_requests(&mut self.state, &self.config, input);
+        let mut res = Vec::with_capacity(reqs.len());
+        for (host, req) in reqs {
+            let bytes = __encode_request(&req)?;
+            let resp = self.conns.send_receive(host, &bytes, true).await?;
+            let p = protocol::fetch::ResponseParser {
+                validate_crc: self.config.fetch_crc_validation,
+                max_uncompressed_size: self.conns.max_response_size,
+                requests: Some(&req),
+            };
+            res.push(p.parse(resp)?);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::AsyncKafkaClient;
+    use crate::client::fake_broker::MockBroker;
+    use crate::client::{FetchPartition, ProduceMessage, RequiredAcks};
+    use crate::error::{Error, KafkaCode};
+
+    #[tokio::test]
+    async fn test_load_metadata() {
+        let broker = MockBroker::start(&[("foo", 2), ("bar", 1)], (0, 10));
+        let mut client = AsyncKafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().await.unwrap();
+        let mut names: Vec<_> = client.topics().names().map(ToOwned::to_owned).collect();
+        names.sort_unstable();
+        assert_eq!(vec!["bar", "foo"], names);
+        assert_eq!(Some(2), client.topics().partitions("foo").map(|p| p.len()));
+    }
+
+    #[tokio::test]
+    async fn test_produce_and_fetch_messages() {
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut client = AsyncKafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().await.unwrap();
+
+        let msgs = vec![
+            ProduceMessage::new("foo", 0, None, Some(b"a".as_ref())),
+            ProduceMessage::new("foo", 1, None, Some(b"b".as_ref())),
+            ProduceMessage::new("foo", 1, None, Some(b"c".as_ref())),
+        ];
+        let confirms = client
+            .produce_messages(RequiredAcks::One, Duration::from_millis(100), &msgs)
+            .await
+            .unwrap();
+        assert_eq!(1, confirms.len());
+        assert!(confirms[0]
+            .partition_confirms
+            .iter()
+            .all(|p| p.offset.is_ok()));
+        assert_eq!(1, broker.num_produced("foo", 0));
+        assert_eq!(2, broker.num_produced("foo", 1));
+
+        let resps = client
+            .fetch_messages(&[FetchPartition::new("foo", 0, 3)])
+            .await
+            .unwrap();
+        assert_eq!(1, resps.len());
+        let data = resps[0].topics()[0].partitions()[0].data().unwrap();
+        assert_eq!(3, data.messages()[0].offset);
+    }
+
+    #[tokio::test]
+    async fn test_produce_unknown_topic() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut client = AsyncKafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().await.unwrap();
+        let r = client
+            .produce_messages(
+                RequiredAcks::One,
+                Duration::from_millis(100),
+                &[ProduceMessage::new("bar", 0, None, Some(b"a".as_ref()))],
+            )
+            .await;
+        assert!(matches!(
+            r,
+            Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut client = AsyncKafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().await.unwrap();
+        broker.set_response_delay(Duration::from_millis(500));
+        client.set_request_timeout(Some(Duration::from_millis(50)));
+        let r = client
+            .fetch_messages(&[FetchPartition::new("foo", 0, 0)])
+            .await;
+        assert!(matches!(r, Err(Error::Kafka(KafkaCode::RequestTimedOut))));
+    }
+}
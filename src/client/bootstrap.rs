@@ -0,0 +1,105 @@
+//! Periodic re-resolution of the bootstrap hosts.  See
+//! `KafkaClient::set_bootstrap_refresh`.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Resolves a bootstrap host ("host:port") to the socket addresses
+/// of the brokers behind it.
+///
+/// The client consults its resolver only if re-resolution of the
+/// bootstrap hosts is enabled through
+/// `KafkaClient::set_bootstrap_refresh`.
+pub trait Resolver: fmt::Debug + Send + Sync {
+    /// Resolves the given "host:port" to its current addresses.
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// The default `Resolver` looking up hosts through the system's
+/// name resolution.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DnsResolver;
+
+impl Resolver for DnsResolver {
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        Ok(host.to_socket_addrs()?.collect())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Bootstrap {
+    // ~ the interval at which to re-resolve the bootstrap hosts;
+    // `None` if disabled
+    pub refresh: Option<Duration>,
+    resolver: Box<dyn Resolver>,
+    // ~ the addresses ("ip:port") the bootstrap hosts resolved to
+    // most recently
+    addrs: Vec<String>,
+    // ~ the time of the last resolution; `None` if not resolved yet
+    resolved_at: Option<Instant>,
+}
+
+impl Bootstrap {
+    pub fn new() -> Bootstrap {
+        Bootstrap {
+            refresh: None,
+            resolver: Box::new(DnsResolver),
+            addrs: Vec::new(),
+            resolved_at: None,
+        }
+    }
+
+    pub fn set_resolver(&mut self, resolver: Box<dyn Resolver>) {
+        self.resolver = resolver;
+        self.resolved_at = None;
+    }
+
+    /// Retrieves the addresses the given bootstrap hosts resolve to
+    /// in addition to the hosts themselves, re-resolving them first
+    /// if the refresh interval has elapsed.  Yields nothing if
+    /// re-resolution is disabled.
+    pub fn addrs(&mut self, hosts: &[String], now: Instant) -> &[String] {
+        let Some(refresh) = self.refresh else {
+            return &[];
+        };
+        if self
+            .resolved_at
+            .map(|t| now.duration_since(t) >= refresh)
+            .unwrap_or(true)
+        {
+            self.resolve(hosts);
+            self.resolved_at = Some(now);
+        }
+        &self.addrs
+    }
+
+    fn resolve(&mut self, hosts: &[String]) {
+        let mut addrs = Vec::new();
+        for host in hosts {
+            match self.resolver.resolve(host) {
+                Ok(resolved) => {
+                    for addr in resolved {
+                        let addr = addr.to_string();
+                        if !hosts.contains(&addr) && !addrs.contains(&addr) {
+                            addrs.push(addr);
+                        }
+                    }
+                }
+                Err(e) => {
+                    // ~ keep the previously resolved addresses as
+                    // long as the name cannot be resolved
+                    warn!("bootstrap: failed to resolve {}: {}", host, e);
+                    return;
+                }
+            }
+        }
+        for addr in &addrs {
+            if !self.addrs.contains(addr) {
+                debug!("bootstrap: discovered {}", addr);
+            }
+        }
+        self.addrs = addrs;
+    }
+}
@@ -0,0 +1,1144 @@
+//! A minimal, in-process stand-in for a Kafka broker to be used by
+//! tests.  It understands just enough of the protocol (v0) to answer
+//! metadata (up to v7), produce, offset, fetch, (zookeeper based)
+//! group offset, leader epoch, SCRAM credential, log dir (v1) and
+//! api versions (v3) requests.
+//!
+//! The crate's own tests use it through the `serve*` functions;
+//! other crates can use it as `kafka::client::MockBroker` by enabling
+//! the `test-util` feature.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::codecs::{self, FromByte, ToByte};
+use crate::error::KafkaCode;
+use crate::protocol::to_crc;
+
+const API_KEY_PRODUCE: i16 = 0;
+const API_KEY_FETCH: i16 = 1;
+const API_KEY_OFFSET: i16 = 2;
+const API_KEY_METADATA: i16 = 3;
+const API_KEY_OFFSET_COMMIT: i16 = 8;
+const API_KEY_OFFSET_FETCH: i16 = 9;
+const API_KEY_GROUP_COORDINATOR: i16 = 10;
+const API_KEY_API_VERSIONS: i16 = 18;
+const API_KEY_OFFSET_FOR_LEADER_EPOCH: i16 = 23;
+const API_KEY_DESCRIBE_LOG_DIRS: i16 = 35;
+const API_KEY_DESCRIBE_USER_SCRAM_CREDENTIALS: i16 = 50;
+const API_KEY_ALTER_USER_SCRAM_CREDENTIALS: i16 = 51;
+
+// ~ the key and value of a produced message
+type Produced = (Option<Vec<u8>>, Vec<u8>);
+
+struct Broker {
+    addr: SocketAddr,
+    topics: TopicsHandle,
+    // ~ (earliest, latest) offset of every partition
+    offsets: (i64, i64),
+    // ~ the minimum size of the value of fetched messages
+    value_size: usize,
+    // ~ group offsets committed so far by (topic, partition)
+    committed: Mutex<HashMap<(String, i32), i64>>,
+    // ~ messages produced so far by (topic, partition)
+    produced: Mutex<HashMap<(String, i32), Vec<Produced>>>,
+    // ~ errors to answer produce and fetch requests with by (topic,
+    // partition)
+    errors: Mutex<HashMap<(String, i32), i16>>,
+    // ~ the (error, number of requests still to be failed) to answer
+    // produce requests with by (topic, partition)
+    produce_failures: Mutex<HashMap<(String, i32), (i16, usize)>>,
+    // ~ the api keys of the requests served so far
+    requests: Mutex<Vec<i16>>,
+    // ~ the correlation ids of the requests served so far
+    correlation_ids: Mutex<Vec<i32>>,
+    // ~ the ack timeouts of the produce requests served so far
+    ack_timeouts: Mutex<Vec<i32>>,
+    // ~ the max bytes last requested by fetch requests by (topic,
+    // partition)
+    fetch_max_bytes: Mutex<HashMap<(String, i32), i32>>,
+    // ~ the (max_wait_time, min_bytes) of the last fetch request
+    fetch_wait: Mutex<Option<(i32, i32)>>,
+    // ~ the replica id of the last fetch request
+    fetch_replica_id: Mutex<Option<i32>>,
+    // ~ the highest version supported by api key; unlimited if absent
+    max_versions: Mutex<HashMap<i16, i16>>,
+    num_requests: Arc<AtomicUsize>,
+    coordinator: Coordinator,
+    // ~ the time to wait before answering a request
+    delay: Mutex<Duration>,
+    // ~ the number of metadata requests still to be rejected
+    rejected_metadata: AtomicUsize,
+    // ~ the (mechanism, iterations) of the scram credentials by user
+    scram_credentials: Mutex<HashMap<String, Vec<(i8, i32)>>>,
+    // ~ the end offsets of the past leader epochs of all partitions;
+    // the current epoch is the one following the last of them
+    epoch_end_offsets: Mutex<Vec<i64>>,
+    // ~ the (topic, partition)s reported without a leader in metadata
+    // responses
+    leaderless: Mutex<Vec<(String, i32)>>,
+    // ~ the (name, version) of the client software reported by the
+    // last api versions request
+    client_software: Mutex<Option<(String, String)>>,
+    // ~ whether to report an offline log dir in addition to the one
+    // hosting all partitions
+    offline_log_dir: AtomicBool,
+    // ~ the size in bytes above which the message set produced to a
+    // partition is rejected; unlimited if zero
+    max_message_set_size: AtomicUsize,
+    // ~ whether to create requested topics not existing yet in
+    // metadata requests allowing so
+    auto_create_topics: AtomicBool,
+    // ~ the topics created through metadata requests so far; each
+    // having a single partition
+    auto_created: Mutex<Vec<String>>,
+    // ~ the (id, host) of another broker described in metadata
+    // responses next to this one
+    peer: Mutex<Option<(i32, String)>>,
+}
+
+/// The (node id, "host:port") of the broker a fake broker directs
+/// group coordinator lookups to.  While `None`, the fake broker
+/// coordinates all groups itself; otherwise it rejects group offset
+/// requests with `NotCoordinatorForGroup`.
+pub type Coordinator = Arc<Mutex<Option<(i32, String)>>>;
+
+/// The topics (name, number of partitions) a fake broker describes
+/// in its metadata responses.  Replacing them simulates the creation,
+/// deletion or expansion of topics.
+pub type TopicsHandle = Arc<Mutex<&'static [(&'static str, i32)]>>;
+
+/// Starts a fake broker answering metadata requests with a response
+/// describing the given topics (name, number of partitions) all led
+/// by the fake broker itself.  Returns the "host:port" of the broker
+/// and a counter of the requests it served so far.
+#[cfg(test)]
+pub fn serve_metadata(topics: &'static [(&'static str, i32)]) -> (String, Arc<AtomicUsize>) {
+    serve(topics, (0, 0))
+}
+
+/// Like `serve_metadata` but additionally lets every partition host
+/// messages in the range of the given (earliest, latest) offsets.
+/// Fetch requests are answered with at most one message each, the
+/// message's value being its offset rendered as a string.
+#[cfg(test)]
+pub fn serve(
+    topics: &'static [(&'static str, i32)],
+    offsets: (i64, i64),
+) -> (String, Arc<AtomicUsize>) {
+    let b = spawn(topics, offsets, 0);
+    (b.host, Arc::clone(&b.broker.num_requests))
+}
+
+/// Like `serve` but additionally returns a handle to move the
+/// coordination of groups away from the fake broker.  See
+/// `Coordinator`.
+#[cfg(test)]
+pub fn serve_with_coordinator(
+    topics: &'static [(&'static str, i32)],
+    offsets: (i64, i64),
+) -> (String, Arc<AtomicUsize>, Coordinator) {
+    let b = spawn(topics, offsets, 0);
+    let num_requests = Arc::clone(&b.broker.num_requests);
+    (b.host, num_requests, Arc::clone(&b.broker.coordinator))
+}
+
+/// Like `serve` but additionally returns a handle to change the
+/// topics the fake broker describes.  See `TopicsHandle`.
+#[cfg(test)]
+pub fn serve_with_topics(
+    topics: &'static [(&'static str, i32)],
+    offsets: (i64, i64),
+) -> (String, TopicsHandle) {
+    let b = spawn(topics, offsets, 0);
+    let topics = Arc::clone(&b.broker.topics);
+    (b.host, topics)
+}
+
+/// Like `serve` but pads the value of every fetched message with
+/// spaces to at least `value_size` bytes.  Fetch responses are
+/// truncated to the requested max_bytes of a partition such that
+/// messages exceeding it are delivered only partially, just like a
+/// real broker does.
+#[cfg(test)]
+pub fn serve_with_value_size(
+    topics: &'static [(&'static str, i32)],
+    offsets: (i64, i64),
+    value_size: usize,
+) -> (String, Arc<AtomicUsize>) {
+    let b = spawn(topics, offsets, value_size);
+    (b.host, Arc::clone(&b.broker.num_requests))
+}
+
+fn spawn(
+    topics: &'static [(&'static str, i32)],
+    offsets: (i64, i64),
+    value_size: usize,
+) -> MockBroker {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let broker = Arc::new(Broker {
+        addr: listener.local_addr().unwrap(),
+        topics: Arc::new(Mutex::new(topics)),
+        offsets,
+        value_size,
+        committed: Mutex::new(HashMap::new()),
+        produced: Mutex::new(HashMap::new()),
+        errors: Mutex::new(HashMap::new()),
+        produce_failures: Mutex::new(HashMap::new()),
+        requests: Mutex::new(Vec::new()),
+        correlation_ids: Mutex::new(Vec::new()),
+        num_requests: Arc::new(AtomicUsize::new(0)),
+        coordinator: Arc::new(Mutex::new(None)),
+        delay: Mutex::new(Duration::ZERO),
+        rejected_metadata: AtomicUsize::new(0),
+        ack_timeouts: Mutex::new(Vec::new()),
+        fetch_max_bytes: Mutex::new(HashMap::new()),
+        fetch_wait: Mutex::new(None),
+        fetch_replica_id: Mutex::new(None),
+        max_versions: Mutex::new(HashMap::new()),
+        scram_credentials: Mutex::new(HashMap::new()),
+        epoch_end_offsets: Mutex::new(Vec::new()),
+        leaderless: Mutex::new(Vec::new()),
+        client_software: Mutex::new(None),
+        offline_log_dir: AtomicBool::new(false),
+        max_message_set_size: AtomicUsize::new(0),
+        auto_create_topics: AtomicBool::new(false),
+        auto_created: Mutex::new(Vec::new()),
+        peer: Mutex::new(None),
+    });
+    let host = broker.addr.to_string();
+    let b = Arc::clone(&broker);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let b = Arc::clone(&b);
+            thread::spawn(move || b.serve_conn(stream));
+        }
+    });
+    MockBroker { host, broker }
+}
+
+// --------------------------------------------------------------------
+
+/// An in-memory stand-in for a single node Kafka cluster listening
+/// on a local port, intended for testing code built on top of this
+/// crate without a real Kafka installation.
+///
+/// The broker leads all partitions of the topics it is started with
+/// and speaks just enough of the (v0) protocol to serve metadata,
+/// produce, offset, fetch, group coordinator, group offset and
+/// leader epoch requests:
+///
+/// - every partition hosts messages in the range of the offsets the
+///   broker is started with, the value of each being its offset
+///   rendered as a string; produced messages are appended to this
+///   range,
+/// - fetch requests are answered with at most one message per
+///   partition,
+/// - compression is not supported.
+///
+/// Responses can be scripted to some extent (see e.g.
+/// `MockBroker::set_partition_error`) and the served requests can be
+/// inspected afterwards (see `MockBroker::requests`).
+///
+/// # Example
+///
+/// ```
+/// use kafka::client::MockBroker;
+/// use kafka::producer::{Producer, Record};
+///
+/// let broker = MockBroker::start(&[("my-topic", 1)], (0, 0));
+/// let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+///     .create()
+///     .unwrap();
+/// producer.send(&Record::from_value("my-topic", "hello")).unwrap();
+/// assert_eq!(1, broker.num_produced("my-topic", 0));
+/// ```
+pub struct MockBroker {
+    host: String,
+    broker: Arc<Broker>,
+}
+
+impl MockBroker {
+    /// Starts a new broker serving the given topics (name, number of
+    /// partitions) each partition hosting messages in the range of
+    /// the given (earliest, latest) offsets.  The broker keeps
+    /// serving requests for the rest of the process' life time.
+    ///
+    /// Panics if the broker cannot bind to a local port.
+    pub fn start(topics: &'static [(&'static str, i32)], offsets: (i64, i64)) -> MockBroker {
+        spawn(topics, offsets, 0)
+    }
+
+    /// Retrieves the "host:port" to bootstrap clients from.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Retrieves the number of requests served so far.
+    pub fn num_requests(&self) -> usize {
+        self.broker.num_requests.load(Ordering::SeqCst)
+    }
+
+    /// Retrieves the api keys of the requests served so far in the
+    /// order they were received in, e.g. `3` for a metadata request.
+    pub fn requests(&self) -> Vec<i16> {
+        self.broker.requests.lock().unwrap().clone()
+    }
+
+    /// Retrieves the correlation ids of the requests served so far in
+    /// the order they were received in.
+    pub fn correlation_ids(&self) -> Vec<i32> {
+        self.broker.correlation_ids.lock().unwrap().clone()
+    }
+
+    /// Replaces the topics described in metadata responses, e.g. to
+    /// simulate the creation or expansion of topics.
+    pub fn set_topics(&self, topics: &'static [(&'static str, i32)]) {
+        *self.broker.topics.lock().unwrap() = topics;
+    }
+
+    /// Directs group coordinator lookups to the specified broker
+    /// (node id, "host:port").  While set, group offset requests are
+    /// rejected with `KafkaCode::NotCoordinatorForGroup`; `None`
+    /// makes this broker coordinate all groups again.
+    pub fn set_coordinator(&self, coordinator: Option<(i32, String)>) {
+        *self.broker.coordinator.lock().unwrap() = coordinator;
+    }
+
+    /// Makes the broker answer produce and fetch requests for the
+    /// specified topic partition with the given error; `None` clears
+    /// a previously set error.
+    pub fn set_partition_error(&self, topic: &str, partition: i32, error: Option<KafkaCode>) {
+        let mut errors = self.broker.errors.lock().unwrap();
+        let tp = (topic.to_owned(), partition);
+        match error {
+            Some(e) => errors.insert(tp, e as i16),
+            None => errors.remove(&tp),
+        };
+    }
+
+    /// Makes the broker reject the next `n` produce requests for the
+    /// specified topic partition with the given error, e.g. to
+    /// simulate a transient failure.
+    pub fn fail_produce_requests(&self, topic: &str, partition: i32, error: KafkaCode, n: usize) {
+        self.broker
+            .produce_failures
+            .lock()
+            .unwrap()
+            .insert((topic.to_owned(), partition), (error as i16, n));
+    }
+
+    /// Makes the broker describe the specified topic partition
+    /// without a leader in its metadata responses, e.g. to simulate
+    /// all of its replicas being offline; `false` restores the
+    /// broker as its leader.  Fetch and produce requests for the
+    /// partition are still served unless scripted otherwise through
+    /// `MockBroker::set_partition_error`.
+    pub fn set_partition_leaderless(&self, topic: &str, partition: i32, leaderless: bool) {
+        let mut ps = self.broker.leaderless.lock().unwrap();
+        ps.retain(|(t, p)| (t.as_str(), *p) != (topic, partition));
+        if leaderless {
+            ps.push((topic.to_owned(), partition));
+        }
+    }
+
+    /// Makes the broker report an offline log dir (in addition to
+    /// the one hosting all partitions) in `DescribeLogDirs`
+    /// responses, e.g. to simulate a failed disk.
+    pub fn set_log_dir_offline(&self, offline: bool) {
+        self.broker.offline_log_dir.store(offline, Ordering::SeqCst);
+    }
+
+    /// Makes the broker reject the message sets of produce requests
+    /// exceeding the given size in bytes per partition with
+    /// `KafkaCode::RecordListTooLarge`, e.g. to simulate a batch not
+    /// fitting into a log segment; zero lifts the limit.
+    pub fn set_max_message_set_size(&self, size: usize) {
+        self.broker
+            .max_message_set_size
+            .store(size, Ordering::SeqCst);
+    }
+
+    /// Makes the broker create topics not existing yet with a single
+    /// partition when their metadata is requested, unless the request
+    /// disallows it, just like a broker configured with
+    /// `auto.create.topics.enable`.
+    pub fn set_auto_create_topics(&self, enabled: bool) {
+        self.broker
+            .auto_create_topics
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    /// Retrieves the topics created through metadata requests so far.
+    /// See `MockBroker::set_auto_create_topics`.
+    pub fn auto_created_topics(&self) -> Vec<String> {
+        self.broker.auto_created.lock().unwrap().clone()
+    }
+
+    /// Makes the broker describe another broker with the given id
+    /// and "host:port" in its metadata responses in addition to
+    /// itself, e.g. another `MockBroker` acting as a follower.  The
+    /// peer leads none of the partitions.  `None` removes the peer.
+    pub fn set_peer(&self, peer: Option<(i32, String)>) {
+        *self.broker.peer.lock().unwrap() = peer;
+    }
+
+    /// Makes the broker wait the given time before answering any
+    /// subsequent request, e.g. to simulate an overloaded broker.
+    pub fn set_response_delay(&self, delay: Duration) {
+        *self.broker.delay.lock().unwrap() = delay;
+    }
+
+    /// Makes the broker drop the connection instead of answering the
+    /// next `n` metadata requests, e.g. to simulate a cluster still
+    /// starting up.
+    pub fn reject_metadata_requests(&self, n: usize) {
+        self.broker.rejected_metadata.store(n, Ordering::SeqCst);
+    }
+
+    /// Ends the current leader epoch of all partitions at the given
+    /// offset and starts a new one, e.g. to simulate a leadership
+    /// change.  Metadata requests of version 7 or later report the
+    /// new epoch; requests for the end offset of the ended epoch are
+    /// answered with `end_offset`.
+    pub fn bump_leader_epoch(&self, end_offset: i64) {
+        self.broker
+            .epoch_end_offsets
+            .lock()
+            .unwrap()
+            .push(end_offset);
+    }
+
+    /// Retrieves the number of messages produced to the specified
+    /// topic partition so far.
+    pub fn num_produced(&self, topic: &str, partition: i32) -> usize {
+        self.broker
+            .produced
+            .lock()
+            .unwrap()
+            .get(&(topic.to_owned(), partition))
+            .map_or(0, Vec::len)
+    }
+
+    /// Retrieves the values of the messages produced to the specified
+    /// topic partition so far in the order they were appended.
+    pub fn produced_values(&self, topic: &str, partition: i32) -> Vec<Vec<u8>> {
+        self.broker
+            .produced
+            .lock()
+            .unwrap()
+            .get(&(topic.to_owned(), partition))
+            .map_or_else(Vec::new, |log| log.iter().map(|m| m.1.clone()).collect())
+    }
+
+    /// Retrieves the offset last committed for the specified topic
+    /// partition, if any.  The broker does not distinguish between
+    /// groups.
+    pub fn committed_offset(&self, topic: &str, partition: i32) -> Option<i64> {
+        self.broker
+            .committed
+            .lock()
+            .unwrap()
+            .get(&(topic.to_owned(), partition))
+            .copied()
+    }
+
+    /// Retrieves the ack timeouts (in milliseconds) of the produce
+    /// requests served so far in the order of their arrival.
+    pub fn ack_timeouts(&self) -> Vec<i32> {
+        self.broker.ack_timeouts.lock().unwrap().clone()
+    }
+
+    /// Retrieves the max bytes requested by the last fetch request
+    /// for the specified topic partition, if any.
+    pub fn fetch_max_bytes(&self, topic: &str, partition: i32) -> Option<i32> {
+        self.broker
+            .fetch_max_bytes
+            .lock()
+            .unwrap()
+            .get(&(topic.to_owned(), partition))
+            .copied()
+    }
+
+    /// Retrieves the (max wait time in milliseconds, min bytes) of
+    /// the last fetch request, if any.  The broker itself answers
+    /// fetch requests right away regardless of these.
+    pub fn fetch_wait(&self) -> Option<(i32, i32)> {
+        *self.broker.fetch_wait.lock().unwrap()
+    }
+
+    /// Retrieves the replica id of the last fetch request, if any;
+    /// `-1` for ordinary consumers.
+    pub fn fetch_replica_id(&self) -> Option<i32> {
+        *self.broker.fetch_replica_id.lock().unwrap()
+    }
+
+    /// Retrieves the (name, version) of the client software reported
+    /// by the last `ApiVersions` request, if any.
+    pub fn client_software(&self) -> Option<(String, String)> {
+        self.broker.client_software.lock().unwrap().clone()
+    }
+
+    /// Makes the broker drop the connection on requests of the given
+    /// api with a version higher than `version`, e.g. to simulate an
+    /// older broker.
+    pub fn set_max_api_version(&self, api_key: i16, version: i16) {
+        self.broker
+            .max_versions
+            .lock()
+            .unwrap()
+            .insert(api_key, version);
+    }
+}
+
+impl Broker {
+    fn serve_conn(&self, mut stream: TcpStream) {
+        loop {
+            let mut size = [0u8; 4];
+            if stream.read_exact(&mut size).is_err() {
+                return;
+            }
+            let mut req = vec![0u8; i32::from_be_bytes(size) as usize];
+            if stream.read_exact(&mut req).is_err() {
+                return;
+            }
+            self.num_requests.fetch_add(1, Ordering::SeqCst);
+
+            // ~ request header: api_key, api_version, correlation_id, client_id
+            let mut r = Cursor::new(&req[..]);
+            let api_key = i16::decode_new(&mut r).unwrap();
+            let api_version = i16::decode_new(&mut r).unwrap();
+            let correlation_id = i32::decode_new(&mut r).unwrap();
+            let _client_id = String::decode_new(&mut r).unwrap();
+            self.requests.lock().unwrap().push(api_key);
+            self.correlation_ids.lock().unwrap().push(correlation_id);
+            if let Some(&max) = self.max_versions.lock().unwrap().get(&api_key) {
+                if api_version > max {
+                    return;
+                }
+            }
+
+            let mut resp = Vec::new();
+            correlation_id.encode(&mut resp).unwrap();
+            match api_key {
+                API_KEY_PRODUCE => {
+                    if !self.render_produce(&mut resp, &mut r) {
+                        // ~ no acks required, no response
+                        continue;
+                    }
+                }
+                API_KEY_METADATA => {
+                    let rejected = self.rejected_metadata.fetch_update(
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                        |n| n.checked_sub(1),
+                    );
+                    if rejected.is_ok() {
+                        return;
+                    }
+                    self.render_metadata(&mut resp, &mut r, api_version);
+                }
+                API_KEY_OFFSET => self.render_offsets(&mut resp, &mut r),
+                API_KEY_FETCH => self.render_fetch(&mut resp, &mut r),
+                API_KEY_GROUP_COORDINATOR => self.render_group_coordinator(&mut resp),
+                API_KEY_OFFSET_COMMIT => self.render_offset_commit(&mut resp, &mut r),
+                API_KEY_OFFSET_FETCH => self.render_offset_fetch(&mut resp, &mut r),
+                API_KEY_OFFSET_FOR_LEADER_EPOCH => {
+                    self.render_offset_for_leader_epoch(&mut resp, &mut r);
+                }
+                API_KEY_API_VERSIONS => self.render_api_versions(&mut resp, &mut r),
+                API_KEY_DESCRIBE_LOG_DIRS => self.render_describe_log_dirs(&mut resp, &mut r),
+                API_KEY_DESCRIBE_USER_SCRAM_CREDENTIALS => {
+                    self.render_describe_scram_credentials(&mut resp, &mut r);
+                }
+                API_KEY_ALTER_USER_SCRAM_CREDENTIALS => {
+                    self.render_alter_scram_credentials(&mut resp, &mut r);
+                }
+                _ => return,
+            }
+
+            let delay = *self.delay.lock().unwrap();
+            if !delay.is_zero() {
+                thread::sleep(delay);
+            }
+            let mut out = Vec::with_capacity(resp.len() + 4);
+            (resp.len() as i32).encode(&mut out).unwrap();
+            out.extend_from_slice(&resp);
+            if stream.write_all(&out).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn render_metadata<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R, version: i16) {
+        // ~ request: [topic]; empty for all topics
+        let requested = Vec::<String>::decode_new(req).unwrap_or_default();
+        // ~ allow_auto_topic_creation (v4+)
+        let allow_create = version < 4 || i8::decode_new(req).unwrap() != 0;
+        let mut auto_created = self.auto_created.lock().unwrap();
+        if allow_create && self.auto_create_topics.load(Ordering::SeqCst) {
+            let topics = *self.topics.lock().unwrap();
+            for name in &requested {
+                if !topics.iter().any(|t| t.0 == name) && !auto_created.contains(name) {
+                    auto_created.push(name.clone());
+                }
+            }
+        }
+        if version >= 3 {
+            // ~ throttle_time_ms
+            0i32.encode(resp).unwrap();
+        }
+        // ~ brokers: [node_id host port rack(v1+)]
+        let mut brokers = vec![(0, self.addr.to_string())];
+        brokers.extend(self.peer.lock().unwrap().clone());
+        (brokers.len() as i32).encode(resp).unwrap();
+        for (id, host) in brokers {
+            let (host, port) = host.rsplit_once(':').unwrap();
+            id.encode(resp).unwrap();
+            host.encode(resp).unwrap();
+            port.parse::<i32>().unwrap().encode(resp).unwrap();
+            if version >= 1 {
+                (-1i16).encode(resp).unwrap();
+            }
+        }
+        if version >= 2 {
+            // ~ cluster_id
+            "mock".encode(resp).unwrap();
+        }
+        if version >= 1 {
+            // ~ controller_id
+            0i32.encode(resp).unwrap();
+        }
+        let leader_epoch = self.epoch_end_offsets.lock().unwrap().len() as i32;
+        // ~ topics: [error name is_internal(v1+) [error id leader
+        //   leader_epoch(v7+) [replicas] [isr] [offline(v5+)]]]
+        let topics = *self.topics.lock().unwrap();
+        let all = topics
+            .iter()
+            .map(|&(name, n)| (name, n))
+            .chain(auto_created.iter().map(|name| (name.as_str(), 1)));
+        let topics: Vec<(&str, Option<i32>)> = if requested.is_empty() {
+            all.map(|(name, n)| (name, Some(n))).collect()
+        } else {
+            let all: Vec<_> = all.collect();
+            requested
+                .iter()
+                .map(|name| {
+                    let n = all.iter().find(|t| t.0 == name).map(|t| t.1);
+                    (name.as_str(), n)
+                })
+                .collect()
+        };
+        (topics.len() as i32).encode(resp).unwrap();
+        for (name, num_partitions) in topics {
+            match num_partitions {
+                Some(_) => 0i16.encode(resp).unwrap(),
+                None => (KafkaCode::UnknownTopicOrPartition as i16)
+                    .encode(resp)
+                    .unwrap(),
+            }
+            name.encode(resp).unwrap();
+            if version >= 1 {
+                0i8.encode(resp).unwrap();
+            }
+            let num_partitions = num_partitions.unwrap_or(0);
+            num_partitions.encode(resp).unwrap();
+            let leaderless = self.leaderless.lock().unwrap();
+            for id in 0..num_partitions {
+                if leaderless
+                    .iter()
+                    .any(|(t, p)| (t.as_str(), *p) == (name, id))
+                {
+                    (KafkaCode::LeaderNotAvailable as i16).encode(resp).unwrap();
+                    id.encode(resp).unwrap();
+                    (-1i32).encode(resp).unwrap();
+                } else {
+                    0i16.encode(resp).unwrap();
+                    id.encode(resp).unwrap();
+                    0i32.encode(resp).unwrap();
+                }
+                if version >= 7 {
+                    leader_epoch.encode(resp).unwrap();
+                }
+                [0i32].encode(resp).unwrap();
+                [0i32].encode(resp).unwrap();
+                if version >= 5 {
+                    [0i32; 0].encode(resp).unwrap();
+                }
+            }
+        }
+    }
+
+    // ~ returns false if the request requires no response
+    fn render_produce<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) -> bool {
+        // ~ request: acks timeout [topic [partition message_set]]
+        let acks = i16::decode_new(req).unwrap();
+        let timeout = i32::decode_new(req).unwrap();
+        self.ack_timeouts.lock().unwrap().push(timeout);
+        let mut produced = self.produced.lock().unwrap();
+        let errors = self.errors.lock().unwrap();
+        let mut failures = self.produce_failures.lock().unwrap();
+        let max_size = self.max_message_set_size.load(Ordering::SeqCst);
+        render_topic_partitions(resp, req, |resp, req, topic, partition| {
+            let mset = Vec::<u8>::decode_new(req).unwrap();
+            let tp = (topic.to_owned(), partition);
+            // ~ response: partition error base_offset
+            partition.encode(resp).unwrap();
+            let failure = match failures.get_mut(&tp) {
+                Some((error, n)) if *n > 0 => {
+                    *n -= 1;
+                    Some(*error)
+                }
+                _ if max_size > 0 && mset.len() > max_size => {
+                    Some(KafkaCode::RecordListTooLarge as i16)
+                }
+                _ => None,
+            };
+            if let Some(error) = errors.get(&tp).copied().or(failure) {
+                error.encode(resp).unwrap();
+                (-1i64).encode(resp).unwrap();
+                return;
+            }
+            let log = produced.entry(tp).or_default();
+            0i16.encode(resp).unwrap();
+            (self.offsets.1 + log.len() as i64).encode(resp).unwrap();
+            // ~ message set: [offset size crc magic attributes key value]
+            let mut r = Cursor::new(&mset[..]);
+            while (r.position() as usize) < mset.len() {
+                let _offset = i64::decode_new(&mut r).unwrap();
+                let _size = i32::decode_new(&mut r).unwrap();
+                let _crc = i32::decode_new(&mut r).unwrap();
+                let _magic = i8::decode_new(&mut r).unwrap();
+                let _attributes = i8::decode_new(&mut r).unwrap();
+                let key = decode_bytes(&mut r);
+                let value = decode_bytes(&mut r).unwrap_or_default();
+                log.push((key, value));
+            }
+        });
+        acks != 0
+    }
+
+    fn render_offsets<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request: replica_id [topic [partition time max_offsets]]
+        let _replica_id = i32::decode_new(req).unwrap();
+        render_topic_partitions(resp, req, |resp, req, _, partition| {
+            let time = i64::decode_new(req).unwrap();
+            let _max_offsets = i32::decode_new(req).unwrap();
+            // ~ response: partition error [offset]
+            partition.encode(resp).unwrap();
+            0i16.encode(resp).unwrap();
+            let (earliest, latest) = self.offsets;
+            [if time == -2 { earliest } else { latest }]
+                .encode(resp)
+                .unwrap();
+        });
+    }
+
+    fn render_fetch<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request: replica_id max_wait_time min_bytes [topic [partition offset max_bytes]]
+        let replica_id = i32::decode_new(req).unwrap();
+        *self.fetch_replica_id.lock().unwrap() = Some(replica_id);
+        let max_wait_time = i32::decode_new(req).unwrap();
+        let min_bytes = i32::decode_new(req).unwrap();
+        *self.fetch_wait.lock().unwrap() = Some((max_wait_time, min_bytes));
+        let produced = self.produced.lock().unwrap();
+        let errors = self.errors.lock().unwrap();
+        let mut fetch_max_bytes = self.fetch_max_bytes.lock().unwrap();
+        render_topic_partitions(resp, req, |resp, req, topic, partition| {
+            let offset = i64::decode_new(req).unwrap();
+            let max_bytes = i32::decode_new(req).unwrap();
+            let tp = (topic.to_owned(), partition);
+            fetch_max_bytes.insert(tp.clone(), max_bytes);
+            // ~ response: partition error highwatermark message_set
+            partition.encode(resp).unwrap();
+            if let Some(&error) = errors.get(&tp) {
+                error.encode(resp).unwrap();
+                (-1i64).encode(resp).unwrap();
+                0i32.encode(resp).unwrap();
+                return;
+            }
+            let (earliest, latest) = self.offsets;
+            // ~ messages produced so far are appended to the log
+            let log = produced.get(&tp).map_or(&[][..], Vec::as_slice);
+            let appended = latest;
+            let latest = latest + log.len() as i64;
+            if offset < earliest || offset > latest {
+                1i16.encode(resp).unwrap();
+                latest.encode(resp).unwrap();
+                0i32.encode(resp).unwrap();
+                return;
+            }
+            0i16.encode(resp).unwrap();
+            latest.encode(resp).unwrap();
+            let mut mset = Vec::new();
+            if offset >= appended && offset < latest {
+                let (ref key, ref value) = log[(offset - appended) as usize];
+                render_message(&mut mset, offset, key.as_deref(), value);
+            } else if offset < latest {
+                let value = format!("{:<1$}", offset, self.value_size);
+                render_message(&mut mset, offset, None, value.as_bytes());
+            }
+            mset.truncate(max_bytes as usize);
+            mset.encode(resp).unwrap();
+        });
+    }
+
+    fn render_describe_log_dirs<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request: [topic [partition]]; null for all partitions
+        let n = i32::decode_new(req).unwrap();
+        let requested: Option<Vec<(String, Vec<i32>)>> = (n >= 0).then(|| {
+            (0..n)
+                .map(|_| {
+                    let topic = String::decode_new(req).unwrap();
+                    (topic, Vec::<i32>::decode_new(req).unwrap())
+                })
+                .collect()
+        });
+        let topics = *self.topics.lock().unwrap();
+        let requested = requested.unwrap_or_else(|| {
+            topics
+                .iter()
+                .map(|&(name, n)| (name.to_owned(), (0..n).collect()))
+                .collect()
+        });
+        // ~ response: throttle_time_ms [error log_dir [topic
+        //   [partition size offset_lag is_future]]]
+        0i32.encode(resp).unwrap();
+        let offline = self.offline_log_dir.load(Ordering::SeqCst);
+        (if offline { 2i32 } else { 1i32 }).encode(resp).unwrap();
+        0i16.encode(resp).unwrap();
+        "/var/lib/kafka/data".encode(resp).unwrap();
+        // ~ only partitions the broker hosts are reported
+        let requested: Vec<(String, Vec<i32>)> = requested
+            .into_iter()
+            .filter_map(|(topic, ps)| {
+                let n = topics.iter().find(|t| t.0 == topic)?.1;
+                Some((topic, ps.into_iter().filter(|&p| p >= 0 && p < n).collect()))
+            })
+            .collect();
+        let produced = self.produced.lock().unwrap();
+        (requested.len() as i32).encode(resp).unwrap();
+        for (topic, partitions) in requested {
+            topic.encode(resp).unwrap();
+            (partitions.len() as i32).encode(resp).unwrap();
+            for partition in partitions {
+                // ~ the size of the messages produced so far; 26 bytes
+                // of overhead per message
+                let size: usize = produced.get(&(topic.clone(), partition)).map_or(0, |log| {
+                    log.iter()
+                        .map(|(k, v)| 26 + k.as_ref().map_or(0, Vec::len) + v.len())
+                        .sum()
+                });
+                partition.encode(resp).unwrap();
+                (size as i64).encode(resp).unwrap();
+                0i64.encode(resp).unwrap();
+                0i8.encode(resp).unwrap();
+            }
+        }
+        if offline {
+            (KafkaCode::KafkaStorageError as i16).encode(resp).unwrap();
+            "/var/lib/kafka/offline".encode(resp).unwrap();
+            0i32.encode(resp).unwrap();
+        }
+    }
+
+    fn render_api_versions<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request (v3): tagged fields (of the header)
+        //   client_software_name client_software_version tagged fields
+        codecs::skip_tagged_fields(req).unwrap();
+        let name = codecs::decode_compact_string(req).unwrap();
+        let version = codecs::decode_compact_string(req).unwrap();
+        *self.client_software.lock().unwrap() = Some((name, version));
+        // ~ response: error [api_key min_version max_version tagged
+        //   fields] throttle_time_ms tagged fields
+        let max_versions = self.max_versions.lock().unwrap();
+        let apis = [
+            API_KEY_PRODUCE,
+            API_KEY_FETCH,
+            API_KEY_OFFSET,
+            API_KEY_METADATA,
+            API_KEY_OFFSET_COMMIT,
+            API_KEY_OFFSET_FETCH,
+            API_KEY_GROUP_COORDINATOR,
+            API_KEY_API_VERSIONS,
+        ];
+        0i16.encode(resp).unwrap();
+        codecs::encode_compact_array_len(resp, apis.len()).unwrap();
+        for api_key in apis {
+            api_key.encode(resp).unwrap();
+            0i16.encode(resp).unwrap();
+            max_versions
+                .get(&api_key)
+                .copied()
+                .unwrap_or(i16::MAX)
+                .encode(resp)
+                .unwrap();
+            0i8.encode(resp).unwrap();
+        }
+        0i32.encode(resp).unwrap();
+        0i8.encode(resp).unwrap();
+    }
+
+    fn render_group_coordinator(&self, resp: &mut Vec<u8>) {
+        // ~ response: error coordinator_id host port
+        0i16.encode(resp).unwrap();
+        match *self.coordinator.lock().unwrap() {
+            Some((id, ref host)) => {
+                let (host, port) = host.rsplit_once(':').unwrap();
+                id.encode(resp).unwrap();
+                host.encode(resp).unwrap();
+                port.parse::<i32>().unwrap().encode(resp).unwrap();
+            }
+            None => {
+                0i32.encode(resp).unwrap();
+                self.addr.ip().to_string().encode(resp).unwrap();
+                i32::from(self.addr.port()).encode(resp).unwrap();
+            }
+        }
+    }
+
+    // ~ the error code for group offset requests; non-zero if the
+    // groups are coordinated elsewhere
+    fn group_error(&self) -> i16 {
+        if self.coordinator.lock().unwrap().is_some() {
+            16
+        } else {
+            0
+        }
+    }
+
+    fn render_offset_commit<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request (v0): group [topic [partition offset metadata]]
+        let _group = String::decode_new(req).unwrap();
+        let error = self.group_error();
+        let mut committed = self.committed.lock().unwrap();
+        render_topic_partitions(resp, req, |resp, req, topic, partition| {
+            let offset = i64::decode_new(req).unwrap();
+            let _metadata = String::decode_new(req).unwrap();
+            if error == 0 {
+                committed.insert((topic.to_owned(), partition), offset);
+            }
+            // ~ response: partition error
+            partition.encode(resp).unwrap();
+            error.encode(resp).unwrap();
+        });
+    }
+
+    fn render_offset_fetch<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request: group [topic [partition]]
+        let _group = String::decode_new(req).unwrap();
+        let error = self.group_error();
+        let committed = self.committed.lock().unwrap();
+        render_topic_partitions(resp, req, |resp, _, topic, partition| {
+            // ~ response: partition offset metadata error
+            partition.encode(resp).unwrap();
+            match committed.get(&(topic.to_owned(), partition)) {
+                _ if error != 0 => {
+                    (-1i64).encode(resp).unwrap();
+                    "".encode(resp).unwrap();
+                    error.encode(resp).unwrap();
+                }
+                Some(offset) => {
+                    offset.encode(resp).unwrap();
+                    "".encode(resp).unwrap();
+                    0i16.encode(resp).unwrap();
+                }
+                None => {
+                    (-1i64).encode(resp).unwrap();
+                    "".encode(resp).unwrap();
+                    3i16.encode(resp).unwrap();
+                }
+            }
+        });
+    }
+
+    fn render_offset_for_leader_epoch<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request: [topic [partition leader_epoch]]
+        let ends = self.epoch_end_offsets.lock().unwrap();
+        let produced = self.produced.lock().unwrap();
+        render_topic_partitions(resp, req, |resp, req, topic, partition| {
+            let epoch = i32::decode_new(req).unwrap();
+            // ~ response: error partition end_offset
+            let end_offset = match usize::try_from(epoch) {
+                Ok(i) if i < ends.len() => Some(ends[i]),
+                Ok(i) if i == ends.len() => {
+                    let tp = (topic.to_owned(), partition);
+                    let n = produced.get(&tp).map_or(0, Vec::len);
+                    Some(self.offsets.1 + n as i64)
+                }
+                _ => None,
+            };
+            match end_offset {
+                Some(end_offset) => {
+                    0i16.encode(resp).unwrap();
+                    partition.encode(resp).unwrap();
+                    end_offset.encode(resp).unwrap();
+                }
+                None => {
+                    (KafkaCode::UnknownLeaderEpoch as i16).encode(resp).unwrap();
+                    partition.encode(resp).unwrap();
+                    (-1i64).encode(resp).unwrap();
+                }
+            }
+        });
+    }
+
+    fn render_describe_scram_credentials<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request (flexible): [user]; null for all users
+        codecs::skip_tagged_fields(req).unwrap();
+        let requested = match codecs::decode_uvarint(req).unwrap() {
+            0 => None,
+            n => Some(
+                (1..n)
+                    .map(|_| {
+                        let user = codecs::decode_compact_string(req).unwrap();
+                        codecs::skip_tagged_fields(req).unwrap();
+                        user
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        };
+        let credentials = self.scram_credentials.lock().unwrap();
+        let users = requested.unwrap_or_else(|| {
+            let mut users: Vec<_> = credentials.keys().cloned().collect();
+            users.sort();
+            users
+        });
+        // ~ response: header-tags throttle error error_message
+        //   [user error error_message [mechanism iterations]]
+        0i8.encode(resp).unwrap();
+        0i32.encode(resp).unwrap();
+        0i16.encode(resp).unwrap();
+        codecs::encode_uvarint(resp, 0).unwrap();
+        codecs::encode_compact_array_len(resp, users.len()).unwrap();
+        for user in &users {
+            codecs::encode_compact_string(resp, user).unwrap();
+            let infos = credentials.get(user).map(Vec::as_slice).unwrap_or_default();
+            let error = if infos.is_empty() {
+                KafkaCode::ResourceNotFound as i16
+            } else {
+                0
+            };
+            error.encode(resp).unwrap();
+            codecs::encode_uvarint(resp, 0).unwrap();
+            codecs::encode_compact_array_len(resp, infos.len()).unwrap();
+            for &(mechanism, iterations) in infos {
+                mechanism.encode(resp).unwrap();
+                iterations.encode(resp).unwrap();
+                0i8.encode(resp).unwrap();
+            }
+            0i8.encode(resp).unwrap();
+        }
+        0i8.encode(resp).unwrap();
+    }
+
+    fn render_alter_scram_credentials<R: Read>(&self, resp: &mut Vec<u8>, req: &mut R) {
+        // ~ request (flexible): [user mechanism] [user mechanism
+        //   iterations salt salted_password]
+        codecs::skip_tagged_fields(req).unwrap();
+        let mut results: Vec<(String, i16)> = Vec::new();
+        let mut credentials = self.scram_credentials.lock().unwrap();
+        for _ in 0..codecs::decode_compact_array_len(req).unwrap() {
+            let user = codecs::decode_compact_string(req).unwrap();
+            let mechanism = i8::decode_new(req).unwrap();
+            codecs::skip_tagged_fields(req).unwrap();
+            let infos = credentials.entry(user.clone()).or_default();
+            let error = match infos.iter().position(|&(m, _)| m == mechanism) {
+                Some(i) => {
+                    infos.remove(i);
+                    0
+                }
+                None => KafkaCode::ResourceNotFound as i16,
+            };
+            results.push((user, error));
+        }
+        for _ in 0..codecs::decode_compact_array_len(req).unwrap() {
+            let user = codecs::decode_compact_string(req).unwrap();
+            let mechanism = i8::decode_new(req).unwrap();
+            let iterations = i32::decode_new(req).unwrap();
+            for _ in 0..2 {
+                // ~ salt, salted_password
+                let n = codecs::decode_compact_array_len(req).unwrap();
+                req.read_exact(&mut vec![0; n]).unwrap();
+            }
+            codecs::skip_tagged_fields(req).unwrap();
+            let infos = credentials.entry(user.clone()).or_default();
+            infos.retain(|&(m, _)| m != mechanism);
+            infos.push((mechanism, iterations));
+            results.push((user, 0));
+        }
+        credentials.retain(|_, infos| !infos.is_empty());
+        // ~ response: header-tags throttle [user error error_message]
+        0i8.encode(resp).unwrap();
+        0i32.encode(resp).unwrap();
+        codecs::encode_compact_array_len(resp, results.len()).unwrap();
+        for (user, error) in &results {
+            codecs::encode_compact_string(resp, user).unwrap();
+            error.encode(resp).unwrap();
+            codecs::encode_uvarint(resp, 0).unwrap();
+            0i8.encode(resp).unwrap();
+        }
+        0i8.encode(resp).unwrap();
+    }
+}
+
+// ~ reads the [topic [partition ...]] structure common to many
+// requests, echoing it into the response and delegating the
+// rendering of the partition specific parts to `f`
+fn render_topic_partitions<R, F>(resp: &mut Vec<u8>, req: &mut R, mut f: F)
+where
+    R: Read,
+    F: FnMut(&mut Vec<u8>, &mut R, &str, i32),
+{
+    let num_topics = i32::decode_new(req).unwrap();
+    num_topics.encode(resp).unwrap();
+    for _ in 0..num_topics {
+        let topic = String::decode_new(req).unwrap();
+        topic.encode(resp).unwrap();
+        let num_partitions = i32::decode_new(req).unwrap();
+        num_partitions.encode(resp).unwrap();
+        for _ in 0..num_partitions {
+            let partition = i32::decode_new(req).unwrap();
+            f(resp, req, &topic, partition);
+        }
+    }
+}
+
+// ~ reads nullable bytes: size data
+fn decode_bytes<R: Read>(r: &mut R) -> Option<Vec<u8>> {
+    let size = i32::decode_new(r).unwrap();
+    if size < 0 {
+        return None;
+    }
+    let mut data = vec![0; size as usize];
+    r.read_exact(&mut data).unwrap();
+    Some(data)
+}
+
+// ~ renders a v0 message: offset size crc magic attributes key value
+/// Renders a single uncompressed (v0) message with the given offset
+/// into `out` as part of a message set.
+pub fn render_message(out: &mut Vec<u8>, offset: i64, key: Option<&[u8]>, value: &[u8]) {
+    let mut msg = Vec::new();
+    0i8.encode(&mut msg).unwrap();
+    0i8.encode(&mut msg).unwrap();
+    match key {
+        Some(key) => key.encode(&mut msg).unwrap(),
+        None => (-1i32).encode(&mut msg).unwrap(),
+    }
+    value.encode(&mut msg).unwrap();
+
+    offset.encode(out).unwrap();
+    (msg.len() as i32 + 4).encode(out).unwrap();
+    (to_crc(&msg) as i32).encode(out).unwrap();
+    out.extend_from_slice(&msg);
+}
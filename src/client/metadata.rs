@@ -6,6 +6,7 @@ use std::fmt;
 
 use super::state::{ClientState, TopicPartition, TopicPartitionIter, TopicPartitions};
 use super::KafkaClient;
+use crate::protocol;
 
 // public re-export
 pub use super::state::Broker;
@@ -21,9 +22,12 @@ impl<'a> Topics<'a> {
     /// the specified kafka client.
     #[inline]
     pub fn new(client: &KafkaClient) -> Topics<'_> {
-        Topics {
-            state: &client.state,
-        }
+        Topics::from_state(&client.state)
+    }
+
+    #[inline]
+    pub(crate) fn from_state(state: &ClientState) -> Topics<'_> {
+        Topics { state }
     }
 
     /// Retrieves the number of the underlying topics.
@@ -301,6 +305,30 @@ impl<'a> Partition<'a> {
         self.partition.broker(self.state)
     }
 
+    /// Retrieves the epoch of this partition's current leader as
+    /// reported by the brokers.  The epoch is bumped every time the
+    /// partition's leadership changes and is delivered only when
+    /// using `MetadataVersion::V7` or later.  See
+    /// `KafkaClient::set_metadata_version`.
+    #[inline]
+    pub fn leader_epoch(&self) -> Option<i32> {
+        self.partition.leader_epoch()
+    }
+
+    /// Retrieves the node ids of the brokers replicating this
+    /// partition, including the leader.
+    #[inline]
+    pub fn replicas(&self) -> &'a [i32] {
+        self.partition.replicas()
+    }
+
+    /// Retrieves the node ids of the replicas currently in sync with
+    /// this partition's leader.
+    #[inline]
+    pub fn isr(&self) -> &'a [i32] {
+        self.partition.isr()
+    }
+
     /// Determines whether this partition is currently "available".
     /// See `Partition::leader()`.
     pub fn is_available(&self) -> bool {
@@ -318,3 +346,196 @@ impl<'a> fmt::Debug for Partition<'a> {
         )
     }
 }
+
+// --------------------------------------------------------------------
+
+/// An owned snapshot of the loaded cluster metadata.  See
+/// `KafkaClient::cluster_metadata`.
+///
+/// With the `serde` feature enabled, the snapshot and its parts
+/// implement `serde::Serialize`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ClusterMetadata {
+    /// The id of the cluster - if known.  See
+    /// `KafkaClient::cluster_id`.
+    pub cluster_id: Option<String>,
+    /// The node id of the controller broker - if known.  See
+    /// `KafkaClient::controller_id`.
+    pub controller_id: Option<i32>,
+    /// The known brokers ordered by their node id.
+    pub brokers: Vec<BrokerMetadata>,
+    /// The known topics ordered by their name.
+    pub topics: Vec<TopicMetadata>,
+}
+
+/// A snapshot of the metadata of a single broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BrokerMetadata {
+    /// The node id of the broker.
+    pub id: i32,
+    /// The host name of the broker.
+    pub host: String,
+    /// The port the broker is listening on.
+    pub port: i32,
+    /// The rack of the broker - if known.  See `Broker::rack`.
+    pub rack: Option<String>,
+}
+
+/// A snapshot of the metadata of a single topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TopicMetadata {
+    /// The name of the topic.
+    pub name: String,
+    /// The partitions of the topic ordered by their id.
+    pub partitions: Vec<PartitionMetadata>,
+}
+
+/// A snapshot of the metadata of a single topic partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PartitionMetadata {
+    /// The id of the partition.
+    pub id: i32,
+    /// The node id of the partition's leader broker - if any.
+    pub leader: Option<i32>,
+    /// The epoch of the partition's leader - if known.  See
+    /// `Partition::leader_epoch`.
+    pub leader_epoch: Option<i32>,
+    /// The node ids of the brokers replicating the partition.
+    pub replicas: Vec<i32>,
+    /// The node ids of the replicas in sync with the leader.
+    pub isr: Vec<i32>,
+}
+
+impl ClusterMetadata {
+    /// Takes a snapshot of the metadata currently loaded by the
+    /// specified kafka client.
+    pub fn new(client: &KafkaClient) -> ClusterMetadata {
+        let state = &client.state;
+        let mut brokers: Vec<_> = state
+            .brokers()
+            .iter()
+            .map(|b| {
+                // ~ the broker's host is maintained as "host:port"
+                let (host, port) = match b.host().rsplit_once(':') {
+                    Some((host, port)) => (host, port.parse().unwrap_or(-1)),
+                    None => (b.host(), -1),
+                };
+                BrokerMetadata {
+                    id: b.id(),
+                    host: host.to_owned(),
+                    port,
+                    rack: b.rack().map(str::to_owned),
+                }
+            })
+            .collect();
+        brokers.sort_by_key(|b| b.id);
+
+        let mut topics: Vec<_> = Topics::new(client)
+            .iter()
+            .map(|t| TopicMetadata {
+                name: t.name().to_owned(),
+                partitions: t
+                    .partitions()
+                    .iter()
+                    .map(|p| PartitionMetadata {
+                        id: p.id(),
+                        leader: p.leader().map(Broker::id),
+                        leader_epoch: p.leader_epoch(),
+                        replicas: p.replicas().to_vec(),
+                        isr: p.isr().to_vec(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        topics.sort_by(|a, b| a.name.cmp(&b.name));
+
+        ClusterMetadata {
+            cluster_id: state.cluster_id().map(str::to_owned),
+            controller_id: state.controller_id(),
+            brokers,
+            topics,
+        }
+    }
+}
+
+// --------------------------------------------------------------------
+
+/// A description of the cluster as obtained through
+/// `KafkaClient::describe_cluster`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ClusterDescription {
+    /// The id of the cluster - if known.
+    pub cluster_id: Option<String>,
+    /// The node id of the controller broker - if known.
+    pub controller_id: Option<i32>,
+    /// The brokers of the cluster ordered by their node id.
+    pub brokers: Vec<BrokerMetadata>,
+    /// The operations the client is authorized to perform on the
+    /// cluster as a bit field; bit `n` is set if the ACL operation
+    /// with code `n` (e.g. `7` for "alter") is allowed.  `None` if
+    /// the brokers did not report the authorized operations, e.g.
+    /// because they do not support the `DescribeCluster` api.
+    pub authorized_operations: Option<i32>,
+}
+
+impl ClusterDescription {
+    /// Determines whether the ACL operation with the given code is
+    /// known to be authorized.
+    pub fn is_authorized(&self, operation: u8) -> bool {
+        self.authorized_operations
+            .is_some_and(|ops| operation < 32 && ops & (1 << operation) != 0)
+    }
+
+    pub(crate) fn from_response(r: protocol::DescribeClusterResponse) -> Self {
+        let authorized_operations = r.authorized_operations();
+        let mut brokers: Vec<_> = r
+            .brokers
+            .into_iter()
+            .map(|b| BrokerMetadata {
+                id: b.broker_id,
+                host: b.host,
+                port: b.port,
+                rack: b.rack,
+            })
+            .collect();
+        brokers.sort_by_key(|b| b.id);
+        ClusterDescription {
+            cluster_id: Some(r.cluster_id),
+            // ~ -1 denotes an unknown controller
+            controller_id: Some(r.controller_id).filter(|&id| id >= 0),
+            brokers,
+            authorized_operations,
+        }
+    }
+}
+
+impl From<ClusterMetadata> for ClusterDescription {
+    fn from(m: ClusterMetadata) -> Self {
+        ClusterDescription {
+            cluster_id: m.cluster_id,
+            controller_id: m.controller_id,
+            brokers: m.brokers,
+            authorized_operations: None,
+        }
+    }
+}
+
+// --------------------------------------------------------------------
+
+/// The range of versions a broker supports for one of its apis as
+/// obtained through `KafkaClient::fetch_api_versions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ApiVersion {
+    /// The key identifying the api, e.g. `3` for metadata requests.
+    pub api_key: i16,
+    /// The lowest supported version of the api.
+    pub min_version: i16,
+    /// The highest supported version of the api.
+    pub max_version: i16,
+}
@@ -6,26 +6,45 @@
 
 use std;
 use std::collections::hash_map;
-use std::collections::hash_map::HashMap;
-use std::io::Cursor;
+use std::collections::hash_map::{HashMap, RandomState};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Cursor};
 use std::iter::Iterator;
 use std::mem;
 use std::thread;
 use std::time::{Duration, Instant};
 
 // pub re-export
-pub use crate::compression::Compression;
+pub use crate::compression::{Codec, Compression};
+pub use crate::protocol::MetadataVersion;
+pub use crate::protocol::{
+    AlterScramCredentialResult, LogDir, LogDirPartition, ScramCredentialDeletion,
+    ScramCredentialInfo, ScramCredentialUpsertion, ScramMechanism, UserScramCredentials,
+};
 pub use crate::utils::PartitionOffset;
 
+pub use self::bootstrap::{DnsResolver, Resolver};
+
 #[cfg(feature = "security")]
 pub use self::network::SecurityConfig;
 
+#[cfg(feature = "test-util")]
+pub use self::fake_broker::MockBroker;
+
+#[cfg(feature = "async")]
+pub use self::async_client::AsyncKafkaClient;
+
 use crate::codecs::{FromByte, ToByte};
 use crate::error::{Error, KafkaCode, Result};
-use crate::protocol::{self, ResponseParser};
+use crate::protocol::{self, ResponseParser, SliceResponseParser};
 
 use crate::client_internals::KafkaClientInternals;
 
+#[cfg(feature = "async")]
+pub mod async_client;
+mod bootstrap;
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) mod fake_broker;
 pub mod metadata;
 mod network;
 mod state;
@@ -35,10 +54,19 @@ mod state;
 pub mod fetch {
     //! A representation of fetched messages from Kafka.
 
-    pub use crate::protocol::fetch::{Data, Message, Partition, Response, Topic};
+    pub use crate::protocol::fetch::{BorrowedResponse, Data, Message, Partition, Response, Topic};
+
+    #[cfg(feature = "bytes")]
+    pub use crate::protocol::fetch::{BytesMessage, BytesMessageSet};
 }
 
-const DEFAULT_CONNECTION_RW_TIMEOUT_SECS: u64 = 120;
+/// The default value for `KafkaClient::set_control_timeout(..)` and
+/// `KafkaClient::set_data_timeout(..)` in seconds
+pub const DEFAULT_CONNECTION_RW_TIMEOUT_SECS: u64 = 120;
+
+// ~ the names of kafka's internal topics; recognized also by metadata
+// versions not flagging internal topics
+const INTERNAL_TOPICS: &[&str] = &["__consumer_offsets", "__transaction_state"];
 
 fn default_conn_rw_timeout() -> Option<Duration> {
     match DEFAULT_CONNECTION_RW_TIMEOUT_SECS {
@@ -47,9 +75,18 @@ fn default_conn_rw_timeout() -> Option<Duration> {
     }
 }
 
+/// The default software name for `KafkaClient::set_client_software(..)`
+pub const DEFAULT_CLIENT_SOFTWARE_NAME: &str = "kafka-rust";
+
+/// The default software version for `KafkaClient::set_client_software(..)`
+pub const DEFAULT_CLIENT_SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 /// The default value for `KafkaClient::set_compression(..)`
 pub const DEFAULT_COMPRESSION: Compression = Compression::NONE;
 
+/// The default value for `KafkaClient::set_compression_min_size(..)`
+pub const DEFAULT_COMPRESSION_MIN_SIZE: usize = 0;
+
 /// The default value for `KafkaClient::set_fetch_max_wait_time(..)`
 pub const DEFAULT_FETCH_MAX_WAIT_TIME_MILLIS: u64 = 100;
 
@@ -73,9 +110,32 @@ pub const DEFAULT_RETRY_BACKOFF_TIME_MILLIS: u64 = 100;
 // approximetaly up to two minutes
 pub const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 120_000 / DEFAULT_RETRY_BACKOFF_TIME_MILLIS as u32;
 
+/// The default value for the attempts of
+/// `KafkaClient::set_metadata_retries(..)`; metadata loads are not
+/// retried by default.
+pub const DEFAULT_METADATA_RETRY_MAX_ATTEMPTS: u32 = 1;
+
 /// The default value for `KafkaClient::set_connection_idle_timeout(..)`
 pub const DEFAULT_CONNECTION_IDLE_TIMEOUT_MILLIS: u64 = 540_000;
 
+/// The default value for `KafkaClient::set_metadata_version(..)`
+pub const DEFAULT_METADATA_VERSION: MetadataVersion = MetadataVersion::V0;
+
+/// The default value for `KafkaClient::set_allow_auto_topic_creation(..)`
+pub const DEFAULT_ALLOW_AUTO_TOPIC_CREATION: bool = true;
+
+/// The default value for `KafkaClient::set_unsupported_api_policy(..)`
+pub const DEFAULT_UNSUPPORTED_API_POLICY: ApiPolicy = ApiPolicy::SkipFeature;
+
+/// The default value for `KafkaClient::set_report_client_software(..)`
+pub const DEFAULT_REPORT_CLIENT_SOFTWARE: bool = false;
+
+/// The default value for `KafkaClient::set_randomize_bootstrap_order(..)`
+pub const DEFAULT_RANDOMIZE_BOOTSTRAP_ORDER: bool = true;
+
+/// The default value for `KafkaClient::set_max_response_size(..)`
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 100 * 1024 * 1024;
+
 /// Client struct keeping track of brokers and topic metadata.
 ///
 /// Implements methods described by the [Kafka Protocol](http://kafka.apache.org/protocol.html).
@@ -96,9 +156,28 @@ pub struct KafkaClient {
 #[derive(Debug)]
 struct ClientConfig {
     client_id: String,
+    // ~ the name and version of the client software reported to the
+    // brokers through the `ApiVersions` api (KIP-511)
+    client_software_name: String,
+    client_software_version: String,
+    // ~ whether to report the client software over every newly
+    // established connection
+    report_client_software: bool,
     hosts: Vec<String>,
+    // ~ whether to start contacting `hosts` at a position chosen
+    // randomly per client instance instead of always at the first
+    // one; see `bootstrap_offset`
+    randomize_bootstrap_order: bool,
+    // ~ the (random) position in `hosts` to start contacting the
+    // bootstrap hosts at - if `randomize_bootstrap_order` is enabled
+    bootstrap_offset: usize,
+    // ~ the addresses `hosts` periodically re-resolve to
+    bootstrap: bootstrap::Bootstrap,
     // ~ compression to use when sending messages
     compression: Compression,
+    // ~ the uncompressed size below which message sets are sent
+    // uncompressed
+    compression_min_size: usize,
     // ~ these are the defaults when fetching messages for details
     // refer to the kafka wire protocol
     fetch_max_wait_time: i32,
@@ -112,6 +191,17 @@ struct ClientConfig {
     // storage type.
     offset_fetch_version: protocol::OffsetFetchVersion,
     offset_commit_version: protocol::OffsetCommitVersion,
+    // ~ whether to fetch only stable group offsets; requires kafka
+    // based offset storage
+    require_stable_offsets: bool,
+    // ~ how to deal with brokers not supporting the api (version)
+    // needed for a feature
+    unsupported_api_policy: ApiPolicy,
+    // ~ the version of the API to use when loading metadata
+    metadata_version: MetadataVersion,
+    // ~ whether metadata requests may auto-create the requested
+    // topics; effective as of metadata v4
+    allow_auto_topic_creation: bool,
     // ~ the duration to wait before retrying a failed
     // operation like refreshing group coordinators; this avoids
     // operation retries in a tight loop.
@@ -119,6 +209,44 @@ struct ClientConfig {
     // ~ the number of repeated retry attempts; prevents endless
     // repetition of a retry attempt
     retry_max_attempts: u32,
+    // ~ the number of attempts and the time to wait in between for
+    // loading metadata; independent of the retries above
+    metadata_retry_max_attempts: u32,
+    metadata_retry_backoff_time: Duration,
+}
+
+impl ClientConfig {
+    fn new(hosts: Vec<String>) -> ClientConfig {
+        ClientConfig {
+            client_id: String::new(),
+            client_software_name: DEFAULT_CLIENT_SOFTWARE_NAME.to_owned(),
+            client_software_version: DEFAULT_CLIENT_SOFTWARE_VERSION.to_owned(),
+            report_client_software: DEFAULT_REPORT_CLIENT_SOFTWARE,
+            hosts,
+            randomize_bootstrap_order: DEFAULT_RANDOMIZE_BOOTSTRAP_ORDER,
+            bootstrap_offset: random_offset(),
+            bootstrap: bootstrap::Bootstrap::new(),
+            compression: DEFAULT_COMPRESSION,
+            compression_min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            fetch_max_wait_time: protocol::to_millis_i32(Duration::from_millis(
+                DEFAULT_FETCH_MAX_WAIT_TIME_MILLIS,
+            ))
+            .expect("invalid default-fetch-max-time-millis"),
+            fetch_min_bytes: DEFAULT_FETCH_MIN_BYTES,
+            fetch_max_bytes_per_partition: DEFAULT_FETCH_MAX_BYTES_PER_PARTITION,
+            fetch_crc_validation: DEFAULT_FETCH_CRC_VALIDATION,
+            offset_fetch_version: DEFAULT_GROUP_OFFSET_STORAGE.offset_fetch_version(),
+            offset_commit_version: DEFAULT_GROUP_OFFSET_STORAGE.offset_commit_version(),
+            require_stable_offsets: false,
+            unsupported_api_policy: DEFAULT_UNSUPPORTED_API_POLICY,
+            metadata_version: DEFAULT_METADATA_VERSION,
+            allow_auto_topic_creation: DEFAULT_ALLOW_AUTO_TOPIC_CREATION,
+            retry_backoff_time: Duration::from_millis(DEFAULT_RETRY_BACKOFF_TIME_MILLIS),
+            retry_max_attempts: DEFAULT_RETRY_MAX_ATTEMPTS,
+            metadata_retry_max_attempts: DEFAULT_METADATA_RETRY_MAX_ATTEMPTS,
+            metadata_retry_backoff_time: Duration::from_millis(DEFAULT_RETRY_BACKOFF_TIME_MILLIS),
+        }
+    }
 }
 
 // --------------------------------------------------------------------
@@ -149,6 +277,28 @@ impl FetchOffset {
 
 // --------------------------------------------------------------------
 
+/// Identifies a particular partition of a topic.  See
+/// `KafkaClient::list_offsets`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TopicPartition {
+    /// The name of the topic
+    pub topic: String,
+    /// The partition of the topic
+    pub partition: i32,
+}
+
+impl TopicPartition {
+    #[inline]
+    pub fn new(topic: &str, partition: i32) -> Self {
+        TopicPartition {
+            topic: topic.to_owned(),
+            partition,
+        }
+    }
+}
+
+// --------------------------------------------------------------------
+
 /// Defines the available storage types to utilize when fetching or
 /// committing group offsets.  See also `KafkaClient::set_group_offset_storage`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -179,6 +329,19 @@ impl GroupOffsetStorage {
     }
 }
 
+/// Defines how the client deals with brokers not supporting the api
+/// (version) required for an optional feature.  See
+/// `KafkaClient::set_unsupported_api_policy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ApiPolicy {
+    /// Carry out the operation without the feature, e.g. fall back
+    /// to an older api providing less information.
+    SkipFeature,
+    /// Fail the operation with the error reported for the
+    /// unsupported api.
+    Error,
+}
+
 /// Data point identifying a topic partition to fetch a group's offset
 /// for.  See `KafkaClient::fetch_group_offsets`.
 #[derive(Debug)]
@@ -254,6 +417,23 @@ pub enum RequiredAcks {
     All = -1,
 }
 
+impl TryFrom<i16> for RequiredAcks {
+    type Error = Error;
+
+    /// Converts the numeric acks setting as understood by Kafka
+    /// (e.g. `acks` of a configuration file) into `RequiredAcks`.
+    /// Fails with `Error::InvalidRequiredAcks` for anything other
+    /// than -1, 0 or 1 without any broker being asked.
+    fn try_from(acks: i16) -> Result<RequiredAcks> {
+        match acks {
+            0 => Ok(RequiredAcks::None),
+            1 => Ok(RequiredAcks::One),
+            -1 => Ok(RequiredAcks::All),
+            n => Err(Error::InvalidRequiredAcks(n)),
+        }
+    }
+}
+
 // --------------------------------------------------------------------
 
 /// Message data to be sent/produced to a particular topic partition.
@@ -385,22 +565,7 @@ impl KafkaClient {
     /// ```
     pub fn new(hosts: Vec<String>) -> KafkaClient {
         KafkaClient {
-            config: ClientConfig {
-                client_id: String::new(),
-                hosts,
-                compression: DEFAULT_COMPRESSION,
-                fetch_max_wait_time: protocol::to_millis_i32(Duration::from_millis(
-                    DEFAULT_FETCH_MAX_WAIT_TIME_MILLIS,
-                ))
-                .expect("invalid default-fetch-max-time-millis"),
-                fetch_min_bytes: DEFAULT_FETCH_MIN_BYTES,
-                fetch_max_bytes_per_partition: DEFAULT_FETCH_MAX_BYTES_PER_PARTITION,
-                fetch_crc_validation: DEFAULT_FETCH_CRC_VALIDATION,
-                offset_fetch_version: DEFAULT_GROUP_OFFSET_STORAGE.offset_fetch_version(),
-                offset_commit_version: DEFAULT_GROUP_OFFSET_STORAGE.offset_commit_version(),
-                retry_backoff_time: Duration::from_millis(DEFAULT_RETRY_BACKOFF_TIME_MILLIS),
-                retry_max_attempts: DEFAULT_RETRY_MAX_ATTEMPTS,
-            },
+            config: ClientConfig::new(hosts),
             conn_pool: network::Connections::new(
                 default_conn_rw_timeout(),
                 Duration::from_millis(DEFAULT_CONNECTION_IDLE_TIMEOUT_MILLIS),
@@ -454,22 +619,7 @@ impl KafkaClient {
     #[cfg(feature = "security")]
     pub fn new_secure(hosts: Vec<String>, security: SecurityConfig) -> KafkaClient {
         KafkaClient {
-            config: ClientConfig {
-                client_id: String::new(),
-                hosts,
-                compression: DEFAULT_COMPRESSION,
-                fetch_max_wait_time: protocol::to_millis_i32(Duration::from_millis(
-                    DEFAULT_FETCH_MAX_WAIT_TIME_MILLIS,
-                ))
-                .expect("invalid default-fetch-max-time-millis"),
-                fetch_min_bytes: DEFAULT_FETCH_MIN_BYTES,
-                fetch_max_bytes_per_partition: DEFAULT_FETCH_MAX_BYTES_PER_PARTITION,
-                fetch_crc_validation: DEFAULT_FETCH_CRC_VALIDATION,
-                offset_fetch_version: DEFAULT_GROUP_OFFSET_STORAGE.offset_fetch_version(),
-                offset_commit_version: DEFAULT_GROUP_OFFSET_STORAGE.offset_commit_version(),
-                retry_backoff_time: Duration::from_millis(DEFAULT_RETRY_BACKOFF_TIME_MILLIS),
-                retry_max_attempts: DEFAULT_RETRY_MAX_ATTEMPTS,
-            },
+            config: ClientConfig::new(hosts),
             conn_pool: network::Connections::new_with_security(
                 default_conn_rw_timeout(),
                 Duration::from_millis(DEFAULT_CONNECTION_IDLE_TIMEOUT_MILLIS),
@@ -479,6 +629,35 @@ impl KafkaClient {
         }
     }
 
+    /// Sets the TLS configuration to connect to the brokers with from
+    /// the given, fully configured connector builder.  This is an
+    /// escape hatch for settings `SecurityConfig` does not cover,
+    /// e.g. cipher suites, the range of accepted protocol versions,
+    /// or ALPN protocols.  The client then uses TLS for all
+    /// subsequent broker connections; existing connections are closed
+    /// and re-established on their next use.
+    ///
+    /// The hostname verification setting of the client's
+    /// `SecurityConfig` - if any - is retained; it defaults to
+    /// verifying hostnames otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use kafka::client::KafkaClient;
+    /// use openssl::ssl::{SslConnector, SslMethod, SslVersion};
+    ///
+    /// let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    /// builder.set_min_proto_version(Some(SslVersion::TLS1_3)).unwrap();
+    /// let mut client = KafkaClient::new(vec!("localhost:9093".to_owned()));
+    /// client.set_tls_config(builder);
+    /// client.load_metadata_all().unwrap();
+    /// ```
+    #[cfg(feature = "security")]
+    pub fn set_tls_config(&mut self, builder: openssl::ssl::SslConnectorBuilder) {
+        self.conn_pool.set_tls_connector(builder.build());
+    }
+
     /// Exposes the hosts used for discovery of the target kafka
     /// cluster.  This set of hosts corresponds to the values supplied
     /// to `KafkaClient::new`.
@@ -487,6 +666,64 @@ impl KafkaClient {
         &self.config.hosts
     }
 
+    /// Specifies whether the hosts supplied to `KafkaClient::new` are
+    /// to be contacted in a randomized order when loading metadata.
+    ///
+    /// If enabled, each client instance starts contacting the hosts
+    /// at a randomly chosen position in the list (and proceeds with
+    /// the following ones, wrapping around at its end) such that the
+    /// load of many clients spreads across all of the hosts.  If
+    /// disabled, the hosts are strictly tried in the given order.  In
+    /// either case, all of the hosts are tried before giving up with
+    /// `Error::NoHostReachable`.
+    #[inline]
+    pub fn set_randomize_bootstrap_order(&mut self, randomize: bool) {
+        self.config.randomize_bootstrap_order = randomize;
+    }
+
+    /// Retrieves the current
+    /// `KafkaClient::set_randomize_bootstrap_order` setting.
+    #[inline]
+    pub fn randomize_bootstrap_order(&self) -> bool {
+        self.config.randomize_bootstrap_order
+    }
+
+    /// Specifies the interval at which to re-resolve the bootstrap
+    /// hosts; `None` disables re-resolution.  By default, the
+    /// bootstrap hosts are not re-resolved.
+    ///
+    /// If enabled, the addresses the bootstrap hosts resolve to are
+    /// tried after the hosts themselves whenever metadata is
+    /// (re)loaded.  This keeps a client connected to a cluster whose
+    /// bootstrap hostname is a DNS record rotating the brokers'
+    /// addresses, even after all of the originally resolved brokers
+    /// are gone.  Resolution is carried out by the configured
+    /// `Resolver` (see `KafkaClient::set_bootstrap_resolver`) at most
+    /// once per interval; a failed resolution keeps the previously
+    /// resolved addresses.
+    ///
+    /// Note that the resolved addresses are contacted by ip such that
+    /// hostname verification of secured connections will fail for
+    /// them.
+    #[inline]
+    pub fn set_bootstrap_refresh(&mut self, interval: Option<Duration>) {
+        self.config.bootstrap.refresh = interval;
+    }
+
+    /// Retrieves the current bootstrap refresh setting.
+    #[inline]
+    pub fn bootstrap_refresh(&self) -> Option<Duration> {
+        self.config.bootstrap.refresh
+    }
+
+    /// Sets the resolver to re-resolve the bootstrap hosts with.  See
+    /// `KafkaClient::set_bootstrap_refresh`.  By default, the hosts
+    /// are resolved by `DnsResolver`.
+    #[inline]
+    pub fn set_bootstrap_resolver<R: Resolver + 'static>(&mut self, resolver: R) {
+        self.config.bootstrap.set_resolver(Box::new(resolver));
+    }
+
     /// Sets the client_id to be sent along every request to the
     /// remote Kafka brokers.  By default, this value is the empty
     /// string.
@@ -495,6 +732,7 @@ impl KafkaClient {
     /// request/response trace log - if configured appropriately.
     pub fn set_client_id(&mut self, client_id: String) {
         self.config.client_id = client_id;
+        self.update_conn_greeting();
     }
 
     /// Retrieves the current `KafkaClient::set_client_id` setting.
@@ -502,6 +740,124 @@ impl KafkaClient {
         &self.config.client_id
     }
 
+    /// Sets the name and version of the client software reported to
+    /// the brokers along `ApiVersions` requests (KIP-511, as of Kafka
+    /// 2.4.)  Brokers expose these per connection in their metrics.
+    /// By default, these are `DEFAULT_CLIENT_SOFTWARE_NAME` and
+    /// `DEFAULT_CLIENT_SOFTWARE_VERSION`.
+    ///
+    /// Brokers reject values other than alphanumerics, dots and
+    /// dashes - starting and ending with an alphanumeric - as
+    /// `KafkaCode::InvalidRequest`.
+    ///
+    /// See also `KafkaClient::set_report_client_software` and
+    /// `KafkaClient::fetch_api_versions`.
+    pub fn set_client_software(&mut self, name: String, version: String) {
+        self.config.client_software_name = name;
+        self.config.client_software_version = version;
+        self.update_conn_greeting();
+    }
+
+    /// Retrieves the current `KafkaClient::set_client_software`
+    /// setting as a (name, version) pair.
+    pub fn client_software(&self) -> (&str, &str) {
+        (
+            &self.config.client_software_name,
+            &self.config.client_software_version,
+        )
+    }
+
+    /// Makes the client send an `ApiVersions` request reporting the
+    /// client software (see `KafkaClient::set_client_software`) once
+    /// over every newly established connection to a broker before
+    /// using it.  Connections established before enabling this are
+    /// not affected.
+    ///
+    /// This is disabled by default since brokers older than Kafka
+    /// 0.10 do not know the `ApiVersions` api and close the
+    /// connection, such that no connection could be established to
+    /// them at all.  Brokers older than Kafka 2.4 answer the request
+    /// with an error, which is ignored.
+    ///
+    /// The default value is `DEFAULT_REPORT_CLIENT_SOFTWARE`.
+    pub fn set_report_client_software(&mut self, report: bool) {
+        self.config.report_client_software = report;
+        self.update_conn_greeting();
+    }
+
+    /// Retrieves the current `KafkaClient::set_report_client_software`
+    /// setting.
+    pub fn report_client_software(&self) -> bool {
+        self.config.report_client_software
+    }
+
+    // ~ renders the `ApiVersions` request the connection pool sends
+    // over newly established connections according to the current
+    // settings.  the response is discarded such that the correlation
+    // id does not matter.
+    fn update_conn_greeting(&mut self) {
+        let greeting = if self.config.report_client_software {
+            let req = protocol::ApiVersionsRequest::new(
+                0,
+                &self.config.client_id,
+                &self.config.client_software_name,
+                &self.config.client_software_version,
+            );
+            match __encode_request(req) {
+                Ok(buf) => Some(buf),
+                Err(e) => {
+                    warn!("failed to render the api versions greeting: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        self.conn_pool.set_greeting(greeting);
+    }
+
+    /// Makes the client obtain the correlation ids of its requests
+    /// from the given generator rather than from its built-in
+    /// sequence, e.g. to embed a fragment of a trace id such that the
+    /// brokers' request logs can be correlated with client side
+    /// traces.  The generator is invoked once per logical request;
+    /// requests fanned out to multiple brokers share the same id.
+    ///
+    /// The generated ids must be unique among the requests in flight
+    /// over a connection at a time.  This client awaits the response
+    /// to a request before sending the next one over the same
+    /// connection, hence it suffices for the generator not to repeat
+    /// ids in quick succession, e.g. by combining a fixed prefix with
+    /// a wrapping counter.  Kafka reserves no id values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicI32, Ordering};
+    ///
+    /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// // ~ a trace fragment in the upper bits, a sequence in the lower ones
+    /// let trace = 0x2a << 20;
+    /// let seq = AtomicI32::new(0);
+    /// client.set_correlation_id_generator(move || {
+    ///     trace | (seq.fetch_add(1, Ordering::Relaxed) & 0xf_ffff)
+    /// });
+    /// ```
+    pub fn set_correlation_id_generator<F>(&mut self, generator: F)
+    where
+        F: Fn() -> i32 + Send + Sync + 'static,
+    {
+        self.state
+            .set_correlation_generator(Some(state::CorrelationIdGenerator(Box::new(generator))));
+    }
+
+    /// Reverts a previous `KafkaClient::set_correlation_id_generator`
+    /// such that the client generates correlation ids on its own
+    /// again.
+    pub fn clear_correlation_id_generator(&mut self) {
+        self.state.set_correlation_generator(None);
+    }
+
     /// Sets the compression algorithm to use when sending out messages.
     ///
     /// # Example
@@ -524,8 +880,30 @@ impl KafkaClient {
         self.config.compression
     }
 
+    /// Sets the size in bytes a partition's message set must reach
+    /// before being compressed.  Smaller message sets are sent
+    /// uncompressed regardless of `KafkaClient::set_compression`
+    /// since compressing them wastes cpu and may even grow them.
+    #[inline]
+    pub fn set_compression_min_size(&mut self, min_size: usize) {
+        self.config.compression_min_size = min_size;
+    }
+
+    /// Retrieves the current `KafkaClient::set_compression_min_size`
+    /// setting.
+    #[inline]
+    pub fn compression_min_size(&self) -> usize {
+        self.config.compression_min_size
+    }
+
     /// Sets the maximum time in milliseconds to wait for insufficient
-    /// data to become available when fetching messages.
+    /// data to become available when fetching messages.  Fractions of
+    /// a millisecond are truncated.
+    ///
+    /// A zero duration in combination with a zero
+    /// `KafkaClient::set_fetch_min_bytes` makes fetches non-blocking:
+    /// the brokers answer right away, delivering an empty message
+    /// set for partitions without data.
     ///
     /// See also `KafkaClient::set_fetch_min_bytes(..)` and
     /// `KafkaClient::set_fetch_max_bytes_per_partition(..)`.
@@ -551,7 +929,10 @@ impl KafkaClient {
     /// latency for reading only large chunks of data (e.g. setting
     /// MaxWaitTime to 100 ms and setting MinBytes to 64k would allow
     /// the server to wait up to 100ms to try to accumulate 64k of
-    /// data before responding).
+    /// data before responding).  A value of zero lets the brokers
+    /// answer as soon as they have any (or no) data at all; see
+    /// `KafkaClient::set_fetch_max_wait_time` for non-blocking
+    /// fetches.
     ///
     /// # Example
     ///
@@ -663,6 +1044,104 @@ impl KafkaClient {
         }
     }
 
+    /// Makes `KafkaClient::fetch_group_offsets` and
+    /// `KafkaClient::fetch_group_topic_offsets` deliver only stable
+    /// offsets, i.e. no offsets committed as part of a transaction
+    /// still in flight.  Partitions with such pending commits are
+    /// reported with `KafkaCode::UnstableOffsetCommit`, a retriable
+    /// error; callers typically retry after a short while.
+    ///
+    /// Requiring stable offsets takes effect only with
+    /// `GroupOffsetStorage::Kafka` and requires Kafka 2.5 or newer.
+    /// By default, it is disabled.
+    #[inline]
+    pub fn set_require_stable_offsets(&mut self, require_stable: bool) {
+        self.config.require_stable_offsets = require_stable;
+    }
+
+    /// Retrieves the current `KafkaClient::set_require_stable_offsets`
+    /// setting.
+    #[inline]
+    pub fn require_stable_offsets(&self) -> bool {
+        self.config.require_stable_offsets
+    }
+
+    /// Specifies how to deal with brokers which turn out not to
+    /// support the api (version) required for an optional feature:
+    /// either degrade gracefully by carrying out the operation without
+    /// the feature (`ApiPolicy::SkipFeature`) or fail the operation
+    /// (`ApiPolicy::Error`).  This concerns:
+    ///
+    /// - `KafkaClient::describe_cluster` falling back to a metadata
+    ///   request on brokers before Kafka 2.8, and
+    /// - `KafkaClient::fetch_group_offsets` and
+    ///   `KafkaClient::fetch_group_topic_offsets` falling back to
+    ///   possibly unstable offsets on brokers before Kafka 2.5 (see
+    ///   `KafkaClient::set_require_stable_offsets`).
+    ///
+    /// A broker is taken not to support an api (version) only if it
+    /// replies with `KafkaCode::UnsupportedVersion` or - after
+    /// dropping the connection on such a request - confirms it
+    /// through its `ApiVersions`.  Other connection failures are
+    /// handled as usual and never cause a fallback.  With
+    /// `ApiPolicy::Error` such operations fail with
+    /// `KafkaCode::UnsupportedVersion`.
+    ///
+    /// The default value is `DEFAULT_UNSUPPORTED_API_POLICY`.
+    #[inline]
+    pub fn set_unsupported_api_policy(&mut self, policy: ApiPolicy) {
+        self.config.unsupported_api_policy = policy;
+    }
+
+    /// Retrieves the current `KafkaClient::set_unsupported_api_policy`
+    /// setting.
+    #[inline]
+    pub fn unsupported_api_policy(&self) -> ApiPolicy {
+        self.config.unsupported_api_policy
+    }
+
+    /// Specifies the version of the metadata API to use when loading
+    /// metadata from the brokers.
+    ///
+    /// Newer versions deliver additional information about the
+    /// cluster, e.g. `KafkaClient::cluster_id` and
+    /// `KafkaClient::controller_id`, but require correspondingly
+    /// recent brokers.  The default, `MetadataVersion::V0`, is
+    /// understood by all Kafka versions.
+    #[inline]
+    pub fn set_metadata_version(&mut self, version: MetadataVersion) {
+        self.config.metadata_version = version;
+    }
+
+    /// Retrieves the current `KafkaClient::set_metadata_version`
+    /// setting.
+    #[inline]
+    pub fn metadata_version(&self) -> MetadataVersion {
+        self.config.metadata_version
+    }
+
+    /// Specifies whether loading the metadata of topics not existing
+    /// yet may make the brokers create them, provided they are
+    /// configured to do so (`auto.create.topics.enable`).  Disabling
+    /// this allows read-only clients to look up topics without
+    /// creating them as a side effect.
+    ///
+    /// Note: the setting is sent to the brokers only with
+    /// `MetadataVersion::V4` or later (see
+    /// `KafkaClient::set_metadata_version`); with earlier versions the
+    /// brokers' configuration alone decides.
+    #[inline]
+    pub fn set_allow_auto_topic_creation(&mut self, allow: bool) {
+        self.config.allow_auto_topic_creation = allow;
+    }
+
+    /// Retrieves the current
+    /// `KafkaClient::set_allow_auto_topic_creation` setting.
+    #[inline]
+    pub fn allow_auto_topic_creation(&self) -> bool {
+        self.config.allow_auto_topic_creation
+    }
+
     /// Specifies the time to wait before retrying a failed,
     /// repeatable operation against Kafka.  This avoids retrying such
     /// operations in a tight loop.
@@ -692,6 +1171,33 @@ impl KafkaClient {
         self.config.retry_max_attempts
     }
 
+    /// Specifies the upper limit of attempts to load metadata (see
+    /// `KafkaClient::load_metadata`) and the time to wait before
+    /// re-attempting a failed load, e.g. when none of the bootstrap
+    /// hosts answers.  This applies to both the initial and any later
+    /// refreshes of the metadata, independently of
+    /// `KafkaClient::set_retry_max_attempts` and
+    /// `KafkaClient::set_retry_backoff_time`, such that a client can
+    /// wait patiently for a cluster still starting up while keeping
+    /// other retries short.
+    ///
+    /// By default, a failed metadata load is not retried.
+    #[inline]
+    pub fn set_metadata_retries(&mut self, attempts: u32, backoff: Duration) {
+        self.config.metadata_retry_max_attempts = attempts;
+        self.config.metadata_retry_backoff_time = backoff;
+    }
+
+    /// Retrieves the current `KafkaClient::set_metadata_retries`
+    /// setting as (attempts, backoff time).
+    #[inline]
+    pub fn metadata_retries(&self) -> (u32, Duration) {
+        (
+            self.config.metadata_retry_max_attempts,
+            self.config.metadata_retry_backoff_time,
+        )
+    }
+
     /// Specifies the timeout after which idle connections will
     /// transparently be closed/re-established by `KafkaClient`.
     ///
@@ -710,6 +1216,83 @@ impl KafkaClient {
         self.conn_pool.idle_timeout()
     }
 
+    /// Sets the timeout for reading or writing a single chunk of data
+    /// from or to a broker while carrying out control requests, i.e.
+    /// all requests but produce and fetch requests, e.g. metadata,
+    /// offset or group offset requests.  These are usually answered
+    /// quickly, hence a short timeout detects unresponsive brokers
+    /// early.  `None` disables the timeout; a zero duration is
+    /// treated alike.
+    ///
+    /// A timed out operation fails with `Error::Io` and closes the
+    /// connection to the broker; the connection is re-established on
+    /// its next use.
+    ///
+    /// The default value is `DEFAULT_CONNECTION_RW_TIMEOUT_SECS`
+    /// seconds.  See also `KafkaClient::set_data_timeout`.
+    pub fn set_control_timeout(&mut self, timeout: Option<Duration>) {
+        self.conn_pool
+            .set_control_timeout(timeout.filter(|t| !t.is_zero()));
+    }
+
+    /// Retrieves the current `KafkaClient::set_control_timeout`
+    /// setting.
+    pub fn control_timeout(&self) -> Option<Duration> {
+        self.conn_pool.control_timeout()
+    }
+
+    /// Sets the timeout for reading or writing a single chunk of data
+    /// from or to a broker while carrying out produce and fetch
+    /// requests.  Fetches waiting for data (see
+    /// `KafkaClient::set_fetch_max_wait_time`) or transferring large
+    /// message sets legitimately take longer than control requests;
+    /// this timeout should exceed the fetch max wait time.  `None`
+    /// disables the timeout; a zero duration is treated alike.
+    ///
+    /// The default value is `DEFAULT_CONNECTION_RW_TIMEOUT_SECS`
+    /// seconds.  See also `KafkaClient::set_control_timeout`.
+    pub fn set_data_timeout(&mut self, timeout: Option<Duration>) {
+        self.conn_pool
+            .set_data_timeout(timeout.filter(|t| !t.is_zero()));
+    }
+
+    /// Retrieves the current `KafkaClient::set_data_timeout` setting.
+    pub fn data_timeout(&self) -> Option<Duration> {
+        self.conn_pool.data_timeout()
+    }
+
+    /// Sets the maximum size in bytes of a single response this
+    /// client is willing to receive from a broker.  This guards
+    /// against misbehaving brokers (or peers not speaking the Kafka
+    /// protocol at all) announcing an enormous response which the
+    /// client would otherwise try to allocate and read.
+    ///
+    /// A response announcing a larger size fails the corresponding
+    /// operation with `Error::CodecError` and closes the connection
+    /// to the broker; the connection is re-established on its next
+    /// use.
+    ///
+    /// This must be large enough to hold the largest expected fetch
+    /// response, see `KafkaClient::set_fetch_max_bytes_per_partition`.
+    /// The same limit applies to the total decompressed size of the
+    /// (gzip or snappy) compressed message sets of a single fetch
+    /// response; exceeding it fails the fetch with
+    /// `KafkaCode::MessageSizeTooLarge` without buffering more
+    /// decompressed data than the limit.
+    ///
+    /// The default value is `DEFAULT_MAX_RESPONSE_SIZE`.
+    #[inline]
+    pub fn set_max_response_size(&mut self, max_response_size: usize) {
+        self.conn_pool.set_max_response_size(max_response_size);
+    }
+
+    /// Retrieves the current `KafkaClient::set_max_response_size`
+    /// setting.
+    #[inline]
+    pub fn max_response_size(&self) -> usize {
+        self.conn_pool.max_response_size()
+    }
+
     /// Provides a view onto the currently loaded metadata of known .
     ///
     /// # Examples
@@ -733,101 +1316,693 @@ impl KafkaClient {
         metadata::Topics::new(self)
     }
 
-    /// Resets and loads metadata for all topics from the underlying
-    /// brokers.
+    /// Retrieves the id of the cluster as reported by the brokers
+    /// when loading metadata.  This is available only when using
+    /// `MetadataVersion::V2` or later.  See
+    /// `KafkaClient::set_metadata_version`.
+    #[inline]
+    pub fn cluster_id(&self) -> Option<&str> {
+        self.state.cluster_id()
+    }
+
+    /// Retrieves the node id of the broker currently acting as the
+    /// cluster's controller as reported when loading metadata.  This
+    /// is available only when using `MetadataVersion::V1` or later.
+    /// See `KafkaClient::set_metadata_version`.
+    #[inline]
+    pub fn controller_id(&self) -> Option<i32> {
+        self.state.controller_id()
+    }
+
+    /// Takes an owned snapshot of the currently loaded metadata about
+    /// the cluster's brokers, topics and partitions.  With the
+    /// `serde` feature enabled, the snapshot can be serialized e.g.
+    /// to export the cluster topology.
+    pub fn cluster_metadata(&self) -> metadata::ClusterMetadata {
+        metadata::ClusterMetadata::new(self)
+    }
+
+    /// Describes the cluster - its id, controller, brokers and the
+    /// operations this client is authorized to perform on it - using
+    /// the `DescribeCluster` api (KIP-700) of one of the bootstrap
+    /// hosts.
+    ///
+    /// Brokers which do not support the api (before Kafka 2.8) are
+    /// asked for a full metadata response instead, which replaces
+    /// the currently loaded metadata.  In that case the authorized
+    /// operations are not available.  With `ApiPolicy::Error` (see
+    /// `KafkaClient::set_unsupported_api_policy`) the error is
+    /// reported instead.
     ///
     /// # Examples
     ///
     /// ```no_run
     /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
-    /// client.load_metadata_all().unwrap();
-    /// for topic in client.topics().names() {
-    ///   println!("topic: {}", topic);
-    /// }
+    /// let cluster = client.describe_cluster().unwrap();
+    /// println!("{:?} has {} brokers", cluster.cluster_id, cluster.brokers.len());
     /// ```
-    ///
-    /// Returns the metadata for all loaded topics underlying this
-    /// client.
-    #[inline]
-    pub fn load_metadata_all(&mut self) -> Result<()> {
-        self.reset_metadata();
-        self.load_metadata::<&str>(&[])
+    pub fn describe_cluster(&mut self) -> Result<metadata::ClusterDescription> {
+        match self.fetch_cluster_description() {
+            Ok(r) => Ok(metadata::ClusterDescription::from_response(r)),
+            Err(e)
+                if is_unsupported_api(&e)
+                    && self.config.unsupported_api_policy == ApiPolicy::SkipFeature =>
+            {
+                debug!("describe_cluster: falling back to metadata after: {}", e);
+                self.load_metadata_all()?;
+                Ok(self.cluster_metadata().into())
+            }
+            Err(e) => Err(e),
+        }
     }
 
-    /// Reloads metadata for a list of supplied topics.
-    ///
-    /// Note: if any of the specified topics does not exist yet on the
-    /// underlying brokers and these have the [configuration for "auto
-    /// create topics"
-    /// enabled](https://kafka.apache.org/documentation.html#configuration),
-    /// the remote kafka instance will create the yet missing topics
-    /// on the fly as a result of explicitly loading their metadata.
-    /// This is in contrast to other methods of this `KafkaClient`
-    /// which will silently filter out requests to
-    /// not-yet-loaded/not-yet-known topics and, thus, not cause
-    /// topics to be automatically created.
+    /// Retrieves the range of versions the first reachable bootstrap
+    /// host supports for each of its apis using the `ApiVersions` api
+    /// (version 3, as of Kafka 2.4.)  The request reports the client
+    /// software configured through `KafkaClient::set_client_software`
+    /// to the broker.
     ///
     /// # Examples
     ///
     /// ```no_run
     /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
-    /// let _ = client.load_metadata(&["my-topic"]).unwrap();
+    /// client.set_client_software("my-app".to_owned(), "1.0.0".to_owned());
+    /// for v in client.fetch_api_versions().unwrap() {
+    ///     println!("api {}: v{}..=v{}", v.api_key, v.min_version, v.max_version);
+    /// }
     /// ```
-    ///
-    /// Returns the metadata for _all_ loaded topics underlying this
-    /// client (this might be more topics than specified right to this
-    /// method call.)
-    #[inline]
-    pub fn load_metadata<T: AsRef<str>>(&mut self, topics: &[T]) -> Result<()> {
-        let resp = self.fetch_metadata(topics)?;
-        self.state.update_metadata(resp)
-    }
-
-    /// Clears metadata stored in the client.  You must load metadata
-    /// after this call if you want to use the client.
-    #[inline]
-    pub fn reset_metadata(&mut self) {
-        self.state.clear_metadata();
-    }
-
-    /// Fetches metadata about the specified topics from all of the
-    /// underlying brokers (`self.hosts`).
-    fn fetch_metadata<T: AsRef<str>>(
-        &mut self,
-        topics: &[T],
-    ) -> Result<protocol::MetadataResponse> {
+    pub fn fetch_api_versions(&mut self) -> Result<Vec<metadata::ApiVersion>> {
         let correlation = self.state.next_correlation_id();
         let now = Instant::now();
 
         for host in &self.config.hosts {
-            debug!("fetch_metadata: requesting metadata from {}", host);
-            match self.conn_pool.get_conn(host, now) {
-                Ok(conn) => {
-                    let req =
-                        protocol::MetadataRequest::new(correlation, &self.config.client_id, topics);
-                    match __send_request(conn, req) {
-                        Ok(_) => return __get_response::<protocol::MetadataResponse>(conn),
-                        Err(e) => debug!(
-                            "fetch_metadata: failed to request metadata from {}: {}",
-                            host, e
-                        ),
-                    }
-                }
+            debug!("fetch_api_versions: requesting from {}", host);
+            let conn = match self.conn_pool.get_conn(host, now) {
+                Ok(conn) => conn,
                 Err(e) => {
-                    debug!("fetch_metadata: failed to connect to {}: {}", host, e);
+                    debug!("fetch_api_versions: failed to connect to {}: {}", host, e);
+                    continue;
                 }
+            };
+            let req = protocol::ApiVersionsRequest::new(
+                correlation,
+                &self.config.client_id,
+                &self.config.client_software_name,
+                &self.config.client_software_version,
+            );
+            if let Err(e) = __send_request(conn, req) {
+                debug!("fetch_api_versions: failed to send to {}: {}", host, e);
+                continue;
             }
+            let resp = __get_response::<protocol::ApiVersionsResponse>(conn)?.into_result()?;
+            return Ok(resp
+                .api_keys
+                .into_iter()
+                .map(|k| metadata::ApiVersion {
+                    api_key: k.api_key,
+                    min_version: k.min_version,
+                    max_version: k.max_version,
+                })
+                .collect());
         }
         Err(Error::NoHostReachable)
     }
 
-    /// Fetch offsets for a list of topics
-    ///
-    /// # Examples
-    ///
-    /// ```no_run
-    /// use kafka::client::KafkaClient;
-    ///
+    /// Repeatedly invokes `f` - passing it the time left until the
+    /// deadline - until it succeeds, fails with a non-retriable
+    /// error, or the retry attempts or the deadline are exhausted.
+    /// All broker i/o carried out by `f` is bounded by the deadline.
+    fn with_deadline<T, F>(&mut self, deadline: Instant, mut f: F) -> Result<T>
+    where
+        F: FnMut(&mut KafkaClient, Duration) -> Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            let left = match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => left,
+                _ => return Err(Error::Kafka(KafkaCode::RequestTimedOut)),
+            };
+            self.conn_pool.set_deadline(Some(deadline));
+            let r = f(self, left);
+            self.conn_pool.set_deadline(None);
+            let e = match r {
+                Err(e) if is_retriable(&e) => e,
+                r => return r,
+            };
+            if attempt >= self.config.retry_max_attempts
+                || Instant::now() + self.config.retry_backoff_time >= deadline
+            {
+                return Err(e);
+            }
+            debug!("with_deadline: will retry after attempt {}: {}", attempt, e);
+            attempt += 1;
+            __retry_sleep(&self.config);
+        }
+    }
+
+    /// Describes the SCRAM credentials of the given users, or of all
+    /// users having any if `users` is empty, using the
+    /// `DescribeUserScramCredentials` api (KIP-554, as of Kafka 2.7.)
+    /// Errors concerning individual users, e.g.
+    /// `KafkaCode::ResourceNotFound` for a user without credentials,
+    /// are reported along with the user.
+    ///
+    /// The request is sent to the cluster's controller; see
+    /// `KafkaClient::controller_id`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// for user in client.describe_scram_credentials(&["alice"]).unwrap() {
+    ///     println!("{}: {:?}", user.user, user.credentials);
+    /// }
+    /// ```
+    pub fn describe_scram_credentials<T: AsRef<str>>(
+        &mut self,
+        users: &[T],
+    ) -> Result<Vec<UserScramCredentials>> {
+        // ~ the request borrows the client id for as long as it is
+        // being (re-)sent to the controller
+        let client_id = self.config.client_id.clone();
+        let req = protocol::DescribeUserScramCredentialsRequest::new(
+            users,
+            self.state.next_correlation_id(),
+            &client_id,
+        );
+        let resp = self
+            .controller_send_receive::<_, protocol::DescribeUserScramCredentialsResponse, _>(
+                &req,
+                |r| r.error == KafkaCode::NotController as i16,
+            )?;
+        Ok(resp.into_result()?.results)
+    }
+
+    /// Creates or replaces (`upsertions`) and removes (`deletions`)
+    /// SCRAM credentials of users using the `AlterUserScramCredentials`
+    /// api (KIP-554, as of Kafka 2.7.)  The outcome is reported per
+    /// user; a user's alterations are applied either all or none.
+    ///
+    /// The request is sent to the cluster's controller; see
+    /// `KafkaClient::controller_id`.
+    ///
+    /// # Examples
+    ///
+    /// Deriving the credentials from a plain text password through
+    /// `ScramCredentialUpsertion::new` requires the `security`
+    /// feature:
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "security")]
+    /// # {
+    /// use kafka::client::{KafkaClient, ScramCredentialUpsertion, ScramMechanism};
+    ///
+    /// let mut client = KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// let upsertion =
+    ///     ScramCredentialUpsertion::new("alice", ScramMechanism::Sha512, 8192, "secret").unwrap();
+    /// for r in client.alter_scram_credentials(&[upsertion], &[]).unwrap() {
+    ///     println!("{}: {:?}", r.user, r.result);
+    /// }
+    /// # }
+    /// ```
+    pub fn alter_scram_credentials(
+        &mut self,
+        upsertions: &[ScramCredentialUpsertion],
+        deletions: &[ScramCredentialDeletion],
+    ) -> Result<Vec<AlterScramCredentialResult>> {
+        let client_id = self.config.client_id.clone();
+        let req = protocol::AlterUserScramCredentialsRequest::new(
+            upsertions,
+            deletions,
+            self.state.next_correlation_id(),
+            &client_id,
+        );
+        let resp = self
+            .controller_send_receive::<_, protocol::AlterUserScramCredentialsResponse, _>(
+                &req,
+                |r| {
+                    r.results
+                        .iter()
+                        .any(|u| u.result == Err(KafkaCode::NotController))
+                },
+            )?;
+        Ok(resp.results)
+    }
+
+    /// Describes the log directories of the given brokers along with
+    /// the size and offset lag of the partitions stored in them
+    /// using the `DescribeLogDirs` api (KIP-113, as of Kafka 1.1.)
+    /// An empty `brokers` slice describes all brokers known to the
+    /// loaded metadata.  `partitions` restricts the description to
+    /// the given topic partitions; `None` describes all partitions.
+    ///
+    /// The request is sent to each of the brokers individually.  Log
+    /// directories a broker cannot access, e.g. due to a failed disk,
+    /// are reported along with their error, usually
+    /// `KafkaCode::KafkaStorageError`.  Brokers not known to the
+    /// metadata (even after reloading it) result in
+    /// `Error::NoHostReachable`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// client.load_metadata_all().unwrap();
+    /// for log_dir in client.describe_log_dirs(&[], None).unwrap() {
+    ///     match log_dir.partitions {
+    ///         Ok(ps) => {
+    ///             let size: i64 = ps.iter().map(|p| p.size).sum();
+    ///             println!("{}:{} {} bytes", log_dir.broker_id, log_dir.path, size);
+    ///         }
+    ///         Err(e) => println!("{}:{} {:?}", log_dir.broker_id, log_dir.path, e),
+    ///     }
+    /// }
+    /// ```
+    pub fn describe_log_dirs(
+        &mut self,
+        brokers: &[i32],
+        partitions: Option<&[TopicPartition]>,
+    ) -> Result<Vec<LogDir>> {
+        let find_host = |client: &KafkaClient, broker_id: i32| {
+            client
+                .state
+                .brokers()
+                .iter()
+                .find(|b| b.id() == broker_id)
+                .map(|b| b.host().to_owned())
+        };
+        if brokers.iter().any(|&id| find_host(self, id).is_none()) {
+            self.load_metadata::<&str>(&[])?;
+        }
+        let hosts = if brokers.is_empty() {
+            self.state
+                .brokers()
+                .iter()
+                .map(|b| (b.id(), b.host().to_owned()))
+                .collect()
+        } else {
+            brokers
+                .iter()
+                .map(|&id| find_host(self, id).map(|host| (id, host)))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(Error::NoHostReachable)?
+        };
+
+        let correlation = self.state.next_correlation_id();
+        let mut req = protocol::DescribeLogDirsRequest::new(correlation, &self.config.client_id);
+        if let Some(tps) = partitions {
+            req.topic_partitions = Some(Vec::new());
+            for tp in tps {
+                req.add(&tp.topic, tp.partition);
+            }
+        }
+        let now = Instant::now();
+        let mut log_dirs = Vec::new();
+        for (broker_id, host) in hosts {
+            let resp = __send_receive::<_, protocol::DescribeLogDirsResponse>(
+                &mut self.conn_pool,
+                &host,
+                now,
+                &req,
+            )?;
+            log_dirs.extend(resp.into_log_dirs(broker_id));
+        }
+        Ok(log_dirs)
+    }
+
+    // XXX the client implements only the SCRAM credential admin
+    // requests yet, but no others (e.g. CreateTopics, DeleteTopics,
+    // AlterConfigs.)  these, too, need to be sent through here.  a
+    // client level default replication factor for CreateTopics
+    // belongs with them; note that "-1" (the broker's default) is
+    // accepted only as of CreateTopics v4 (KIP-464).
+
+    /// Sends the given (admin) request to the cluster's controller -
+    /// or to the first reachable bootstrap host if the controller is
+    /// not known (see `KafkaClient::controller_id`) - and delivers
+    /// the response.  Responses rejected by `not_controller` are
+    /// retried after reloading the metadata, within the configured
+    /// retry attempts and backoff, as are failing connections.
+    fn controller_send_receive<R, T, F>(&mut self, req: &R, not_controller: F) -> Result<T::R>
+    where
+        R: ToByte,
+        T: FromByte,
+        F: Fn(&T::R) -> bool,
+    {
+        let mut attempt = 1;
+        loop {
+            let now = Instant::now();
+            let controller = self
+                .state
+                .controller_id()
+                .and_then(|id| self.state.brokers().iter().find(|b| b.id() == id))
+                .map(|b| b.host().to_owned());
+            let r = match controller {
+                Some(host) => __send_receive::<_, T>(&mut self.conn_pool, &host, now, req),
+                None => self
+                    .config
+                    .hosts
+                    .iter()
+                    .find_map(|host| match self.conn_pool.get_conn(host, now) {
+                        Ok(conn) => Some(__send_receive_conn::<_, T>(conn, req)),
+                        Err(e) => {
+                            debug!(
+                                "controller_send_receive: failed to connect to {}: {}",
+                                host, e
+                            );
+                            None
+                        }
+                    })
+                    .unwrap_or(Err(Error::NoHostReachable)),
+            };
+            let e = match r {
+                Ok(resp) if not_controller(&resp) => Error::Kafka(KafkaCode::NotController),
+                Err(e @ Error::Io(_)) => e,
+                r => return r,
+            };
+            if attempt >= self.config.retry_max_attempts {
+                return Err(e);
+            }
+            debug!(
+                "controller_send_receive: will retry after attempt {}: {}",
+                attempt, e
+            );
+            attempt += 1;
+            __retry_sleep(&self.config);
+            self.load_metadata_all()?;
+        }
+    }
+
+    /// Sends a `DescribeCluster` request to the first reachable
+    /// bootstrap host.
+    fn fetch_cluster_description(&mut self) -> Result<protocol::DescribeClusterResponse> {
+        let correlation = self.state.next_correlation_id();
+        let now = Instant::now();
+
+        for host in &self.config.hosts {
+            debug!("fetch_cluster_description: requesting from {}", host);
+            let conn = match self.conn_pool.get_conn(host, now) {
+                Ok(conn) => conn,
+                Err(e) => {
+                    debug!(
+                        "fetch_cluster_description: failed to connect to {}: {}",
+                        host, e
+                    );
+                    continue;
+                }
+            };
+            let req = protocol::DescribeClusterRequest::new(correlation, &self.config.client_id);
+            if let Err(e) = __send_request(conn, req) {
+                debug!(
+                    "fetch_cluster_description: failed to send to {}: {}",
+                    host, e
+                );
+                continue;
+            }
+            let r = __get_response::<protocol::DescribeClusterResponse>(conn);
+            return __confirm_unsupported_api(
+                &mut self.conn_pool,
+                &self.config,
+                correlation,
+                host,
+                (protocol::API_KEY_DESCRIBE_CLUSTER, 0),
+                r,
+            )?
+            .into_result();
+        }
+        Err(Error::NoHostReachable)
+    }
+
+    /// Resets and loads metadata for all topics from the underlying
+    /// brokers.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// client.load_metadata_all().unwrap();
+    /// for topic in client.topics().names() {
+    ///   println!("topic: {}", topic);
+    /// }
+    /// ```
+    ///
+    /// Returns the metadata for all loaded topics underlying this
+    /// client.
+    #[inline]
+    pub fn load_metadata_all(&mut self) -> Result<()> {
+        self.reset_metadata();
+        self.load_metadata::<&str>(&[])
+    }
+
+    /// Reloads metadata for a list of supplied topics.
+    ///
+    /// Note: if any of the specified topics does not exist yet on the
+    /// underlying brokers and these have the [configuration for "auto
+    /// create topics"
+    /// enabled](https://kafka.apache.org/documentation.html#configuration),
+    /// the remote kafka instance will create the yet missing topics
+    /// on the fly as a result of explicitly loading their metadata.
+    /// This is in contrast to other methods of this `KafkaClient`
+    /// which will silently filter out requests to
+    /// not-yet-loaded/not-yet-known topics and, thus, not cause
+    /// topics to be automatically created.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// let _ = client.load_metadata(&["my-topic"]).unwrap();
+    /// ```
+    ///
+    /// Returns the metadata for _all_ loaded topics underlying this
+    /// client (this might be more topics than specified right to this
+    /// method call.)
+    #[inline]
+    pub fn load_metadata<T: AsRef<str>>(&mut self, topics: &[T]) -> Result<()> {
+        let mut attempt = 1;
+        let resp = loop {
+            match self.fetch_metadata(topics) {
+                Err(e) if attempt < self.config.metadata_retry_max_attempts => {
+                    debug!(
+                        "load_metadata: will retry (attempt: {}) due to: {}",
+                        attempt, e
+                    );
+                    attempt += 1;
+                    thread::sleep(self.config.metadata_retry_backoff_time);
+                }
+                r => break r?,
+            }
+        };
+        self.state.update_metadata(resp)
+    }
+
+    /// Refreshes the metadata of just the specified topics through a
+    /// single metadata request.  The metadata of other loaded topics
+    /// is left untouched.  Does nothing if no topics are specified.
+    ///
+    /// Unlike with `KafkaClient::load_metadata`, the specified topics
+    /// are refreshed only; this method never asks for the metadata of
+    /// all topics.  Topics for which the brokers report an error, e.g.
+    /// `KafkaCode::UnknownTopicOrPartition` for a topic not existing
+    /// (yet), do not fail the call; the error is recorded and
+    /// available through `KafkaClient::topic_error`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// client.metadata_for_topics(&["my-topic", "my-other-topic"]).unwrap();
+    /// if let Some(e) = client.topic_error("my-other-topic") {
+    ///     println!("my-other-topic: {:?}", e);
+    /// }
+    /// ```
+    pub fn metadata_for_topics(&mut self, topics: &[&str]) -> Result<()> {
+        if topics.is_empty() {
+            return Ok(());
+        }
+        self.load_metadata(topics)
+    }
+
+    /// Retrieves the error the brokers reported for the specified
+    /// topic when its metadata was last loaded, if any.
+    #[inline]
+    pub fn topic_error(&self, topic: &str) -> Option<KafkaCode> {
+        self.state.topic_error(topic)
+    }
+
+    /// Retrieves the number of partitions of the specified topic.
+    ///
+    /// The number is served from the already loaded metadata.  Only
+    /// if the topic is not known yet (or known to have no partitions
+    /// at all), its metadata is (re)loaded from the brokers.  Note
+    /// that topics for which a broker answers a produce request with
+    /// `KafkaCode::UnknownTopicOrPartition` are evicted from the
+    /// loaded metadata such that the next call to this method will
+    /// refresh them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let mut client = kafka::client::KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// let n = client.partition_count("my-topic").unwrap();
+    /// println!("my-topic has {} partitions", n);
+    /// ```
+    ///
+    /// Results in `KafkaCode::UnknownTopicOrPartition` if the topic
+    /// is not known to the brokers either.
+    pub fn partition_count(&mut self, topic: &str) -> Result<usize> {
+        match self.state.partitions_for(topic) {
+            Some(tp) if !tp.is_empty() => return Ok(tp.len()),
+            _ => {}
+        }
+        self.load_metadata(&[topic])?;
+        match self.state.partitions_for(topic) {
+            Some(tp) if !tp.is_empty() => Ok(tp.len()),
+            _ => Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)),
+        }
+    }
+
+    /// Closes all connections to the brokers this client currently
+    /// maintains.  Loaded metadata is kept; the next request to a
+    /// broker transparently establishes a new connection to it.
+    ///
+    /// This is useful to release connections of a client which is
+    /// known to stay idle for a while without dropping the client.
+    pub fn disconnect(&mut self) {
+        self.conn_pool.clear();
+    }
+
+    /// Closes the connection to the broker with the given node id
+    /// and immediately establishes a new one.  Connections to other
+    /// brokers are not affected.
+    ///
+    /// This is useful to recover from a connection known or
+    /// suspected to be stuck without disconnecting the whole client.
+    /// If the broker is not known to the loaded metadata, the
+    /// metadata is refreshed first.
+    ///
+    /// Results in `Error::NoHostReachable` if the broker is unknown
+    /// even after refreshing the metadata.
+    pub fn reconnect(&mut self, broker_id: i32) -> Result<()> {
+        let find_host = |client: &KafkaClient| {
+            client
+                .state
+                .brokers()
+                .iter()
+                .find(|b| b.id() == broker_id)
+                .map(|b| b.host().to_owned())
+        };
+        let host = if let Some(host) = find_host(self) {
+            host
+        } else {
+            self.load_metadata::<&str>(&[])?;
+            find_host(self).ok_or(Error::NoHostReachable)?
+        };
+        self.conn_pool.reconnect(&host)
+    }
+
+    /// Determines for how long the connection to the broker with the
+    /// given node id has been idle, i.e. the time elapsed since the
+    /// last successful read or write on it.  Connections idle for at
+    /// least `KafkaClient::connection_idle_timeout` are re-established
+    /// on their next use.
+    ///
+    /// Returns `None` if the broker is not known to the loaded
+    /// metadata or if there is no connection to it currently.
+    pub fn broker_idle_time(&self, broker_id: i32) -> Option<Duration> {
+        let broker = self.state.brokers().iter().find(|b| b.id() == broker_id)?;
+        self.conn_pool.idle_time(broker.host(), Instant::now())
+    }
+
+    /// Fetches the names of all topics known to the cluster without
+    /// loading their metadata into this client, e.g. for the purpose
+    /// of discovery.  Kafka's internal topics, such as
+    /// `__consumer_offsets`, are included only if `include_internal`
+    /// is `true`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use kafka::client::KafkaClient;
+    ///
+    /// let mut client = KafkaClient::new(vec!["localhost:9092".to_owned()]);
+    /// for topic in client.list_topic_names(false).unwrap() {
+    ///   println!("topic: {}", topic);
+    /// }
+    /// ```
+    pub fn list_topic_names(&mut self, include_internal: bool) -> Result<Vec<String>> {
+        let resp = self.fetch_metadata::<&str>(&[])?;
+        Ok(resp
+            .topics
+            .into_iter()
+            .filter(|t| {
+                include_internal || !(t.is_internal || INTERNAL_TOPICS.contains(&t.topic.as_str()))
+            })
+            .map(|t| t.topic)
+            .collect())
+    }
+
+    /// Clears metadata stored in the client.  You must load metadata
+    /// after this call if you want to use the client.
+    #[inline]
+    pub fn reset_metadata(&mut self) {
+        self.state.clear_metadata();
+    }
+
+    /// Fetches metadata about the specified topics from all of the
+    /// underlying brokers (`self.hosts`).
+    fn fetch_metadata<T: AsRef<str>>(
+        &mut self,
+        topics: &[T],
+    ) -> Result<protocol::MetadataResponse> {
+        let correlation = self.state.next_correlation_id();
+        let now = Instant::now();
+
+        let hosts = &self.config.hosts;
+        let start = if self.config.randomize_bootstrap_order && !hosts.is_empty() {
+            self.config.bootstrap_offset % hosts.len()
+        } else {
+            0
+        };
+        let resolved = self.config.bootstrap.addrs(hosts, now);
+        for host in hosts[start..].iter().chain(&hosts[..start]).chain(resolved) {
+            debug!("fetch_metadata: requesting metadata from {}", host);
+            match self.conn_pool.get_conn(host, now) {
+                Ok(conn) => {
+                    let version = self.config.metadata_version;
+                    let mut req = protocol::MetadataRequest::new(
+                        version,
+                        correlation,
+                        &self.config.client_id,
+                        topics,
+                    );
+                    req.allow_auto_topic_creation = self.config.allow_auto_topic_creation;
+                    match __send_request(conn, req) {
+                        Ok(_) => {
+                            let p = protocol::metadata::ResponseParser { version };
+                            return __get_parsed_response(conn, &p);
+                        }
+                        Err(e) => debug!(
+                            "fetch_metadata: failed to request metadata from {}: {}",
+                            host, e
+                        ),
+                    }
+                }
+                Err(e) => {
+                    debug!("fetch_metadata: failed to connect to {}: {}", host, e);
+                }
+            }
+        }
+        Err(Error::NoHostReachable)
+    }
+
+    /// Fetch offsets for a list of topics
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kafka::client::KafkaClient;
+    ///
     /// let mut client = KafkaClient::new(vec!["localhost:9092".to_owned()]);
     /// client.load_metadata_all().unwrap();
     /// let topics: Vec<String> = client.topics().names().map(ToOwned::to_owned).collect();
@@ -919,6 +2094,105 @@ impl KafkaClient {
         Ok(res)
     }
 
+    /// Fetches the offsets for many topic partitions at once.  Each
+    /// of the given topic partitions is queried for the paired kind
+    /// of offset; the same partition may be queried for different
+    /// kinds of offsets within one call.  The queries are coalesced
+    /// into as few requests as possible to the partitions' leaders.
+    ///
+    /// Returns the offsets in the order of the given queries.  Fails
+    /// with `Error::TopicPartitionError` if any of the partitions is
+    /// unknown, has no leader, or reports an error.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kafka::client::{FetchOffset, KafkaClient, TopicPartition};
+    ///
+    /// let mut client = KafkaClient::new(vec!["localhost:9092".to_owned()]);
+    /// client.load_metadata_all().unwrap();
+    /// let offsets = client
+    ///     .list_offsets(&[
+    ///         (TopicPartition::new("my-topic", 0), FetchOffset::Earliest),
+    ///         (TopicPartition::new("my-topic", 0), FetchOffset::Latest),
+    ///     ])
+    ///     .unwrap();
+    /// ```
+    pub fn list_offsets(
+        &mut self,
+        reqs: &[(TopicPartition, FetchOffset)],
+    ) -> Result<Vec<(TopicPartition, i64)>> {
+        let state = &mut self.state;
+        let correlation = state.next_correlation_id();
+
+        // ~ a single request may ask for a topic partition only once;
+        // the n-th query for the same partition goes into the n-th
+        // request to the partition's leader
+        let config = &self.config;
+        let mut seen: HashMap<(&str, i32), usize> = HashMap::new();
+        let mut broker_reqs: HashMap<&str, Vec<protocol::OffsetRequest<'_>>> = HashMap::new();
+        // ~ (host, round, topic, partition) -> index into `reqs`
+        let mut index: HashMap<(&str, usize, &str, i32), usize> =
+            HashMap::with_capacity(reqs.len());
+        for (i, (tp, offset)) in reqs.iter().enumerate() {
+            let host = match state.find_broker(&tp.topic, tp.partition) {
+                Some(host) => host,
+                None => {
+                    return Err(Error::TopicPartitionError {
+                        topic_name: tp.topic.clone(),
+                        partition_id: tp.partition,
+                        error_code: KafkaCode::UnknownTopicOrPartition,
+                    })
+                }
+            };
+            let round = seen.entry((&tp.topic, tp.partition)).or_insert(0);
+            let host_reqs = broker_reqs.entry(host).or_default();
+            if host_reqs.len() <= *round {
+                host_reqs.push(protocol::OffsetRequest::new(correlation, &config.client_id));
+            }
+            host_reqs[*round].add(&tp.topic, tp.partition, offset.to_kafka_value());
+            index.insert((host, *round, &tp.topic, tp.partition), i);
+            *round += 1;
+        }
+
+        let now = Instant::now();
+        let mut offsets = vec![-1; reqs.len()];
+        for (host, host_reqs) in broker_reqs {
+            for (round, req) in host_reqs.into_iter().enumerate() {
+                let resp = __send_receive::<_, protocol::OffsetResponse>(
+                    &mut self.conn_pool,
+                    host,
+                    now,
+                    req,
+                )?;
+                for tp in resp.topic_partitions {
+                    for p in tp.partitions {
+                        let i = match index.get(&(host, round, &tp.topic[..], p.partition)) {
+                            Some(&i) => i,
+                            None => continue,
+                        };
+                        match p.to_offset() {
+                            Ok(po) => offsets[i] = po.offset,
+                            Err(code) => {
+                                return Err(Error::TopicPartitionError {
+                                    topic_name: tp.topic,
+                                    partition_id: p.partition,
+                                    error_code: code,
+                                })
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(reqs
+            .iter()
+            .zip(offsets)
+            .map(|((tp, _), offset)| (tp.clone(), offset))
+            .collect())
+    }
+
     /// Takes ownership back from the given HashMap Entry.
     fn get_key_from_entry<'a, K: 'a, V: 'a>(entry: hash_map::Entry<'a, K, V>) -> K {
         match entry {
@@ -957,6 +2231,97 @@ impl KafkaClient {
         }
     }
 
+    /// Fetches the earliest offset still available in the specified
+    /// topic partition, i.e. the partition's "log start offset."
+    /// Messages below this offset have been deleted by the broker,
+    /// e.g. due to the topic's retention policy, and can no longer be
+    /// fetched.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kafka::client::KafkaClient;
+    ///
+    /// let mut client = KafkaClient::new(vec!["localhost:9092".to_owned()]);
+    /// client.load_metadata_all().unwrap();
+    /// let offset = client.earliest_available_offset("my-topic", 0).unwrap();
+    /// ```
+    ///
+    /// See also `KafkaClient::fetch_topic_offsets`.
+    pub fn earliest_available_offset(&mut self, topic: &str, partition: i32) -> Result<i64> {
+        self.fetch_topic_offsets(topic, FetchOffset::Earliest)?
+            .into_iter()
+            .find(|po| po.partition == partition)
+            .map(|po| po.offset)
+            .ok_or(Error::Kafka(KafkaCode::UnknownTopicOrPartition))
+    }
+
+    /// Fetches the end offset of the given leader epoch of the
+    /// specified topic partition, i.e. the offset of the first
+    /// message written by a subsequent leader, from the partition's
+    /// current leader.  For the current epoch, this is the
+    /// partition's log end offset.
+    ///
+    /// An end offset lower than the offset a consumer has fetched up
+    /// to at the given epoch reveals that the partition's log has been
+    /// truncated, e.g. due to an unclean leader election.  See
+    /// `MetadataVersion::V7` to learn about the partitions' leader
+    /// epochs.
+    ///
+    /// Requires Kafka 0.11.0 or later.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kafka::client::{KafkaClient, MetadataVersion};
+    ///
+    /// let mut client = KafkaClient::new(vec!["localhost:9092".to_owned()]);
+    /// client.set_metadata_version(MetadataVersion::V7);
+    /// client.load_metadata_all().unwrap();
+    /// let epoch = client.topics().partitions("my-topic").unwrap()
+    ///     .partition(0).unwrap().leader_epoch().unwrap();
+    /// let end_offset = client.fetch_epoch_end_offset("my-topic", 0, epoch).unwrap();
+    /// ```
+    pub fn fetch_epoch_end_offset(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        leader_epoch: i32,
+    ) -> Result<i64> {
+        let correlation = self.state.next_correlation_id();
+        let host = match self.state.find_broker(topic, partition) {
+            Some(host) => host,
+            None => {
+                return Err(Error::TopicPartitionError {
+                    topic_name: topic.to_owned(),
+                    partition_id: partition,
+                    error_code: KafkaCode::UnknownTopicOrPartition,
+                })
+            }
+        };
+        let mut req =
+            protocol::OffsetForLeaderEpochRequest::new(correlation, &self.config.client_id);
+        req.add(topic, partition, leader_epoch);
+        let resp = __send_receive::<_, protocol::OffsetForLeaderEpochResponse>(
+            &mut self.conn_pool,
+            host,
+            Instant::now(),
+            req,
+        )?;
+        resp.topic_partitions
+            .into_iter()
+            .filter(|tp| tp.topic == topic)
+            .flat_map(|tp| tp.partitions)
+            .find(|p| p.partition == partition)
+            .ok_or(Error::Kafka(KafkaCode::UnknownTopicOrPartition))?
+            .to_end_offset()
+            .map_err(|code| Error::TopicPartitionError {
+                topic_name: topic.to_owned(),
+                partition_id: partition,
+                error_code: code,
+            })
+    }
+
     /// Fetch messages from Kafka (multiple topic, partitions).
     ///
     /// It takes a vector specifying the topic partitions and their
@@ -1029,39 +2394,80 @@ impl KafkaClient {
         J: AsRef<FetchPartition<'a>>,
         I: IntoIterator<Item = J>,
     {
-        let state = &mut self.state;
-        let config = &self.config;
-
-        let correlation = state.next_correlation_id();
+        let reqs = __fetch_requests(&mut self.state, &self.config, input);
+        __fetch_messages(&mut self.conn_pool, &self.config, reqs)
+    }
 
-        // Map topic and partition to the corresponding broker
-        let mut reqs: HashMap<&str, protocol::FetchRequest<'_, '_>> = HashMap::new();
-        for inp in input {
-            let inp = inp.as_ref();
-            if let Some(broker) = state.find_broker(inp.topic, inp.partition) {
-                reqs.entry(broker)
-                    .or_insert_with(|| {
-                        protocol::FetchRequest::new(
-                            correlation,
-                            &config.client_id,
-                            config.fetch_max_wait_time,
-                            config.fetch_min_bytes,
-                        )
-                    })
-                    .add(
-                        inp.topic,
-                        inp.partition,
-                        inp.offset,
-                        if inp.max_bytes > 0 {
-                            inp.max_bytes
-                        } else {
-                            config.fetch_max_bytes_per_partition
-                        },
-                    );
-            }
+    /// Fetch messages from Kafka like `KafkaClient::fetch_messages`
+    /// but reads the brokers' responses into the given buffer instead
+    /// of allocating a new one for each response.  The returned
+    /// responses are views into `buf`; messages taken out of them
+    /// stay valid as long as the response they were taken from.
+    ///
+    /// `buf` is cleared first and grown as needed to hold the
+    /// responses of all involved brokers.  Re-using the same buffer
+    /// across calls avoids re-allocating it in tight fetch loops.
+    /// Only the data of compressed message sets is still allocated
+    /// when uncompressing it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use kafka::client::{KafkaClient, FetchPartition};
+    ///
+    /// let mut client = KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// client.load_metadata_all().unwrap();
+    /// let reqs = &[FetchPartition::new("my-topic", 0, 0)];
+    /// let mut buf = Vec::new();
+    /// loop {
+    ///   for resp in client.fetch_messages_into(reqs, &mut buf).unwrap() {
+    ///     for t in resp.topics() {
+    ///       for p in t.partitions() {
+    ///         if let Ok(data) = p.data() {
+    ///           for msg in data.messages() {
+    ///             println!("{}: {:?}", msg.offset, msg.value);
+    ///           }
+    ///         }
+    ///       }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    pub fn fetch_messages_into<'a, 'b, I, J>(
+        &mut self,
+        input: I,
+        buf: &'b mut Vec<u8>,
+    ) -> Result<Vec<fetch::BorrowedResponse<'b>>>
+    where
+        J: AsRef<FetchPartition<'a>>,
+        I: IntoIterator<Item = J>,
+    {
+        let reqs = __fetch_requests(&mut self.state, &self.config, input);
+        // ~ read all responses back to back before parsing any of
+        // them; the parsed responses borrow from the buffer
+        buf.clear();
+        let now = Instant::now();
+        let mut ranges = Vec::with_capacity(reqs.len());
+        for (host, req) in &reqs {
+            let start = buf.len();
+            let conn = self.conn_pool.get_conn(host, now)?;
+            __send_request(conn, req)?;
+            __read_response_into(conn, buf)?;
+            ranges.push((start..buf.len(), req));
         }
-
-        __fetch_messages(&mut self.conn_pool, config, reqs)
+        let buf: &'b [u8] = buf;
+        let max_size = self.conn_pool.max_response_size();
+        ranges
+            .into_iter()
+            .map(|(range, req)| {
+                fetch::BorrowedResponse::from_slice(
+                    &buf[range],
+                    Some(req),
+                    self.config.fetch_crc_validation,
+                    max_size,
+                )
+            })
+            .collect()
     }
 
     /// Fetch messages from a single kafka partition.
@@ -1074,6 +2480,94 @@ impl KafkaClient {
         self.fetch_messages(&[req])
     }
 
+    /// Like `KafkaClient::fetch_messages` but bounded by the given
+    /// deadline.
+    ///
+    /// No i/o operation on a broker connection will outlast the
+    /// deadline.  Failed attempts to fetch the messages are retried
+    /// (see `KafkaClient::set_retry_max_attempts`) as long as the
+    /// deadline allows for another attempt after the configured
+    /// backoff time.  Once the deadline has passed, the last error
+    /// encountered is returned or `KafkaCode::RequestTimedOut` if
+    /// the deadline had passed before the first attempt.
+    pub fn fetch_messages_deadline<'a, I, J>(
+        &mut self,
+        deadline: Instant,
+        input: I,
+    ) -> Result<Vec<fetch::Response>>
+    where
+        J: AsRef<FetchPartition<'a>>,
+        I: IntoIterator<Item = J>,
+    {
+        let input: Vec<J> = input.into_iter().collect();
+        self.with_deadline(deadline, |client, _| client.fetch_messages(&input))
+    }
+
+    /// Fetches the messages of the given topic partition starting at
+    /// the given offset directly from the broker with the given node
+    /// id, regardless of whether it leads the partition or merely
+    /// hosts a follower replica of it, e.g. to diagnose diverging
+    /// replicas.  If the broker is not known to the loaded metadata,
+    /// the metadata is refreshed first.
+    ///
+    /// The request identifies itself as issued by a debugging tool
+    /// (through the replica id `-2`) which makes brokers serve it
+    /// from whatever replica they host.  This works with all broker
+    /// versions, unlike fetching from followers as introduced by
+    /// KIP-392, which requires fetch v11.
+    ///
+    /// **Caution:** this is an advanced api meant for debugging and
+    /// repair only.  A follower may deliver messages which are not
+    /// committed yet (and may never be), or lag behind the leader,
+    /// and its high watermark may be outdated.  Do not use this to
+    /// consume data.
+    ///
+    /// Results in `Error::NoHostReachable` if the broker is unknown
+    /// even after refreshing the metadata.
+    pub fn fetch_from_replica(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        broker_id: i32,
+        offset: i64,
+    ) -> Result<fetch::Response> {
+        let find_host = |client: &KafkaClient| {
+            client
+                .state
+                .brokers()
+                .iter()
+                .find(|b| b.id() == broker_id)
+                .map(|b| b.host().to_owned())
+        };
+        let host = if let Some(host) = find_host(self) {
+            host
+        } else {
+            self.load_metadata::<&str>(&[])?;
+            find_host(self).ok_or(Error::NoHostReachable)?
+        };
+        let correlation = self.state.next_correlation_id();
+        let config = &self.config;
+        let mut req = protocol::FetchRequest::new(
+            correlation,
+            &config.client_id,
+            config.fetch_max_wait_time,
+            config.fetch_min_bytes,
+        );
+        req.replica = protocol::fetch::DEBUGGING_REPLICA_ID;
+        req.add(
+            topic,
+            partition,
+            offset,
+            config.fetch_max_bytes_per_partition,
+        );
+        let p = protocol::fetch::ResponseParser {
+            validate_crc: config.fetch_crc_validation,
+            max_uncompressed_size: self.conn_pool.max_response_size(),
+            requests: Some(&req),
+        };
+        __z_send_receive(&mut self.conn_pool, &host, Instant::now(), &req, &p)
+    }
+
     /// Send a message to Kafka
     ///
     /// `required_acks` - indicates how many acknowledgements the
@@ -1126,6 +2620,120 @@ impl KafkaClient {
         self.internal_produce_messages(acks as i16, protocol::to_millis_i32(ack_timeout)?, messages)
     }
 
+    /// Like `KafkaClient::produce_messages` but bounded by the given
+    /// deadline.
+    ///
+    /// The `ack_timeout` is limited to the time left until the
+    /// deadline and no i/o operation on a broker connection will
+    /// outlast it.  Failed attempts to deliver the messages are
+    /// retried (see `KafkaClient::set_retry_max_attempts`) as long
+    /// as the deadline allows for another attempt after the
+    /// configured backoff time; note that a retried attempt may
+    /// deliver messages a second time.  Once the deadline has
+    /// passed, the last error encountered is returned or
+    /// `KafkaCode::RequestTimedOut` if the deadline had passed before
+    /// the first attempt.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::{Duration, Instant};
+    /// use kafka::client::{KafkaClient, ProduceMessage, RequiredAcks};
+    ///
+    /// let mut client = KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// client.load_metadata_all().unwrap();
+    /// let deadline = Instant::now() + Duration::from_millis(250);
+    /// let req = vec![ProduceMessage::new("my-topic", 0, None, Some("a".as_bytes()))];
+    /// let resp = client.produce_messages_deadline(
+    ///     deadline, RequiredAcks::One, Duration::from_millis(100), req);
+    /// println!("{:?}", resp);
+    /// ```
+    pub fn produce_messages_deadline<'a, 'b, I, J>(
+        &mut self,
+        deadline: Instant,
+        acks: RequiredAcks,
+        ack_timeout: Duration,
+        messages: I,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        J: AsRef<ProduceMessage<'a, 'b>>,
+        I: IntoIterator<Item = J>,
+    {
+        let ack_timeout = protocol::to_millis_i32(ack_timeout)?;
+        self.internal_produce_messages_deadline(deadline, acks as i16, ack_timeout, messages)
+    }
+
+    /// Sends a pre-encoded message set to the given topic partition.
+    ///
+    /// This is a low-level facility for advanced users, e.g. tools
+    /// mirroring or replicating topics, which need to control the
+    /// produced data entirely.  The given `batch` is sent unchanged
+    /// as the message set of a produce request; neither partitioners
+    /// nor compression settings (see `KafkaClient::set_compression`)
+    /// apply to it.  It is the caller's responsibility to render a
+    /// well-formed message set in a format supported by the
+    /// brokers' produce api version in use by this client (i.e.
+    /// message format v0 or v1.)
+    ///
+    /// Note: brokers usually assign new offsets to produced messages
+    /// ignoring those found in `batch`.  Preserving the offsets
+    /// of the source requires the broker to support doing so, e.g.
+    /// in replication contexts.
+    ///
+    /// Like `KafkaClient::produce_messages` the returned vector is
+    /// empty if `acks` is `RequiredAcks::None`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use kafka::client::{KafkaClient, RequiredAcks};
+    ///
+    /// let mut client = KafkaClient::new(vec!("localhost:9092".to_owned()));
+    /// client.load_metadata_all().unwrap();
+    /// let batch: Vec<u8> = vec![/* a pre-encoded message set */];
+    /// let resp = client.produce_raw_batch(
+    ///     RequiredAcks::One, Duration::from_millis(100), "my-topic", 0, &batch);
+    /// println!("{:?}", resp);
+    /// ```
+    pub fn produce_raw_batch(
+        &mut self,
+        acks: RequiredAcks,
+        ack_timeout: Duration,
+        topic: &str,
+        partition: i32,
+        batch: &[u8],
+    ) -> Result<Vec<ProduceConfirm>> {
+        let ack_timeout = protocol::to_millis_i32(ack_timeout)?;
+        let correlation = self.state.next_correlation_id();
+        let host = match self.state.find_broker(topic, partition) {
+            None => return Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)),
+            Some(host) => host,
+        };
+        let req = protocol::RawProduceRequest::new(
+            acks as i16,
+            ack_timeout,
+            correlation,
+            &self.config.client_id,
+            topic,
+            partition,
+            batch,
+        );
+        let now = Instant::now();
+        if acks as i16 == 0 {
+            __send_noack::<_, protocol::ProduceResponse>(&mut self.conn_pool, host, now, req)?;
+            Ok(vec![])
+        } else {
+            let resp = __send_receive::<_, protocol::ProduceResponse>(
+                &mut self.conn_pool,
+                host,
+                now,
+                req,
+            )?;
+            Ok(resp.get_response())
+        }
+    }
+
     /// Commit offset for a topic partitions on behalf of a consumer group.
     ///
     /// # Examples
@@ -1147,6 +2755,14 @@ impl KafkaClient {
     /// retrieved using `fetch_group_offsets` even from another
     /// process or at much later point in time to resume comusing the
     /// topic partitions as of these offsets.
+    ///
+    /// Commits rejected because the group's coordinator has moved or
+    /// is unavailable, e.g. during a coordinator failover, are
+    /// retried transparently against the re-discovered coordinator
+    /// as configured through `KafkaClient::set_retry_max_attempts`
+    /// and `KafkaClient::set_retry_backoff_time`.  Once the attempts
+    /// are exhausted, the commit fails with
+    /// `Error::CoordinatorNotFound`.
     pub fn commit_offsets<'a, J, I>(&mut self, group: &str, offsets: I) -> Result<()>
     where
         J: AsRef<CommitOffset<'a>>,
@@ -1225,12 +2841,7 @@ impl KafkaClient {
         J: AsRef<FetchGroupOffset<'a>>,
         I: IntoIterator<Item = J>,
     {
-        let mut req = protocol::OffsetFetchRequest::new(
-            group,
-            self.config.offset_fetch_version,
-            self.state.next_correlation_id(),
-            &self.config.client_id,
-        );
+        let mut req = __offset_fetch_request(&self.config, group, self.state.next_correlation_id());
         for p in partitions {
             let p = p.as_ref();
             if self.state.contains_topic_partition(p.topic, p.partition) {
@@ -1258,12 +2869,7 @@ impl KafkaClient {
         group: &str,
         topic: &str,
     ) -> Result<Vec<PartitionOffset>> {
-        let mut req = protocol::OffsetFetchRequest::new(
-            group,
-            self.config.offset_fetch_version,
-            self.state.next_correlation_id(),
-            &self.config.client_id,
-        );
+        let mut req = __offset_fetch_request(&self.config, group, self.state.next_correlation_id());
 
         match self.state.partitions_for(topic) {
             None => return Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)),
@@ -1283,6 +2889,24 @@ impl KafkaClient {
 }
 
 impl KafkaClientInternals for KafkaClient {
+    fn internal_produce_messages_deadline<'a, 'b, I, J>(
+        &mut self,
+        deadline: Instant,
+        required_acks: i16,
+        ack_timeout: i32,
+        messages: I,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        J: AsRef<ProduceMessage<'a, 'b>>,
+        I: IntoIterator<Item = J>,
+    {
+        let messages: Vec<J> = messages.into_iter().collect();
+        self.with_deadline(deadline, |client, left| {
+            let left = protocol::to_millis_i32(left)?;
+            client.internal_produce_messages(required_acks, ack_timeout.min(left), &messages)
+        })
+    }
+
     fn internal_produce_messages<'a, 'b, I, J>(
         &mut self,
         required_acks: i16,
@@ -1312,12 +2936,29 @@ impl KafkaClientInternals for KafkaClient {
                             correlation,
                             &config.client_id,
                             config.compression,
+                            config.compression_min_size,
                         )
                     })
                     .add(msg.topic, msg.partition, msg.key, msg.value),
             }
         }
-        __produce_messages(&mut self.conn_pool, reqs, required_acks == 0)
+        let confirms = __produce_messages(&mut self.conn_pool, reqs, required_acks == 0)?;
+        // ~ forget about topics the brokers do not know (anymore)
+        // such that subsequent metadata lookups refresh them
+        for confirm in &confirms {
+            if confirm
+                .partition_confirms
+                .iter()
+                .any(|p| p.offset == Err(KafkaCode::UnknownTopicOrPartition))
+            {
+                debug!(
+                    "produce_messages: evicting metadata for unknown topic '{}'",
+                    confirm.topic
+                );
+                state.remove_topic(&confirm.topic);
+            }
+        }
+        Ok(confirms)
     }
 }
 
@@ -1366,7 +3007,9 @@ fn __get_group_coordinator<'a>(
             attempt += 1;
             __retry_sleep(config);
         } else {
-            return Err(Error::Kafka(retry_code));
+            return Err(Error::CoordinatorNotFound {
+                group: group.to_owned(),
+            });
         }
     }
 }
@@ -1381,62 +3024,101 @@ fn __commit_offsets(
     loop {
         let now = Instant::now();
 
-        let tps = {
+        let resp = {
             let host = __get_group_coordinator(req.group, state, conn_pool, config, now)?;
             debug!(
                 "__commit_offsets: sending offset commit request '{:?}' to: {}",
                 req, host
             );
-            __send_receive::<_, protocol::OffsetCommitResponse>(conn_pool, host, now, &req)?
-                .topic_partitions
+            __send_receive::<_, protocol::OffsetCommitResponse>(conn_pool, host, now, &req)
         };
 
-        let mut retry_code = None;
-
-        'rproc: for tp in tps {
-            for p in tp.partitions {
-                match p.to_error() {
-                    None => {}
-                    Some(e @ KafkaCode::GroupLoadInProgress) => {
-                        retry_code = Some(e);
-                        break 'rproc;
-                    }
-                    Some(e @ KafkaCode::NotCoordinatorForGroup) => {
-                        debug!(
-                            "commit_offsets: resetting group coordinator for '{}'",
-                            req.group
-                        );
-                        state.remove_group_coordinator(req.group);
-                        retry_code = Some(e);
-                        break 'rproc;
-                    }
-                    Some(code) => {
-                        // ~ immediately abort with the error
-                        return Err(Error::Kafka(code));
+        let retry_err = match resp {
+            Err(e @ Error::Io(_)) => {
+                // ~ the coordinator might have gone away
+                debug!(
+                    "commit_offsets: resetting group coordinator for '{}' due to: {}",
+                    req.group, e
+                );
+                state.remove_group_coordinator(req.group);
+                e
+            }
+            Err(e) => {
+                return Err(e);
+            }
+            Ok(resp) => {
+                let mut retry_err = None;
+                'rproc: for tp in resp.topic_partitions {
+                    for p in tp.partitions {
+                        match p.to_error() {
+                            None => {}
+                            Some(e @ KafkaCode::GroupLoadInProgress) => {
+                                retry_err = Some(Error::Kafka(e));
+                                break 'rproc;
+                            }
+                            Some(
+                                KafkaCode::NotCoordinatorForGroup
+                                | KafkaCode::GroupCoordinatorNotAvailable,
+                            ) => {
+                                debug!(
+                                    "commit_offsets: resetting group coordinator for '{}'",
+                                    req.group
+                                );
+                                state.remove_group_coordinator(req.group);
+                                retry_err = Some(Error::CoordinatorNotFound {
+                                    group: req.group.to_owned(),
+                                });
+                                break 'rproc;
+                            }
+                            Some(code) => {
+                                // ~ immediately abort with the error
+                                return Err(Error::Kafka(code));
+                            }
+                        }
                     }
                 }
-            }
-        }
-        match retry_code {
-            Some(e) => {
-                if attempt < config.retry_max_attempts {
-                    debug!(
-                        "commit_offsets: will retry request (c: {}) due to: {:?}",
-                        req.header.correlation_id, e
-                    );
-                    attempt += 1;
-                    __retry_sleep(config);
+                match retry_err {
+                    Some(e) => e,
+                    None => return Ok(()),
                 }
             }
-            None => {
-                return Ok(());
-            }
+        };
+
+        if attempt < config.retry_max_attempts {
+            debug!(
+                "commit_offsets: will retry request (c: {}) due to: {:?}",
+                req.header.correlation_id, retry_err
+            );
+            attempt += 1;
+            __retry_sleep(config);
+        } else {
+            return Err(retry_err);
         }
     }
 }
 
+// ~ creates an offset fetch request according to the configured
+// group offset storage; see `KafkaClient::set_require_stable_offsets`
+fn __offset_fetch_request<'a, 'b, 'c>(
+    config: &'a ClientConfig,
+    group: &'b str,
+    correlation_id: i32,
+) -> protocol::OffsetFetchRequest<'a, 'b, 'c> {
+    let stable = config.require_stable_offsets
+        && config.offset_fetch_version != GroupOffsetStorage::Zookeeper.offset_fetch_version();
+    let version = if stable {
+        protocol::OffsetFetchVersion::V7
+    } else {
+        config.offset_fetch_version
+    };
+    let mut req =
+        protocol::OffsetFetchRequest::new(group, version, correlation_id, &config.client_id);
+    req.require_stable = stable;
+    req
+}
+
 fn __fetch_group_offsets(
-    req: protocol::OffsetFetchRequest<'_, '_, '_>,
+    mut req: protocol::OffsetFetchRequest<'_, '_, '_>,
     state: &mut state::ClientState,
     conn_pool: &mut network::Connections,
     config: &ClientConfig,
@@ -1451,68 +3133,175 @@ fn __fetch_group_offsets(
                 "fetch_group_offsets: sending request {:?} to: {}",
                 req, host
             );
-            __send_receive::<_, protocol::OffsetFetchResponse>(conn_pool, host, now, &req)?
+            let p = protocol::OffsetFetchResponseParser {
+                version: req.header.api_version,
+            };
+            let r = __send_receive_parsed(conn_pool, host, now, &req, &p);
+            if req.require_stable {
+                __confirm_unsupported_api(
+                    conn_pool,
+                    config,
+                    req.header.correlation_id,
+                    host,
+                    (protocol::API_KEY_OFFSET_FETCH, req.header.api_version),
+                    r,
+                )
+            } else {
+                r
+            }
         };
 
-        debug!("fetch_group_offsets: received response: {:#?}", r);
+        let retry_err = match r {
+            Err(e)
+                if req.require_stable
+                    && is_unsupported_api(&e)
+                    && config.unsupported_api_policy == ApiPolicy::SkipFeature =>
+            {
+                debug!(
+                    "fetch_group_offsets: falling back to unstable offsets for '{}' due to: {}",
+                    req.group, e
+                );
+                req.require_stable = false;
+                req.header.api_version = config.offset_fetch_version as i16;
+                continue;
+            }
+            Err(e @ Error::Io(_)) => {
+                // ~ the coordinator might have gone away
+                debug!(
+                    "fetch_group_offsets: resetting group coordinator for '{}' due to: {}",
+                    req.group, e
+                );
+                state.remove_group_coordinator(req.group);
+                e
+            }
+            Err(e) => {
+                return Err(e);
+            }
+            Ok(r) => {
+                debug!("fetch_group_offsets: received response: {:#?}", r);
+
+                let mut retry_err = None;
+                let mut topic_map = HashMap::with_capacity(r.topic_partitions.len());
 
-        let mut retry_code = None;
-        let mut topic_map = HashMap::with_capacity(r.topic_partitions.len());
+                // ~ a group level error (as of v7) applies to all
+                // partitions
+                let r = match r.into_result() {
+                    Ok(r) => r,
+                    Err(e) => {
+                        retry_err = Some(__offset_fetch_retry_err(e, req.group, state)?);
+                        protocol::OffsetFetchResponse::default()
+                    }
+                };
 
-        'rproc: for tp in r.topic_partitions {
-            let mut partition_offsets = Vec::with_capacity(tp.partitions.len());
+                'rproc: for tp in r.topic_partitions {
+                    let mut partition_offsets = Vec::with_capacity(tp.partitions.len());
 
-            for p in tp.partitions {
-                match p.get_offsets() {
-                    Ok(o) => {
-                        partition_offsets.push(o);
-                    }
-                    Err(Error::Kafka(e @ KafkaCode::GroupLoadInProgress)) => {
-                        retry_code = Some(e);
-                        break 'rproc;
-                    }
-                    Err(Error::Kafka(e @ KafkaCode::NotCoordinatorForGroup)) => {
-                        debug!(
-                            "fetch_group_offsets: resetting group coordinator for '{}'",
-                            req.group
-                        );
-                        state.remove_group_coordinator(req.group);
-                        retry_code = Some(e);
-                        break 'rproc;
-                    }
-                    Err(e) => {
-                        // ~ immediately abort with the error
-                        return Err(e);
+                    for p in tp.partitions {
+                        match p.get_offsets() {
+                            Ok(o) => {
+                                partition_offsets.push(o);
+                            }
+                            Err(e) => {
+                                retry_err = Some(__offset_fetch_retry_err(e, req.group, state)?);
+                                break 'rproc;
+                            }
+                        }
                     }
+
+                    topic_map.insert(tp.topic, partition_offsets);
+                }
+
+                // ~ have we processed the result successfully or
+                // shall we retry once more?
+                match retry_err {
+                    Some(e) => e,
+                    None => return Ok(topic_map),
                 }
             }
+        };
 
-            topic_map.insert(tp.topic, partition_offsets);
+        if attempt < config.retry_max_attempts {
+            debug!(
+                "fetch_group_offsets: will retry request (c: {}) due to: {:?}",
+                req.header.correlation_id, retry_err
+            );
+            attempt += 1;
+            __retry_sleep(config)
+        } else {
+            return Err(retry_err);
         }
+    }
+}
 
-        // ~ have we processed the result successfully or shall we
-        // retry once more?
-        match retry_code {
-            Some(e) => {
-                if attempt < config.retry_max_attempts {
-                    debug!(
-                        "fetch_group_offsets: will retry request (c: {}) due to: {:?}",
-                        req.header.correlation_id, e
-                    );
-                    attempt += 1;
-                    __retry_sleep(config)
-                } else {
-                    return Err(Error::Kafka(e));
-                }
-            }
-            None => {
-                return Ok(topic_map);
-            }
+// ~ determines the error to retry a failed offset fetch for the given
+// group with; fails with the given error if retrying is futile
+fn __offset_fetch_retry_err(
+    e: Error,
+    group: &str,
+    state: &mut state::ClientState,
+) -> Result<Error> {
+    match e {
+        Error::Kafka(KafkaCode::GroupLoadInProgress) => Ok(e),
+        Error::Kafka(
+            KafkaCode::NotCoordinatorForGroup | KafkaCode::GroupCoordinatorNotAvailable,
+        ) => {
+            debug!(
+                "fetch_group_offsets: resetting group coordinator for '{}'",
+                group
+            );
+            state.remove_group_coordinator(group);
+            Ok(Error::CoordinatorNotFound {
+                group: group.to_owned(),
+            })
         }
+        // ~ immediately abort with the error
+        e => Err(e),
     }
 }
 
 /// ~ carries out the given fetch requests and returns the response
+/// ~ maps the given topic partitions to fetch requests by the broker
+/// leading them; partitions without a known leader are skipped
+fn __fetch_requests<'s, 'a, I, J>(
+    state: &'s mut state::ClientState,
+    config: &'s ClientConfig,
+    input: I,
+) -> HashMap<&'s str, protocol::FetchRequest<'s, 'a>>
+where
+    J: AsRef<FetchPartition<'a>>,
+    I: IntoIterator<Item = J>,
+{
+    let correlation = state.next_correlation_id();
+    let state: &'s state::ClientState = state;
+
+    let mut reqs: HashMap<&str, protocol::FetchRequest<'_, '_>> = HashMap::new();
+    for inp in input {
+        let inp = inp.as_ref();
+        if let Some(broker) = state.find_broker(inp.topic, inp.partition) {
+            reqs.entry(broker)
+                .or_insert_with(|| {
+                    protocol::FetchRequest::new(
+                        correlation,
+                        &config.client_id,
+                        config.fetch_max_wait_time,
+                        config.fetch_min_bytes,
+                    )
+                })
+                .add(
+                    inp.topic,
+                    inp.partition,
+                    inp.offset,
+                    if inp.max_bytes > 0 {
+                        inp.max_bytes
+                    } else {
+                        config.fetch_max_bytes_per_partition
+                    },
+                );
+        }
+    }
+    reqs
+}
+
 fn __fetch_messages(
     conn_pool: &mut network::Connections,
     config: &ClientConfig,
@@ -1523,6 +3312,7 @@ fn __fetch_messages(
     for (host, req) in reqs {
         let p = protocol::fetch::ResponseParser {
             validate_crc: config.fetch_crc_validation,
+            max_uncompressed_size: conn_pool.max_response_size(),
             requests: Some(&req),
         };
         res.push(__z_send_receive(conn_pool, host, now, &req, &p)?);
@@ -1591,6 +3381,26 @@ where
 }
 
 fn __send_request<T: ToByte>(conn: &mut network::KafkaConnection, request: T) -> Result<usize> {
+    let buffer = __encode_request(request)?;
+
+    // ~ the request header starts with the api key right after the
+    // size; produce and fetch requests are subject to the data
+    // timeout, all others to the control timeout
+    let api_key = i16::from_be_bytes([buffer[4], buffer[5]]);
+    conn.use_data_timeout(matches!(
+        api_key,
+        protocol::API_KEY_PRODUCE | protocol::API_KEY_FETCH
+    ))?;
+
+    trace!("__send_request: Sending bytes: {:?}", &buffer);
+
+    // ~ send the prepared buffer
+    let r = conn.send(&buffer);
+    __close_on_fatal(conn, r)
+}
+
+// ~ renders the given request prefixed by its size
+fn __encode_request<T: ToByte>(request: T) -> Result<Vec<u8>> {
     // ~ buffer to receive data to be sent
     let mut buffer = Vec::with_capacity(4);
     // ~ reserve bytes for the actual request size (we'll fill in that later)
@@ -1600,18 +3410,39 @@ fn __send_request<T: ToByte>(conn: &mut network::KafkaConnection, request: T) ->
     // ~ put the size of the request data into the reserved area
     let size = buffer.len() as i32 - 4;
     size.encode(&mut &mut buffer[..])?;
+    Ok(buffer)
+}
 
-    trace!("__send_request: Sending bytes: {:?}", &buffer);
+fn __get_response<T: FromByte>(conn: &mut network::KafkaConnection) -> Result<T::R> {
+    // ~ the decoded response does not borrow from the raw data; read
+    // it into the connection's re-used buffer
+    let r = __get_response_size(conn).and_then(|size| {
+        let resp = conn.read_response(size)?;
+        trace!("__get_response: received bytes: {:?}", resp);
+        T::decode_new(&mut Cursor::new(resp))
+    });
+    __close_on_fatal(conn, r)
+}
 
-    // ~ send the prepared buffer
-    conn.send(&buffer)
+// ~ shuts down the given connection if the given result is an error
+// leaving it unusable; the pool re-establishes it on its next use.
+// see `Error::is_fatal_for_connection`
+fn __close_on_fatal<T>(conn: &mut network::KafkaConnection, r: Result<T>) -> Result<T> {
+    if let Err(ref e) = r {
+        if e.is_fatal_for_connection() {
+            debug!("closing {:?} after: {}", conn, e);
+            conn.close();
+        }
+    }
+    r
 }
 
-fn __get_response<T: FromByte>(conn: &mut network::KafkaConnection) -> Result<T::R> {
+// ~ reads the raw data of the next response from the given connection
+fn __read_response(conn: &mut network::KafkaConnection) -> Result<Vec<u8>> {
     let size = __get_response_size(conn)?;
     let resp = conn.read_exact_alloc(size as u64)?;
 
-    trace!("__get_response: received bytes: {:?}", &resp);
+    trace!("__read_response: received bytes: {:?}", &resp);
 
     // {
     //     use std::fs::OpenOptions;
@@ -1625,7 +3456,17 @@ fn __get_response<T: FromByte>(conn: &mut network::KafkaConnection) -> Result<T:
     //     f.write_all(&resp[..]).unwrap();
     // }
 
-    T::decode_new(&mut Cursor::new(resp))
+    Ok(resp)
+}
+
+// ~ appends the raw data of the next response from the given
+// connection to the given buffer
+fn __read_response_into(conn: &mut network::KafkaConnection, buf: &mut Vec<u8>) -> Result<()> {
+    let size = __get_response_size(conn)?;
+    let start = buf.len();
+    buf.resize(start + size, 0);
+    let r = conn.read_exact(&mut buf[start..]);
+    __close_on_fatal(conn, r)
 }
 
 fn __z_send_receive<R, P>(
@@ -1644,32 +3485,142 @@ where
     __z_get_response(conn, parser)
 }
 
+// ~ the parsed response owns the raw data, e.g. a fetch response
+// whose messages point into it; the data is read into a buffer of its
+// own
 fn __z_get_response<P>(conn: &mut network::KafkaConnection, parser: &P) -> Result<P::T>
 where
     P: ResponseParser,
 {
-    let size = __get_response_size(conn)?;
-    let resp = conn.read_exact_alloc(size as u64)?;
+    let r = __read_response(conn).and_then(|resp| parser.parse(resp));
+    __close_on_fatal(conn, r)
+}
 
-    // {
-    //     use std::fs::OpenOptions;
-    //     use std::io::Write;
-    //     let mut f = OpenOptions::new()
-    //         .write(true)
-    //         .truncate(true)
-    //         .create(true)
-    //         .open("/tmp/dump.dat")
-    //         .unwrap();
-    //     f.write_all(&resp[..]).unwrap();
-    // }
+fn __send_receive_parsed<R, P>(
+    conn_pool: &mut network::Connections,
+    host: &str,
+    now: Instant,
+    req: R,
+    parser: &P,
+) -> Result<P::T>
+where
+    R: ToByte,
+    P: SliceResponseParser,
+{
+    let conn = conn_pool.get_conn(host, now)?;
+    __send_request(conn, req)?;
+    __get_parsed_response(conn, parser)
+}
 
-    parser.parse(resp)
+// ~ the parsed response does not borrow from the raw data; read it
+// into the connection's re-used buffer
+fn __get_parsed_response<P>(conn: &mut network::KafkaConnection, parser: &P) -> Result<P::T>
+where
+    P: SliceResponseParser,
+{
+    let r = __get_response_size(conn).and_then(|size| {
+        let resp = conn.read_response(size)?;
+        trace!("__get_parsed_response: received bytes: {:?}", resp);
+        parser.parse_slice(resp)
+    });
+    __close_on_fatal(conn, r)
 }
 
-fn __get_response_size(conn: &mut network::KafkaConnection) -> Result<i32> {
+fn __get_response_size(conn: &mut network::KafkaConnection) -> Result<usize> {
     let mut buf = [0u8; 4];
     conn.read_exact(&mut buf)?;
-    i32::decode_new(&mut Cursor::new(&buf))
+    let size = i32::decode_new(&mut Cursor::new(&buf))?;
+    match usize::try_from(size) {
+        Ok(size) if size <= conn.max_response_size() => Ok(size),
+        _ => {
+            // ~ the rest of the stream cannot be trusted anymore
+            warn!(
+                "__get_response_size: closing {:?} after receiving an invalid response size: {}",
+                conn, size
+            );
+            conn.close();
+            Err(Error::CodecError)
+        }
+    }
+}
+
+/// Determines whether an operation failing with the given error is
+/// worth retrying.
+fn is_retriable(e: &Error) -> bool {
+    match e {
+        Error::Io(_) => true,
+        Error::Kafka(code) => code.is_retriable(),
+        _ => false,
+    }
+}
+
+/// Determines whether the given error indicates that a broker does
+/// not support the requested api (version.)  See
+/// `__confirm_unsupported_api`.
+fn is_unsupported_api(e: &Error) -> bool {
+    matches!(e, Error::Kafka(KafkaCode::UnsupportedVersion))
+}
+
+// ~ brokers drop the connection on requests of apis (versions) they
+// do not know instead of replying with `UnsupportedVersion`.  since a
+// dropped connection may as well be a transient failure, the given
+// result is turned into `UnsupportedVersion` only if the broker at
+// `host` confirms through the `ApiVersions` api not to support the
+// given version of the given api; otherwise it is passed through.
+fn __confirm_unsupported_api<T>(
+    conn_pool: &mut network::Connections,
+    config: &ClientConfig,
+    correlation_id: i32,
+    host: &str,
+    (api_key, version): (i16, i16),
+    r: Result<T>,
+) -> Result<T> {
+    match r {
+        Err(Error::Io(ref e))
+            if matches!(
+                e.kind(),
+                io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+            ) => {}
+        r => return r,
+    }
+    let req = protocol::ApiVersionsRequest::new(
+        correlation_id,
+        &config.client_id,
+        &config.client_software_name,
+        &config.client_software_version,
+    );
+    let supported =
+        __send_receive::<_, protocol::ApiVersionsResponse>(conn_pool, host, Instant::now(), req)
+            .and_then(protocol::ApiVersionsResponse::into_result)
+            .map(|resp| {
+                resp.api_keys.iter().any(|k| {
+                    k.api_key == api_key && (k.min_version..=k.max_version).contains(&version)
+                })
+            });
+    match supported {
+        Ok(false) => {
+            debug!(
+                "{} does not support version {} of api {}",
+                host, version, api_key
+            );
+            Err(Error::Kafka(KafkaCode::UnsupportedVersion))
+        }
+        Ok(true) => r,
+        Err(e) => {
+            debug!("failed to fetch the api versions of {}: {}", host, e);
+            r
+        }
+    }
+}
+
+/// Produces a random number to be used as an offset into the list
+/// of bootstrap hosts.  See `ClientConfig::bootstrap_offset`.
+fn random_offset() -> usize {
+    // ~ `RandomState` is randomly seeded per instance which is good
+    // enough for our purposes and saves us a dependency
+    RandomState::new().build_hasher().finish() as usize
 }
 
 /// Suspends the calling thread for the configured "retry" time. This
@@ -1677,3 +3628,1023 @@ fn __get_response_size(conn: &mut network::KafkaConnection) -> Result<i32> {
 fn __retry_sleep(cfg: &ClientConfig) {
     thread::sleep(cfg.retry_backoff_time)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::fake_broker::{serve, serve_metadata, MockBroker};
+    use super::{
+        FetchOffset, FetchPartition, KafkaClient, ProduceMessage, RequiredAcks, Resolver,
+        TopicPartition, DEFAULT_CLIENT_SOFTWARE_NAME, DEFAULT_CLIENT_SOFTWARE_VERSION,
+    };
+    use crate::error::{Error, KafkaCode};
+
+    #[test]
+    fn test_max_response_size() {
+        use std::io::{Read, Write};
+        use std::sync::mpsc;
+        use std::thread;
+
+        // ~ a peer announcing a ~2GB response to every request and
+        // reporting whether the client closed the connection
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut size = [0u8; 4];
+                stream.read_exact(&mut size).unwrap();
+                let mut req = vec![0u8; i32::from_be_bytes(size) as usize];
+                stream.read_exact(&mut req).unwrap();
+                stream.write_all(&i32::MAX.to_be_bytes()).unwrap();
+                let closed = matches!(stream.read(&mut [0u8; 1]), Ok(0));
+                tx.send(closed).unwrap();
+            }
+        });
+        let mut client = KafkaClient::new(vec![host]);
+        assert_eq!(super::DEFAULT_MAX_RESPONSE_SIZE, client.max_response_size());
+        for _ in 0..2 {
+            assert!(matches!(client.load_metadata_all(), Err(Error::CodecError)));
+            assert!(rx.recv().unwrap());
+        }
+
+        // ~ a limit below a regular response; raising the limit
+        // re-establishes the connection transparently
+        let (host, _) = serve_metadata(&[("foo", 3)]);
+        let mut client = KafkaClient::new(vec![host]);
+        client.set_max_response_size(10);
+        assert!(matches!(client.load_metadata_all(), Err(Error::CodecError)));
+        client.set_max_response_size(1024);
+        client.load_metadata_all().unwrap();
+        assert_eq!(3, client.partition_count("foo").unwrap());
+    }
+
+    #[test]
+    fn test_partition_count_is_cached() {
+        let (host, num_requests) = serve_metadata(&[("foo", 3)]);
+        let mut client = KafkaClient::new(vec![host]);
+
+        assert_eq!(3, client.partition_count("foo").unwrap());
+        assert_eq!(1, num_requests.load(Ordering::SeqCst));
+
+        // ~ the second call must be served from the loaded metadata
+        assert_eq!(3, client.partition_count("foo").unwrap());
+        assert_eq!(1, num_requests.load(Ordering::SeqCst));
+
+        // ~ an evicted topic gets refreshed again
+        client.state.remove_topic("foo");
+        assert_eq!(3, client.partition_count("foo").unwrap());
+        assert_eq!(2, num_requests.load(Ordering::SeqCst));
+    }
+
+    /// Retrieves an address no broker is listening on.
+    fn unreachable_host() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_bootstrap_skips_unreachable_hosts() {
+        let (host, num_requests) = serve_metadata(&[("foo", 1)]);
+        let hosts = vec![unreachable_host(), unreachable_host(), host];
+
+        let mut client = KafkaClient::new(hosts.clone());
+        client.set_randomize_bootstrap_order(false);
+        client.load_metadata_all().unwrap();
+        assert!(client.topics().contains("foo"));
+        assert_eq!(1, num_requests.load(Ordering::SeqCst));
+
+        for _ in 0..5 {
+            let mut client = KafkaClient::new(hosts.clone());
+            assert!(client.randomize_bootstrap_order());
+            client.load_metadata_all().unwrap();
+            assert!(client.topics().contains("foo"));
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_no_host_reachable() {
+        let mut client = KafkaClient::new(vec![unreachable_host(), unreachable_host()]);
+        match client.load_metadata_all() {
+            Err(Error::NoHostReachable) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_refresh() {
+        #[derive(Debug, Clone)]
+        struct TestResolver(Arc<Mutex<Vec<SocketAddr>>>);
+        impl Resolver for TestResolver {
+            fn resolve(&self, _: &str) -> io::Result<Vec<SocketAddr>> {
+                Ok(self.0.lock().unwrap().clone())
+            }
+        }
+
+        let (host, _) = serve_metadata(&[("foo", 1)]);
+        let addrs = Arc::new(Mutex::new(vec![unreachable_host().parse().unwrap()]));
+        let mut client = KafkaClient::new(vec![unreachable_host()]);
+        client.set_bootstrap_resolver(TestResolver(Arc::clone(&addrs)));
+
+        // ~ the bootstrap host is not re-resolved unless enabled
+        *addrs.lock().unwrap() = vec![host.parse().unwrap()];
+        assert!(matches!(
+            client.load_metadata_all(),
+            Err(Error::NoHostReachable)
+        ));
+
+        client.set_bootstrap_refresh(Some(Duration::from_secs(3600)));
+        client.load_metadata_all().unwrap();
+        assert!(client.topics().contains("foo"));
+
+        // ~ the resolved address is kept until the interval elapses
+        *addrs.lock().unwrap() = vec![unreachable_host().parse().unwrap()];
+        client.load_metadata_all().unwrap();
+
+        client.set_bootstrap_refresh(Some(Duration::ZERO));
+        assert!(matches!(
+            client.load_metadata_all(),
+            Err(Error::NoHostReachable)
+        ));
+        *addrs.lock().unwrap() = vec![host.parse().unwrap()];
+        client.load_metadata_all().unwrap();
+        assert!(client.topics().contains("foo"));
+    }
+
+    #[test]
+    fn test_earliest_available_offset() {
+        let (host, _) = serve(&[("foo", 2)], (100, 200));
+        let mut client = KafkaClient::new(vec![host]);
+        client.load_metadata_all().unwrap();
+
+        assert_eq!(100, client.earliest_available_offset("foo", 1).unwrap());
+        match client.earliest_available_offset("foo", 2) {
+            Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (host, num_requests) = serve(&[("foo", 1)], (0, 5));
+        let mut client = KafkaClient::new(vec![host]);
+        client.load_metadata_all().unwrap();
+        assert_eq!(1, client.conn_pool.len());
+
+        client.disconnect();
+        assert_eq!(0, client.conn_pool.len());
+        assert!(client.topics().contains("foo"));
+
+        // ~ the next request reconnects
+        assert_eq!(0, client.earliest_available_offset("foo", 0).unwrap());
+        assert_eq!(1, client.conn_pool.len());
+        assert_eq!(2, num_requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_cluster_metadata() {
+        let (host, _) = serve_metadata(&[("foo", 2), ("bar", 1)]);
+        let mut client = KafkaClient::new(vec![host.clone()]);
+        client.load_metadata_all().unwrap();
+
+        let md = client.cluster_metadata();
+        assert_eq!(None, md.cluster_id);
+        assert_eq!(1, md.brokers.len());
+        assert_eq!(0, md.brokers[0].id);
+        assert_eq!(
+            host,
+            format!("{}:{}", md.brokers[0].host, md.brokers[0].port)
+        );
+        assert_eq!(None, md.brokers[0].rack);
+        let topics: Vec<_> = md.topics.iter().map(|t| &t.name[..]).collect();
+        assert_eq!(vec!["bar", "foo"], topics);
+        let p = &md.topics[1].partitions[1];
+        assert_eq!(1, p.id);
+        assert_eq!(Some(0), p.leader);
+        assert_eq!(vec![0], p.replicas);
+        assert_eq!(vec![0], p.isr);
+    }
+
+    #[test]
+    fn test_produce_messages_deadline() {
+        use super::fake_broker::MockBroker;
+        use super::{ProduceMessage, RequiredAcks};
+        use std::time::{Duration, Instant};
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.set_retry_backoff_time(Duration::from_millis(10));
+        client.load_metadata_all().unwrap();
+        let msgs = [ProduceMessage::new("foo", 0, None, Some(b"a"))];
+
+        // ~ a broker slower than the deadline
+        broker.set_response_delay(Duration::from_millis(500));
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(100);
+        let r = client.produce_messages_deadline(
+            deadline,
+            RequiredAcks::One,
+            Duration::from_secs(1),
+            &msgs,
+        );
+        assert!(r.is_err(), "{:?}", r);
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        // ~ a past deadline doesn't even reach the broker
+        let n = broker.num_requests();
+        let r = client.produce_messages_deadline(
+            Instant::now(),
+            RequiredAcks::One,
+            Duration::from_secs(1),
+            &msgs,
+        );
+        assert!(matches!(r, Err(Error::Kafka(KafkaCode::RequestTimedOut))));
+        assert_eq!(n, broker.num_requests());
+
+        // ~ a responsive broker within the deadline
+        broker.set_response_delay(Duration::ZERO);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        client
+            .produce_messages_deadline(deadline, RequiredAcks::One, Duration::from_secs(1), &msgs)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_require_stable_offsets() {
+        let mut client = KafkaClient::new(vec![]);
+        client.set_group_offset_storage(super::GroupOffsetStorage::Kafka);
+        assert!(!client.require_stable_offsets());
+        let req = super::__offset_fetch_request(&client.config, "g", 1);
+        assert_eq!((1, false), (req.header.api_version, req.require_stable));
+
+        client.set_require_stable_offsets(true);
+        let req = super::__offset_fetch_request(&client.config, "g", 1);
+        assert_eq!((7, true), (req.header.api_version, req.require_stable));
+
+        // ~ not supported by zookeeper based storage
+        client.set_group_offset_storage(super::GroupOffsetStorage::Zookeeper);
+        let req = super::__offset_fetch_request(&client.config, "g", 1);
+        assert_eq!((0, false), (req.header.api_version, req.require_stable));
+    }
+
+    #[test]
+    fn test_unsupported_api_policy() {
+        use super::ApiPolicy;
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        // ~ OffsetFetch (api key 9) as of kafka 2.4
+        broker.set_max_api_version(9, 6);
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.set_retry_max_attempts(1);
+        client.set_group_offset_storage(super::GroupOffsetStorage::Kafka);
+        client.set_require_stable_offsets(true);
+        client.load_metadata_all().unwrap();
+        assert_eq!(ApiPolicy::SkipFeature, client.unsupported_api_policy());
+
+        // ~ degrade to possibly unstable offsets and metadata
+        let offsets = client.fetch_group_topic_offsets("g", "foo").unwrap();
+        assert_eq!(1, offsets.len());
+        assert!(client.describe_cluster().is_ok());
+
+        client.set_unsupported_api_policy(ApiPolicy::Error);
+        fn unsupported<T>(r: Result<T, Error>) -> bool {
+            matches!(r, Err(Error::Kafka(KafkaCode::UnsupportedVersion)))
+        }
+        assert!(unsupported(client.fetch_group_topic_offsets("g", "foo")));
+        assert!(unsupported(client.describe_cluster()));
+
+        // ~ a dropped connection alone does not indicate a missing
+        // api; the broker has to confirm it
+        let disconnect = || -> Result<(), Error> {
+            Err(Error::Io(io::Error::from(io::ErrorKind::ConnectionReset)))
+        };
+        let host = broker.host().to_owned();
+        let r = super::__confirm_unsupported_api(
+            &mut client.conn_pool,
+            &client.config,
+            1,
+            &host,
+            (crate::protocol::API_KEY_OFFSET_FETCH, 6),
+            disconnect(),
+        );
+        assert!(matches!(r, Err(Error::Io(_))));
+        let r = super::__confirm_unsupported_api(
+            &mut client.conn_pool,
+            &client.config,
+            1,
+            &host,
+            (crate::protocol::API_KEY_OFFSET_FETCH, 7),
+            disconnect(),
+        );
+        assert!(unsupported(r));
+    }
+
+    #[test]
+    fn test_scram_credentials() {
+        use super::{
+            ScramCredentialDeletion, ScramCredentialInfo, ScramCredentialUpsertion, ScramMechanism,
+        };
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().unwrap();
+
+        let upsertion = |user: &str, mechanism| ScramCredentialUpsertion {
+            user: user.to_owned(),
+            mechanism,
+            iterations: 8192,
+            salt: vec![1; 32],
+            salted_password: vec![2; 64],
+        };
+        let r = client
+            .alter_scram_credentials(
+                &[
+                    upsertion("alice", ScramMechanism::Sha256),
+                    upsertion("alice", ScramMechanism::Sha512),
+                ],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(2, r.len());
+        assert!(r.iter().all(|r| r.user == "alice" && r.result.is_ok()));
+
+        // ~ a user without credentials is reported along with the others
+        let users = client
+            .describe_scram_credentials(&["alice", "bob"])
+            .unwrap();
+        assert_eq!(2, users.len());
+        assert_eq!("alice", users[0].user);
+        assert_eq!(
+            Ok(vec![
+                ScramCredentialInfo {
+                    mechanism: ScramMechanism::Sha256,
+                    iterations: 8192,
+                },
+                ScramCredentialInfo {
+                    mechanism: ScramMechanism::Sha512,
+                    iterations: 8192,
+                },
+            ]),
+            users[0].credentials
+        );
+        assert_eq!("bob", users[1].user);
+        assert_eq!(Err(KafkaCode::ResourceNotFound), users[1].credentials);
+
+        // ~ deleting credentials
+        let deletion = |user: &str| ScramCredentialDeletion {
+            user: user.to_owned(),
+            mechanism: ScramMechanism::Sha256,
+        };
+        let r = client
+            .alter_scram_credentials(&[], &[deletion("alice"), deletion("bob")])
+            .unwrap();
+        assert_eq!(Ok(()), r[0].result);
+        assert_eq!(Err(KafkaCode::ResourceNotFound), r[1].result);
+        let users = client.describe_scram_credentials::<&str>(&[]).unwrap();
+        assert_eq!(1, users.len());
+        assert_eq!(
+            Ok(vec![ScramCredentialInfo {
+                mechanism: ScramMechanism::Sha512,
+                iterations: 8192,
+            }]),
+            users[0].credentials
+        );
+    }
+
+    #[test]
+    fn test_metadata_retries() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.set_retry_max_attempts(1);
+
+        // ~ no retries by default
+        broker.reject_metadata_requests(2);
+        assert!(client.load_metadata_all().is_err());
+
+        client.set_metadata_retries(3, Duration::from_millis(10));
+        assert_eq!((3, Duration::from_millis(10)), client.metadata_retries());
+        broker.reject_metadata_requests(2);
+        client.load_metadata_all().unwrap();
+        assert!(client.topics().contains("foo"));
+        assert_eq!(4, broker.requests().len());
+
+        // ~ giving up after the configured attempts
+        broker.reject_metadata_requests(3);
+        assert!(client.load_metadata_all().is_err());
+    }
+
+    #[test]
+    fn test_allow_auto_topic_creation() {
+        use super::{MetadataVersion, DEFAULT_ALLOW_AUTO_TOPIC_CREATION};
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        broker.set_auto_create_topics(true);
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.set_metadata_version(MetadataVersion::V4);
+        assert_eq!(
+            DEFAULT_ALLOW_AUTO_TOPIC_CREATION,
+            client.allow_auto_topic_creation()
+        );
+
+        // ~ read-only lookups leave the cluster untouched
+        client.set_allow_auto_topic_creation(false);
+        client.load_metadata(&["bar"]).unwrap();
+        let num_partitions =
+            |c: &KafkaClient| c.topics().partitions("bar").map_or(0, |ps| ps.len());
+        assert_eq!(0, num_partitions(&client));
+        assert!(broker.auto_created_topics().is_empty());
+
+        client.set_allow_auto_topic_creation(true);
+        client.load_metadata(&["bar"]).unwrap();
+        assert_eq!(1, num_partitions(&client));
+        assert_eq!(vec!["bar"], broker.auto_created_topics());
+    }
+
+    #[test]
+    fn test_commit_offsets_coordinator_move() {
+        let old = MockBroker::start(&[("foo", 1)], (0, 10));
+        let new = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut client = KafkaClient::new(vec![old.host().to_owned()]);
+        client.set_retry_backoff_time(Duration::from_millis(1));
+        client.load_metadata_all().unwrap();
+        let committed = |client: &mut KafkaClient| {
+            client
+                .fetch_group_topic_offsets("my-group", "foo")
+                .unwrap()
+                .pop()
+                .map(|p| p.offset)
+        };
+        client.commit_offset("my-group", "foo", 0, 1).unwrap();
+        assert_eq!(Some(1), committed(&mut client));
+
+        // ~ the coordination moves while the client still knows the
+        // old coordinator; the commit is retried at the new one
+        old.set_coordinator(Some((1, new.host().to_owned())));
+        client.commit_offset("my-group", "foo", 0, 2).unwrap();
+        assert_eq!(Some(2), committed(&mut client));
+
+        // ~ a coordinator never settling surfaces the error once the
+        // retries are exhausted
+        new.set_coordinator(Some((0, old.host().to_owned())));
+        client.set_retry_max_attempts(3);
+        match client.commit_offset("my-group", "foo", 0, 3) {
+            Err(Error::CoordinatorNotFound { group }) => assert_eq!("my-group", group),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_correlation_id_generator() {
+        use super::ProduceMessage;
+        use std::sync::atomic::AtomicI32;
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        let seq = Arc::new(AtomicI32::new(0));
+        let generated = Arc::clone(&seq);
+        client.set_correlation_id_generator(move || {
+            (0x2a << 20) | generated.fetch_add(1, Ordering::SeqCst)
+        });
+        client.load_metadata_all().unwrap();
+        for _ in 0..3 {
+            client
+                .produce_messages(
+                    RequiredAcks::One,
+                    Duration::from_millis(100),
+                    vec![ProduceMessage::new("foo", 0, None, Some(&b"a"[..]))],
+                )
+                .unwrap();
+        }
+        assert_eq!(4, seq.load(Ordering::SeqCst));
+        let ids = broker.correlation_ids();
+        assert_eq!(
+            vec![0x2a << 20, 0x2a << 20 | 1, 0x2a << 20 | 2, 0x2a << 20 | 3],
+            ids
+        );
+
+        // ~ back to the built-in sequence
+        client.clear_correlation_id_generator();
+        client.load_metadata_all().unwrap();
+        assert_eq!(4, seq.load(Ordering::SeqCst));
+        let ids = broker.correlation_ids();
+        assert!(!ids[..4].contains(&ids[4]));
+    }
+
+    #[test]
+    fn test_list_topic_names() {
+        let broker =
+            MockBroker::start(&[("foo", 1), ("__consumer_offsets", 3), ("bar", 2)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+
+        assert_eq!(vec!["foo", "bar"], client.list_topic_names(false).unwrap());
+        assert_eq!(
+            vec!["foo", "__consumer_offsets", "bar"],
+            client.list_topic_names(true).unwrap()
+        );
+        // ~ the metadata is not loaded into the client
+        assert_eq!(0, client.topics().len());
+    }
+
+    #[test]
+    fn test_broker_idle_time() {
+        let (host, _) = serve_metadata(&[("foo", 1)]);
+        let mut client = KafkaClient::new(vec![host]);
+        assert_eq!(None, client.broker_idle_time(0));
+        client.load_metadata_all().unwrap();
+
+        let gap = Duration::from_millis(50);
+        std::thread::sleep(gap);
+        let idle = client.broker_idle_time(0).unwrap();
+        assert!(idle >= gap, "{:?}", idle);
+
+        // ~ a request resets the idle time
+        client.load_metadata_all().unwrap();
+        assert!(client.broker_idle_time(0).unwrap() < idle);
+        assert_eq!(None, client.broker_idle_time(1));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let (host, _) = serve_metadata(&[("foo", 1)]);
+        let mut client = KafkaClient::new(vec![host.clone()]);
+        client.load_metadata_all().unwrap();
+
+        // ~ a stale connection is replaced by a new one
+        let now = std::time::Instant::now();
+        let stale = format!("{:?}", client.conn_pool.get_conn(&host, now).unwrap());
+        client.reconnect(0).unwrap();
+        assert_eq!(1, client.conn_pool.len());
+        let fresh = format!("{:?}", client.conn_pool.get_conn(&host, now).unwrap());
+        assert_ne!(stale, fresh);
+        client.load_metadata_all().unwrap();
+
+        // ~ unknown even after refreshing the metadata
+        assert!(matches!(client.reconnect(1), Err(Error::NoHostReachable)));
+    }
+
+    #[test]
+    fn test_fetch_messages_into() {
+        let (host, _) = serve(&[("foo", 2)], (0, 10));
+        let mut client = KafkaClient::new(vec![host]);
+        client.load_metadata_all().unwrap();
+        let reqs = [
+            FetchPartition::new("foo", 0, 3),
+            FetchPartition::new("foo", 1, 8),
+        ];
+
+        let mut expected = Vec::new();
+        for resp in client.fetch_messages(&reqs).unwrap() {
+            for t in resp.topics() {
+                for p in t.partitions() {
+                    for m in p.data().unwrap().messages() {
+                        expected.push((p.partition(), m.offset, m.value.to_vec()));
+                    }
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+
+        let mut buf = Vec::new();
+        let msgs = {
+            let resps = client.fetch_messages_into(&reqs, &mut buf).unwrap();
+            let mut msgs = Vec::new();
+            for resp in &resps {
+                for t in resp.topics() {
+                    for p in t.partitions() {
+                        for m in p.data().unwrap().messages() {
+                            msgs.push((p.partition(), m.offset, m.value.to_vec()));
+                        }
+                    }
+                }
+            }
+            msgs
+        };
+        assert_eq!(expected, msgs);
+
+        // ~ the buffer is re-used by subsequent fetches
+        let capacity = buf.capacity();
+        let resps = client.fetch_messages_into(&reqs, &mut buf).unwrap();
+        assert_eq!(1, resps.len());
+        assert_eq!(capacity, buf.capacity());
+    }
+
+    #[test]
+    fn test_describe_cluster_fallback() {
+        // ~ the fake broker drops the connection on the unknown
+        // `DescribeCluster` api; the client falls back to metadata
+        let (host, _) = serve_metadata(&[("foo", 2)]);
+        let mut client = KafkaClient::new(vec![host.clone()]);
+
+        let cluster = client.describe_cluster().unwrap();
+        assert_eq!(None, cluster.cluster_id);
+        assert_eq!(None, cluster.authorized_operations);
+        assert!(!cluster.is_authorized(7));
+        assert_eq!(1, cluster.brokers.len());
+        assert_eq!(
+            host,
+            format!("{}:{}", cluster.brokers[0].host, cluster.brokers[0].port)
+        );
+        assert!(client.topics().contains("foo"));
+    }
+
+    #[cfg(feature = "security")]
+    #[test]
+    fn test_set_tls_config() {
+        use openssl::asn1::Asn1Time;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::ssl::{SslAcceptor, SslConnector, SslMethod, SslVerifyMode, SslVersion};
+        use openssl::x509::{X509NameBuilder, X509};
+        use std::sync::mpsc;
+
+        // ~ a server with a self-signed certificate accepting tls 1.3
+        // only; reports the outcome of every handshake
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&pkey).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert = cert.build();
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&pkey).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        acceptor
+            .set_min_proto_version(Some(SslVersion::TLS1_3))
+            .unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let ok = acceptor.accept(stream.unwrap()).is_ok();
+                if tx.send(ok).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let connector = |max_version| {
+            let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+            builder.set_verify(SslVerifyMode::NONE);
+            builder.set_max_proto_version(max_version).unwrap();
+            builder
+        };
+        let mut client = KafkaClient::new(vec![host]);
+        client.set_metadata_retries(1, Duration::ZERO);
+
+        // ~ a connector restricted to tls 1.2 fails the handshake
+        client.set_tls_config(connector(Some(SslVersion::TLS1_2)));
+        assert!(client.load_metadata_all().is_err());
+        assert_eq!(Ok(false), rx.recv_timeout(Duration::from_secs(5)));
+
+        // ~ while an unrestricted one succeeds; the server hangs up
+        // right after the handshake though
+        client.set_tls_config(connector(None));
+        assert!(client.load_metadata_all().is_err());
+        let accepted =
+            std::iter::from_fn(|| rx.recv_timeout(Duration::from_secs(5)).ok()).any(|ok| ok);
+        assert!(accepted);
+    }
+
+    #[test]
+    fn test_non_blocking_fetch() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().unwrap();
+        client.set_fetch_min_bytes(0);
+        client.set_fetch_max_wait_time(Duration::ZERO).unwrap();
+
+        let start = std::time::Instant::now();
+        let resps = client
+            .fetch_messages_for_partition(&FetchPartition::new("foo", 0, 0))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(Some((0, 0)), broker.fetch_wait());
+        let p = &resps[0].topics()[0].partitions()[0];
+        assert!(p.data().as_ref().unwrap().messages().is_empty());
+    }
+
+    #[test]
+    fn test_fetch_from_replica() {
+        let leader = MockBroker::start(&[("foo", 1)], (0, 10));
+        let follower = MockBroker::start(&[("foo", 1)], (0, 3));
+        leader.set_peer(Some((1, follower.host().to_owned())));
+        let mut client = KafkaClient::new(vec![leader.host().to_owned()]);
+        client.load_metadata_all().unwrap();
+
+        // ~ the fetch goes to the follower (lagging behind) although
+        // the leader is known
+        let n = leader.num_requests();
+        let resp = client.fetch_from_replica("foo", 0, 1, 2).unwrap();
+        let p = &resp.topics()[0].partitions()[0];
+        let data = p.data().unwrap();
+        assert_eq!(3, data.highwatermark_offset());
+        assert_eq!(2, data.messages()[0].offset);
+        assert_eq!(n, leader.num_requests());
+        assert_eq!(vec![crate::protocol::API_KEY_FETCH], follower.requests());
+        assert_eq!(Some(-2), follower.fetch_replica_id());
+
+        // ~ unknown brokers
+        match client.fetch_from_replica("foo", 0, 7, 0) {
+            Err(Error::NoHostReachable) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_describe_log_dirs() {
+        let broker = MockBroker::start(&[("foo", 2), ("bar", 1)], (0, 10));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().unwrap();
+        client
+            .produce_messages(
+                RequiredAcks::One,
+                Duration::from_secs(1),
+                vec![ProduceMessage::new("foo", 1, None, Some(b"abc"))],
+            )
+            .unwrap();
+
+        let log_dirs = client.describe_log_dirs(&[0], None).unwrap();
+        assert_eq!(1, log_dirs.len());
+        assert_eq!(0, log_dirs[0].broker_id);
+        let mut sizes: Vec<_> = log_dirs[0]
+            .partitions
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|p| (p.topic.as_str(), p.partition, p.size))
+            .collect();
+        sizes.sort_unstable();
+        assert_eq!(vec![("bar", 0, 0), ("foo", 0, 0), ("foo", 1, 29)], sizes);
+
+        // ~ restricted to particular partitions; offline log dirs
+        // reported along with their error
+        broker.set_log_dir_offline(true);
+        let tps = [TopicPartition::new("foo", 1)];
+        let log_dirs = client.describe_log_dirs(&[], Some(&tps)).unwrap();
+        assert_eq!(2, log_dirs.len());
+        let ps = log_dirs[0].partitions.as_ref().unwrap();
+        assert_eq!(1, ps.len());
+        assert_eq!(("foo", 1), (ps[0].topic.as_str(), ps[0].partition));
+        assert!(ps[0].size > 0);
+        assert_eq!(
+            Err(KafkaCode::KafkaStorageError),
+            log_dirs[1].partitions.clone().map(|_| ())
+        );
+
+        // ~ unknown brokers
+        match client.describe_log_dirs(&[1], None) {
+            Err(Error::NoHostReachable) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_produce_raw_batch() {
+        use super::fake_broker::render_message;
+
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().unwrap();
+
+        // ~ a hand-built message set carrying the offsets of a source
+        let mut batch = Vec::new();
+        render_message(&mut batch, 42, Some(b"k1"), b"v1");
+        render_message(&mut batch, 43, None, b"v2");
+        let confirms = client
+            .produce_raw_batch(RequiredAcks::One, Duration::from_secs(1), "foo", 1, &batch)
+            .unwrap();
+        assert_eq!(1, confirms.len());
+        assert_eq!("foo", confirms[0].topic);
+        assert_eq!(1, confirms[0].partition_confirms[0].partition);
+        assert_eq!(Ok(10), confirms[0].partition_confirms[0].offset);
+        assert_eq!(2, broker.num_produced("foo", 1));
+        assert_eq!(0, broker.num_produced("foo", 0));
+
+        // ~ the messages read back as they were rendered
+        let mut fetched = Vec::new();
+        for offset in 10..12 {
+            let resps = client
+                .fetch_messages_for_partition(&FetchPartition::new("foo", 1, offset))
+                .unwrap();
+            match resps[0].topics()[0].partitions()[0].data() {
+                Ok(data) => fetched.extend(
+                    data.messages()
+                        .iter()
+                        .map(|m| (m.key.map(<[u8]>::to_vec), m.value.to_vec())),
+                ),
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert_eq!(
+            vec![
+                (Some(b"k1".to_vec()), b"v1".to_vec()),
+                (None, b"v2".to_vec())
+            ],
+            fetched
+        );
+
+        match client.produce_raw_batch(RequiredAcks::One, Duration::from_secs(1), "bar", 0, &batch)
+        {
+            Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_control_and_data_timeouts() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().unwrap();
+        client.set_control_timeout(Some(Duration::from_millis(100)));
+        client.set_data_timeout(Some(Duration::from_secs(10)));
+        assert_eq!(Some(Duration::from_millis(100)), client.control_timeout());
+        assert_eq!(Some(Duration::from_secs(10)), client.data_timeout());
+        broker.set_response_delay(Duration::from_millis(500));
+
+        // ~ a slow fetch is not killed by the short control timeout
+        let resps = client
+            .fetch_messages_for_partition(&FetchPartition::new("foo", 0, 0))
+            .unwrap();
+        assert_eq!(1, resps.len());
+
+        // ~ while a slow metadata request times out quickly
+        let start = std::time::Instant::now();
+        match client.load_metadata_all() {
+            Err(Error::Io(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_fetch_api_versions() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        broker.set_max_api_version(3, 7);
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        assert_eq!(
+            (
+                DEFAULT_CLIENT_SOFTWARE_NAME,
+                DEFAULT_CLIENT_SOFTWARE_VERSION
+            ),
+            client.client_software()
+        );
+        client.set_client_software("my-app".to_owned(), "1.2.3".to_owned());
+
+        let versions = client.fetch_api_versions().unwrap();
+        let metadata = versions.iter().find(|v| v.api_key == 3);
+        assert_eq!(Some(7), metadata.map(|v| v.max_version));
+        assert_eq!(
+            Some(("my-app".to_owned(), "1.2.3".to_owned())),
+            broker.client_software()
+        );
+    }
+
+    #[test]
+    fn test_report_client_software() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        assert!(!client.report_client_software());
+        client.load_metadata_all().unwrap();
+        assert_eq!(None, broker.client_software());
+
+        // ~ reported once per newly established connection only
+        client.set_client_software("my-app".to_owned(), "1.2.3".to_owned());
+        client.set_report_client_software(true);
+        client.reconnect(0).unwrap();
+        client.load_metadata_all().unwrap();
+        client.load_metadata_all().unwrap();
+        assert_eq!(
+            Some(("my-app".to_owned(), "1.2.3".to_owned())),
+            broker.client_software()
+        );
+        assert_eq!(vec![3, 18, 3, 3], broker.requests());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_cluster_metadata_serialize() {
+        let (host, _) = serve_metadata(&[("foo", 2)]);
+        let mut client = KafkaClient::new(vec![host]);
+        client.load_metadata_all().unwrap();
+
+        let json = serde_json::to_value(client.cluster_metadata()).unwrap();
+        let broker = &json["brokers"][0];
+        assert_eq!(0, broker["id"]);
+        assert_eq!("127.0.0.1", broker["host"]);
+        assert!(broker["port"].as_i64().unwrap() > 0);
+        assert!(broker["rack"].is_null());
+        assert_eq!("foo", json["topics"][0]["name"]);
+        let partitions = json["topics"][0]["partitions"].as_array().unwrap();
+        assert_eq!(2, partitions.len());
+        assert_eq!(1, partitions[1]["id"]);
+        assert_eq!(0, partitions[1]["leader"]);
+        assert_eq!(serde_json::json!([0]), partitions[1]["replicas"]);
+        assert_eq!(serde_json::json!([0]), partitions[1]["isr"]);
+    }
+
+    #[test]
+    fn test_list_offsets() {
+        let (host, num_requests) = serve(&[("foo", 3), ("bar", 1)], (5, 20));
+        let mut client = KafkaClient::new(vec![host]);
+        client.load_metadata_all().unwrap();
+
+        let mut reqs = Vec::new();
+        for p in 0..3 {
+            reqs.push((TopicPartition::new("foo", p), FetchOffset::Earliest));
+            reqs.push((TopicPartition::new("foo", p), FetchOffset::Latest));
+        }
+        reqs.push((TopicPartition::new("bar", 0), FetchOffset::Latest));
+        let offsets = client.list_offsets(&reqs).unwrap();
+
+        let expected: Vec<_> = (0..3)
+            .flat_map(|p| {
+                vec![
+                    (TopicPartition::new("foo", p), 5),
+                    (TopicPartition::new("foo", p), 20),
+                ]
+            })
+            .chain(Some((TopicPartition::new("bar", 0), 20)))
+            .collect();
+        assert_eq!(expected, offsets);
+        // ~ metadata, two offset requests: the second round asks for
+        // the partitions queried twice
+        assert_eq!(3, num_requests.load(Ordering::SeqCst));
+
+        match client.list_offsets(&[(TopicPartition::new("foo", 3), FetchOffset::Latest)]) {
+            Err(Error::TopicPartitionError {
+                partition_id: 3,
+                error_code: KafkaCode::UnknownTopicOrPartition,
+                ..
+            }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_required_acks_try_from() {
+        for acks in [-1, 0, 1] {
+            assert_eq!(acks, RequiredAcks::try_from(acks).unwrap() as i16);
+        }
+        for acks in [-2, 2, i16::MAX] {
+            match RequiredAcks::try_from(acks) {
+                Err(Error::InvalidRequiredAcks(n)) => assert_eq!(acks, n),
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_metadata_for_topics() {
+        let broker = MockBroker::start(&[("foo", 1), ("bar", 2)], (0, 0));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.load_metadata_all().unwrap();
+
+        broker.set_topics(&[("foo", 3), ("bar", 4)]);
+        let num_requests = broker.num_requests();
+        client.metadata_for_topics(&["foo", "baz"]).unwrap();
+        assert_eq!(num_requests + 1, broker.num_requests());
+
+        let topics = client.topics();
+        assert_eq!(Some(3), topics.partitions("foo").map(|ps| ps.len()));
+        // ~ topics not asked for stay untouched
+        assert_eq!(Some(2), topics.partitions("bar").map(|ps| ps.len()));
+        assert_eq!(None, client.topic_error("foo"));
+        assert_eq!(
+            Some(KafkaCode::UnknownTopicOrPartition),
+            client.topic_error("baz")
+        );
+
+        client.metadata_for_topics(&[]).unwrap();
+        assert_eq!(num_requests + 1, broker.num_requests());
+    }
+
+    #[test]
+    fn test_partition_count_unknown_topic() {
+        let (host, num_requests) = serve_metadata(&[("foo", 3)]);
+        let mut client = KafkaClient::new(vec![host]);
+
+        match client.partition_count("bar") {
+            Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(1, num_requests.load(Ordering::SeqCst));
+    }
+}
@@ -6,7 +6,7 @@
 
 use std::collections::HashMap;
 use std::fmt;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::mem;
 use std::net::{Shutdown, TcpStream};
 use std::time::{Duration, Instant};
@@ -14,7 +14,7 @@ use std::time::{Duration, Instant};
 #[cfg(feature = "security")]
 use openssl::ssl::SslConnector;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 // --------------------------------------------------------------------
 
@@ -58,34 +58,19 @@ impl fmt::Debug for SecurityConfig {
 
 // --------------------------------------------------------------------
 
-struct Pooled<T> {
-    last_checkout: Instant,
-    item: T,
-}
-
-impl<T> Pooled<T> {
-    fn new(last_checkout: Instant, item: T) -> Self {
-        Pooled {
-            last_checkout,
-            item,
-        }
-    }
-}
-
-impl<T: fmt::Debug> fmt::Debug for Pooled<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Pooled {{ last_checkout: {:?}, item: {:?} }}",
-            self.last_checkout, self.item
-        )
-    }
-}
-
 #[derive(Debug)]
 pub struct Config {
-    rw_timeout: Option<Duration>,
+    // ~ the read/write timeouts for control requests (e.g. metadata,
+    // offset or group requests) and data requests (produce and
+    // fetch requests)
+    control_timeout: Option<Duration>,
+    data_timeout: Option<Duration>,
     idle_timeout: Duration,
+    max_response_size: usize,
+    deadline: Option<Instant>,
+    // ~ a (size prefixed) request sent over every newly established
+    // connection before handing it out; its response is discarded
+    greeting: Option<Vec<u8>>,
     #[cfg(feature = "security")]
     security_config: Option<SecurityConfig>,
 }
@@ -93,10 +78,15 @@ pub struct Config {
 impl Config {
     #[cfg(not(feature = "security"))]
     fn new_conn(&self, id: u32, host: &str) -> Result<KafkaConnection> {
-        KafkaConnection::new(id, host, self.rw_timeout).map(|c| {
-            debug!("Established: {:?}", c);
-            c
-        })
+        KafkaConnection::new(id, host, self.control_timeout)
+            .map(|mut c| {
+                c.data_timeout = self.data_timeout;
+                c.max_response_size = self.max_response_size;
+                c.deadline = self.deadline;
+                debug!("Established: {:?}", c);
+                c
+            })
+            .and_then(|c| self.greet(c))
     }
 
     #[cfg(feature = "security")]
@@ -104,15 +94,39 @@ impl Config {
         KafkaConnection::new(
             id,
             host,
-            self.rw_timeout,
+            self.control_timeout,
             self.security_config
                 .as_ref()
                 .map(|c| (c.connector.clone(), c.verify_hostname)),
         )
-        .map(|c| {
+        .map(|mut c| {
+            c.data_timeout = self.data_timeout;
+            c.max_response_size = self.max_response_size;
+            c.deadline = self.deadline;
             debug!("Established: {:?}", c);
             c
         })
+        .and_then(|c| self.greet(c))
+    }
+}
+
+impl Config {
+    // ~ sends the greeting - if any - over the given newly
+    // established connection and skips the broker's response
+    fn greet(&self, mut c: KafkaConnection) -> Result<KafkaConnection> {
+        if let Some(ref greeting) = self.greeting {
+            c.send(greeting)?;
+            let mut size = [0u8; 4];
+            c.read_exact(&mut size)?;
+            match usize::try_from(i32::from_be_bytes(size)) {
+                Ok(size) if size <= c.max_response_size => {
+                    c.read_response(size)?;
+                }
+                _ => return Err(Error::CodecError),
+            }
+            debug!("Greeted: {:?}", c);
+        }
+        Ok(c)
     }
 }
 
@@ -135,7 +149,7 @@ impl State {
 
 #[derive(Debug)]
 pub struct Connections {
-    conns: HashMap<String, Pooled<KafkaConnection>>,
+    conns: HashMap<String, KafkaConnection>,
     state: State,
     config: Config,
 }
@@ -147,8 +161,12 @@ impl Connections {
             conns: HashMap::new(),
             state: State::new(),
             config: Config {
-                rw_timeout,
+                control_timeout: rw_timeout,
+                data_timeout: rw_timeout,
                 idle_timeout,
+                max_response_size: super::DEFAULT_MAX_RESPONSE_SIZE,
+                deadline: None,
+                greeting: None,
             },
         }
     }
@@ -168,13 +186,36 @@ impl Connections {
             conns: HashMap::new(),
             state: State::new(),
             config: Config {
-                rw_timeout,
+                control_timeout: rw_timeout,
+                data_timeout: rw_timeout,
                 idle_timeout,
+                max_response_size: super::DEFAULT_MAX_RESPONSE_SIZE,
+                deadline: None,
+                greeting: None,
                 security_config: security,
             },
         }
     }
 
+    /// Replaces the TLS connector used to establish new connections
+    /// keeping the hostname verification setting, if any.  Existing
+    /// connections are closed such that they get re-established
+    /// using the new connector.
+    #[cfg(feature = "security")]
+    pub fn set_tls_connector(&mut self, connector: SslConnector) {
+        let verify_hostname = self
+            .config
+            .security_config
+            .as_ref()
+            .map(|c| c.verify_hostname)
+            .unwrap_or(true);
+        self.config.security_config = Some(SecurityConfig {
+            connector,
+            verify_hostname,
+        });
+        self.clear();
+    }
+
     pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
         self.config.idle_timeout = idle_timeout;
     }
@@ -183,16 +224,67 @@ impl Connections {
         self.config.idle_timeout
     }
 
+    pub fn set_control_timeout(&mut self, timeout: Option<Duration>) {
+        self.config.control_timeout = timeout;
+        for conn in self.conns.values_mut() {
+            conn.control_timeout = timeout;
+        }
+    }
+
+    pub fn control_timeout(&self) -> Option<Duration> {
+        self.config.control_timeout
+    }
+
+    pub fn set_data_timeout(&mut self, timeout: Option<Duration>) {
+        self.config.data_timeout = timeout;
+        for conn in self.conns.values_mut() {
+            conn.data_timeout = timeout;
+        }
+    }
+
+    pub fn data_timeout(&self) -> Option<Duration> {
+        self.config.data_timeout
+    }
+
+    pub fn set_max_response_size(&mut self, max_response_size: usize) {
+        self.config.max_response_size = max_response_size;
+        for conn in self.conns.values_mut() {
+            conn.max_response_size = max_response_size;
+        }
+    }
+
+    pub fn max_response_size(&self) -> usize {
+        self.config.max_response_size
+    }
+
+    /// Sets the (size prefixed) request to send over every newly
+    /// established connection before using it; `None` disables the
+    /// greeting.  Established connections are not affected.
+    pub fn set_greeting(&mut self, greeting: Option<Vec<u8>>) {
+        self.config.greeting = greeting;
+    }
+
+    /// Sets the deadline for all i/o on pooled connections; `None`
+    /// lifts a previously set deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.config.deadline = deadline;
+        for conn in self.conns.values_mut() {
+            conn.set_deadline(deadline);
+        }
+    }
+
     pub fn get_conn<'a>(&'a mut self, host: &str, now: Instant) -> Result<&'a mut KafkaConnection> {
         if let Some(conn) = self.conns.get_mut(host) {
-            if now.duration_since(conn.last_checkout) >= self.config.idle_timeout {
-                debug!("Idle timeout reached: {:?}", conn.item);
+            if conn.closed {
+                debug!("Re-establishing closed: {:?}", conn);
+                *conn = self.config.new_conn(self.state.next_conn_id(), host)?;
+            } else if conn.idle_time(now) >= self.config.idle_timeout {
+                debug!("Idle timeout reached: {:?}", conn);
                 let new_conn = self.config.new_conn(self.state.next_conn_id(), host)?;
-                let _ = conn.item.shutdown();
-                conn.item = new_conn;
+                let _ = conn.shutdown();
+                *conn = new_conn;
             }
-            conn.last_checkout = now;
-            let kconn: &mut KafkaConnection = &mut conn.item;
+            let kconn: &mut KafkaConnection = conn;
             // ~ decouple the lifetimes to make the borrowck happy;
             // this is safe since we're immediately returning the
             // reference and the rest of the code in this method is
@@ -200,21 +292,49 @@ impl Connections {
             return Ok(unsafe { mem::transmute(kconn) });
         }
         let cid = self.state.next_conn_id();
-        self.conns.insert(
-            host.to_owned(),
-            Pooled::new(now, self.config.new_conn(cid, host)?),
-        );
-        Ok(&mut self.conns.get_mut(host).unwrap().item)
+        self.conns
+            .insert(host.to_owned(), self.config.new_conn(cid, host)?);
+        Ok(self.conns.get_mut(host).unwrap())
+    }
+
+    /// Shuts down the pooled connection to the given host - if any -
+    /// and establishes a new one in its place.
+    pub fn reconnect(&mut self, host: &str) -> Result<()> {
+        if let Some(mut conn) = self.conns.remove(host) {
+            debug!("Reconnecting: {:?}", conn);
+            let _ = conn.shutdown();
+        }
+        let conn = self.config.new_conn(self.state.next_conn_id(), host)?;
+        self.conns.insert(host.to_owned(), conn);
+        Ok(())
+    }
+
+    /// Determines for how long the pooled connection to the given
+    /// host - if any - has seen no i/o as of `now`.
+    pub fn idle_time(&self, host: &str, now: Instant) -> Option<Duration> {
+        self.conns.get(host).map(|conn| conn.idle_time(now))
+    }
+
+    /// Shuts down and forgets all pooled connections.
+    pub fn clear(&mut self) {
+        for (_, mut conn) in self.conns.drain() {
+            let _ = conn.shutdown();
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.conns.len()
     }
 
     pub fn get_conn_any(&mut self, now: Instant) -> Option<&mut KafkaConnection> {
         for (host, conn) in &mut self.conns {
-            if now.duration_since(conn.last_checkout) >= self.config.idle_timeout {
-                debug!("Idle timeout reached: {:?}", conn.item);
+            if conn.closed || conn.idle_time(now) >= self.config.idle_timeout {
+                debug!("Idle timeout reached or closed: {:?}", conn);
                 let new_conn_id = self.state.next_conn_id();
                 let new_conn = match self.config.new_conn(new_conn_id, host.as_str()) {
                     Ok(new_conn) => {
-                        let _ = conn.item.shutdown();
+                        let _ = conn.shutdown();
                         new_conn
                     }
                     Err(e) => {
@@ -222,11 +342,9 @@ impl Connections {
                         continue;
                     }
                 };
-                conn.item = new_conn;
+                *conn = new_conn;
             }
-            conn.last_checkout = now;
-            let kconn: &mut KafkaConnection = &mut conn.item;
-            return Some(kconn);
+            return Some(conn);
         }
         None
     }
@@ -318,6 +436,56 @@ mod openssled {
     }
 }
 
+// ~ the factor by which the expected size of responses decays with
+// every response received; the larger, the slower a response buffer
+// shrinks after a large response
+const RESPONSE_SIZE_DECAY: usize = 64;
+
+/// A buffer to read responses into, re-used across the responses
+/// received over a connection.  It grows to the size of the largest
+/// recent response and shrinks slowly once responses get smaller,
+/// such that a connection alternating between tiny (e.g. group
+/// coordinator) and huge (e.g. metadata of a large cluster)
+/// responses does not re-allocate on every response.
+///
+/// Only responses which are decoded into structures of their own are
+/// read into this buffer.  Fetch responses are handed out as they
+/// are, i.e. the messages point into the raw data owned by the
+/// `fetch::Response`, and are thus read into buffers of their own;
+/// see `KafkaClient::fetch_messages_into` for re-using a buffer
+/// across fetches.
+#[derive(Debug, Default)]
+struct ResponseBuffer {
+    buf: Vec<u8>,
+    // ~ the decaying maximum of the sizes of recent responses
+    size_hint: usize,
+}
+
+impl ResponseBuffer {
+    /// Prepares the buffer to receive a response of the given size
+    /// and returns the slice to read it into.
+    fn prepare(&mut self, size: usize) -> &mut [u8] {
+        self.size_hint = size.max(self.size_hint - self.size_hint / RESPONSE_SIZE_DECAY);
+        if self.buf.len() > 2 * self.size_hint {
+            // ~ responses got considerably smaller; release the
+            // memory held beyond the hint
+            self.buf.truncate(self.size_hint);
+            self.buf.shrink_to_fit();
+        }
+        // ~ the buffer is never truncated to the response size such
+        // that only newly grown memory needs initializing
+        if self.buf.len() < size {
+            self.buf.resize(size, 0);
+        }
+        &mut self.buf[..size]
+    }
+
+    #[cfg(test)]
+    fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+}
+
 /// A TCP stream to a remote Kafka broker.
 pub struct KafkaConnection {
     // a surrogate identifier to distinguish between
@@ -327,6 +495,23 @@ pub struct KafkaConnection {
     host: String,
     // the (wrapped) tcp stream
     stream: KafkaStream,
+    // the timeout for completing a single read or write operation;
+    // either `control_timeout` or `data_timeout` depending on the
+    // request last sent
+    rw_timeout: Option<Duration>,
+    control_timeout: Option<Duration>,
+    data_timeout: Option<Duration>,
+    // the maximum size of a response to accept
+    max_response_size: usize,
+    // whether the stream has been shut down due to a protocol error
+    closed: bool,
+    // an overall deadline for i/o operations clamping `rw_timeout`
+    deadline: Option<Instant>,
+    // the time of the last successful read or write on the stream
+    // (or of its establishment)
+    last_activity: Instant,
+    // the buffer responses are read into by `read_response`
+    response_buf: ResponseBuffer,
 }
 
 impl fmt::Debug for KafkaConnection {
@@ -343,13 +528,23 @@ impl fmt::Debug for KafkaConnection {
 
 impl KafkaConnection {
     pub fn send(&mut self, msg: &[u8]) -> Result<usize> {
-        let r = self.stream.write(msg).map_err(From::from);
+        let deadline = self.op_deadline()?;
+        let r = write_all(&mut self.stream, msg, deadline)
+            .map(|()| msg.len())
+            .map_err(From::from);
+        if r.is_ok() {
+            self.last_activity = Instant::now();
+        }
         trace!("Sent {} bytes to: {:?} => {:?}", msg.len(), self, r);
         r
     }
 
     pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
-        let r = (&mut self.stream).read_exact(buf).map_err(From::from);
+        let deadline = self.op_deadline()?;
+        let r = read_exact(&mut self.stream, buf, deadline).map_err(From::from);
+        if r.is_ok() {
+            self.last_activity = Instant::now();
+        }
         trace!("Read {} bytes from: {:?} => {:?}", buf.len(), self, r);
         r
     }
@@ -360,6 +555,87 @@ impl KafkaConnection {
         Ok(buffer)
     }
 
+    /// Reads a response of the given size into this connection's
+    /// re-used response buffer.  The returned data is valid only
+    /// until the next response is read; use `read_exact_alloc` to
+    /// obtain data outliving it.
+    pub fn read_response(&mut self, size: usize) -> Result<&[u8]> {
+        let deadline = self.op_deadline()?;
+        let buf = self.response_buf.prepare(size);
+        let r = read_exact(&mut self.stream, buf, deadline).map_err(From::from);
+        if r.is_ok() {
+            self.last_activity = Instant::now();
+        }
+        trace!("Read {} bytes from: {:?} => {:?}", size, self, r);
+        r.map(|()| &self.response_buf.buf[..size])
+    }
+
+    pub fn max_response_size(&self) -> usize {
+        self.max_response_size
+    }
+
+    /// Applies the data timeout (for produce and fetch requests) or
+    /// the control timeout (for all other requests) to the subsequent
+    /// i/o operations on this connection.
+    pub fn use_data_timeout(&mut self, data: bool) -> Result<()> {
+        let timeout = if data {
+            self.data_timeout
+        } else {
+            self.control_timeout
+        };
+        if timeout != self.rw_timeout {
+            self.stream.set_read_timeout(timeout)?;
+            self.stream.set_write_timeout(timeout)?;
+            self.rw_timeout = timeout;
+        }
+        Ok(())
+    }
+
+    /// Determines for how long this connection has seen no i/o as of
+    /// `now`.
+    pub fn idle_time(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_activity)
+    }
+
+    /// Sets an overall deadline for subsequent i/o operations on this
+    /// connection; `None` lifts a previously set deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        if self.deadline.is_some() && deadline.is_none() {
+            // ~ restore the socket timeouts clamped by `op_deadline`
+            let _ = self.stream.set_read_timeout(self.rw_timeout);
+            let _ = self.stream.set_write_timeout(self.rw_timeout);
+        }
+        self.deadline = deadline;
+    }
+
+    // ~ determines the deadline of an i/o operation starting now.
+    // while an overall deadline is set, the socket timeouts are
+    // clamped to it such that a blocking operation cannot outlast
+    // it.
+    fn op_deadline(&mut self) -> io::Result<Option<Instant>> {
+        let now = Instant::now();
+        let rw_deadline = self.rw_timeout.map(|t| now + t);
+        let Some(deadline) = self.deadline else {
+            return Ok(rw_deadline);
+        };
+        let deadline = rw_deadline.map_or(deadline, |d| d.min(deadline));
+        match deadline.checked_duration_since(now) {
+            Some(left) if !left.is_zero() => {
+                self.stream.set_read_timeout(Some(left))?;
+                self.stream.set_write_timeout(Some(left))?;
+                Ok(Some(deadline))
+            }
+            _ => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+
+    /// Shuts down this connection such that the pool re-establishes
+    /// it on its next use.
+    pub fn close(&mut self) {
+        let _ = self.shutdown();
+        self.closed = true;
+    }
+
     fn shutdown(&mut self) -> Result<()> {
         let r = self.stream.shutdown(Shutdown::Both);
         debug!("Shut down: {:?} => {:?}", self, r);
@@ -378,6 +654,14 @@ impl KafkaConnection {
             id,
             host: host.to_owned(),
             stream,
+            rw_timeout,
+            control_timeout: rw_timeout,
+            data_timeout: rw_timeout,
+            max_response_size: super::DEFAULT_MAX_RESPONSE_SIZE,
+            closed: false,
+            deadline: None,
+            last_activity: Instant::now(),
+            response_buf: ResponseBuffer::default(),
         })
     }
 
@@ -422,3 +706,224 @@ impl KafkaConnection {
         KafkaConnection::from_stream(stream, id, host, rw_timeout)
     }
 }
+
+// --------------------------------------------------------------------
+
+// ~ determines whether an i/o operation failing with the given error
+// is worth retrying given the deadline for the operation to complete.
+// interrupted operations are always retried; operations which would
+// block (e.g. since a socket timeout elapsed) only until the
+// deadline.
+fn is_retriable(e: &io::Error, deadline: Option<Instant>) -> bool {
+    match e.kind() {
+        io::ErrorKind::Interrupted => true,
+        io::ErrorKind::WouldBlock => deadline.map(|d| Instant::now() < d).unwrap_or(true),
+        _ => false,
+    }
+}
+
+// ~ maps a "would block" error surfacing past the deadline to a time
+// out; leaves all other errors untouched
+fn timed_out(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::WouldBlock {
+        io::Error::new(io::ErrorKind::TimedOut, e)
+    } else {
+        e
+    }
+}
+
+// ~ like `Write::write_all` but retrying on transient errors; see
+// `is_retriable`
+fn write_all<W: Write>(w: &mut W, mut buf: &[u8], deadline: Option<Instant>) -> io::Result<()> {
+    while !buf.is_empty() {
+        match w.write(buf) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => buf = &buf[n..],
+            Err(e) if is_retriable(&e, deadline) => {}
+            Err(e) => return Err(timed_out(e)),
+        }
+    }
+    Ok(())
+}
+
+// ~ like `Read::read_exact` but retrying on transient errors; see
+// `is_retriable`.  a closed connection is reported as
+// `UnexpectedEof`.
+fn read_exact<R: Read>(r: &mut R, mut buf: &mut [u8], deadline: Option<Instant>) -> io::Result<()> {
+    while !buf.is_empty() {
+        match r.read(buf) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => buf = &mut buf[n..],
+            Err(e) if is_retriable(&e, deadline) => {}
+            Err(e) => return Err(timed_out(e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::time::{Duration, Instant};
+
+    use super::{read_exact, write_all, ResponseBuffer};
+
+    // ~ a stream replaying the given outcomes of read/write calls; a
+    // successful outcome transfers at most the given number of bytes
+    struct MockStream {
+        outcomes: VecDeque<io::Result<usize>>,
+        written: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(outcomes: Vec<io::Result<usize>>) -> Self {
+            MockStream {
+                outcomes: outcomes.into(),
+                written: Vec::new(),
+            }
+        }
+
+        fn next(&mut self, len: usize) -> io::Result<usize> {
+            self.outcomes
+                .pop_front()
+                .unwrap_or(Ok(0))
+                .map(|n| n.min(len))
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.next(buf.len())?;
+            for (i, b) in buf[..n].iter_mut().enumerate() {
+                *b = i as u8;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = self.next(buf.len())?;
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn err(kind: io::ErrorKind) -> io::Result<usize> {
+        Err(kind.into())
+    }
+
+    #[test]
+    fn test_retry_interrupted() {
+        let mut s = MockStream::new(vec![err(io::ErrorKind::Interrupted), Ok(2), Ok(10)]);
+        let mut buf = [0xffu8; 4];
+        read_exact(&mut s, &mut buf, None).unwrap();
+        assert_eq!([0, 1, 0, 1], buf);
+
+        let mut s = MockStream::new(vec![Ok(1), err(io::ErrorKind::Interrupted), Ok(10)]);
+        write_all(&mut s, b"abc", None).unwrap();
+        assert_eq!(b"abc", &s.written[..]);
+    }
+
+    #[test]
+    fn test_retry_would_block_until_deadline() {
+        let deadline = Some(Instant::now() + Duration::from_secs(60));
+        let mut s = MockStream::new(vec![err(io::ErrorKind::WouldBlock), Ok(4)]);
+        read_exact(&mut s, &mut [0u8; 4], deadline).unwrap();
+
+        // ~ past the deadline the operation times out
+        let deadline = Some(Instant::now());
+        let mut s = MockStream::new(vec![err(io::ErrorKind::WouldBlock), Ok(4)]);
+        let e = write_all(&mut s, b"abcd", deadline).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, e.kind());
+    }
+
+    #[test]
+    fn test_response_buffer_reuse() {
+        let mut buf = ResponseBuffer::default();
+        buf.prepare(10).fill(1);
+        // ~ a large response following a small one grows the buffer
+        let data = buf.prepare(100_000);
+        assert_eq!(100_000, data.len());
+        data.fill(2);
+        let ptr = data.as_ptr();
+
+        // ~ subsequent responses of either size are read into the
+        // very same memory
+        for (i, size) in [(3, 10), (4, 100_000), (5, 20), (6, 50_000)] {
+            let data = buf.prepare(size);
+            assert_eq!((size, ptr), (data.len(), data.as_ptr()));
+            data.fill(i);
+            assert!(buf.buf[..size].iter().all(|&b| b == i));
+        }
+    }
+
+    #[test]
+    fn test_response_buffer_adapts() {
+        let mut buf = ResponseBuffer::default();
+        buf.prepare(100_000);
+        let capacity = buf.capacity();
+        assert!(capacity >= 100_000);
+
+        // ~ smaller responses in between large ones don't re-allocate
+        for _ in 0..3 {
+            buf.prepare(100);
+            assert_eq!(capacity, buf.capacity());
+        }
+        buf.prepare(100_000);
+        assert_eq!(capacity, buf.capacity());
+
+        // ~ persistently small responses eventually release the memory
+        for _ in 0..1_000 {
+            buf.prepare(100);
+        }
+        assert!(buf.capacity() < 1_000);
+    }
+
+    #[test]
+    fn test_no_retry_on_disconnect() {
+        let mut s = MockStream::new(vec![Ok(2)]);
+        let e = read_exact(&mut s, &mut [0u8; 4], None).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, e.kind());
+
+        let mut s = MockStream::new(vec![err(io::ErrorKind::ConnectionReset), Ok(4)]);
+        let e = write_all(&mut s, b"abcd", None).unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionReset, e.kind());
+    }
+
+    #[cfg(feature = "nightly")]
+    mod benches {
+        use test::{black_box, Bencher};
+
+        use super::super::ResponseBuffer;
+
+        // ~ a mix of small metadata/offset like responses and large
+        // fetch like responses
+        const SIZES: &[usize] = &[120, 64, 1 << 20, 200, 96, 4 << 20, 150, 1 << 20];
+
+        #[bench]
+        fn bench_response_alloc_mixed(b: &mut Bencher) {
+            b.iter(|| {
+                for &size in SIZES {
+                    let buf = black_box(vec![0u8; size]);
+                    black_box(buf.len());
+                }
+            });
+        }
+
+        #[bench]
+        fn bench_response_buffer_mixed(b: &mut Bencher) {
+            let mut buf = ResponseBuffer::default();
+            b.iter(|| {
+                for &size in SIZES {
+                    black_box(buf.prepare(size).len());
+                }
+            });
+        }
+    }
+}
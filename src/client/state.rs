@@ -1,17 +1,31 @@
 use std::collections::hash_map::{Entry, HashMap, Keys};
 use std::convert::AsRef;
+use std::fmt;
 use std::slice;
 use std::u32;
 
-use crate::error::Result;
+use crate::error::{KafkaCode, Result};
 use crate::protocol;
 
+/// A user supplied source of correlation ids; see
+/// `KafkaClient::set_correlation_id_generator`.
+pub struct CorrelationIdGenerator(pub Box<dyn Fn() -> i32 + Send + Sync>);
+
+impl fmt::Debug for CorrelationIdGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CorrelationIdGenerator")
+    }
+}
+
 #[derive(Debug)]
 pub struct ClientState {
     // ~ the last correlation used when communicating with kafka
     // (see `#next_correlation_id`)
     correlation: i32,
 
+    // ~ replaces the built-in sequence of correlation ids if present
+    correlation_generator: Option<CorrelationIdGenerator>,
+
     // ~ a list of known brokers referred to by the index in this
     // vector.  This index is also referred to as `BrokerRef` and is
     // enforced by this module.
@@ -26,6 +40,18 @@ pub struct ClientState {
 
     // ~ a mapping of groups to their coordinators
     group_coordinators: HashMap<String, BrokerRef>,
+
+    // ~ the id of the cluster as reported by the last loaded
+    // metadata (available with metadata versions 2+)
+    cluster_id: Option<String>,
+
+    // ~ the node id of the controller broker as reported by the last
+    // loaded metadata (available with metadata versions 1+)
+    controller_id: Option<i32>,
+
+    // ~ the errors reported for topics by the last metadata load
+    // including them
+    topic_errors: HashMap<String, KafkaCode>,
 }
 
 // --------------------------------------------------------------------
@@ -41,6 +67,9 @@ pub struct Broker {
     /// "host:port" of this broker. This information is advertised by
     /// and originating from Kafka cluster itself.
     host: String,
+    /// The rack of this broker as advertised by the Kafka cluster
+    /// (available with metadata versions 1+)
+    rack: Option<String>,
 }
 
 impl Broker {
@@ -56,6 +85,14 @@ impl Broker {
     pub fn host(&self) -> &str {
         &self.host
     }
+
+    /// Retrieves the rack of this Kafka broker - if any.  This is
+    /// available only when using `MetadataVersion::V1` or later.  See
+    /// `KafkaClient::set_metadata_version`.
+    #[inline]
+    pub fn rack(&self) -> Option<&str> {
+        self.rack.as_deref()
+    }
 }
 
 // See `Brokerref`
@@ -150,15 +187,35 @@ impl<'a> IntoIterator for &'a TopicPartitions {
 #[derive(Debug)]
 pub struct TopicPartition {
     broker: BrokerRef,
+    leader_epoch: Option<i32>,
+    // ~ node ids of the brokers replicating this partition
+    replicas: Vec<i32>,
+    // ~ node ids of the replicas currently in sync with the leader
+    isr: Vec<i32>,
 }
 
 impl TopicPartition {
     fn new() -> TopicPartition {
         TopicPartition {
             broker: BrokerRef::new(UNKNOWN_BROKER_INDEX),
+            leader_epoch: None,
+            replicas: Vec::new(),
+            isr: Vec::new(),
         }
     }
 
+    pub fn leader_epoch(&self) -> Option<i32> {
+        self.leader_epoch
+    }
+
+    pub fn replicas(&self) -> &[i32] {
+        &self.replicas
+    }
+
+    pub fn isr(&self) -> &[i32] {
+        &self.isr
+    }
+
     pub fn broker<'a>(&self, state: &'a ClientState) -> Option<&'a Broker> {
         state.brokers.get(self.broker.index())
     }
@@ -209,9 +266,13 @@ impl ClientState {
     pub fn new() -> Self {
         ClientState {
             correlation: 0,
+            correlation_generator: None,
             brokers: Vec::new(),
             topic_partitions: HashMap::new(),
             group_coordinators: HashMap::new(),
+            cluster_id: None,
+            controller_id: None,
+            topic_errors: HashMap::new(),
         }
     }
 
@@ -237,6 +298,10 @@ impl ClientState {
     }
 
     // exposed for the sake of the metadata module
+    pub fn brokers(&self) -> &[Broker] {
+        &self.brokers
+    }
+
     pub fn topic_partitions(&self) -> &HashMap<String, TopicPartitions> {
         &self.topic_partitions
     }
@@ -245,11 +310,30 @@ impl ClientState {
         self.topic_partitions.get(topic)
     }
 
+    pub fn cluster_id(&self) -> Option<&str> {
+        self.cluster_id.as_deref()
+    }
+
+    pub fn controller_id(&self) -> Option<i32> {
+        self.controller_id
+    }
+
+    pub fn topic_error(&self, topic: &str) -> Option<KafkaCode> {
+        self.topic_errors.get(topic).copied()
+    }
+
     pub fn next_correlation_id(&mut self) -> i32 {
+        if let Some(CorrelationIdGenerator(ref generate)) = self.correlation_generator {
+            return generate();
+        }
         self.correlation = (self.correlation + 1) % (1i32 << 30);
         self.correlation
     }
 
+    pub fn set_correlation_generator(&mut self, generator: Option<CorrelationIdGenerator>) {
+        self.correlation_generator = generator;
+    }
+
     pub fn find_broker<'a>(&'a self, topic: &str, partition_id: i32) -> Option<&'a str> {
         self.topic_partitions
             .get(topic)
@@ -258,25 +342,45 @@ impl ClientState {
             .map(|b| &b.host[..])
     }
 
+    /// Forgets all metadata about the specified topic - if any.
+    pub fn remove_topic(&mut self, topic: &str) {
+        self.topic_partitions.remove(topic);
+    }
+
     /// Clears all metadata.
     pub fn clear_metadata(&mut self) {
         // ~ important to clear both since one references the other
         // through `BrokerIndex`
         self.topic_partitions.clear();
         self.brokers.clear();
+        self.cluster_id = None;
+        self.controller_id = None;
+        self.topic_errors.clear();
     }
 
     /// Loads new and updates existing metadata from the given
     /// metadata response.
-    pub fn update_metadata(&mut self, md: protocol::MetadataResponse) -> Result<()> {
+    pub fn update_metadata(&mut self, mut md: protocol::MetadataResponse) -> Result<()> {
         debug!("updating metadata from: {:?}", md);
 
+        self.cluster_id = md.cluster_id.take();
+        self.controller_id = md.controller_id;
+
         // ~ register new brokers with self.brokers and obtain an
         // index over them by broker-node-id
         let brokers = self.update_brokers(&md);
 
         // ~ now update partitions
         for t in md.topics {
+            match t.error_code() {
+                Some(e) => {
+                    debug!("metadata for topic '{}' reported error: {:?}", t.topic, e);
+                    self.topic_errors.insert(t.topic.clone(), e);
+                }
+                None => {
+                    self.topic_errors.remove(&t.topic);
+                }
+            }
             // ~ get a mutable reference to the partitions vector
             // (maintained in self.topic_partitions) for the topic
             let tps = match self.topic_partitions.entry(t.topic) {
@@ -308,6 +412,9 @@ impl ClientState {
                 } else {
                     tp.broker.set_unknown()
                 }
+                tp.leader_epoch = partition.leader_epoch;
+                tp.replicas = partition.replicas;
+                tp.isr = partition.isr;
             }
         }
         Ok(())
@@ -335,6 +442,9 @@ impl ClientState {
                     if b.host != broker_host {
                         b.host = broker_host;
                     }
+                    if b.rack != broker.rack {
+                        b.rack.clone_from(&broker.rack);
+                    }
                 }
                 Entry::Vacant(e) => {
                     // ~ insert the new broker
@@ -342,6 +452,7 @@ impl ClientState {
                     self.brokers.push(Broker {
                         node_id: broker.node_id,
                         host: broker_host,
+                        rack: broker.rack.clone(),
                     });
                     // ~ track the pushed broker's index
                     e.insert(BrokerRef::new(new_index as u32));
@@ -401,6 +512,7 @@ impl ClientState {
             self.brokers.push(Broker {
                 node_id: gc.broker_id,
                 host: group_host,
+                rack: None,
             });
         }
         if let Some(br) = self.group_coordinators.get_mut(group) {
@@ -426,6 +538,8 @@ mod tests {
             leader,
             replicas: vec![],
             isr: vec![],
+            offline_replicas: vec![],
+            leader_epoch: None,
         }
     }
 
@@ -439,27 +553,33 @@ mod tests {
     fn metadata_response_initial() -> protocol::MetadataResponse {
         protocol::MetadataResponse {
             header: protocol::HeaderResponse { correlation: 1 },
+            cluster_id: None,
+            controller_id: None,
             brokers: vec![
                 md::BrokerMetadata {
                     node_id: 10,
                     host: "gin1.dev".to_owned(),
                     port: 1234,
+                    rack: None,
                 },
                 md::BrokerMetadata {
                     node_id: 50,
                     host: "gin2.dev".to_owned(),
                     port: 9876,
+                    rack: None,
                 },
                 md::BrokerMetadata {
                     node_id: 30,
                     host: "gin3.dev".to_owned(),
                     port: 9092,
+                    rack: None,
                 },
             ],
             topics: vec![
                 md::TopicMetadata {
                     error: 0,
                     topic: "tee-one".to_owned(),
+                    is_internal: false,
                     partitions: vec![
                         new_partition(0, 50),
                         new_partition(1, 10),
@@ -471,6 +591,7 @@ mod tests {
                 md::TopicMetadata {
                     error: 0,
                     topic: "tee-two".to_owned(),
+                    is_internal: false,
                     partitions: vec![
                         new_partition(0, 30),
                         new_partition(1, -1),
@@ -481,6 +602,7 @@ mod tests {
                 md::TopicMetadata {
                     error: 0,
                     topic: "tee-three".to_owned(),
+                    is_internal: false,
                     partitions: vec![],
                 },
             ],
@@ -510,6 +632,8 @@ mod tests {
     }
 
     fn assert_initial_metadata_load(state: &ClientState) {
+        assert_eq!(None, state.cluster_id());
+        assert_eq!(None, state.controller_id());
         assert_eq!(
             vec!["tee-one", "tee-three", "tee-two"],
             sorted(state.topic_names().collect::<Vec<_>>())
@@ -555,11 +679,14 @@ mod tests {
     fn metadata_response_update() -> protocol::MetadataResponse {
         protocol::MetadataResponse {
             header: protocol::HeaderResponse { correlation: 2 },
+            cluster_id: Some("cluster-1".to_owned()),
+            controller_id: Some(10),
             brokers: vec![
                 md::BrokerMetadata {
                     node_id: 10,
                     host: "gin1.dev".to_owned(),
                     port: 1234,
+                    rack: None,
                 },
                 // note: compared to the initial metadata
                 // response this broker moved to a different
@@ -568,17 +695,20 @@ mod tests {
                     node_id: 50,
                     host: "aladin1.dev".to_owned(),
                     port: 9091,
+                    rack: None,
                 },
                 md::BrokerMetadata {
                     node_id: 30,
                     host: "gin3.dev".to_owned(),
                     port: 9092,
+                    rack: None,
                 },
             ],
             // metadata for topic "tee-two" only
             topics: vec![md::TopicMetadata {
                 error: 0,
                 topic: "tee-two".to_owned(),
+                is_internal: false,
                 partitions: vec![
                     new_partition(0, 10),
                     new_partition(1, 10),
@@ -591,6 +721,8 @@ mod tests {
     }
 
     fn assert_updated_metadata_load(state: &ClientState) {
+        assert_eq!(Some("cluster-1"), state.cluster_id());
+        assert_eq!(Some(10), state.controller_id());
         assert_eq!(
             vec!["tee-one", "tee-three", "tee-two"],
             sorted(state.topic_names().collect::<Vec<_>>())
@@ -634,6 +766,24 @@ mod tests {
         assert_partitions(state, "tee-three", &[]);
     }
 
+    #[test]
+    fn test_loading_leader_epoch() {
+        let mut md = metadata_response_initial();
+        md.topics[0].partitions[1].leader_epoch = Some(7);
+
+        let mut state = ClientState::new();
+        state.update_metadata(md).unwrap();
+        let ps = state.partitions_for("tee-one").unwrap();
+        assert_eq!(None, ps.partition(0).unwrap().leader_epoch());
+        assert_eq!(Some(7), ps.partition(1).unwrap().leader_epoch());
+
+        // ~ a refresh without epochs (e.g. older metadata versions)
+        // must not keep stale epochs around
+        state.update_metadata(metadata_response_initial()).unwrap();
+        let ps = state.partitions_for("tee-one").unwrap();
+        assert_eq!(None, ps.partition(1).unwrap().leader_epoch());
+    }
+
     #[test]
     fn test_loading_metadata() {
         let mut state = ClientState::new();
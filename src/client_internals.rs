@@ -1,6 +1,8 @@
 //! A crate private module to expose `KafkaClient` internals for use
 //! within this crate but not outside of it.
 
+use std::time::Instant;
+
 use crate::client::ProduceMessage;
 use crate::error::Result;
 use crate::producer::ProduceConfirm;
@@ -15,4 +17,15 @@ pub trait KafkaClientInternals {
     where
         J: AsRef<ProduceMessage<'a, 'b>>,
         I: IntoIterator<Item = J>;
+
+    fn internal_produce_messages_deadline<'a, 'b, I, J>(
+        &mut self,
+        deadline: Instant,
+        required_acks: i16,
+        ack_timeout: i32,
+        messages: I,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        J: AsRef<ProduceMessage<'a, 'b>>,
+        I: IntoIterator<Item = J>;
 }
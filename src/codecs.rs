@@ -200,6 +200,26 @@ impl FromByte for String {
     }
 }
 
+// ~ a nullable string; a negative length denotes `None`
+impl FromByte for Option<String> {
+    type R = Option<String>;
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        let mut length: i16 = 0;
+        length.decode(buffer)?;
+        if length < 0 {
+            *self = None;
+            return Ok(());
+        }
+        let mut s = String::with_capacity(length as usize);
+        let _ = buffer.take(length as u64).read_to_string(&mut s);
+        if s.len() != length as usize {
+            return Err(Error::UnexpectedEOF);
+        }
+        *self = Some(s);
+        Ok(())
+    }
+}
+
 impl<V: FromByte + Default> FromByte for Vec<V> {
     type R = Vec<V>;
 
@@ -247,6 +267,109 @@ impl FromByte for Vec<u8> {
     }
 }
 
+// --------------------------------------------------------------------
+// ~ "flexible version" encodings (KIP-482) used by newer apis only
+
+/// Decodes an unsigned variable length integer.
+pub fn decode_uvarint<T: Read>(buffer: &mut T) -> Result<u32> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let b = buffer.read_u8()?;
+        value |= u32::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::CodecError)
+}
+
+/// Encodes an unsigned variable length integer.
+#[allow(clippy::cast_possible_truncation)]
+pub fn encode_uvarint<T: Write>(buffer: &mut T, mut value: u32) -> Result<()> {
+    while value >= 0x80 {
+        buffer.write_u8((value & 0x7f) as u8 | 0x80)?;
+        value >>= 7;
+    }
+    buffer.write_u8(value as u8).map_err(From::from)
+}
+
+/// Encodes a compact (non-nullable) string.
+pub fn encode_compact_string<T: Write>(buffer: &mut T, s: &str) -> Result<()> {
+    encode_compact_array_len(buffer, s.len())?;
+    buffer.write_all(s.as_bytes()).map_err(From::from)
+}
+
+/// Encodes the length of a compact (non-null) array as an unsigned
+/// varint of the actual length plus one.
+pub fn encode_compact_array_len<T: Write>(buffer: &mut T, len: usize) -> Result<()> {
+    let n = u32::try_from(len)
+        .ok()
+        .and_then(|n| n.checked_add(1))
+        .ok_or(Error::CodecError)?;
+    encode_uvarint(buffer, n)
+}
+
+/// Decodes a compact nullable string; its length is encoded as an
+/// unsigned varint of the actual length plus one, with zero denoting
+/// `None`.
+pub fn decode_compact_nullable_string<T: Read>(buffer: &mut T) -> Result<Option<String>> {
+    let length = match decode_uvarint(buffer)? {
+        0 => return Ok(None),
+        n => u64::from(n - 1),
+    };
+    let mut s = String::new();
+    buffer.take(length).read_to_string(&mut s)?;
+    if s.len() as u64 != length {
+        return Err(Error::UnexpectedEOF);
+    }
+    Ok(Some(s))
+}
+
+/// Decodes a compact (non-nullable) string.
+pub fn decode_compact_string<T: Read>(buffer: &mut T) -> Result<String> {
+    decode_compact_nullable_string(buffer)?.ok_or(Error::CodecError)
+}
+
+/// Decodes the length of a compact array; zero denotes a null array
+/// which we treat as empty.
+pub fn decode_compact_array_len<T: Read>(buffer: &mut T) -> Result<usize> {
+    Ok(decode_uvarint(buffer)?.saturating_sub(1) as usize)
+}
+
+/// Skips over a set of tagged fields.  None of the fields we
+/// understand are tagged, hence all are ignored.
+pub fn skip_tagged_fields<T: Read>(buffer: &mut T) -> Result<()> {
+    for _ in 0..decode_uvarint(buffer)? {
+        let _tag = decode_uvarint(buffer)?;
+        let size = u64::from(decode_uvarint(buffer)?);
+        if std::io::copy(&mut buffer.take(size), &mut std::io::sink())? != size {
+            return Err(Error::UnexpectedEOF);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn codec_uvarint() {
+    use std::io::Cursor;
+    assert_eq!(0, decode_uvarint(&mut Cursor::new(&[0u8])).unwrap());
+    assert_eq!(1, decode_uvarint(&mut Cursor::new(&[1u8])).unwrap());
+    assert_eq!(
+        300,
+        decode_uvarint(&mut Cursor::new(&[0xac, 0x02])).unwrap()
+    );
+    assert!(decode_uvarint(&mut Cursor::new(&[0x80u8])).is_err());
+
+    for n in [0, 1, 127, 128, 300, u32::MAX] {
+        let mut buf = Vec::new();
+        encode_uvarint(&mut buf, n).unwrap();
+        assert_eq!(n, decode_uvarint(&mut Cursor::new(buf)).unwrap());
+    }
+    let mut buf = Vec::new();
+    encode_compact_string(&mut buf, "abc").unwrap();
+    assert_eq!(vec![4, b'a', b'b', b'c'], buf);
+}
+
 #[test]
 fn codec_i8() {
     use std::io::Cursor;
@@ -347,6 +470,41 @@ fn codec_string() {
     assert_eq!(dec2, orig);
 }
 
+#[test]
+fn codec_nullable_string() {
+    use std::io::Cursor;
+
+    // ~ null
+    let mut buf = Vec::new();
+    (-1i16).encode(&mut buf).unwrap();
+    assert_eq!(
+        None,
+        Option::<String>::decode_new(&mut Cursor::new(buf)).unwrap()
+    );
+
+    // ~ empty
+    let mut buf = Vec::new();
+    "".encode(&mut buf).unwrap();
+    assert_eq!(
+        Some(String::new()),
+        Option::<String>::decode_new(&mut Cursor::new(buf)).unwrap()
+    );
+
+    // ~ non-empty
+    let mut buf = Vec::new();
+    "abc".encode(&mut buf).unwrap();
+    assert_eq!(
+        Some("abc".to_owned()),
+        Option::<String>::decode_new(&mut Cursor::new(buf)).unwrap()
+    );
+
+    // ~ truncated
+    let mut buf = Vec::new();
+    "abc".encode(&mut buf).unwrap();
+    buf.pop();
+    assert!(Option::<String>::decode_new(&mut Cursor::new(buf)).is_err());
+}
+
 #[test]
 fn codec_vec_u8() {
     use std::io::Cursor;
@@ -4,7 +4,7 @@ use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 
-use crate::error::Result;
+use crate::error::{Error, KafkaCode, Result};
 
 pub fn compress(src: &[u8]) -> Result<Vec<u8>> {
     let mut e = GzEncoder::new(Vec::new(), Compression::best());
@@ -14,14 +14,21 @@ pub fn compress(src: &[u8]) -> Result<Vec<u8>> {
     Ok(compressed_bytes)
 }
 
-pub fn uncompress<T: Read>(src: T) -> Result<Vec<u8>> {
-    let mut d = GzDecoder::new(src);
+/// Decompresses the given gzip stream failing with
+/// `KafkaCode::MessageSizeTooLarge` as soon as the decompressed data
+/// exceeds `max_size` bytes; never buffering more than that.
+pub fn uncompress_max<T: Read>(src: T, max_size: usize) -> Result<Vec<u8>> {
+    let limit = u64::try_from(max_size)
+        .unwrap_or(u64::MAX)
+        .saturating_add(1);
+    let mut d = GzDecoder::new(src).take(limit);
 
     let mut buffer: Vec<u8> = Vec::new();
-    match d.read_to_end(&mut buffer) {
-        Err(err) => Err(From::from(err)),
-        Ok(_) => Ok(buffer),
+    d.read_to_end(&mut buffer)?;
+    if buffer.len() > max_size {
+        return Err(Error::Kafka(KafkaCode::MessageSizeTooLarge));
     }
+    Ok(buffer)
 }
 
 #[test]
@@ -32,7 +39,8 @@ fn test_uncompress() {
         31, 139, 8, 0, 192, 248, 79, 85, 2, 255, 43, 73, 45, 46, 1, 0, 12, 126, 127, 216, 4, 0, 0,
         0,
     ];
-    let uncomp_msg = String::from_utf8(uncompress(Cursor::new(msg)).unwrap()).unwrap();
+    let uncomp_msg =
+        String::from_utf8(uncompress_max(Cursor::new(msg), usize::MAX).unwrap()).unwrap();
     assert_eq!(&uncomp_msg[..], "test");
 }
 
@@ -43,6 +51,23 @@ fn test_uncompress_panic() {
     let msg: Vec<u8> = vec![
         12, 42, 84, 104, 105, 115, 32, 105, 115, 32, 116, 101, 115, 116,
     ];
-    let uncomp_msg = String::from_utf8(uncompress(Cursor::new(msg)).unwrap()).unwrap();
+    let uncomp_msg =
+        String::from_utf8(uncompress_max(Cursor::new(msg), usize::MAX).unwrap()).unwrap();
     assert_eq!(&uncomp_msg[..], "This is test");
 }
+
+#[test]
+fn test_uncompress_max() {
+    let data = vec![b'x'; 1024 * 1024];
+    let msg = compress(&data).unwrap();
+    assert!(msg.len() < 64 * 1024);
+
+    assert_eq!(data, uncompress_max(&msg[..], data.len()).unwrap());
+    match uncompress_max(&msg[..], 64 * 1024) {
+        Err(Error::Kafka(KafkaCode::MessageSizeTooLarge)) => {}
+        r => panic!(
+            "Expected MessageSizeTooLarge, but got: {:?}",
+            r.map(|v| v.len())
+        ),
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::{Error, Result};
+
 #[cfg(feature = "gzip")]
 pub mod gzip;
 
@@ -7,7 +9,11 @@ pub mod snappy;
 /// Compression types supported by kafka. The numeral values of this
 /// enumeration correspond to the compression encoding in the
 /// attributes of a Message in the protocol.
-#[derive(Debug, Copy, Clone)]
+///
+/// Note: `zstd` (4) is not available since brokers accept it only in
+/// the v2 record batch format (Kafka 2.1+), while this client
+/// produces and consumes the older message set format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Compression {
     NONE = 0,
     #[cfg(feature = "gzip")]
@@ -21,3 +27,89 @@ impl Default for Compression {
         Compression::NONE
     }
 }
+
+impl Compression {
+    /// Determines the codec of a message (set) or record batch from
+    /// its attributes, i.e. their lowest three bits, without decoding
+    /// any of the compressed data.  The attributes of the older
+    /// message format (an `i8`) are to be passed on widened.
+    ///
+    /// Unlike `Compression`, the result names all codecs known to the
+    /// protocol regardless of whether this client can handle them;
+    /// see `Codec::compression`.
+    pub fn detect(attributes: i16) -> Codec {
+        match attributes & 0x07 {
+            0 => Codec::None,
+            1 => Codec::Gzip,
+            2 => Codec::Snappy,
+            3 => Codec::Lz4,
+            4 => Codec::Zstd,
+            id => Codec::Unknown(id),
+        }
+    }
+}
+
+/// The compression codec of a message (set) or record batch as
+/// determined by `Compression::detect`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+    /// A codec id not defined by the protocol (5 to 7.)
+    Unknown(i16),
+}
+
+impl Codec {
+    /// Retrieves the `Compression` corresponding to this codec.
+    ///
+    /// Fails with `Error::UnsupportedCompression` for codecs this
+    /// client cannot handle, i.e. `lz4` and `zstd` as well as `gzip`
+    /// and `snappy` if the corresponding crate feature is not enabled.
+    pub fn compression(self) -> Result<Compression> {
+        match self {
+            Codec::None => Ok(Compression::NONE),
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Ok(Compression::GZIP),
+            #[cfg(feature = "snappy")]
+            Codec::Snappy => Ok(Compression::SNAPPY),
+            _ => Err(Error::UnsupportedCompression),
+        }
+    }
+}
+
+#[test]
+fn test_detect() {
+    let codecs = [
+        (0, Codec::None),
+        (1, Codec::Gzip),
+        (2, Codec::Snappy),
+        (3, Codec::Lz4),
+        (4, Codec::Zstd),
+        (5, Codec::Unknown(5)),
+    ];
+    for (attributes, codec) in codecs {
+        assert_eq!(codec, Compression::detect(attributes));
+    }
+    // ~ bits other than the codec's are ignored, e.g. the timestamp
+    // type or the transactional flag of record batches
+    assert_eq!(Codec::None, Compression::detect(0x08 | 0x10));
+    assert_eq!(Codec::Zstd, Compression::detect(0x18 | 4));
+}
+
+#[test]
+fn test_codec_compression() {
+    assert_eq!(Compression::NONE, Codec::None.compression().unwrap());
+    #[cfg(feature = "gzip")]
+    assert_eq!(Compression::GZIP, Codec::Gzip.compression().unwrap());
+    #[cfg(feature = "snappy")]
+    assert_eq!(Compression::SNAPPY, Codec::Snappy.compression().unwrap());
+    for codec in [Codec::Lz4, Codec::Zstd, Codec::Unknown(7)] {
+        assert!(matches!(
+            codec.compression(),
+            Err(Error::UnsupportedCompression)
+        ));
+    }
+}
@@ -3,7 +3,7 @@ use std::io::{self, Read};
 use byteorder::{BigEndian, ByteOrder};
 use snap;
 
-use crate::{Error, Result};
+use crate::error::{Error, KafkaCode, Result};
 
 pub fn compress(src: &[u8]) -> Result<Vec<u8>> {
     let mut buf = vec![0; snap::raw::max_compress_len(src.len())];
@@ -84,6 +84,15 @@ fn test_validate_stream() {
     assert_eq!(rest, &[0x56]);
 }
 
+/// Decompresses the given stream of snappy compressed chunks failing
+/// with `KafkaCode::MessageSizeTooLarge` as soon as the decompressed
+/// data would exceed `max_size` bytes; never buffering more than that.
+pub fn uncompress_max(src: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    SnappyReader::new(src)?.read_to_end_max(&mut buffer, max_size)?;
+    Ok(buffer)
+}
+
 // ~ An implementation of a reader over a stream of snappy compressed
 // chunks as produced by org.xerial.snappy.SnappyOutputStream
 // (https://github.com/xerial/snappy-java/ version: 1.1.1.*)
@@ -148,10 +157,20 @@ impl<'a> SnappyReader<'a> {
     }
 
     fn _read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        self.read_to_end_max(buf, usize::MAX)
+    }
+
+    // ~ reads the rest of the stream into `buf` failing with
+    // `KafkaCode::MessageSizeTooLarge` before decompressing a chunk
+    // which would make the read data exceed `max_size` bytes
+    fn read_to_end_max(&mut self, buf: &mut Vec<u8>, max_size: usize) -> Result<usize> {
         let init_len = buf.len();
         // ~ first consume already uncompressed and unconsumed data - if any
         if self.uncompressed_pos < self.uncompressed_chunk.len() {
             let rest = &self.uncompressed_chunk[self.uncompressed_pos..];
+            if rest.len() > max_size {
+                return Err(Error::Kafka(KafkaCode::MessageSizeTooLarge));
+            }
             buf.extend_from_slice(rest);
             self.uncompressed_pos += rest.len();
         }
@@ -165,6 +184,10 @@ impl<'a> SnappyReader<'a> {
                 }));
             }
             let (c1, c2) = self.compressed_data.split_at(chunk_size as usize);
+            let read = buf.len() - init_len;
+            if snap::raw::decompress_len(c1)? > max_size - read {
+                return Err(Error::Kafka(KafkaCode::MessageSizeTooLarge));
+            }
             uncompress_to(c1, buf)?;
             self.compressed_data = c2;
         }
@@ -201,8 +224,8 @@ mod tests {
     use std::io::Read;
     use std::str;
 
-    use super::{compress, uncompress_to, SnappyReader};
-    use crate::error::{Error, Result};
+    use super::{compress, uncompress_max, uncompress_to, SnappyReader};
+    use crate::error::{Error, KafkaCode, Result};
 
     fn uncompress(src: &[u8]) -> Result<Vec<u8>> {
         let mut v = Vec::new();
@@ -272,4 +295,14 @@ mod tests {
         r.read_to_end(&mut buf).unwrap();
         assert_eq!(ORIGINAL, str::from_utf8(&buf[..]).unwrap());
     }
+
+    #[test]
+    fn test_uncompress_max() {
+        let buf = uncompress_max(COMPRESSED, ORIGINAL.len()).unwrap();
+        assert_eq!(ORIGINAL, str::from_utf8(&buf[..]).unwrap());
+        match uncompress_max(COMPRESSED, ORIGINAL.len() - 1) {
+            Err(Error::Kafka(KafkaCode::MessageSizeTooLarge)) => {}
+            r => panic!("Expected MessageSizeTooLarge, but got: {:?}", r),
+        }
+    }
 }
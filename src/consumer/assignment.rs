@@ -1,5 +1,6 @@
 //! Encapsulates topic partition assignments to a consumer. Once
-//! constructed these assignments are not modified.
+//! constructed, existing assignments are not modified; new ones can
+//! only be appended such that `AssignmentRef`s stay valid.
 
 use std::collections::HashMap;
 use std::ops::Index;
@@ -42,10 +43,24 @@ impl Assignments {
 
     pub fn topic_ref(&self, topic: &str) -> Option<AssignmentRef> {
         self.0
-            .binary_search_by(|x| x.topic.as_str().cmp(topic))
-            .ok()
+            .iter()
+            .position(|x| x.topic == topic)
             .map(|i| AssignmentRef(i as u32))
     }
+
+    /// Appends an assignment to consume all available partitions of
+    /// the given topic unless the topic is already assigned.  Returns
+    /// a reference to the topic's assignment.
+    pub fn add_topic(&mut self, topic: &str) -> AssignmentRef {
+        if let Some(r) = self.topic_ref(topic) {
+            return r;
+        }
+        self.0.push(Assignment {
+            topic: topic.to_owned(),
+            partitions: Vec::new(),
+        });
+        AssignmentRef((self.0.len() - 1) as u32)
+    }
 }
 
 impl Index<AssignmentRef> for Assignments {
@@ -62,8 +77,7 @@ pub fn from_map(src: HashMap<String, Vec<i32>>) -> Assignments {
         partitions.dedup();
         xs.push(Assignment { topic, partitions });
     }
-    // ~ sort by topic such has we can apply binary search by that
-    // attribute later
+    // ~ sort by topic for a deterministic order
     xs.sort_by(|a, b| a.topic.cmp(&b.topic));
     Assignments(xs)
 }
@@ -1,3 +1,4 @@
+use std::cmp;
 use std::collections::HashMap;
 use std::time::Duration;
 
@@ -5,9 +6,21 @@ use crate::client::{self, FetchOffset, GroupOffsetStorage, KafkaClient};
 use crate::error::{Error, Result};
 
 use super::assignment;
-use super::config::Config;
+use super::config::{AdaptiveFetch, Config};
 use super::state::State;
-use super::{Consumer, DEFAULT_FALLBACK_OFFSET, DEFAULT_RETRY_MAX_BYTES_LIMIT};
+use super::typed::{self, BytesDeserializer, Deserializer, TypedBuilder};
+use super::{
+    ConsumeInterceptor, Consumer, OffsetResetPolicy, TruncationListener, DEFAULT_FALLBACK_OFFSET,
+    DEFAULT_MANUAL_ACK, DEFAULT_OFFSET_RESET_POLICY, DEFAULT_RETRY_MAX_BYTES_LIMIT,
+    DEFAULT_SEEK_CLAMPING,
+};
+
+#[cfg(feature = "regex")]
+use super::pattern::TopicPattern;
+#[cfg(feature = "regex")]
+use super::DEFAULT_TOPIC_PATTERN_REFRESH_INTERVAL_MILLIS;
+#[cfg(feature = "regex")]
+use regex::Regex;
 
 #[cfg(feature = "security")]
 use crate::client::SecurityConfig;
@@ -25,14 +38,29 @@ pub struct Builder {
     assignments: HashMap<String, Vec<i32>>,
     fallback_offset: FetchOffset,
     fetch_max_wait_time: Duration,
+    adaptive_fetch: Option<AdaptiveFetch>,
     fetch_min_bytes: i32,
     fetch_max_bytes_per_partition: i32,
+    fetch_max_bytes: Option<usize>,
+    partition_fetch_bytes: HashMap<String, i32>,
     retry_max_bytes_limit: i32,
+    seek_clamping: bool,
+    manual_ack: bool,
+    offset_reset_policy: OffsetResetPolicy,
+    auto_commit_interval: Option<Duration>,
+    interceptors: Vec<Box<dyn ConsumeInterceptor>>,
+    truncation_listener: Option<Box<dyn TruncationListener>>,
+    leaderless_max_attempts: Option<u32>,
+    fetch_buffer_bounds: Option<(usize, usize)>,
     fetch_crc_validation: bool,
     security_config: Option<SecurityConfig>,
     group_offset_storage: GroupOffsetStorage,
     conn_idle_timeout: Duration,
     client_id: Option<String>,
+    #[cfg(feature = "regex")]
+    topic_pattern: Option<Regex>,
+    #[cfg(feature = "regex")]
+    topic_pattern_refresh_interval: Duration,
 }
 
 // ~ public only to be shared inside the kafka crate; not supposed to
@@ -42,10 +70,21 @@ pub fn new(client: Option<KafkaClient>, hosts: Vec<String>) -> Builder {
         client,
         hosts,
         fetch_max_wait_time: Duration::from_millis(client::DEFAULT_FETCH_MAX_WAIT_TIME_MILLIS),
+        adaptive_fetch: None,
         fetch_min_bytes: client::DEFAULT_FETCH_MIN_BYTES,
         fetch_max_bytes_per_partition: client::DEFAULT_FETCH_MAX_BYTES_PER_PARTITION,
+        fetch_max_bytes: None,
+        partition_fetch_bytes: HashMap::new(),
         fetch_crc_validation: client::DEFAULT_FETCH_CRC_VALIDATION,
         retry_max_bytes_limit: DEFAULT_RETRY_MAX_BYTES_LIMIT,
+        seek_clamping: DEFAULT_SEEK_CLAMPING,
+        manual_ack: DEFAULT_MANUAL_ACK,
+        offset_reset_policy: DEFAULT_OFFSET_RESET_POLICY,
+        auto_commit_interval: None,
+        interceptors: Vec::new(),
+        truncation_listener: None,
+        leaderless_max_attempts: None,
+        fetch_buffer_bounds: None,
         group: String::new(),
         assignments: HashMap::new(),
         fallback_offset: DEFAULT_FALLBACK_OFFSET,
@@ -53,6 +92,12 @@ pub fn new(client: Option<KafkaClient>, hosts: Vec<String>) -> Builder {
         group_offset_storage: client::DEFAULT_GROUP_OFFSET_STORAGE,
         conn_idle_timeout: Duration::from_millis(client::DEFAULT_CONNECTION_IDLE_TIMEOUT_MILLIS),
         client_id: None,
+        #[cfg(feature = "regex")]
+        topic_pattern: None,
+        #[cfg(feature = "regex")]
+        topic_pattern_refresh_interval: Duration::from_millis(
+            DEFAULT_TOPIC_PATTERN_REFRESH_INTERVAL_MILLIS,
+        ),
     };
     if let Some(ref c) = b.client {
         b.fetch_max_wait_time = c.fetch_max_wait_time();
@@ -90,6 +135,34 @@ impl Builder {
         self
     }
 
+    /// Specifies a pattern of topics to consume.  All of the
+    /// available partitions of all topics whose name matches the
+    /// given regular expression as a whole will be consumed in
+    /// addition to the topics specified through `with_topic` and
+    /// `with_topic_partitions`.
+    ///
+    /// The pattern is evaluated against the topics in the cluster
+    /// when creating the consumer and re-evaluated periodically
+    /// afterwards in order to pick up newly created topics.  See
+    /// `with_topic_pattern_refresh_interval`.
+    ///
+    /// With a topic pattern specified, the consumer may be created
+    /// even if no topic (yet) matches the pattern.
+    #[cfg(feature = "regex")]
+    pub fn with_topic_pattern(mut self, pattern: Regex) -> Builder {
+        self.topic_pattern = Some(pattern);
+        self
+    }
+
+    /// Specifies the interval at which to re-evaluate the pattern
+    /// specified through `with_topic_pattern`.  The default value for
+    /// this setting is `DEFAULT_TOPIC_PATTERN_REFRESH_INTERVAL_MILLIS`.
+    #[cfg(feature = "regex")]
+    pub fn with_topic_pattern_refresh_interval(mut self, interval: Duration) -> Builder {
+        self.topic_pattern_refresh_interval = interval;
+        self
+    }
+
     /// Explicitly specifies topic partitions to consume. Only the
     /// specified partitions for the identified topic will be consumed
     /// unless overridden later using `with_topic`.
@@ -135,6 +208,26 @@ impl Builder {
         self
     }
 
+    /// Enables adapting the fetch max wait time to the observed data
+    /// flow within the given bounds.  Starting with `min_wait`, the
+    /// consumer doubles the wait time whenever a fetch delivers no
+    /// data at all and halves it whenever a fetch leaves more data
+    /// available at the brokers than it delivered.  This reduces the
+    /// number of empty fetches on idle topics while keeping the
+    /// latency low when data flows.
+    ///
+    /// If enabled, this setting overrides
+    /// `Builder::with_fetch_max_wait_time`.  A `max_wait` smaller
+    /// than `min_wait` is treated as `min_wait`.  By default, the
+    /// fetch max wait time is fixed.
+    pub fn with_adaptive_fetch(mut self, min_wait: Duration, max_wait: Duration) -> Builder {
+        self.adaptive_fetch = Some(AdaptiveFetch {
+            min_wait,
+            max_wait: cmp::max(min_wait, max_wait),
+        });
+        self
+    }
+
     /// See `KafkaClient::set_fetch_min_bytes`
     pub fn with_fetch_min_bytes(mut self, min_bytes: i32) -> Builder {
         self.fetch_min_bytes = min_bytes;
@@ -147,6 +240,40 @@ impl Builder {
         self
     }
 
+    /// Overrides the maximum number of bytes to fetch per partition
+    /// (see `Builder::with_fetch_max_bytes_per_partition`) for the
+    /// partitions of the given topic, e.g. for topics carrying large
+    /// messages.  Partitions of other topics are fetched with the
+    /// default limit.  Values exceeding `i32::MAX` are capped.
+    ///
+    /// Like the default limit, the override is subject to
+    /// `Builder::with_fetch_max_bytes` and is increased on retries up
+    /// to `Builder::with_retry_max_bytes_limit`.
+    pub fn with_partition_fetch_bytes(mut self, topic: String, max_bytes: usize) -> Builder {
+        self.partition_fetch_bytes
+            .insert(topic, i32::try_from(max_bytes).unwrap_or(i32::MAX));
+        self
+    }
+
+    /// Specifies the maximum number of bytes to fetch across all
+    /// consumed partitions in a single poll.  By default, a poll is
+    /// bounded only by the per-partition limit (see
+    /// `Builder::with_fetch_max_bytes_per_partition`.)
+    ///
+    /// The budget is shared equally by the partitions fetched in a
+    /// poll; the per-partition limit is reduced to the budget if it
+    /// exceeds it.  Since this client speaks fetch requests of
+    /// version 0 which lack an overall limit, the budget is enforced
+    /// on the client side.  Note that a message larger than a
+    /// partition's share of the budget is delivered only by retrying
+    /// that partition on its own, subject to
+    /// `Builder::with_retry_max_bytes_limit`, and never if it exceeds
+    /// the whole budget.
+    pub fn with_fetch_max_bytes(mut self, max_bytes: usize) -> Builder {
+        self.fetch_max_bytes = Some(max_bytes);
+        self
+    }
+
     /// See `KafkaClient::set_fetch_crc_validation`
     pub fn with_fetch_crc_validation(mut self, validate_crc: bool) -> Builder {
         self.fetch_crc_validation = validate_crc;
@@ -169,7 +296,10 @@ impl Builder {
     /// in an underlying partition which could not be delivered, the
     /// request to that partition might be retried a few times with an
     /// increased `fetch_max_bytes_per_partition`.  The value
-    /// specified here defines a limit to this increment.
+    /// specified here defines a limit to this increment.  Every
+    /// increase is logged as a warning; once the limit is reached
+    /// without the message being delivered, polling fails with
+    /// `KafkaCode::MessageSizeTooLarge`.
     ///
     /// A value smaller than the
     /// `KafkaClient::fetch_max_bytes_per_partition`, e.g. zero, will
@@ -186,6 +316,179 @@ impl Builder {
         self
     }
 
+    /// Specifies whether `Consumer::seek` shall clamp the requested
+    /// offset to the earliest offset still available in the
+    /// partition.  If enabled, seeking to an offset which has been
+    /// already deleted by the broker (e.g. due to the topic's
+    /// retention policy) positions the consumer at the partition's
+    /// log start instead of having the next fetch fail with
+    /// `KafkaCode::OffsetOutOfRange`.
+    ///
+    /// The default value for this setting is `DEFAULT_SEEK_CLAMPING`.
+    pub fn with_seek_clamping(mut self, clamp: bool) -> Builder {
+        self.seek_clamping = clamp;
+        self
+    }
+
+    /// Specifies whether the consumer shall advance its consumed
+    /// offsets only for messages explicitly acknowledged through
+    /// `Consumer::ack`.  In this mode, the consumer keeps track of
+    /// the messages delivered by `Consumer::poll` and commits, for
+    /// each partition, only the offset up to which all delivered
+    /// messages have been acknowledged.  Messages not acknowledged
+    /// by the time of a commit are therefore redelivered after a
+    /// restart of the consumer, even if later messages of the same
+    /// partition have been acknowledged.
+    ///
+    /// The default value for this setting is `DEFAULT_MANUAL_ACK`.
+    pub fn with_manual_ack(mut self, manual_ack: bool) -> Builder {
+        self.manual_ack = manual_ack;
+        self
+    }
+
+    /// Specifies what the consumer does when fetching from a
+    /// partition fails because its position is out of the range of
+    /// offsets available in the partition, e.g. after the messages
+    /// have been deleted due to the topic's retention policy.  Unless
+    /// `OffsetResetPolicy::Error` is specified, the consumer resets
+    /// its position in the partition accordingly, logs a warning and
+    /// continues; messages skipped by the reset are lost to the
+    /// consumer.
+    ///
+    /// Note: unlike `Builder::with_fallback_offset`, which applies
+    /// to partitions without a committed offset when the consumer is
+    /// created, this setting applies to positions becoming invalid
+    /// while consuming.
+    ///
+    /// The default value for this setting is
+    /// `DEFAULT_OFFSET_RESET_POLICY`, avoiding silent data loss.
+    pub fn with_offset_reset_policy(mut self, policy: OffsetResetPolicy) -> Builder {
+        self.offset_reset_policy = policy;
+        self
+    }
+
+    /// Makes the consumer skip partitions without an available
+    /// leader, e.g. because all of their replicas are offline, such
+    /// that the other partitions continue to be served.
+    ///
+    /// Fetching from a partition failing with
+    /// `KafkaCode::LeaderNotAvailable` or
+    /// `KafkaCode::NotLeaderForPartition` no longer fails the poll;
+    /// instead, the metadata of the partition's topic is refreshed.
+    /// Once `max_attempts` consecutive refreshes - one per poll -
+    /// confirm the partition to have no leader, the partition is
+    /// skipped and reported by `Consumer::stalled_partitions`.  While
+    /// partitions are stalled, every poll refreshes the metadata of
+    /// their topics and resumes fetching from partitions having
+    /// regained a leader at the position they were left at.
+    ///
+    /// By default, partitions are never skipped and such errors are
+    /// reported by `Consumer::poll`.
+    pub fn with_leaderless_partition_skipping(mut self, max_attempts: u32) -> Builder {
+        self.leaderless_max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Bounds the number of messages the consumer keeps delivering
+    /// per partition without them being marked as consumed.
+    ///
+    /// Messages count as buffered from the poll delivering them
+    /// until they are marked as consumed through
+    /// `Consumer::consume_message`, `Consumer::consume_messageset`,
+    /// `Consumer::ack` or `Consumer::store_offset`, or until the
+    /// partition is re-positioned through `Consumer::seek`.  Once
+    /// more than `high` messages of a partition are buffered, the
+    /// partition is paused, i.e. no longer fetched from, and polls
+    /// deliver no further messages for it.  Fetching resumes as soon
+    /// as fewer than `low` messages are left buffered.  A `low` mark
+    /// exceeding `high` is lowered to `high`.
+    ///
+    /// By default, partitions are fetched from regardless of how
+    /// many delivered messages have not been consumed.
+    pub fn with_fetch_buffer_bounds(mut self, low: usize, high: usize) -> Builder {
+        self.fetch_buffer_bounds = Some((cmp::min(low, high), high));
+        self
+    }
+
+    /// Makes the consumer commit the offsets of consumed messages
+    /// automatically, at most once per the given interval.  The
+    /// commit is carried out as part of `Consumer::poll` (before
+    /// fetching new messages) once the interval has elapsed since
+    /// the last automatic commit.  Failing to commit is logged and
+    /// retried with the next due commit.
+    ///
+    /// Note: only messages marked as consumed (see
+    /// `Consumer::consume_message` and
+    /// `Consumer::consume_messageset`) are committed.  This provides
+    /// "at least once" semantics: messages consumed since the last
+    /// automatic commit are delivered again after a restart of the
+    /// consumer, unless they are committed explicitly through
+    /// `Consumer::commit_consumed` before the consumer is dropped.
+    ///
+    /// By default, offsets are committed only explicitly.
+    pub fn with_auto_commit(mut self, interval: Duration) -> Builder {
+        self.auto_commit_interval = Some(interval);
+        self
+    }
+
+    /// Registers an interceptor to inspect, modify or filter fetched
+    /// messages before `Consumer::poll` delivers them.  Multiple
+    /// interceptors are invoked in the order of their registration.
+    /// See `ConsumeInterceptor`.
+    ///
+    /// Note: with interceptors registered, the key and value of every
+    /// fetched message are copied.
+    pub fn with_interceptor<I: ConsumeInterceptor + 'static>(mut self, interceptor: I) -> Builder {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// Registers a listener to be notified whenever the consumer
+    /// detects the log of a consumed partition to have been truncated
+    /// below its fetch position and resets the position to the new
+    /// end of the log.  See `TruncationListener`.
+    ///
+    /// Note: truncation is detected only while the underlying client
+    /// learns about the partitions' leader epochs, i.e. with
+    /// `MetadataVersion::V7` or later.  See
+    /// `KafkaClient::set_metadata_version`.
+    pub fn with_truncation_listener<L: TruncationListener + 'static>(
+        mut self,
+        listener: L,
+    ) -> Builder {
+        self.truncation_listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Specifies the deserializer for the keys of the fetched
+    /// messages turning this builder into one for a `TypedConsumer`.
+    /// The values of the messages are delivered as is unless
+    /// `TypedBuilder::with_value_deserializer` is called as well.
+    ///
+    /// Note: all other settings are to be specified before calling
+    /// this method.
+    pub fn with_key_deserializer<K, D: Deserializer<K> + 'static>(
+        self,
+        deserializer: D,
+    ) -> TypedBuilder<K, Vec<u8>> {
+        typed::new_builder(self, Box::new(deserializer), Box::new(BytesDeserializer))
+    }
+
+    /// Specifies the deserializer for the values of the fetched
+    /// messages turning this builder into one for a `TypedConsumer`,
+    /// e.g. `JsonDeserializer` for JSON encoded values.  The keys of
+    /// the messages are delivered as is unless
+    /// `TypedBuilder::with_key_deserializer` is called as well.
+    ///
+    /// Note: all other settings are to be specified before calling
+    /// this method.
+    pub fn with_value_deserializer<V, D: Deserializer<V> + 'static>(
+        self,
+        deserializer: D,
+    ) -> TypedBuilder<Vec<u8>, V> {
+        typed::new_builder(self, Box::new(BytesDeserializer), Box::new(deserializer))
+    }
+
     /// Specifies the timeout for idle connections.
     /// See `KafkaClient::set_connection_idle_timeout`.
     pub fn with_connection_idle_timeout(mut self, timeout: Duration) -> Self {
@@ -221,8 +524,12 @@ impl Builder {
     /// `with_topic` nor `with_topic_partitions` have been called to
     /// assign at least one topic for consumption.
     pub fn create(self) -> Result<Consumer> {
+        #[cfg(feature = "regex")]
+        let has_topic_pattern = self.topic_pattern.is_some();
+        #[cfg(not(feature = "regex"))]
+        let has_topic_pattern = false;
         // ~ fail immediately if there's no topic to be consumed
-        if self.assignments.is_empty() {
+        if self.assignments.is_empty() && !has_topic_pattern {
             return Err(Error::NoTopicsAssigned);
         }
         // ~ create the client if necessary
@@ -234,25 +541,65 @@ impl Builder {
             ),
         };
         // ~ apply configuration settings
-        client.set_fetch_max_wait_time(self.fetch_max_wait_time)?;
+        if let Some(ref adaptive) = self.adaptive_fetch {
+            // ~ validate the upper bound once such that adapting the
+            // wait time later cannot fail
+            client.set_fetch_max_wait_time(adaptive.max_wait)?;
+            client.set_fetch_max_wait_time(adaptive.min_wait)?;
+        } else {
+            client.set_fetch_max_wait_time(self.fetch_max_wait_time)?;
+        }
         client.set_fetch_min_bytes(self.fetch_min_bytes);
-        client.set_fetch_max_bytes_per_partition(self.fetch_max_bytes_per_partition);
+        client.set_fetch_max_bytes_per_partition(match self.fetch_max_bytes {
+            Some(total) => self
+                .fetch_max_bytes_per_partition
+                .min(i32::try_from(total).unwrap_or(i32::MAX)),
+            None => self.fetch_max_bytes_per_partition,
+        });
         client.set_group_offset_storage(self.group_offset_storage);
         client.set_connection_idle_timeout(self.conn_idle_timeout);
         if let Some(client_id) = self.client_id {
             client.set_client_id(client_id)
         }
         // ~ load metadata if necessary
-        if need_metadata {
+        if need_metadata || has_topic_pattern {
             client.load_metadata_all()?;
         }
+        // ~ resolve the topic pattern, if any
+        #[cfg(feature = "regex")]
+        let topic_pattern = self
+            .topic_pattern
+            .map(|p| TopicPattern::new(&p, self.topic_pattern_refresh_interval));
+        #[cfg(feature = "regex")]
+        let assignments = {
+            let mut assignments = self.assignments;
+            if let Some(ref p) = topic_pattern {
+                for topic in p.matching_topics(&client) {
+                    assignments.entry(topic).or_default();
+                }
+            }
+            assignments
+        };
+        #[cfg(not(feature = "regex"))]
+        let assignments = self.assignments;
         // ~ load consumer state
         let config = Config {
             group: self.group,
             fallback_offset: self.fallback_offset,
             retry_max_bytes_limit: self.retry_max_bytes_limit,
+            seek_clamping: self.seek_clamping,
+            adaptive_fetch: self.adaptive_fetch,
+            manual_ack: self.manual_ack,
+            offset_reset_policy: self.offset_reset_policy,
+            auto_commit_interval: self.auto_commit_interval,
+            fetch_max_bytes: self.fetch_max_bytes,
+            interceptors: self.interceptors,
+            truncation_listener: self.truncation_listener,
+            leaderless_max_attempts: self.leaderless_max_attempts,
+            fetch_buffer_bounds: self.fetch_buffer_bounds,
+            partition_fetch_bytes: self.partition_fetch_bytes,
         };
-        let state = State::new(&mut client, &config, assignment::from_map(self.assignments))?;
+        let state = State::new(&mut client, &config, assignment::from_map(assignments))?;
         debug!(
             "initialized: Consumer {{ config: {:?}, state: {:?} }}",
             config, state
@@ -261,6 +608,8 @@ impl Builder {
             client,
             state,
             config,
+            #[cfg(feature = "regex")]
+            topic_pattern,
         })
     }
 }
@@ -1,8 +1,73 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Duration;
+
 use crate::client::FetchOffset;
 
+use super::{ConsumeInterceptor, OffsetResetPolicy, TruncationListener};
+
 #[derive(Debug)]
 pub struct Config {
     pub group: String,
     pub fallback_offset: FetchOffset,
     pub retry_max_bytes_limit: i32,
+    pub seek_clamping: bool,
+    pub adaptive_fetch: Option<AdaptiveFetch>,
+    pub manual_ack: bool,
+    pub offset_reset_policy: OffsetResetPolicy,
+    pub auto_commit_interval: Option<Duration>,
+    // ~ the maximum number of bytes to fetch across all partitions
+    // per poll; `None` if unbounded
+    pub fetch_max_bytes: Option<usize>,
+    pub interceptors: Vec<Box<dyn ConsumeInterceptor>>,
+    pub truncation_listener: Option<Box<dyn TruncationListener>>,
+    // ~ the number of checks after which partitions without a leader
+    // are skipped; `None` if they are never skipped
+    pub leaderless_max_attempts: Option<u32>,
+    // ~ the (low, high) marks of delivered but not yet consumed
+    // messages per partition between which fetching is paused;
+    // `None` if fetching is never paused
+    pub fetch_buffer_bounds: Option<(usize, usize)>,
+    // ~ the maximum number of bytes to fetch per partition by topic
+    // overriding the client's default
+    pub partition_fetch_bytes: HashMap<String, i32>,
+}
+
+impl Config {
+    /// Determines the maximum number of bytes to fetch per partition
+    /// of the given topic; `default` unless overridden for the topic.
+    pub fn partition_fetch_bytes(&self, topic: &str, default: i32) -> i32 {
+        self.partition_fetch_bytes
+            .get(topic)
+            .copied()
+            .unwrap_or(default)
+    }
+}
+
+/// Bounds within which to adapt the fetch max wait time to the
+/// observed data flow.
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveFetch {
+    pub min_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl AdaptiveFetch {
+    /// Determines the max wait time for the next fetch given the one
+    /// of the previous fetch and whether that fetch delivered no data
+    /// at all (`empty`) or left more data available at the brokers
+    /// than it delivered (`full`).
+    pub fn next_wait(&self, wait: Duration, empty: bool, full: bool) -> Duration {
+        let next = if empty {
+            // ~ wait longer for data to accumulate at the brokers;
+            // step off a zero wait time by at least a millisecond
+            cmp::max(wait * 2, Duration::from_millis(1))
+        } else if full {
+            // ~ data is flowing, keep the latency low
+            wait / 2
+        } else {
+            wait
+        };
+        next.clamp(self.min_wait, self.max_wait)
+    }
 }
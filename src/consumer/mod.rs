@@ -60,31 +60,96 @@
 //! The configuration of a group is optional.  If the consumer has no
 //! group configured, it will behave as if it had one, only that
 //! committing consumed message offsets resolves into a void operation.
+//!
+//! # Ordering
+//!
+//! The messages of a partition are delivered contiguously, i.e.
+//! within a single `MessageSet` per poll, and in the order of their
+//! offsets.  A partition's messages delivered by a later poll always
+//! follow those delivered by an earlier one.  Messages of _different_
+//! partitions, however, are never guaranteed to be delivered in any
+//! particular order relative to each other, not even in the order
+//! they were produced in.
 
 use std::collections::hash_map::{Entry, HashMap};
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
 use std::slice;
+use std::time::Instant;
 
-use crate::client::fetch;
-use crate::client::{CommitOffset, FetchPartition, KafkaClient};
+use crate::client::{self, fetch};
+use crate::client::{CommitOffset, FetchGroupOffset, FetchPartition, KafkaClient};
 use crate::error::{Error, KafkaCode, Result};
 
 // public re-exports
 pub use self::builder::Builder;
+pub use self::typed::{
+    BytesDeserializer, Deserializer, StringDeserializer, TypedBuilder, TypedConsumer, TypedMessage,
+};
+#[cfg(feature = "serde")]
+pub use self::typed::{CborDeserializer, JsonDeserializer};
 pub use crate::client::fetch::Message;
+#[cfg(feature = "bytes")]
+pub use crate::client::fetch::{BytesMessage, BytesMessageSet};
 pub use crate::client::FetchOffset;
 pub use crate::client::GroupOffsetStorage;
+pub use crate::protocol::MemberAssignment;
 
 mod assignment;
 mod builder;
 mod config;
+#[cfg(feature = "regex")]
+mod pattern;
 mod state;
+mod typed;
+
+// XXX partition assignment strategies (range, round-robin, sticky)
+// are negotiated through JoinGroup and distributed through SyncGroup;
+// add them once the consumer takes part in the group membership
+// protocol
 
 /// The default value for `Builder::with_retry_max_bytes_limit`.
-pub const DEFAULT_RETRY_MAX_BYTES_LIMIT: i32 = 0;
+/// This corresponds to the default of Kafka's `fetch.max.bytes`.
+pub const DEFAULT_RETRY_MAX_BYTES_LIMIT: i32 = 50 * 1024 * 1024;
+
+/// The default value for `Builder::with_seek_clamping`.
+pub const DEFAULT_SEEK_CLAMPING: bool = false;
+
+/// The default value for `Builder::with_manual_ack`.
+pub const DEFAULT_MANUAL_ACK: bool = false;
+
+/// The default value for `Builder::with_topic_pattern_refresh_interval`.
+#[cfg(feature = "regex")]
+pub const DEFAULT_TOPIC_PATTERN_REFRESH_INTERVAL_MILLIS: u64 = 5 * 60 * 1000;
 
 /// The default value for `Builder::with_fallback_offset`.
 pub const DEFAULT_FALLBACK_OFFSET: FetchOffset = FetchOffset::Latest;
 
+/// The default value for `Builder::with_offset_reset_policy`.
+pub const DEFAULT_OFFSET_RESET_POLICY: OffsetResetPolicy = OffsetResetPolicy::Error;
+
+/// The generation id identifying a consumer committing offsets
+/// without being a member of its group.  See
+/// `Consumer::group_metadata`.
+pub const GROUP_GENERATION_NONE: i32 = -1;
+
+/// What a consumer does when fetching from a partition fails with
+/// `KafkaCode::OffsetOutOfRange`, e.g. because the messages at its
+/// position have been deleted by the broker due to the topic's
+/// retention policy.  See `Builder::with_offset_reset_policy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OffsetResetPolicy {
+    /// Continue with the earliest message still available in the
+    /// partition.
+    Earliest,
+    /// Continue with the next message to be appended to the
+    /// partition, skipping all messages currently available.
+    Latest,
+    /// Fail the poll with the error; the consumer's position stays
+    /// unchanged.
+    Error,
+}
+
 /// The Kafka Consumer
 ///
 /// See module level documentation.
@@ -93,10 +158,11 @@ pub struct Consumer {
     client: KafkaClient,
     state: state::State,
     config: config::Config,
+    #[cfg(feature = "regex")]
+    topic_pattern: Option<pattern::TopicPattern>,
 }
 
-// XXX 1) Allow returning to a previous offset (aka seeking)
-// XXX 2) Issue IO in a separate (background) thread and pre-fetch messagesets
+// XXX 1) Issue IO in a separate (background) thread and pre-fetch messagesets
 
 impl Consumer {
     /// Starts building a consumer using the given kafka client.
@@ -152,10 +218,305 @@ impl Consumer {
         h
     }
 
+    /// Starts consuming all available partitions of the given topic
+    /// in addition to the topic partitions consumed so far.  The
+    /// offsets to start consuming the topic at are determined the
+    /// same way as for topics specified at build time, i.e. through
+    /// the offsets committed on behalf of this consumer's group, if
+    /// any, and `Builder::with_fallback_offset` otherwise.
+    ///
+    /// Subscribing to a topic which is being consumed already is a
+    /// no-op.  Note: since this consumer does not take part in Kafka's
+    /// group membership protocol, there is no rebalance of the
+    /// topic's partitions among the members of the group; this
+    /// consumer will consume all of the topic's partitions.
+    pub fn subscribe(&mut self, topic: &str) -> Result<()> {
+        debug!("subscribe: {}", topic);
+        self.state.subscribe(&mut self.client, &self.config, topic)
+    }
+
+    /// Stops consuming the given topic.  Any messages of the topic
+    /// marked as consumed but not committed yet are committed (on
+    /// behalf of this consumer's group, if any) before the topic is
+    /// dropped.  Unsubscribing from a topic not being consumed is a
+    /// no-op.
+    pub fn unsubscribe(&mut self, topic: &str) -> Result<()> {
+        debug!("unsubscribe: {}", topic);
+        if let Some(topic_ref) = self.state.topic_ref(topic) {
+            self.commit_dirty(Some(topic_ref))?;
+            self.state.unsubscribe(topic_ref);
+        }
+        Ok(())
+    }
+
+    /// Re-evaluates the pattern specified through
+    /// `Builder::with_topic_pattern` against the current set of
+    /// topics in the cluster, subscribing to newly created matching
+    /// topics.  This happens automatically during `Consumer::poll`
+    /// at the interval specified through
+    /// `Builder::with_topic_pattern_refresh_interval`.  Without a
+    /// topic pattern, this is a no-op.
+    #[cfg(feature = "regex")]
+    pub fn refresh_topic_pattern(&mut self) -> Result<()> {
+        if let Some(ref mut p) = self.topic_pattern {
+            for topic in p.refresh(&mut self.client)? {
+                self.state
+                    .subscribe(&mut self.client, &self.config, &topic)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Polls for the next available message data.
     pub fn poll(&mut self) -> Result<MessageSets> {
+        #[cfg(feature = "regex")]
+        {
+            let now = std::time::Instant::now();
+            if matches!(self.topic_pattern, Some(ref p) if p.is_due(now)) {
+                self.refresh_topic_pattern()?;
+            }
+        }
+        self.auto_commit();
+        self.validate_leader_epochs()?;
+        self.check_leaderless_partitions()?;
+        self.update_paused_partitions();
         let (n, resps) = self.fetch_messages();
-        self.process_fetch_responses(n, resps?)
+        let mut mss = self.process_fetch_responses(n, resps?)?;
+        if !self.config.interceptors.is_empty() {
+            self.intercept(&mut mss)?;
+        }
+        Ok(mss)
+    }
+
+    // ~ passes the fetched messages through the registered
+    // interceptors.  the fetch offsets have been advanced already
+    // such that dropped messages are not fetched again; with manual
+    // acknowledgements, dropped messages are acknowledged right away.
+    fn intercept(&mut self, mss: &mut MessageSets) -> Result<()> {
+        let interceptors = &self.config.interceptors;
+        let mut dropped = Vec::new();
+        for resp in &mut mss.responses {
+            resp.rewrite_messages(|topic, partition, msg| {
+                let mut owned = OwnedMessage::from(msg);
+                for i in interceptors {
+                    if !i.on_consume(&mut owned) {
+                        dropped.push((topic.to_owned(), partition, msg.offset));
+                        return None;
+                    }
+                }
+                Some((owned.key, owned.value))
+            });
+        }
+        mss.empty = mss.iter().next().is_none();
+        if self.config.fetch_buffer_bounds.is_some() {
+            for (topic, partition, offset) in &dropped {
+                if let Some(buffered) = self.buffered_offsets(topic, *partition) {
+                    buffered.remove(offset);
+                }
+            }
+        }
+        if self.config.manual_ack {
+            for (topic, partition, offset) in dropped {
+                self.ack(&topic, partition, offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    // ~ detects log truncations of the consumed partitions based on
+    // their leader epochs known to the client and resets the fetch
+    // positions accordingly; see `TruncationListener`
+    fn validate_leader_epochs(&mut self) -> Result<()> {
+        let mut changed = Vec::new();
+        for (tp, s) in &mut self.state.fetch_offsets {
+            let topic = self.state.assignments[tp.topic_ref].topic();
+            let epoch = self
+                .client
+                .topics()
+                .partitions(topic)
+                .and_then(|ps| ps.partition(tp.partition))
+                .and_then(|p| p.leader_epoch());
+            match (s.leader_epoch, epoch) {
+                (Some(prev), Some(curr)) if prev != curr => {
+                    changed.push((tp.topic_ref, tp.partition, prev, curr));
+                }
+                (None, Some(_)) => s.leader_epoch = epoch,
+                _ => {}
+            }
+        }
+        for (topic_ref, partition, prev, curr) in changed {
+            let topic = self.state.topic_name(topic_ref).to_owned();
+            let end_offset = self
+                .client
+                .fetch_epoch_end_offset(&topic, partition, prev)?;
+            let s = self
+                .state
+                .fetch_offsets
+                .get_mut(&state::TopicPartition {
+                    topic_ref,
+                    partition,
+                })
+                .expect("non-requested partition");
+            s.leader_epoch = Some(curr);
+            if end_offset < 0 || s.offset <= end_offset {
+                continue;
+            }
+            let truncation = LogTruncation {
+                topic,
+                partition,
+                leader_epoch: prev,
+                fetch_offset: s.offset,
+                end_offset,
+            };
+            warn!("detected log truncation: {:?}", truncation);
+            s.offset = end_offset;
+            if let Some(ref listener) = self.config.truncation_listener {
+                listener.on_log_truncation(&truncation);
+            }
+        }
+        Ok(())
+    }
+
+    // ~ pauses the partitions with more delivered but not consumed
+    // messages than the high mark and resumes those with fewer than
+    // the low mark; see `Builder::with_fetch_buffer_bounds`
+    fn update_paused_partitions(&mut self) {
+        let Some((low, high)) = self.config.fetch_buffer_bounds else {
+            return;
+        };
+        for (tp, s) in &mut self.state.fetch_offsets {
+            let n = self.state.buffered.get(tp).map_or(0, BTreeSet::len);
+            if !s.paused && n > high {
+                debug!(
+                    "pausing {}:{} with {} buffered messages",
+                    self.state.assignments[tp.topic_ref].topic(),
+                    tp.partition,
+                    n
+                );
+                s.paused = true;
+            } else if s.paused && n < low {
+                debug!(
+                    "resuming {}:{} with {} buffered messages",
+                    self.state.assignments[tp.topic_ref].topic(),
+                    tp.partition,
+                    n
+                );
+                s.paused = false;
+            }
+        }
+    }
+
+    // ~ retrieves the offsets of the delivered but not consumed
+    // messages of the specified topic partition; `None` if the
+    // partition is not being consumed
+    fn buffered_offsets(&mut self, topic: &str, partition: i32) -> Option<&mut BTreeSet<i64>> {
+        let topic_ref = self.state.topic_ref(topic)?;
+        self.state.buffered.get_mut(&state::TopicPartition {
+            topic_ref,
+            partition,
+        })
+    }
+
+    // ~ commits the consumed offsets if an automatic commit is due;
+    // see `Builder::with_auto_commit`
+    fn auto_commit(&mut self) {
+        let Some(interval) = self.config.auto_commit_interval else {
+            return;
+        };
+        let now = Instant::now();
+        if now.duration_since(self.state.last_auto_commit) < interval {
+            return;
+        }
+        self.state.last_auto_commit = now;
+        if let Err(e) = self.commit_consumed() {
+            warn!("auto_commit: failed to commit consumed offsets: {}", e);
+        }
+    }
+
+    /// Polls for the next available message data like
+    /// `Consumer::poll`, but delivers the messages backed by
+    /// reference counted buffers.  The messages' keys and values are
+    /// not copied out of the fetched data and can outlive the poll,
+    /// e.g. to be handed over to other threads.
+    #[cfg(feature = "bytes")]
+    pub fn poll_bytes(&mut self) -> Result<BytesMessageSets> {
+        let mss = self.poll()?;
+        Ok(BytesMessageSets {
+            sets: mss
+                .responses
+                .into_iter()
+                .flat_map(fetch::Response::into_bytes_message_sets)
+                .filter(|s| !s.messages().is_empty())
+                .collect(),
+        })
+    }
+
+    /// Fetches the messages of the given topic partition starting at
+    /// the given offset directly from the broker with the given node
+    /// id, regardless of leadership, e.g. to diagnose diverging
+    /// replicas.  The consumer's positions and offsets are not
+    /// affected.
+    ///
+    /// **Caution:** this is an advanced api meant for debugging and
+    /// repair only; see `KafkaClient::fetch_from_replica`.
+    pub fn fetch_from_replica(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        broker_id: i32,
+        offset: i64,
+    ) -> Result<fetch::Response> {
+        self.client
+            .fetch_from_replica(topic, partition, broker_id, offset)
+    }
+
+    /// Consumes the messages of the specified topic partition in the
+    /// offset range `start..=end` and stops thereafter.  This is
+    /// meant for bounded reads like backfills.
+    ///
+    /// The range is fetched through this consumer's client honoring
+    /// its fetch settings, but independently of the partitions
+    /// assigned to this consumer: the offsets this consumer tracks
+    /// for polling and committing are left untouched.  If `end` lies
+    /// beyond the partition's high watermark, the iterator stops
+    /// after the latest message available at the time of fetching.
+    ///
+    /// The returned iterator stops after delivering its first error,
+    /// e.g. `KafkaCode::OffsetOutOfRange` if `start` is not
+    /// available (anymore) or `KafkaCode::MessageSizeTooLarge` if a
+    /// message does not fit into
+    /// `KafkaClient::fetch_max_bytes_per_partition`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use kafka::consumer::Consumer;
+    ///
+    /// let mut consumer = Consumer::from_hosts(vec!["localhost:9092".to_owned()])
+    ///     .with_topic("my-topic".to_owned())
+    ///     .create()
+    ///     .unwrap();
+    /// for msg in consumer.consume_range("my-topic", 0, 100, 500) {
+    ///     let msg = msg.unwrap();
+    ///     println!("{}: {:?}", msg.offset, msg.value);
+    /// }
+    /// ```
+    pub fn consume_range(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        start: i64,
+        end: i64,
+    ) -> ConsumeRange<'_> {
+        ConsumeRange {
+            client: &mut self.client,
+            topic: topic.to_owned(),
+            partition,
+            offset: start,
+            end,
+            buffered: VecDeque::new(),
+            done: false,
+        }
     }
 
     /// Determines whether this consumer is set up to consume only a
@@ -170,6 +531,54 @@ impl Consumer {
         &self.config.group
     }
 
+    /// Retrieves the partitions currently skipped for having been
+    /// without a leader for too long, ordered by topic and partition.
+    /// See `Builder::with_leaderless_partition_skipping`.
+    pub fn stalled_partitions(&self) -> Vec<client::TopicPartition> {
+        let mut tps: Vec<_> = self
+            .state
+            .fetch_offsets
+            .iter()
+            .filter(|(_, s)| s.stalled)
+            .map(|(tp, _)| {
+                client::TopicPartition::new(self.state.topic_name(tp.topic_ref), tp.partition)
+            })
+            .collect();
+        tps.sort();
+        tps
+    }
+
+    /// Retrieves what this consumer does when its position in a
+    /// partition is out of range.  See
+    /// `Builder::with_offset_reset_policy`.
+    pub fn offset_reset_policy(&self) -> OffsetResetPolicy {
+        self.config.offset_reset_policy
+    }
+
+    /// Retrieves the identity under which this consumer commits
+    /// offsets on behalf of its group, e.g. to commit offsets to an
+    /// external store or as part of a transaction.  Returns `None`
+    /// for a group-less consumer.
+    ///
+    /// Note: since this consumer does not take part in Kafka's group
+    /// membership protocol, it never joins its group and commits
+    /// offsets as a "simple" consumer instead.  The returned metadata
+    /// therefore always carries `GROUP_GENERATION_NONE`, an empty
+    /// member id and an empty protocol; brokers accept commits with
+    /// this identity as long as the group has no active members.
+    pub fn group_metadata(&self) -> Option<GroupMetadata> {
+        if self.config.group.is_empty() {
+            None
+        } else {
+            Some(GroupMetadata {
+                group_id: self.config.group.clone(),
+                generation_id: GROUP_GENERATION_NONE,
+                member_id: String::new(),
+                protocol: String::new(),
+            })
+        }
+    }
+
     // ~ returns (number partitions queried, fecth responses)
     fn fetch_messages(&mut self) -> (u32, Result<Vec<fetch::Response>>) {
         // ~ if there's a retry partition ... fetch messages just for
@@ -186,29 +595,38 @@ impl Consumer {
                     "fetching retry messages: (fetch-offset: {{\"{}:{}\": {:?}}})",
                     topic, tp.partition, s
                 );
+                let max_bytes = partition_max_bytes(s.max_bytes, self.config.fetch_max_bytes, 1);
                 (
                     1,
                     self.client.fetch_messages_for_partition(
                         &FetchPartition::new(topic, tp.partition, s.offset)
-                            .with_max_bytes(s.max_bytes),
+                            .with_max_bytes(max_bytes),
                     ),
                 )
             }
             None => {
                 let client = &mut self.client;
                 let state = &self.state;
+                let budget = self.config.fetch_max_bytes;
                 debug!(
                     "fetching messages: (fetch-offsets: {:?})",
                     state.fetch_offsets_debug()
                 );
-                let reqs = state.fetch_offsets.iter().map(|(tp, s)| {
-                    let topic = state.topic_name(tp.topic_ref);
-                    FetchPartition::new(topic, tp.partition, s.offset).with_max_bytes(s.max_bytes)
-                });
-                (
-                    state.fetch_offsets.len() as u32,
-                    client.fetch_messages(reqs),
-                )
+                let n = state
+                    .fetch_offsets
+                    .values()
+                    .filter(|s| !s.stalled && !s.paused)
+                    .count();
+                let reqs = state
+                    .fetch_offsets
+                    .iter()
+                    .filter(|(_, s)| !s.stalled && !s.paused)
+                    .map(|(tp, s)| {
+                        let topic = state.topic_name(tp.topic_ref);
+                        FetchPartition::new(topic, tp.partition, s.offset)
+                            .with_max_bytes(partition_max_bytes(s.max_bytes, budget, n))
+                    });
+                (n as u32, client.fetch_messages(reqs))
             }
         }
     }
@@ -226,7 +644,17 @@ impl Consumer {
     ) -> Result<MessageSets> {
         let single_partition_consumer = self.single_partition_consumer();
         let mut empty = true;
+        // ~ whether any partition has more data available than
+        // delivered
+        let mut full = false;
         let retry_partitions = &mut self.state.retry_partitions;
+        // ~ partitions to reset according to the offset reset policy
+        let mut resets = Vec::new();
+        // ~ partitions whose leader epoch known to the client is
+        // outdated, e.g. after an unclean leader election
+        let mut stale_epochs = Vec::new();
+        // ~ partitions possibly without a leader
+        let mut leaderless = Vec::new();
 
         for resp in &resps {
             for t in resp.topics() {
@@ -250,24 +678,87 @@ impl Consumer {
                     // transparently for the caller.
 
                     // XXX need to prevent updating fetch_offsets in case we're gonna fail here
-                    let data = p.data()?;
+                    let data = match p.data() {
+                        Ok(data) => data,
+                        Err(e)
+                            if e.kafka_code() == Some(KafkaCode::OffsetOutOfRange)
+                                && self.config.offset_reset_policy != OffsetResetPolicy::Error =>
+                        {
+                            resets.push(tp);
+                            continue;
+                        }
+                        Err(e)
+                            if matches!(
+                                e.kafka_code(),
+                                Some(KafkaCode::FencedLeaderEpoch | KafkaCode::UnknownLeaderEpoch)
+                            ) =>
+                        {
+                            stale_epochs.push(tp);
+                            continue;
+                        }
+                        Err(e)
+                            if self.config.leaderless_max_attempts.is_some()
+                                && matches!(
+                                    e.kafka_code(),
+                                    Some(
+                                        KafkaCode::LeaderNotAvailable
+                                            | KafkaCode::NotLeaderForPartition
+                                    )
+                                ) =>
+                        {
+                            leaderless.push(tp);
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
 
                     let mut fetch_state = self
                         .state
                         .fetch_offsets
                         .get_mut(&tp)
                         .expect("non-requested partition");
+                    fetch_state.high_watermark = Some(data.highwatermark_offset());
                     // ~ book keeping
+                    if self.config.manual_ack && !data.messages().is_empty() {
+                        let pending = self
+                            .state
+                            .pending_acks
+                            .entry(state::TopicPartition {
+                                topic_ref,
+                                partition: tp.partition,
+                            })
+                            .or_default();
+                        for msg in data.messages() {
+                            pending.entry(msg.offset).or_insert(false);
+                        }
+                    }
+                    if self.config.fetch_buffer_bounds.is_some() && !data.messages().is_empty() {
+                        self.state
+                            .buffered
+                            .entry(state::TopicPartition {
+                                topic_ref,
+                                partition: tp.partition,
+                            })
+                            .or_default()
+                            .extend(data.messages().iter().map(|msg| msg.offset));
+                    }
                     if let Some(last_msg) = data.messages().last() {
                         fetch_state.offset = last_msg.offset + 1;
                         empty = false;
+                        if fetch_state.offset < data.highwatermark_offset() {
+                            full = true;
+                        }
 
                         // ~ reset the max_bytes again to its usual
                         // value if we had a retry request and finally
                         // got some data
-                        if fetch_state.max_bytes != self.client.fetch_max_bytes_per_partition() {
+                        let max_bytes = self.config.partition_fetch_bytes(
+                            t.topic(),
+                            self.client.fetch_max_bytes_per_partition(),
+                        );
+                        if fetch_state.max_bytes != max_bytes {
                             let prev_max_bytes = fetch_state.max_bytes;
-                            fetch_state.max_bytes = self.client.fetch_max_bytes_per_partition();
+                            fetch_state.max_bytes = max_bytes;
                             debug!(
                                 "reset max_bytes for {}:{} from {} to {}",
                                 t.topic(),
@@ -302,10 +793,12 @@ impl Consumer {
                                 } else {
                                     fetch_state.max_bytes = incr_max_bytes;
                                 }
-                                debug!(
-                                    "increased max_bytes for {}:{} from {} to {}",
+                                warn!(
+                                    "message too large to be fetched from {}:{} at offset {}; \
+                                     increased max_bytes from {} to {}",
                                     t.topic(),
                                     tp.partition,
+                                    fetch_state.offset,
                                     prev_max_bytes,
                                     fetch_state.max_bytes
                                 );
@@ -333,10 +826,43 @@ impl Consumer {
             }
         }
 
+        for tp in &resets {
+            self.reset_offset(tp)?;
+        }
+        if !stale_epochs.is_empty() {
+            self.refresh_leader_epochs(&stale_epochs)?;
+        }
+        // ~ partitions without a leader in an earlier poll have been
+        // refreshed by `check_leaderless_partitions` already; count
+        // only one attempt per poll for them
+        leaderless.retain(|tp| {
+            self.state
+                .fetch_offsets
+                .get(tp)
+                .is_some_and(|s| s.leaderless_attempts == 0)
+        });
+        if !leaderless.is_empty() {
+            self.update_leaderless_partitions(&leaderless)?;
+        }
+
+        if let Some(ref adaptive) = self.config.adaptive_fetch {
+            let wait = self.client.fetch_max_wait_time();
+            let next_wait = adaptive.next_wait(wait, empty, full);
+            if next_wait != wait {
+                debug!(
+                    "adapted fetch max_wait_time from {:?} to {:?}",
+                    wait, next_wait
+                );
+                self.client.set_fetch_max_wait_time(next_wait)?;
+            }
+        }
+
         // XXX in future, issue one more fetch_messages request in the
         // background such that the next time the client polls that
         // request's response will likely be already ready for
-        // consumption
+        // consumption.  an option to interleave the messages of a
+        // partition from such concurrent fetches - as opposed to
+        // delivering them contiguously - belongs with it.
 
         Ok(MessageSets {
             responses: resps,
@@ -344,6 +870,259 @@ impl Consumer {
         })
     }
 
+    // ~ re-checks the partitions known to have been without a leader
+    // recently - including the stalled ones; see
+    // `Builder::with_leaderless_partition_skipping`
+    fn check_leaderless_partitions(&mut self) -> Result<()> {
+        let tps: Vec<_> = self
+            .state
+            .fetch_offsets
+            .iter()
+            .filter(|(_, s)| s.leaderless_attempts > 0)
+            .map(|(tp, _)| state::TopicPartition {
+                topic_ref: tp.topic_ref,
+                partition: tp.partition,
+            })
+            .collect();
+        if tps.is_empty() {
+            Ok(())
+        } else {
+            self.update_leaderless_partitions(&tps)
+        }
+    }
+
+    // ~ refreshes the metadata of the given partitions possibly
+    // without a leader and stalls those confirmed to have been
+    // without one for too long; resumes those having regained one
+    fn update_leaderless_partitions(&mut self, tps: &[state::TopicPartition]) -> Result<()> {
+        let Some(max_attempts) = self.config.leaderless_max_attempts else {
+            return Ok(());
+        };
+        let mut topics: Vec<&str> = tps
+            .iter()
+            .map(|tp| self.state.topic_name(tp.topic_ref))
+            .collect();
+        topics.sort_unstable();
+        topics.dedup();
+        self.client.metadata_for_topics(&topics)?;
+        for tp in tps {
+            let topic = self.state.assignments[tp.topic_ref].topic();
+            let has_leader = self
+                .client
+                .topics()
+                .partitions(topic)
+                .and_then(|ps| ps.partition(tp.partition))
+                .is_some_and(|p| p.leader().is_some());
+            let s = self
+                .state
+                .fetch_offsets
+                .get_mut(tp)
+                .expect("non-requested partition");
+            if has_leader {
+                if s.stalled {
+                    info!("resuming {}:{} with a leader again", topic, tp.partition);
+                }
+                s.leaderless_attempts = 0;
+                s.stalled = false;
+            } else {
+                s.leaderless_attempts = s.leaderless_attempts.saturating_add(1);
+                if !s.stalled && s.leaderless_attempts >= max_attempts {
+                    warn!(
+                        "skipping {}:{} without a leader after {} attempts",
+                        topic, tp.partition, s.leaderless_attempts
+                    );
+                    s.stalled = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // ~ refreshes the metadata of the given partitions whose fetch
+    // failed with `FencedLeaderEpoch` or `UnknownLeaderEpoch` and
+    // re-validates the fetch positions against the leader epochs now
+    // known.  merely retrying the fetch would fail the same way for
+    // as long as the client holds on to the outdated metadata.
+    fn refresh_leader_epochs(&mut self, tps: &[state::TopicPartition]) -> Result<()> {
+        let mut topics: Vec<&str> = tps
+            .iter()
+            .map(|tp| self.state.topic_name(tp.topic_ref))
+            .collect();
+        topics.sort_unstable();
+        topics.dedup();
+        debug!("refreshing leader epochs of topics: {:?}", topics);
+        self.client.metadata_for_topics(&topics)?;
+        self.validate_leader_epochs()
+    }
+
+    // ~ resets the fetch offset of a partition whose fetch failed with
+    // `OffsetOutOfRange` according to the offset reset policy
+    fn reset_offset(&mut self, tp: &state::TopicPartition) -> Result<()> {
+        let to = match self.config.offset_reset_policy {
+            OffsetResetPolicy::Earliest => FetchOffset::Earliest,
+            OffsetResetPolicy::Latest => FetchOffset::Latest,
+            OffsetResetPolicy::Error => return Err(Error::Kafka(KafkaCode::OffsetOutOfRange)),
+        };
+        let topic = self.state.topic_name(tp.topic_ref);
+        let offset = match self
+            .client
+            .list_offsets(&[(client::TopicPartition::new(topic, tp.partition), to)])?
+            .pop()
+        {
+            Some((_, offset)) if offset >= 0 => offset,
+            // ~ the broker did not report an offset for the partition;
+            // keep the position rather than fetching from an invalid one
+            _ => {
+                return Err(Error::TopicPartitionError {
+                    topic_name: topic.to_owned(),
+                    partition_id: tp.partition,
+                    error_code: KafkaCode::UnknownTopicOrPartition,
+                })
+            }
+        };
+        let fetch_state = self
+            .state
+            .fetch_offsets
+            .get_mut(tp)
+            .expect("non-requested partition");
+        warn!(
+            "offset {} out of range for {}:{}; reset to {:?} offset {}",
+            fetch_state.offset,
+            self.state.assignments[tp.topic_ref].topic(),
+            tp.partition,
+            to,
+            offset
+        );
+        fetch_state.offset = offset;
+        Ok(())
+    }
+
+    /// Positions this consumer at the specified offset of the given
+    /// topic partition; the next poll will fetch messages starting
+    /// with that offset.  The offsets marked as consumed so far are
+    /// not affected by this operation.
+    ///
+    /// If `Builder::with_seek_clamping` is enabled, an offset below
+    /// the earliest offset still available in the partition is
+    /// clamped to that earliest offset.  See
+    /// `KafkaClient::earliest_available_offset`.
+    ///
+    /// Results in an error if the specified topic partition is not
+    /// being consumed by this consumer.
+    pub fn seek(&mut self, topic: &str, partition: i32, offset: i64) -> Result<()> {
+        let tp = match self.state.topic_ref(topic) {
+            None => return Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)),
+            Some(topic_ref) => state::TopicPartition {
+                topic_ref,
+                partition,
+            },
+        };
+        if !self.state.fetch_offsets.contains_key(&tp) {
+            return Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition));
+        }
+        let offset = if self.config.seek_clamping {
+            let earliest = self.client.earliest_available_offset(topic, partition)?;
+            if offset < earliest {
+                debug!(
+                    "seek: clamping offset for {}:{} from {} to {}",
+                    topic, partition, offset, earliest
+                );
+                earliest
+            } else {
+                offset
+            }
+        } else {
+            offset
+        };
+        if let Some(buffered) = self.state.buffered.get_mut(&tp) {
+            buffered.clear();
+        }
+        if let Some(fetch_state) = self.state.fetch_offsets.get_mut(&tp) {
+            fetch_state.offset = offset;
+        }
+        Ok(())
+    }
+
+    /// Retrieves the offset of the next message this consumer will
+    /// fetch from the specified topic partition, i.e. the consumer's
+    /// current in-memory position in that partition.  The position
+    /// advances with every poll delivering messages and can be
+    /// changed through `Consumer::seek`.  Results in `None` if the
+    /// specified topic partition is not being consumed by this
+    /// consumer.
+    ///
+    /// Note: the position is unrelated to the offsets "marked as
+    /// consumed" or committed on behalf of this consumer's group; see
+    /// `Consumer::committed`.
+    pub fn position(&self, topic: &str, partition: i32) -> Option<i64> {
+        self.state
+            .topic_ref(topic)
+            .and_then(|topic_ref| {
+                self.state.fetch_offsets.get(&state::TopicPartition {
+                    topic_ref,
+                    partition,
+                })
+            })
+            .map(|fs| fs.offset)
+    }
+
+    /// Retrieves the high watermark of the specified topic partition,
+    /// i.e. the offset of the next message to be appended to it,
+    /// e.g. to compute the consumer's lag against its `position`.
+    /// Results in `None` if the specified topic partition is not
+    /// being consumed by this consumer.
+    ///
+    /// The high watermark is cached from the last fetch response for
+    /// the partition, such that no extra request is issued.  Only if
+    /// the partition has not been fetched yet, the latest offset is
+    /// requested from the brokers.
+    pub fn high_watermark(&mut self, topic: &str, partition: i32) -> Result<Option<i64>> {
+        let fetch_state = self.state.topic_ref(topic).and_then(|topic_ref| {
+            self.state.fetch_offsets.get(&state::TopicPartition {
+                topic_ref,
+                partition,
+            })
+        });
+        match fetch_state {
+            None => Ok(None),
+            Some(&state::FetchState {
+                high_watermark: Some(hw),
+                ..
+            }) => Ok(Some(hw)),
+            Some(_) => Ok(self
+                .client
+                .fetch_topic_offsets(topic, FetchOffset::Latest)?
+                .into_iter()
+                .find(|po| po.partition == partition)
+                .map(|po| po.offset)),
+        }
+    }
+
+    /// Fetches the offset committed to Kafka on behalf of this
+    /// consumer's group for the specified topic partition.  This is
+    /// the offset of the next message to be consumed by a consumer
+    /// (re-)starting to consume the partition as part of the group.
+    /// Results in `None` if the consumer has no group or if there is
+    /// no offset committed yet for the partition.
+    ///
+    /// Note: unlike `Consumer::position`, the committed offset
+    /// advances only through `Consumer::commit_consumed` and requires
+    /// a round trip to the brokers.
+    pub fn committed(&mut self, topic: &str, partition: i32) -> Result<Option<i64>> {
+        if self.config.group.is_empty() {
+            return Ok(None);
+        }
+        let offsets = self.client.fetch_group_offsets(
+            &self.config.group,
+            &[FetchGroupOffset::new(topic, partition)],
+        )?;
+        Ok(offsets
+            .get(topic)
+            .and_then(|ps| ps.iter().find(|p| p.partition == partition))
+            .map(|p| p.offset)
+            .filter(|&offset| offset >= 0))
+    }
+
     /// Retrieves the offset of the last "consumed" message in the
     /// specified partition. Results in `None` if there is no such
     /// "consumed" message.
@@ -379,6 +1158,9 @@ impl Consumer {
             topic_ref,
             partition,
         };
+        if let Some(buffered) = self.state.buffered.get_mut(&tp) {
+            *buffered = buffered.split_off(&(offset + 1));
+        }
         match self.state.consumed_offsets.entry(tp) {
             Entry::Vacant(v) => {
                 v.insert(state::ConsumedOffset {
@@ -397,6 +1179,90 @@ impl Consumer {
         Ok(())
     }
 
+    /// Stores the specified offset as the position to be committed
+    /// for the specified topic partition by the next
+    /// `Consumer::commit_consumed`.  The offset is the one of the
+    /// next message to be consumed, i.e. the offset of the last
+    /// processed message plus one.
+    ///
+    /// Unlike `Consumer::consume_message`, the stored position is
+    /// taken as is, even if it lies before the currently stored one.
+    /// Nothing is committed inline; this allows recording the
+    /// positions safe to commit while processing messages and
+    /// committing them in bulk later.
+    ///
+    /// Results in an error if the specified topic partition is not
+    /// being consumed by this consumer.
+    pub fn store_offset(&mut self, topic: &str, partition: i32, offset: i64) -> Result<()> {
+        let Some(topic_ref) = self.state.topic_ref(topic) else {
+            return Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition));
+        };
+        let tp = state::TopicPartition {
+            topic_ref,
+            partition,
+        };
+        if let Some(buffered) = self.state.buffered.get_mut(&tp) {
+            *buffered = buffered.split_off(&offset);
+        }
+        // ~ consumed offsets denote the last consumed message
+        self.state.consumed_offsets.insert(
+            tp,
+            state::ConsumedOffset {
+                offset: offset - 1,
+                dirty: true,
+            },
+        );
+        Ok(())
+    }
+
+    /// Acknowledges the message at the specified offset in the
+    /// specified topic partition as processed in manual
+    /// acknowledgment mode.  See `Builder::with_manual_ack`.
+    ///
+    /// Unlike `Consumer::consume_message`, acknowledging a message
+    /// does not implicitly cover the messages preceding it.  The
+    /// partition's consumed offset advances to the last of the
+    /// delivered messages up to which all have been acknowledged;
+    /// acknowledging a message which has not been delivered (yet)
+    /// has no effect.  Without manual acknowledgment mode, this
+    /// method is equivalent to `Consumer::consume_message`.
+    ///
+    /// Results in an error if the specified topic partition is not
+    /// being consumed by this consumer.
+    pub fn ack(&mut self, topic: &str, partition: i32, offset: i64) -> Result<()> {
+        if !self.config.manual_ack {
+            return self.consume_message(topic, partition, offset);
+        }
+        let topic_ref = match self.state.topic_ref(topic) {
+            None => return Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)),
+            Some(topic_ref) => topic_ref,
+        };
+        let tp = state::TopicPartition {
+            topic_ref,
+            partition,
+        };
+        let pending = match self.state.pending_acks.get_mut(&tp) {
+            None => return Ok(()),
+            Some(pending) => pending,
+        };
+        if let Some(acked) = pending.get_mut(&offset) {
+            *acked = true;
+        }
+        // ~ advance over the contiguous range of acknowledged
+        // messages at the front
+        let mut consumed = None;
+        while let Some(entry) = pending.first_entry() {
+            if !*entry.get() {
+                break;
+            }
+            consumed = Some(entry.remove_entry().0);
+        }
+        match consumed {
+            Some(offset) => self.consume_message(topic, partition, offset),
+            None => Ok(()),
+        }
+    }
+
     /// A convenience method to mark the given message set consumed as a
     /// whole by the caller. This is equivalent to marking the last
     /// message of the given set as consumed.
@@ -412,12 +1278,55 @@ impl Consumer {
         }
     }
 
+    /// A convenience method to mark the given message set obtained
+    /// through `Consumer::poll_bytes` as consumed.  See
+    /// `Consumer::consume_messageset`.
+    #[cfg(feature = "bytes")]
+    pub fn consume_bytes_messageset(&mut self, msgs: &BytesMessageSet) -> Result<()> {
+        match msgs.messages().last() {
+            Some(last) => self.consume_message(msgs.topic(), msgs.partition(), last.offset),
+            None => Ok(()),
+        }
+    }
+
     /// Persists the so-far "marked as consumed" messages (on behalf
     /// of this consumer's group for the underlying topic - if any.)
+    /// Positions stored through `Consumer::store_offset` are
+    /// committed as they are.
     ///
     /// See also `Consumer::consume_message` and
     /// `Consumer::consume_messageset`.
     pub fn commit_consumed(&mut self) -> Result<()> {
+        self.commit_dirty(None)
+    }
+
+    /// Shuts this consumer down deterministically: commits the
+    /// consumed offsets one last time if `Builder::with_auto_commit`
+    /// is enabled and closes all connections to the brokers.  Errors
+    /// are returned rather than logged; the connections are closed
+    /// even if the final commit fails.
+    ///
+    /// Merely dropping a consumer commits nothing and leaves the
+    /// connections to be closed by the operating system.
+    ///
+    /// Note: since this consumer does not take part in Kafka's group
+    /// membership protocol (see `Consumer::group_metadata`), it holds
+    /// no membership to release and sends no `LeaveGroup` request.
+    pub fn close(mut self) -> Result<()> {
+        let r = if self.config.auto_commit_interval.is_some() {
+            self.commit_consumed()
+        } else {
+            Ok(())
+        };
+        self.client.disconnect();
+        r
+    }
+
+    // ~ commits the dirty consumed offsets of the specified topic or
+    // all topics if none is given
+    fn commit_dirty(&mut self, topic_ref: Option<assignment::AssignmentRef>) -> Result<()> {
+        let in_scope =
+            |tp: &state::TopicPartition| topic_ref.is_none() || topic_ref == Some(tp.topic_ref);
         if self.config.group.is_empty() {
             debug!("commit_consumed: ignoring commit request since no group defined");
             return Ok(());
@@ -433,7 +1342,7 @@ impl Consumer {
             state
                 .consumed_offsets
                 .iter()
-                .filter(|&(_, o)| o.dirty)
+                .filter(|&(tp, o)| o.dirty && in_scope(tp))
                 .map(|(tp, o)| {
                     let topic = state.topic_name(tp.topic_ref);
 
@@ -445,8 +1354,8 @@ impl Consumer {
                     CommitOffset::new(topic, tp.partition, o.offset + 1)
                 }),
         )?;
-        for co in state.consumed_offsets.values_mut() {
-            if co.dirty {
+        for (tp, co) in &mut state.consumed_offsets {
+            if co.dirty && in_scope(tp) {
                 co.dirty = false;
             }
         }
@@ -454,12 +1363,41 @@ impl Consumer {
     }
 }
 
+// ~ determines the max_bytes to fetch from a partition given its own
+// limit and the budget of a poll shared equally by `n` partitions
+fn partition_max_bytes(max_bytes: i32, budget: Option<usize>, n: usize) -> i32 {
+    match budget {
+        None => max_bytes,
+        Some(budget) => {
+            let share = i32::try_from(budget / n.max(1)).unwrap_or(i32::MAX);
+            max_bytes.min(share.max(1))
+        }
+    }
+}
+
 // --------------------------------------------------------------------
 
 /// Messages retrieved from kafka in one fetch request.  This is a
 /// concatenation of blocks of messages successfully retrieved from
 /// the consumed topic partitions.  Each such partitions is guaranteed
 /// to be present at most once in this structure.
+///
+/// # Example
+/// ```no_run
+/// use kafka::consumer::Consumer;
+///
+/// let mut consumer = Consumer::from_hosts(vec!["localhost:9092".to_owned()])
+///     .with_topic("my-topic".to_owned())
+///     .create()
+///     .unwrap();
+/// let mss = consumer.poll().unwrap();
+/// for ms in &mss {
+///     for m in &ms {
+///         println!("{}:{}@{}: {:?}", ms.topic(), ms.partition(), m.offset, m.value);
+///     }
+///     consumer.consume_messageset(ms).unwrap();
+/// }
+/// ```
 #[derive(Debug)]
 pub struct MessageSets {
     responses: Vec<fetch::Response>,
@@ -494,6 +1432,17 @@ impl MessageSets {
             partitions,
         }
     }
+
+    /// Groups the message sets by the topic partition they were
+    /// fetched from, e.g. to dispatch them to per partition handlers.
+    /// Like `MessageSets::iter`, this delivers only partitions with
+    /// messages; the message sets refer to the fetched data without
+    /// copying it.
+    pub fn partition_sets(&self) -> HashMap<client::TopicPartition, MessageSet<'_>> {
+        self.iter()
+            .map(|ms| (client::TopicPartition::new(ms.topic, ms.partition), ms))
+            .collect()
+    }
 }
 
 /// A set of messages successfully retrieved from a specific topic
@@ -519,10 +1468,43 @@ impl<'a> MessageSet<'a> {
     pub fn messages(&self) -> &'a [Message<'a>] {
         self.messages
     }
+
+    /// Iterates over the messages of this set.
+    #[inline]
+    pub fn iter(&self) -> slice::Iter<'a, Message<'a>> {
+        self.messages.iter()
+    }
 }
 
-/// An iterator over the consumed topic partition message sets.
-pub struct MessageSetsIter<'a> {
+impl<'a> IntoIterator for MessageSet<'a> {
+    type Item = &'a Message<'a>;
+    type IntoIter = slice::Iter<'a, Message<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.messages.iter()
+    }
+}
+
+impl<'a> IntoIterator for &MessageSet<'a> {
+    type Item = &'a Message<'a>;
+    type IntoIter = slice::Iter<'a, Message<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.messages.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MessageSets {
+    type Item = MessageSet<'a>;
+    type IntoIter = MessageSetsIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the consumed topic partition message sets.
+pub struct MessageSetsIter<'a> {
     responses: slice::Iter<'a, fetch::Response>,
     topics: Option<slice::Iter<'a, fetch::Topic<'a>>>,
     curr_topic: &'a str,
@@ -573,3 +1555,1291 @@ impl<'a> Iterator for MessageSetsIter<'a> {
         }
     }
 }
+
+/// Messages retrieved from kafka in one fetch request, backed by
+/// reference counted buffers.  See `Consumer::poll_bytes`.
+#[cfg(feature = "bytes")]
+#[derive(Debug, Clone)]
+pub struct BytesMessageSets {
+    sets: Vec<BytesMessageSet>,
+}
+
+#[cfg(feature = "bytes")]
+impl BytesMessageSets {
+    /// Determines efficiently whether there are any consumeable
+    /// messages in this data set.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    /// Iterates over the message sets delivering the fetched message
+    /// data of consumed topic partitions.
+    #[inline]
+    pub fn iter(&self) -> slice::Iter<'_, BytesMessageSet> {
+        self.sets.iter()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl IntoIterator for BytesMessageSets {
+    type Item = BytesMessageSet;
+    type IntoIter = std::vec::IntoIter<BytesMessageSet>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.sets.into_iter()
+    }
+}
+
+/// A fetched message owning its key and value.  See
+/// `Consumer::consume_range`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedMessage {
+    /// The offset at which this message resides in the remote kafka
+    /// broker topic partition.
+    pub offset: i64,
+
+    /// The "key" data of this message.  `None` if the message was
+    /// sent with a "null" key.
+    pub key: Option<Vec<u8>>,
+
+    /// The value data of this message.  Empty if there is no such
+    /// data for this message.
+    pub value: Vec<u8>,
+}
+
+impl OwnedMessage {
+    /// Retrieves the key of this message; `None` for a "null" key,
+    /// `Some(&[])` for an empty one.
+    #[inline]
+    pub fn key(&self) -> Option<&[u8]> {
+        self.key.as_deref()
+    }
+
+    /// Retrieves the key and the value of this message as a pair,
+    /// e.g. for convenient comparisons in tests.
+    ///
+    /// ```
+    /// use kafka::consumer::OwnedMessage;
+    ///
+    /// let msg = OwnedMessage {
+    ///     offset: 42,
+    ///     key: Some(b"k".to_vec()),
+    ///     value: b"v".to_vec(),
+    /// };
+    /// assert_eq!(msg.pair(), (Some(b"k".as_ref()), b"v".as_ref()));
+    /// ```
+    #[inline]
+    pub fn pair(&self) -> (Option<&[u8]>, &[u8]) {
+        (self.key(), &self.value)
+    }
+}
+
+impl<'a> From<&Message<'a>> for OwnedMessage {
+    fn from(msg: &Message<'a>) -> Self {
+        OwnedMessage {
+            offset: msg.offset,
+            key: msg.key.map(<[u8]>::to_vec),
+            value: msg.value.to_vec(),
+        }
+    }
+}
+
+/// A hook to inspect, modify or filter fetched messages before
+/// `Consumer::poll` delivers them, e.g. to decrypt message values or
+/// to collect metrics.  Interceptors are registered through
+/// `Builder::with_interceptor`; multiple interceptors are invoked in
+/// the order they were registered in, each seeing the message as
+/// modified by its predecessors.
+pub trait ConsumeInterceptor: fmt::Debug + Send + Sync {
+    /// Invoked for every fetched message.  Changes to the message's
+    /// key and value are delivered in place of the fetched data;
+    /// changes to its offset are ignored.
+    ///
+    /// Returning `false` drops the message: it is neither delivered
+    /// nor handed to subsequent interceptors.  The consumer still
+    /// advances past dropped messages such that they are not fetched
+    /// again.  With `Builder::with_manual_ack`, dropped messages are
+    /// acknowledged automatically.
+    fn on_consume(&self, msg: &mut OwnedMessage) -> bool;
+}
+
+/// Describes the truncation of a consumed partition's log detected
+/// by the consumer.  See `TruncationListener`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogTruncation {
+    /// The topic of the truncated partition.
+    pub topic: String,
+    /// The truncated partition.
+    pub partition: i32,
+    /// The leader epoch the consumer was fetching at before the
+    /// partition's leadership changed.
+    pub leader_epoch: i32,
+    /// The offset the consumer was about to fetch from; messages
+    /// below it but at or above `end_offset` have been delivered but
+    /// are no longer part of the partition's log.
+    pub fetch_offset: i64,
+    /// The end offset of `leader_epoch` as reported by the new
+    /// leader; the consumer continues fetching from here.
+    pub end_offset: i64,
+}
+
+/// A hook to be notified about log truncations detected by the
+/// consumer, e.g. after an unclean leader election.  Listeners are
+/// registered through `Builder::with_truncation_listener`.
+///
+/// Whenever a consumed partition's leader epoch changes, the consumer
+/// asks the new leader for the end offset of the epoch it was
+/// fetching at.  If that end offset is lower than the consumer's
+/// fetch position, the log has diverged; the consumer resets the
+/// position to the end offset and notifies the listener before the
+/// next fetch.  Offsets marked as consumed are not affected.
+pub trait TruncationListener: fmt::Debug + Send + Sync {
+    /// Invoked for every detected truncation after the fetch position
+    /// has been reset.
+    fn on_log_truncation(&self, truncation: &LogTruncation);
+}
+
+/// An iterator over the messages of a bounded offset range of a topic
+/// partition.  See `Consumer::consume_range`.
+#[derive(Debug)]
+pub struct ConsumeRange<'a> {
+    client: &'a mut KafkaClient,
+    topic: String,
+    partition: i32,
+    // ~ the offset of the next message to fetch
+    offset: i64,
+    // ~ the offset of the last message to deliver
+    end: i64,
+    // ~ the messages fetched but not delivered yet
+    buffered: VecDeque<OwnedMessage>,
+    // ~ whether to stop fetching, e.g. after an error
+    done: bool,
+}
+
+impl ConsumeRange<'_> {
+    // ~ fetches the next chunk of messages into `self.buffered`
+    fn fetch(&mut self) -> Result<()> {
+        let req = FetchPartition::new(&self.topic, self.partition, self.offset);
+        let resps = self.client.fetch_messages_for_partition(&req)?;
+        let data = resps
+            .iter()
+            .flat_map(fetch::Response::topics)
+            .flat_map(fetch::Topic::partitions)
+            .find(|p| p.partition() == self.partition)
+            .ok_or(Error::Kafka(KafkaCode::UnknownTopicOrPartition))?
+            .data()?;
+
+        // ~ stop at the latest message available by now
+        let hw = data.highwatermark_offset();
+        if self.end >= hw {
+            self.end = hw - 1;
+        }
+        for msg in data.messages() {
+            // ~ compressed message sets may start before the
+            // requested offset
+            if msg.offset < self.offset {
+                continue;
+            }
+            if msg.offset > self.end {
+                break;
+            }
+            self.buffered.push_back(OwnedMessage::from(msg));
+            self.offset = msg.offset + 1;
+        }
+        if self.buffered.is_empty() && self.offset <= self.end && data.is_truncated() {
+            // ~ data is available but did not fit into the fetch
+            return Err(Error::Kafka(KafkaCode::MessageSizeTooLarge));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ConsumeRange<'_> {
+    type Item = Result<OwnedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(msg) = self.buffered.pop_front() {
+                return Some(Ok(msg));
+            }
+            if self.done || self.offset > self.end {
+                return None;
+            }
+            if let Err(e) = self.fetch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// The identity of a consumer within its group as needed to commit
+/// offsets on behalf of the group.  See `Consumer::group_metadata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupMetadata {
+    /// The name of the group.
+    pub group_id: String,
+    /// The generation of the group the consumer is a member of.
+    pub generation_id: i32,
+    /// The id of the consumer as a member of the group.
+    pub member_id: String,
+    /// The assignment protocol selected by the group.
+    pub protocol: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ConsumeInterceptor, Consumer, FetchOffset, OwnedMessage, GROUP_GENERATION_NONE};
+    use crate::client::fake_broker::{serve, serve_with_coordinator, serve_with_value_size};
+    use crate::error::{Error, KafkaCode};
+    use crate::producer::{Producer, Record};
+
+    fn fetch_offset(consumer: &Consumer, topic: &str, partition: i32) -> i64 {
+        let topic_ref = consumer.state.topic_ref(topic).unwrap();
+        consumer.state.fetch_offsets[&super::state::TopicPartition {
+            topic_ref,
+            partition,
+        }]
+            .offset
+    }
+
+    #[test]
+    fn test_partition_sets() {
+        use crate::client::TopicPartition;
+
+        let (host, _) = serve(&[("foo", 3), ("bar", 2)], (0, 5));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_topic("bar".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+
+        let mss = consumer.poll().unwrap();
+        let sets = mss.partition_sets();
+        let mut keys: Vec<_> = sets.keys().cloned().collect();
+        keys.sort();
+        let mut assigned: Vec<_> = consumer
+            .subscriptions()
+            .into_iter()
+            .flat_map(|(t, ps)| ps.into_iter().map(move |p| TopicPartition::new(&t, p)))
+            .collect();
+        assigned.sort();
+        assert_eq!(assigned, keys);
+        for (tp, ms) in &sets {
+            assert_eq!((&tp.topic[..], tp.partition), (ms.topic(), ms.partition()));
+            // ~ the sets refer to the polled data
+            let polled = mss
+                .iter()
+                .find(|m| m.topic() == ms.topic() && m.partition() == ms.partition());
+            assert!(std::ptr::eq(polled.unwrap().messages(), ms.messages()));
+        }
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_poll_bytes() {
+        let (host, _) = serve(&[("foo", 2)], (0, 3));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_group("my-group".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+
+        // ~ the messages outlive the polls they were fetched by
+        let mut msgs = Vec::new();
+        for _ in 0..3 {
+            for ms in consumer.poll_bytes().unwrap() {
+                consumer.consume_bytes_messageset(&ms).unwrap();
+                msgs.extend(ms.into_messages());
+            }
+        }
+        assert!(consumer.poll_bytes().unwrap().is_empty());
+        let mut values: Vec<_> = msgs.iter().map(|m| (m.offset, &m.value[..])).collect();
+        values.sort();
+        assert_eq!(
+            vec![
+                (0, &b"0"[..]),
+                (0, b"0"),
+                (1, b"1"),
+                (1, b"1"),
+                (2, b"2"),
+                (2, b"2")
+            ],
+            values
+        );
+        assert_eq!(Some(3), consumer.position("foo", 1));
+    }
+
+    #[test]
+    fn test_adaptive_fetch() {
+        let (host, _) = serve(&[("foo", 1)], (0, 2));
+        let mut consumer = Consumer::from_hosts(vec![host.clone()])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_adaptive_fetch(Duration::from_millis(10), Duration::from_millis(80))
+            .create()
+            .unwrap();
+        let poll = |consumer: &mut Consumer| {
+            let n = consumer.poll().unwrap().iter().count();
+            (n, consumer.client().fetch_max_wait_time().as_millis())
+        };
+        assert_eq!(10, consumer.client().fetch_max_wait_time().as_millis());
+
+        // ~ one message per fetch: data flows, but at the minimum wait
+        assert_eq!((1, 10), poll(&mut consumer));
+        assert_eq!((1, 10), poll(&mut consumer));
+        // ~ no data: wait longer up to the maximum
+        assert_eq!((0, 20), poll(&mut consumer));
+        assert_eq!((0, 40), poll(&mut consumer));
+        assert_eq!((0, 80), poll(&mut consumer));
+        assert_eq!((0, 80), poll(&mut consumer));
+
+        // ~ a burst of data: shorten the wait as long as there's more
+        // data available than delivered
+        let mut producer = Producer::from_hosts(vec![host]).create().unwrap();
+        for _ in 0..3 {
+            producer
+                .send(&Record::from_value("foo", "bar").with_partition(0))
+                .unwrap();
+        }
+        assert_eq!((1, 40), poll(&mut consumer));
+        assert_eq!((1, 20), poll(&mut consumer));
+        assert_eq!((1, 20), poll(&mut consumer));
+        assert_eq!((0, 40), poll(&mut consumer));
+    }
+
+    #[test]
+    fn test_coordinator_move() {
+        let (host, _, coordinator) = serve_with_coordinator(&[("foo", 1)], (0, 10));
+        let (new_host, _) = serve(&[("foo", 1)], (0, 10));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_group("my-group".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+        consumer
+            .client_mut()
+            .set_retry_backoff_time(Duration::from_millis(1));
+        let consume = |consumer: &mut Consumer| {
+            for ms in consumer.poll().unwrap().iter() {
+                consumer.consume_messageset(ms).unwrap();
+            }
+            consumer.commit_consumed().unwrap();
+        };
+
+        consume(&mut consumer);
+        assert_eq!(Some(1), consumer.committed("foo", 0).unwrap());
+
+        // ~ the group's coordination moves to another broker; the
+        // consumer follows without further ado
+        *coordinator.lock().unwrap() = Some((1, new_host));
+        consume(&mut consumer);
+        assert_eq!(Some(2), consumer.committed("foo", 0).unwrap());
+    }
+
+    #[test]
+    fn test_group_metadata() {
+        let (host, _) = serve(&[("foo", 1)], (0, 3));
+        let consumer = Consumer::from_hosts(vec![host.clone()])
+            .with_topic("foo".to_owned())
+            .with_group("my-group".to_owned())
+            .create()
+            .unwrap();
+        let md = consumer.group_metadata().unwrap();
+        assert_eq!("my-group", md.group_id);
+        assert_eq!(GROUP_GENERATION_NONE, md.generation_id);
+        assert_eq!("", md.member_id);
+        assert_eq!("", md.protocol);
+
+        let consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .create()
+            .unwrap();
+        assert_eq!(None, consumer.group_metadata());
+    }
+
+    #[test]
+    fn test_manual_ack() {
+        let (host, _) = serve(&[("foo", 1)], (0, 10));
+        let create = || {
+            Consumer::from_hosts(vec![host.clone()])
+                .with_topic("foo".to_owned())
+                .with_group("my-group".to_owned())
+                .with_fallback_offset(FetchOffset::Earliest)
+                .with_manual_ack(true)
+                .create()
+                .unwrap()
+        };
+        let poll = |consumer: &mut Consumer| -> Vec<i64> {
+            let mss = consumer.poll().unwrap();
+            mss.iter()
+                .flat_map(|ms| ms.messages().iter().map(|m| m.offset))
+                .collect()
+        };
+
+        let mut consumer = create();
+        let mut delivered = Vec::new();
+        for _ in 0..3 {
+            delivered.extend(poll(&mut consumer));
+        }
+        assert_eq!(vec![0, 1, 2], delivered);
+
+        // ~ the message at offset 1 fails to be processed; the
+        // consumed offset must not advance past it
+        consumer.ack("foo", 0, 0).unwrap();
+        consumer.ack("foo", 0, 2).unwrap();
+        assert_eq!(Some(0), consumer.last_consumed_message("foo", 0));
+        consumer.commit_consumed().unwrap();
+        assert_eq!(Some(1), consumer.committed("foo", 0).unwrap());
+        drop(consumer);
+
+        // ~ after reconnecting the unacknowledged message is
+        // delivered again
+        let mut consumer = create();
+        assert_eq!(vec![1], poll(&mut consumer));
+        consumer.ack("foo", 0, 1).unwrap();
+        assert_eq!(Some(1), consumer.last_consumed_message("foo", 0));
+        assert!(consumer.ack("bar", 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_fetch_buffer_bounds() {
+        let (host, _) = serve(&[("foo", 1)], (0, 100));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_fetch_buffer_bounds(2, 3)
+            .create()
+            .unwrap();
+        let poll = |consumer: &mut Consumer| -> Vec<i64> {
+            let mss = consumer.poll().unwrap();
+            mss.iter()
+                .flat_map(|ms| ms.messages().iter().map(|m| m.offset))
+                .collect()
+        };
+
+        // ~ nothing is consumed; fetching must pause once more than
+        // the high mark of messages is buffered
+        let mut delivered = Vec::new();
+        for _ in 0..10 {
+            delivered.extend(poll(&mut consumer));
+        }
+        assert_eq!(vec![0, 1, 2, 3], delivered);
+        assert_eq!(Some(4), consumer.position("foo", 0));
+
+        // ~ draining down to the high mark is not enough to resume
+        consumer.consume_message("foo", 0, 0).unwrap();
+        assert!(poll(&mut consumer).is_empty());
+
+        // ~ draining below the low mark resumes fetching
+        consumer.consume_message("foo", 0, 2).unwrap();
+        assert_eq!(vec![4], poll(&mut consumer));
+        assert_eq!(vec![5], poll(&mut consumer));
+        assert_eq!(vec![6], poll(&mut consumer));
+        assert!(poll(&mut consumer).is_empty());
+        assert_eq!(Some(7), consumer.position("foo", 0));
+    }
+
+    #[test]
+    fn test_store_offset() {
+        use crate::client::fake_broker::MockBroker;
+
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut consumer = Consumer::from_hosts(vec![broker.host().to_owned()])
+            .with_topic("foo".to_owned())
+            .with_group("my-group".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+
+        // ~ storing offsets commits nothing inline
+        consumer.store_offset("foo", 0, 3).unwrap();
+        consumer.store_offset("foo", 0, 7).unwrap();
+        consumer.store_offset("foo", 1, 2).unwrap();
+        assert_eq!(None, broker.committed_offset("foo", 0));
+
+        // ~ the last stored offset wins, even if it lies before an
+        // earlier stored one
+        consumer.store_offset("foo", 0, 5).unwrap();
+        consumer.commit_consumed().unwrap();
+        assert_eq!(Some(5), broker.committed_offset("foo", 0));
+        assert_eq!(Some(2), broker.committed_offset("foo", 1));
+
+        assert!(consumer.store_offset("bar", 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_auto_commit() {
+        use crate::client::fake_broker::MockBroker;
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut consumer = Consumer::from_hosts(vec![broker.host().to_owned()])
+            .with_topic("foo".to_owned())
+            .with_group("my-group".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_auto_commit(Duration::from_millis(100))
+            .create()
+            .unwrap();
+        let poll = |consumer: &mut Consumer| {
+            for ms in consumer.poll().unwrap().iter() {
+                consumer.consume_messageset(ms).unwrap();
+            }
+        };
+
+        // ~ consumed messages are not committed before the interval
+        // elapsed ...
+        poll(&mut consumer);
+        poll(&mut consumer);
+        assert_eq!(None, broker.committed_offset("foo", 0));
+
+        // ~ ... but with the first poll thereafter
+        std::thread::sleep(Duration::from_millis(150));
+        poll(&mut consumer);
+        assert_eq!(Some(2), broker.committed_offset("foo", 0));
+        poll(&mut consumer);
+        assert_eq!(Some(2), broker.committed_offset("foo", 0));
+    }
+
+    #[test]
+    fn test_close() {
+        use crate::client::fake_broker::MockBroker;
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let create = |auto_commit: bool| {
+            let b = Consumer::from_hosts(vec![broker.host().to_owned()])
+                .with_topic("foo".to_owned())
+                .with_group("my-group".to_owned())
+                .with_fallback_offset(FetchOffset::Earliest);
+            let b = if auto_commit {
+                b.with_auto_commit(Duration::from_secs(3600))
+            } else {
+                b
+            };
+            b.create().unwrap()
+        };
+        let poll = |consumer: &mut Consumer| {
+            for ms in consumer.poll().unwrap().iter() {
+                consumer.consume_messageset(ms).unwrap();
+            }
+        };
+
+        // ~ without auto commits, closing commits nothing
+        let mut consumer = create(false);
+        poll(&mut consumer);
+        consumer.close().unwrap();
+        assert_eq!(None, broker.committed_offset("foo", 0));
+
+        // ~ with auto commits, closing commits the consumed offsets
+        // even though the next automatic commit is not due yet
+        let mut consumer = create(true);
+        poll(&mut consumer);
+        poll(&mut consumer);
+        assert_eq!(None, broker.committed_offset("foo", 0));
+        consumer.close().unwrap();
+        assert_eq!(Some(2), broker.committed_offset("foo", 0));
+    }
+
+    #[test]
+    fn test_fetch_ordering_per_partition() {
+        use std::collections::HashMap;
+
+        let (host, _) = serve(&[("foo", 3), ("bar", 2)], (0, 5));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_topic("bar".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+
+        let mut delivered: HashMap<(String, i32), Vec<i64>> = HashMap::new();
+        for _ in 0..5 {
+            let mss = consumer.poll().unwrap();
+            // ~ within a poll, the messages of a partition form a
+            // single contiguous run
+            let mut seen = Vec::new();
+            for ms in mss.iter() {
+                for m in ms.messages() {
+                    let tp = (ms.topic().to_owned(), ms.partition());
+                    if seen.last() != Some(&tp) {
+                        assert!(!seen.contains(&tp), "{:?} interleaved", tp);
+                        seen.push(tp.clone());
+                    }
+                    delivered.entry(tp).or_default().push(m.offset);
+                }
+            }
+        }
+        // ~ across polls, in offset order without gaps
+        assert_eq!(5, delivered.len());
+        for offsets in delivered.values() {
+            assert_eq!(&vec![0, 1, 2, 3, 4], offsets);
+        }
+    }
+
+    #[test]
+    fn test_fetch_large_message() {
+        let (host, _) = serve_with_value_size(&[("foo", 1)], (0, 3), 1000);
+        let create = |limit| {
+            Consumer::from_hosts(vec![host.clone()])
+                .with_topic("foo".to_owned())
+                .with_fallback_offset(FetchOffset::Earliest)
+                .with_fetch_max_bytes_per_partition(100)
+                .with_retry_max_bytes_limit(limit)
+                .create()
+                .unwrap()
+        };
+
+        // ~ the limit is raised until the message fits
+        let mut consumer = create(super::DEFAULT_RETRY_MAX_BYTES_LIMIT);
+        let mut polls = 0;
+        let value = loop {
+            polls += 1;
+            assert!(polls <= 10, "message not delivered");
+            let mss = consumer.poll().unwrap();
+            if let Some(ms) = mss.iter().next() {
+                assert_eq!(0, ms.messages()[0].offset);
+                break ms.messages()[0].value.len();
+            }
+        };
+        assert_eq!(1000, value);
+        assert!(polls > 1);
+
+        // ~ beyond the cap the consumer gives up
+        let mut consumer = create(500);
+        let e = loop {
+            match consumer.poll() {
+                Ok(mss) => assert!(mss.is_empty()),
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(e, Error::Kafka(KafkaCode::MessageSizeTooLarge)));
+    }
+
+    #[test]
+    fn test_consume_range() {
+        let (host, _) = serve(&[("foo", 2)], (0, 50));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .create()
+            .unwrap();
+        let offsets = |consumer: &mut Consumer, start, end| {
+            consumer
+                .consume_range("foo", 1, start, end)
+                .map(|m| m.unwrap().offset)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            (10..=20).collect::<Vec<_>>(),
+            offsets(&mut consumer, 10, 20)
+        );
+        // ~ the range is cut at the high watermark
+        assert_eq!(
+            (40..50).collect::<Vec<_>>(),
+            offsets(&mut consumer, 40, 100)
+        );
+        assert!(offsets(&mut consumer, 50, 100).is_empty());
+        assert!(offsets(&mut consumer, 20, 10).is_empty());
+
+        // ~ errors terminate the iteration
+        let codes = |rs: Vec<Result<_, Error>>| {
+            rs.into_iter()
+                .map(|r| r.unwrap_err().kafka_code())
+                .collect::<Vec<_>>()
+        };
+        let rs: Vec<_> = consumer.consume_range("foo", 1, 60, 70).collect();
+        assert_eq!(vec![Some(KafkaCode::OffsetOutOfRange)], codes(rs));
+        let rs: Vec<_> = consumer.consume_range("foo", 5, 0, 10).collect();
+        assert_eq!(vec![Some(KafkaCode::UnknownTopicOrPartition)], codes(rs));
+
+        // ~ a message cut off at the fetch size is reported as too large
+        let (host, _) = serve_with_value_size(&[("foo", 1)], (0, 3), 1000);
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_fetch_max_bytes_per_partition(100)
+            .create()
+            .unwrap();
+        let rs: Vec<_> = consumer.consume_range("foo", 0, 0, 2).collect();
+        assert_eq!(vec![Some(KafkaCode::MessageSizeTooLarge)], codes(rs));
+    }
+
+    #[test]
+    fn test_interceptors() {
+        // ~ drops messages at odd offsets and tags the others
+        #[derive(Debug)]
+        struct EvenOnly;
+        impl ConsumeInterceptor for EvenOnly {
+            fn on_consume(&self, msg: &mut OwnedMessage) -> bool {
+                msg.value.extend_from_slice(b"!");
+                msg.offset % 2 == 0
+            }
+        }
+
+        let (host, _) = serve(&[("foo", 1)], (0, 6));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_interceptor(EvenOnly)
+            .create()
+            .unwrap();
+        let (mut values, mut empty) = (Vec::new(), Vec::new());
+        for _ in 0..6 {
+            let mss = consumer.poll().unwrap();
+            empty.push(mss.is_empty());
+            for ms in &mss {
+                for m in ms.messages() {
+                    values.push(String::from_utf8(m.value.to_vec()).unwrap());
+                }
+            }
+        }
+        assert_eq!(vec!["0!", "2!", "4!"], values);
+        assert_eq!(vec![false, true, false, true, false, true], empty);
+        // ~ dropped messages are not fetched again
+        assert_eq!(Some(6), consumer.position("foo", 0));
+        assert!(consumer.poll().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_typed_consumer() {
+        use super::{JsonDeserializer, StringDeserializer};
+
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Event {
+            id: u32,
+            name: String,
+        }
+
+        let (host, _) = serve(&[("foo", 1)], (0, 0));
+        let mut producer = Producer::from_hosts(vec![host.clone()]).create().unwrap();
+        let event = Event {
+            id: 1,
+            name: "one".to_owned(),
+        };
+        let rec = Record::from_json("foo", &event).unwrap();
+        producer
+            .send(&Record::from_key_value("foo", "k", rec.value))
+            .unwrap();
+        producer
+            .send(&Record::from_value("foo", "not json"))
+            .unwrap();
+
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_value_deserializer(JsonDeserializer::<Event>::new())
+            .with_key_deserializer(StringDeserializer)
+            .create()
+            .unwrap();
+        let mut msgs = Vec::new();
+        for _ in 0..2 {
+            msgs.extend(consumer.poll().unwrap());
+        }
+        assert_eq!(2, msgs.len());
+        assert_eq!(
+            ("foo", 0, 0),
+            (&msgs[0].topic[..], msgs[0].partition, msgs[0].offset)
+        );
+        assert_eq!("k", msgs[0].key.as_ref().unwrap().as_ref().unwrap());
+        assert_eq!(&event, msgs[0].value.as_ref().unwrap());
+        // ~ a malformed message does not fail the poll
+        assert_eq!(1, msgs[1].offset);
+        assert!(msgs[1].key.is_none());
+        assert!(matches!(msgs[1].value, Err(Error::CodecError)));
+        // ~ the typed consumer advances like the untyped one
+        assert_eq!(Some(2), consumer.position("foo", 0));
+        assert!(consumer.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fetch_max_bytes() {
+        let (host, _) = serve_with_value_size(&[("foo", 10)], (0, 3), 100);
+        let create = |max_bytes| {
+            Consumer::from_hosts(vec![host.clone()])
+                .with_topic("foo".to_owned())
+                .with_fallback_offset(FetchOffset::Earliest)
+                .with_fetch_max_bytes(max_bytes)
+                .with_retry_max_bytes_limit(0)
+                .create()
+                .unwrap()
+        };
+        let poll = |consumer: &mut Consumer| {
+            let mss = consumer.poll().unwrap();
+            let values: Vec<usize> = mss
+                .iter()
+                .flat_map(|ms| ms.messages().iter().map(|m| m.value.len()))
+                .collect();
+            (values.len(), values.iter().sum::<usize>())
+        };
+
+        // ~ a share of 200 bytes per partition fits a message each
+        let mut consumer = create(2000);
+        let (n, bytes) = poll(&mut consumer);
+        assert_eq!(10, n);
+        assert!(bytes <= 2000);
+
+        // ~ a share of 50 bytes per partition does not
+        let mut consumer = create(500);
+        assert_eq!((0, 0), poll(&mut consumer));
+
+        // ~ the per-partition limit is reduced to the budget
+        let consumer = create(500);
+        assert_eq!(500, consumer.client().fetch_max_bytes_per_partition());
+    }
+
+    #[test]
+    fn test_iterate_message_sets() {
+        let (host, _) = serve(&[("foo", 2)], (0, 3));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+        let mss = consumer.poll().unwrap();
+        let mut seen = Vec::new();
+        for ms in &mss {
+            for m in &ms {
+                seen.push((ms.partition(), m.offset));
+            }
+            assert_eq!(ms.messages().len(), ms.iter().count());
+            assert_eq!(ms.messages().len(), ms.into_iter().count());
+        }
+        seen.sort_unstable();
+        assert_eq!(vec![(0, 0), (1, 0)], seen);
+    }
+
+    #[test]
+    fn test_position_and_committed() {
+        let (host, _) = serve(&[("foo", 1)], (10, 20));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_group("my-group".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+        assert_eq!(Some(10), consumer.position("foo", 0));
+        assert_eq!(None, consumer.position("foo", 1));
+        assert_eq!(None, consumer.committed("foo", 0).unwrap());
+
+        for expected in 11..14 {
+            let mss = consumer.poll().unwrap();
+            for ms in mss.iter() {
+                consumer.consume_messageset(ms).unwrap();
+            }
+            assert_eq!(Some(expected), consumer.position("foo", 0));
+            assert_eq!(None, consumer.committed("foo", 0).unwrap());
+        }
+
+        consumer.commit_consumed().unwrap();
+        assert_eq!(Some(13), consumer.position("foo", 0));
+        assert_eq!(Some(13), consumer.committed("foo", 0).unwrap());
+
+        consumer.poll().unwrap();
+        assert_eq!(Some(14), consumer.position("foo", 0));
+        assert_eq!(Some(13), consumer.committed("foo", 0).unwrap());
+    }
+
+    #[test]
+    fn test_high_watermark() {
+        use crate::client::fake_broker::MockBroker;
+        use crate::producer::{Producer, Record};
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut consumer = Consumer::from_hosts(vec![broker.host().to_owned()])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+        // ~ counts the list offsets requests (api key 2) seen so far
+        let num_offset_requests = || {
+            let reqs = broker.requests();
+            reqs.iter().filter(|&&k| k == 2).count()
+        };
+
+        // ~ not fetched yet; falls back to an offset request
+        let n = num_offset_requests();
+        assert_eq!(Some(10), consumer.high_watermark("foo", 0).unwrap());
+        assert_eq!(n + 1, num_offset_requests());
+        assert_eq!(None, consumer.high_watermark("foo", 1).unwrap());
+        assert_eq!(None, consumer.high_watermark("bar", 0).unwrap());
+
+        consumer.poll().unwrap();
+        let n = num_offset_requests();
+        assert_eq!(Some(10), consumer.high_watermark("foo", 0).unwrap());
+
+        // ~ the cache follows the fetches
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .create()
+            .unwrap();
+        producer.send(&Record::from_value("foo", "x")).unwrap();
+        consumer.poll().unwrap();
+        assert_eq!(Some(11), consumer.high_watermark("foo", 0).unwrap());
+        assert_eq!(n, num_offset_requests());
+    }
+
+    #[test]
+    fn test_log_truncation() {
+        use std::sync::{Arc, Mutex};
+
+        use super::{LogTruncation, TruncationListener};
+        use crate::client::fake_broker::MockBroker;
+        use crate::client::{KafkaClient, MetadataVersion};
+
+        #[derive(Debug, Default, Clone)]
+        struct Recorder(Arc<Mutex<Vec<LogTruncation>>>);
+        impl TruncationListener for Recorder {
+            fn on_log_truncation(&self, truncation: &LogTruncation) {
+                self.0.lock().unwrap().push(truncation.clone());
+            }
+        }
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.set_metadata_version(MetadataVersion::V7);
+        client.load_metadata_all().unwrap();
+        let recorder = Recorder::default();
+        let mut consumer = Consumer::from_client(client)
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_truncation_listener(recorder.clone())
+            .create()
+            .unwrap();
+        for _ in 0..5 {
+            consumer.poll().unwrap();
+        }
+        assert_eq!(5, fetch_offset(&consumer, "foo", 0));
+
+        // ~ the new leader's log ends below the fetch position
+        broker.bump_leader_epoch(3);
+        consumer.client_mut().load_metadata_all().unwrap();
+        let mss = consumer.poll().unwrap();
+        let offsets: Vec<i64> = mss
+            .iter()
+            .flat_map(|ms| ms.messages())
+            .map(|m| m.offset)
+            .collect();
+        assert_eq!(vec![3], offsets);
+        assert_eq!(
+            vec![LogTruncation {
+                topic: "foo".to_owned(),
+                partition: 0,
+                leader_epoch: 0,
+                fetch_offset: 5,
+                end_offset: 3,
+            }],
+            *recorder.0.lock().unwrap()
+        );
+
+        // ~ a leadership change without truncation
+        broker.bump_leader_epoch(10);
+        consumer.client_mut().load_metadata_all().unwrap();
+        consumer.poll().unwrap();
+        assert_eq!(5, fetch_offset(&consumer, "foo", 0));
+        assert_eq!(1, recorder.0.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_fenced_leader_epoch() {
+        use crate::client::fake_broker::MockBroker;
+        use crate::client::{KafkaClient, MetadataVersion};
+
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+        client.set_metadata_version(MetadataVersion::V7);
+        client.load_metadata_all().unwrap();
+        let mut consumer = Consumer::from_client(client)
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+        for _ in 0..5 {
+            consumer.poll().unwrap();
+        }
+        assert_eq!(5, fetch_offset(&consumer, "foo", 0));
+
+        // ~ an unclean leader election the client does not know about
+        // yet; the new leader rejects the fetches of the partition
+        broker.bump_leader_epoch(3);
+        broker.set_partition_error("foo", 0, Some(KafkaCode::FencedLeaderEpoch));
+        let num_requests = broker.requests().len();
+        let mss = consumer.poll().unwrap();
+        let delivered: Vec<_> = mss.iter().map(|ms| ms.partition()).collect();
+        assert_eq!(vec![1], delivered);
+        // ~ the fetch is followed by a metadata refresh and the
+        // validation of the fetch positions against the new epoch
+        // (of both partitions)
+        assert_eq!(vec![1, 3, 23, 23], broker.requests()[num_requests..]);
+        assert_eq!(3, fetch_offset(&consumer, "foo", 0));
+
+        broker.set_partition_error("foo", 0, None);
+        let mss = consumer.poll().unwrap();
+        let offsets: Vec<_> = mss
+            .iter()
+            .filter(|ms| ms.partition() == 0)
+            .flat_map(|ms| ms.messages())
+            .map(|m| m.offset)
+            .collect();
+        assert_eq!(vec![3], offsets);
+    }
+
+    #[test]
+    fn test_partition_fetch_bytes() {
+        use crate::client::fake_broker::MockBroker;
+
+        let broker = MockBroker::start(&[("foo", 2), ("bar", 1)], (0, 10));
+        let mut consumer = Consumer::from_hosts(vec![broker.host().to_owned()])
+            .with_topic("foo".to_owned())
+            .with_topic("bar".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_fetch_max_bytes_per_partition(1000)
+            .with_partition_fetch_bytes("bar".to_owned(), 5000)
+            .create()
+            .unwrap();
+        consumer.poll().unwrap();
+        assert_eq!(Some(1000), broker.fetch_max_bytes("foo", 0));
+        assert_eq!(Some(1000), broker.fetch_max_bytes("foo", 1));
+        assert_eq!(Some(5000), broker.fetch_max_bytes("bar", 0));
+    }
+
+    #[test]
+    fn test_leaderless_partition_skipping() {
+        use crate::client::fake_broker::MockBroker;
+        use crate::client::TopicPartition;
+
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut consumer = Consumer::from_hosts(vec![broker.host().to_owned()])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_leaderless_partition_skipping(3)
+            .create()
+            .unwrap();
+        consumer.poll().unwrap();
+
+        // ~ all replicas of partition 0 go offline
+        broker.set_partition_leaderless("foo", 0, true);
+        broker.set_partition_error("foo", 0, Some(KafkaCode::LeaderNotAvailable));
+        // ~ one metadata refresh, i.e. attempt, per poll
+        for i in 0..3 {
+            let n = broker.requests().len();
+            let mss = consumer.poll().unwrap();
+            let delivered: Vec<_> = mss.iter().map(|ms| ms.partition()).collect();
+            assert_eq!(vec![1], delivered);
+            let metadata_requests = broker.requests()[n..].iter().filter(|&&k| k == 3).count();
+            assert_eq!(1, metadata_requests);
+            assert_eq!(i == 2, !consumer.stalled_partitions().is_empty());
+        }
+        assert_eq!(
+            vec![TopicPartition::new("foo", 0)],
+            consumer.stalled_partitions()
+        );
+        assert_eq!(1, fetch_offset(&consumer, "foo", 0));
+        assert_eq!(4, fetch_offset(&consumer, "foo", 1));
+
+        // ~ the partition regains a leader and is fetched again from
+        // where it was left
+        broker.set_partition_leaderless("foo", 0, false);
+        broker.set_partition_error("foo", 0, None);
+        let mss = consumer.poll().unwrap();
+        assert!(consumer.stalled_partitions().is_empty());
+        let mut delivered: Vec<_> = mss
+            .iter()
+            .flat_map(|ms| {
+                ms.messages()
+                    .iter()
+                    .map(move |m| (ms.partition(), m.offset))
+            })
+            .collect();
+        delivered.sort();
+        assert_eq!(vec![(0, 1), (1, 4)], delivered);
+
+        // ~ by default, the error fails the poll
+        let mut consumer = Consumer::from_hosts(vec![broker.host().to_owned()])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+        broker.set_partition_error("foo", 0, Some(KafkaCode::LeaderNotAvailable));
+        match consumer.poll() {
+            Err(e) => assert_eq!(Some(KafkaCode::LeaderNotAvailable), e.kafka_code()),
+            Ok(_) => panic!("leaderless partition not reported"),
+        }
+    }
+
+    #[test]
+    fn test_subscribe_unsubscribe() {
+        let (host, _) = serve(&[("foo", 1), ("bar", 1)], (0, 3));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_group("my-group".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+
+        let polled = |consumer: &mut Consumer| {
+            let mut topics = Vec::new();
+            for ms in consumer.poll().unwrap().iter() {
+                if !ms.messages().is_empty() {
+                    topics.push(ms.topic().to_owned());
+                    consumer.consume_messageset(ms).unwrap();
+                }
+            }
+            topics.sort();
+            topics
+        };
+        assert_eq!(vec!["foo"], polled(&mut consumer));
+
+        consumer.subscribe("bar").unwrap();
+        assert_eq!(Some(0), consumer.position("bar", 0));
+        assert_eq!(vec!["bar", "foo"], polled(&mut consumer));
+        // ~ subscribing again is a no-op
+        consumer.subscribe("bar").unwrap();
+        assert_eq!(Some(1), consumer.position("bar", 0));
+
+        // ~ the consumed but uncommitted offsets are committed when
+        // unsubscribing
+        consumer.unsubscribe("foo").unwrap();
+        assert_eq!(Some(2), consumer.committed("foo", 0).unwrap());
+        assert_eq!(None, consumer.committed("bar", 0).unwrap());
+        assert_eq!(None, consumer.position("foo", 0));
+        assert!(!consumer.subscriptions().contains_key("foo"));
+        assert_eq!(vec!["bar"], polled(&mut consumer));
+
+        // ~ resubscribing picks up at the committed offset
+        consumer.subscribe("foo").unwrap();
+        assert_eq!(Some(2), consumer.position("foo", 0));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_topic_pattern() {
+        use regex::Regex;
+
+        let (host, _) = serve(&[("events.a", 1), ("events.b", 2), ("other", 1)], (0, 1));
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic_pattern(Regex::new(r"events\..*").unwrap())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+
+        let mut subs: Vec<_> = consumer.subscriptions().into_iter().collect();
+        subs.sort();
+        assert_eq!(
+            vec![
+                ("events.a".to_owned(), vec![0]),
+                ("events.b".to_owned(), vec![0, 1])
+            ],
+            subs.into_iter()
+                .map(|(t, mut ps)| {
+                    ps.sort_unstable();
+                    (t, ps)
+                })
+                .collect::<Vec<_>>()
+        );
+
+        let mut topics: Vec<_> = consumer
+            .poll()
+            .unwrap()
+            .iter()
+            .filter(|ms| !ms.messages().is_empty())
+            .map(|ms| ms.topic().to_owned())
+            .collect();
+        topics.sort();
+        assert_eq!(vec!["events.a", "events.b", "events.b"], topics);
+
+        // ~ the pattern must match whole topic names
+        let (host, _) = serve(&[("events.a", 1), ("my.events.b", 1)], (0, 1));
+        let consumer = Consumer::from_hosts(vec![host])
+            .with_topic_pattern(Regex::new(r"events\..").unwrap())
+            .create()
+            .unwrap();
+        assert_eq!(
+            vec!["events.a"],
+            consumer.subscriptions().keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_offset_reset_policy() {
+        use super::{OffsetResetPolicy, DEFAULT_OFFSET_RESET_POLICY};
+
+        let (host, _) = serve(&[("foo", 2)], (100, 200));
+        let create = |policy| {
+            Consumer::from_hosts(vec![host.clone()])
+                .with_topic("foo".to_owned())
+                .with_fallback_offset(FetchOffset::Earliest)
+                .with_offset_reset_policy(policy)
+                .create()
+                .unwrap()
+        };
+
+        // ~ by default, the poll fails and the position is kept
+        let mut consumer = create(DEFAULT_OFFSET_RESET_POLICY);
+        assert_eq!(OffsetResetPolicy::Error, consumer.offset_reset_policy());
+        consumer.seek("foo", 0, 50).unwrap();
+        match consumer.poll() {
+            Err(e) => assert_eq!(Some(KafkaCode::OffsetOutOfRange), e.kafka_code()),
+            Ok(_) => panic!("out of range offset not reported"),
+        }
+        assert_eq!(50, fetch_offset(&consumer, "foo", 0));
+
+        // ~ otherwise, the partition's position is reset while the
+        // other partitions are served as usual
+        let mut consumer = create(OffsetResetPolicy::Earliest);
+        consumer.seek("foo", 0, 50).unwrap();
+        let mss = consumer.poll().unwrap();
+        let delivered: Vec<_> = mss.iter().map(|ms| ms.partition()).collect();
+        assert_eq!(vec![1], delivered);
+        assert_eq!(100, fetch_offset(&consumer, "foo", 0));
+        assert_eq!(101, fetch_offset(&consumer, "foo", 1));
+        let mss = consumer.poll().unwrap();
+        let offsets: Vec<_> = mss
+            .iter()
+            .filter(|ms| ms.partition() == 0)
+            .flat_map(|ms| ms.messages().iter().map(|m| m.offset))
+            .collect();
+        assert_eq!(vec![100], offsets);
+
+        let mut consumer = create(OffsetResetPolicy::Latest);
+        consumer.seek("foo", 0, 500).unwrap();
+        consumer.poll().unwrap();
+        assert_eq!(200, fetch_offset(&consumer, "foo", 0));
+    }
+
+    #[test]
+    fn test_seek() {
+        let (host, _) = serve(&[("foo", 1)], (100, 200));
+
+        // ~ without clamping, the consumer goes wherever told to
+        let mut consumer = Consumer::from_hosts(vec![host.clone()])
+            .with_topic("foo".to_owned())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .create()
+            .unwrap();
+        assert_eq!(100, fetch_offset(&consumer, "foo", 0));
+        consumer.seek("foo", 0, 50).unwrap();
+        assert_eq!(50, fetch_offset(&consumer, "foo", 0));
+
+        // ~ with clamping, seeking below the log start positions the
+        // consumer at the log start
+        let mut consumer = Consumer::from_hosts(vec![host])
+            .with_topic("foo".to_owned())
+            .with_seek_clamping(true)
+            .create()
+            .unwrap();
+        assert_eq!(200, fetch_offset(&consumer, "foo", 0));
+        consumer.seek("foo", 0, 50).unwrap();
+        assert_eq!(100, fetch_offset(&consumer, "foo", 0));
+        consumer.seek("foo", 0, 150).unwrap();
+        assert_eq!(150, fetch_offset(&consumer, "foo", 0));
+
+        match consumer.seek("foo", 1, 0) {
+            Err(Error::Kafka(KafkaCode::UnknownTopicOrPartition)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Support for subscribing to all topics matching a regular
+//! expression.
+
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::client::KafkaClient;
+use crate::error::Result;
+
+#[derive(Debug)]
+pub struct TopicPattern {
+    /// ~ the user supplied pattern anchored to match whole topic names
+    regex: Regex,
+    /// ~ the interval at which to re-evaluate the pattern
+    refresh_interval: Duration,
+    /// ~ the time the pattern was last evaluated
+    last_refresh: Instant,
+}
+
+impl TopicPattern {
+    pub fn new(pattern: &Regex, refresh_interval: Duration) -> TopicPattern {
+        TopicPattern {
+            regex: Regex::new(&format!("^(?:{})$", pattern.as_str()))
+                .expect("anchoring a valid regex"),
+            refresh_interval,
+            last_refresh: Instant::now(),
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        now.duration_since(self.last_refresh) >= self.refresh_interval
+    }
+
+    /// Reloads the metadata of all topics returning the names of the
+    /// topics matching this pattern.
+    pub fn refresh(&mut self, client: &mut KafkaClient) -> Result<Vec<String>> {
+        client.load_metadata_all()?;
+        self.last_refresh = Instant::now();
+        Ok(self.matching_topics(client))
+    }
+
+    /// Retrieves the names of all topics known to the given client
+    /// matching this pattern.
+    pub fn matching_topics(&self, client: &KafkaClient) -> Vec<String> {
+        client
+            .topics()
+            .names()
+            .filter(|name| self.regex.is_match(name))
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+}
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
 use std::hash::BuildHasherDefault;
+use std::time::Instant;
 
 use fnv::FnvHasher;
 
@@ -21,6 +22,22 @@ pub struct FetchState {
     pub offset: i64,
     /// ~ specifies the max_bytes to be fetched
     pub max_bytes: i32,
+    /// ~ the partition's high watermark as delivered by the last
+    /// fetch; `None` if not fetched yet
+    pub high_watermark: Option<i64>,
+    /// ~ the partition's leader epoch as of the last validation of
+    /// the fetch offset; `None` if not validated yet
+    pub leader_epoch: Option<i32>,
+    /// ~ the number of consecutive checks which found the partition
+    /// without a leader; see `Builder::with_leaderless_partition_skipping`
+    pub leaderless_attempts: u32,
+    /// ~ whether the partition is skipped when fetching since it has
+    /// been without a leader for too long
+    pub stalled: bool,
+    /// ~ whether the partition is skipped when fetching since too many
+    /// delivered messages have not been consumed yet; see
+    /// `Builder::with_fetch_buffer_bounds`
+    pub paused: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -42,7 +59,8 @@ pub struct ConsumedOffset {
 
 pub struct State {
     /// Contains the topic partitions the consumer is assigned to
-    /// consume; this is a _read-only_ data structure
+    /// consume; existing entries are never modified, new ones are
+    /// only appended (see `State::subscribe`)
     pub assignments: Assignments,
 
     /// Contains the information relevant for the next fetch operation
@@ -56,6 +74,20 @@ pub struct State {
     /// Contains the offsets of messages marked as "consumed" (to be
     /// committed)
     pub consumed_offsets: HashMap<TopicPartition, ConsumedOffset, PartitionHasher>,
+
+    /// Contains the offsets of messages delivered but not committable
+    /// yet in manual acknowledgment mode; mapped to whether they have
+    /// been acknowledged already
+    pub pending_acks: HashMap<TopicPartition, BTreeMap<i64, bool>, PartitionHasher>,
+
+    /// Contains the offsets of messages delivered but not marked as
+    /// consumed yet; maintained only if fetching is bounded through
+    /// `Builder::with_fetch_buffer_bounds`
+    pub buffered: HashMap<TopicPartition, BTreeSet<i64>, PartitionHasher>,
+
+    /// The time consumed offsets were last committed automatically;
+    /// see `Builder::with_auto_commit`
+    pub last_auto_commit: Instant,
 }
 
 impl<'a> fmt::Debug for State {
@@ -81,6 +113,18 @@ impl State {
         config: &Config,
         assignments: Assignments,
     ) -> Result<State> {
+        if assignments.as_slice().is_empty() {
+            // ~ nothing to consume (yet)
+            return Ok(State {
+                assignments,
+                fetch_offsets: HashMap::default(),
+                retry_partitions: VecDeque::new(),
+                consumed_offsets: HashMap::default(),
+                pending_acks: HashMap::default(),
+                buffered: HashMap::default(),
+                last_auto_commit: Instant::now(),
+            });
+        }
         let (consumed_offsets, fetch_offsets) = {
             let subscriptions = {
                 let xs = assignments.as_slice();
@@ -103,9 +147,68 @@ impl State {
             fetch_offsets,
             retry_partitions: VecDeque::new(),
             consumed_offsets,
+            pending_acks: HashMap::default(),
+            buffered: HashMap::default(),
+            last_auto_commit: Instant::now(),
         })
     }
 
+    /// Starts consuming all available partitions of the given topic
+    /// unless it is being consumed already.
+    pub fn subscribe(
+        &mut self,
+        client: &mut KafkaClient,
+        config: &Config,
+        topic: &str,
+    ) -> Result<()> {
+        if let Some(topic_ref) = self.assignments.topic_ref(topic) {
+            if self
+                .fetch_offsets
+                .keys()
+                .any(|tp| tp.topic_ref == topic_ref)
+            {
+                return Ok(());
+            }
+        }
+        if !client.topics().contains(topic) {
+            client.load_metadata(&[topic])?;
+        }
+        let topic_ref = self.assignments.add_topic(topic);
+        let (consumed, fetch_next) = {
+            let subscriptions = [determine_partitions(
+                &self.assignments[topic_ref],
+                client.topics(),
+            )?];
+            let n = subscriptions[0].partitions.len();
+            let consumed =
+                load_consumed_offsets(client, &config.group, &self.assignments, &subscriptions, n)?;
+            let fetch_next = load_fetch_states(
+                client,
+                config,
+                &self.assignments,
+                &subscriptions,
+                &consumed,
+                n,
+            )?;
+            (consumed, fetch_next)
+        };
+        self.consumed_offsets.extend(consumed);
+        self.fetch_offsets.extend(fetch_next);
+        Ok(())
+    }
+
+    /// Stops consuming the given topic dropping all state about it.
+    /// The topic's assignment is kept such that the `AssignmentRef`s
+    /// of other topics stay valid.
+    pub fn unsubscribe(&mut self, topic_ref: AssignmentRef) {
+        self.fetch_offsets.retain(|tp, _| tp.topic_ref != topic_ref);
+        self.consumed_offsets
+            .retain(|tp, _| tp.topic_ref != topic_ref);
+        self.retry_partitions.retain(|tp| tp.topic_ref != topic_ref);
+        self.pending_acks.retain(|tp, _| tp.topic_ref != topic_ref);
+        self.buffered.retain(|tp, _| tp.topic_ref != topic_ref);
+    }
+
     pub fn topic_name(&self, assignment: AssignmentRef) -> &str {
         self.assignments[assignment].topic()
     }
@@ -298,7 +401,13 @@ fn load_fetch_states(
                             },
                             FetchState {
                                 offset: *offsets.get(p).unwrap_or(&-1),
-                                max_bytes,
+                                max_bytes: config
+                                    .partition_fetch_bytes(s.assignment.topic(), max_bytes),
+                                high_watermark: None,
+                                leader_epoch: None,
+                                leaderless_attempts: 0,
+                                stalled: false,
+                                paused: false,
                             },
                         );
                     }
@@ -349,7 +458,18 @@ fn load_fetch_states(
                         }
                     },
                 };
-                fetch_offsets.insert(tp, FetchState { offset, max_bytes });
+                fetch_offsets.insert(
+                    tp,
+                    FetchState {
+                        offset,
+                        max_bytes: config.partition_fetch_bytes(s.assignment.topic(), max_bytes),
+                        high_watermark: None,
+                        leader_epoch: None,
+                        leaderless_attempts: 0,
+                        stalled: false,
+                        paused: false,
+                    },
+                );
             }
         }
     }
@@ -0,0 +1,246 @@
+//! Consumption of messages deserialized into custom data types.  See
+//! `Builder::with_value_deserializer`.
+
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use crate::error::{Error, Result};
+
+use super::{Builder, Consumer};
+
+/// Turns the raw key or value data of a fetched message into a value
+/// of type `T`.  Deserializers are registered through
+/// `Builder::with_key_deserializer` and
+/// `Builder::with_value_deserializer`.
+pub trait Deserializer<T>: fmt::Debug + Send + Sync {
+    /// Deserializes the given data of a message fetched from the
+    /// specified topic.  A failure is delivered along with the
+    /// message it occurred for; see `TypedMessage`.
+    fn deserialize(&self, topic: &str, data: &[u8]) -> Result<T>;
+}
+
+/// Delivers the data as is.  The default deserializer for keys and
+/// values not given a deserializer explicitly.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BytesDeserializer;
+
+impl Deserializer<Vec<u8>> for BytesDeserializer {
+    fn deserialize(&self, _topic: &str, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
+
+/// Deserializes the data as a UTF-8 string failing with
+/// `Error::StringDecodeError` for invalid data.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StringDeserializer;
+
+impl Deserializer<String> for StringDeserializer {
+    fn deserialize(&self, _topic: &str, data: &[u8]) -> Result<String> {
+        String::from_utf8(data.to_vec()).map_err(|_| Error::StringDecodeError)
+    }
+}
+
+/// Deserializes the data from its JSON representation failing with
+/// `Error::CodecError` for invalid data.  The counterpart of
+/// `Record::from_json`.
+#[cfg(feature = "serde")]
+pub struct JsonDeserializer<T>(PhantomData<fn() -> T>);
+
+#[cfg(feature = "serde")]
+impl<T> JsonDeserializer<T> {
+    /// Creates a new JSON deserializer.
+    pub fn new() -> Self {
+        JsonDeserializer(PhantomData)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Default for JsonDeserializer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> fmt::Debug for JsonDeserializer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JsonDeserializer")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> Deserializer<T> for JsonDeserializer<T> {
+    fn deserialize(&self, _topic: &str, data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).map_err(|_| Error::CodecError)
+    }
+}
+
+/// Deserializes the data from its CBOR representation failing with
+/// `Error::CodecError` for invalid data.  The counterpart of
+/// `Record::from_cbor`.
+#[cfg(feature = "serde")]
+pub struct CborDeserializer<T>(PhantomData<fn() -> T>);
+
+#[cfg(feature = "serde")]
+impl<T> CborDeserializer<T> {
+    /// Creates a new CBOR deserializer.
+    pub fn new() -> Self {
+        CborDeserializer(PhantomData)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Default for CborDeserializer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> fmt::Debug for CborDeserializer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CborDeserializer")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> Deserializer<T> for CborDeserializer<T> {
+    fn deserialize(&self, _topic: &str, data: &[u8]) -> Result<T> {
+        serde_cbor::from_slice(data).map_err(|_| Error::CodecError)
+    }
+}
+
+// --------------------------------------------------------------------
+
+/// A fetched message with its key and value deserialized.  See
+/// `TypedConsumer::poll`.
+///
+/// Deserialization failures are reported per message such that a
+/// single malformed message does not fail the whole poll.  Such
+/// messages are still to be marked consumed, e.g. through
+/// `Consumer::consume_message`, to move past them.
+#[derive(Debug)]
+pub struct TypedMessage<K, V> {
+    /// The topic the message was fetched from.
+    pub topic: String,
+    /// The partition the message was fetched from.
+    pub partition: i32,
+    /// The offset at which this message resides in the partition.
+    pub offset: i64,
+    /// The deserialized key of this message.  `None` if the message
+    /// was sent with a "null" key.
+    pub key: Option<Result<K>>,
+    /// The deserialized value of this message.
+    pub value: Result<V>,
+}
+
+/// A builder for a `TypedConsumer`.  Obtained through
+/// `Builder::with_key_deserializer` or
+/// `Builder::with_value_deserializer`.
+#[derive(Debug)]
+pub struct TypedBuilder<K, V> {
+    builder: Builder,
+    key: Box<dyn Deserializer<K>>,
+    value: Box<dyn Deserializer<V>>,
+}
+
+// ~ public only to be shared inside the kafka crate; not supposed to
+// be published outside the crate itself
+pub fn new_builder<K, V>(
+    builder: Builder,
+    key: Box<dyn Deserializer<K>>,
+    value: Box<dyn Deserializer<V>>,
+) -> TypedBuilder<K, V> {
+    TypedBuilder {
+        builder,
+        key,
+        value,
+    }
+}
+
+impl<K, V> TypedBuilder<K, V> {
+    /// Specifies the deserializer for the keys of the fetched
+    /// messages replacing the one specified so far.
+    pub fn with_key_deserializer<K2, D: Deserializer<K2> + 'static>(
+        self,
+        deserializer: D,
+    ) -> TypedBuilder<K2, V> {
+        new_builder(self.builder, Box::new(deserializer), self.value)
+    }
+
+    /// Specifies the deserializer for the values of the fetched
+    /// messages replacing the one specified so far.
+    pub fn with_value_deserializer<V2, D: Deserializer<V2> + 'static>(
+        self,
+        deserializer: D,
+    ) -> TypedBuilder<K, V2> {
+        new_builder(self.builder, self.key, Box::new(deserializer))
+    }
+
+    /// Finally creates/builds a new consumer.  See `Builder::create`.
+    pub fn create(self) -> Result<TypedConsumer<K, V>> {
+        Ok(TypedConsumer {
+            consumer: self.builder.create()?,
+            key: self.key,
+            value: self.value,
+        })
+    }
+}
+
+/// A consumer delivering its messages deserialized.  See
+/// `Builder::with_value_deserializer`.
+///
+/// Apart from `TypedConsumer::poll` the consumer behaves exactly like
+/// the `Consumer` it dereferences to.
+#[derive(Debug)]
+pub struct TypedConsumer<K, V> {
+    consumer: Consumer,
+    key: Box<dyn Deserializer<K>>,
+    value: Box<dyn Deserializer<V>>,
+}
+
+impl<K, V> TypedConsumer<K, V> {
+    /// Polls for the next available messages like `Consumer::poll`
+    /// delivering them deserialized.  The poll fails only if fetching
+    /// fails; deserialization failures are reported per message.
+    pub fn poll(&mut self) -> Result<Vec<TypedMessage<K, V>>> {
+        let mss = self.consumer.poll()?;
+        let mut msgs = Vec::new();
+        for ms in &mss {
+            let topic = ms.topic();
+            for m in ms.messages() {
+                msgs.push(TypedMessage {
+                    topic: topic.to_owned(),
+                    partition: ms.partition(),
+                    offset: m.offset,
+                    key: m.key.map(|k| self.key.deserialize(topic, k)),
+                    value: self.value.deserialize(topic, m.value),
+                });
+            }
+        }
+        Ok(msgs)
+    }
+
+    /// Destroys this consumer returning back the underlying untyped
+    /// consumer.
+    pub fn into_consumer(self) -> Consumer {
+        self.consumer
+    }
+}
+
+impl<K, V> Deref for TypedConsumer<K, V> {
+    type Target = Consumer;
+
+    fn deref(&self) -> &Consumer {
+        &self.consumer
+    }
+}
+
+impl<K, V> DerefMut for TypedConsumer<K, V> {
+    fn deref_mut(&mut self) -> &mut Consumer {
+        &mut self.consumer
+    }
+}
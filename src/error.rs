@@ -1,6 +1,6 @@
 //! Error struct and methods
 
-use std::{io, result, sync::Arc};
+use std::{fmt, io, result, sync::Arc};
 use thiserror::Error;
 
 pub type Result<T> = result::Result<T, Error>;
@@ -67,13 +67,209 @@ pub enum Error {
     #[error("Invalid duration")]
     InvalidDuration,
 
+    /// An invalid user-provided required acks value; only -1, 0 and
+    /// 1 are accepted.  See `RequiredAcks`.
+    #[error("Invalid required acks ({0}); expected -1, 0 or 1")]
+    InvalidRequiredAcks(i16),
+
+    /// The coordinator of a group could not be determined or kept
+    /// moving away within the configured number of retry attempts.
+    /// See `KafkaClient::set_retry_max_attempts`.
+    #[error("Coordinator not found for group {group:?}")]
+    CoordinatorNotFound { group: String },
+
+    /// A message to be produced exceeds the configured maximum
+    /// request size and was rejected without contacting any broker.
+    /// Contains the estimated (encoded) size of the message and the
+    /// limit it was checked against, respectively.
+    #[error("Message too large ({size} bytes; limit: {limit} bytes)")]
+    MessageTooLarge { size: usize, limit: usize },
+
     #[error(transparent)]
     ArcSelf(#[from] Arc<Self>),
 }
 
+impl Error {
+    /// Determines whether this error leaves the connection to the
+    /// broker it occurred on unusable, such that the connection must
+    /// be discarded rather than used for further requests.
+    ///
+    /// This is the case for i/o errors (which may leave partially
+    /// transmitted data on the connection) and responses which could
+    /// not be decoded (indicating the client and the broker disagree
+    /// on the protocol), but not for errors reported by the broker
+    /// through a well-formed response nor for errors not related to
+    /// any connection at all.  The client discards its pooled
+    /// connections accordingly.
+    pub fn is_fatal_for_connection(&self) -> bool {
+        match *self {
+            // ~ an interrupted operation did not transfer any data
+            Error::Io(ref e) => e.kind() != io::ErrorKind::Interrupted,
+            #[cfg(feature = "security")]
+            Error::Ssl(_) => true,
+            Error::UnsupportedProtocol
+            | Error::UnexpectedEOF
+            | Error::CodecError
+            | Error::StringDecodeError => true,
+            Error::ArcSelf(ref e) => e.is_fatal_for_connection(),
+            _ => false,
+        }
+    }
+
+    /// Retrieves the error code as reported by a remote Kafka server,
+    /// if any.  This is the code of either an `Error::Kafka` or an
+    /// `Error::TopicPartitionError`, regardless of the topic
+    /// partition the latter refers to.
+    pub fn kafka_code(&self) -> Option<KafkaCode> {
+        match *self {
+            Error::Kafka(code)
+            | Error::TopicPartitionError {
+                error_code: code, ..
+            } => Some(code),
+            Error::ArcSelf(ref e) => e.kafka_code(),
+            _ => None,
+        }
+    }
+}
+
+impl From<KafkaCode> for Error {
+    fn from(code: KafkaCode) -> Self {
+        Error::Kafka(code)
+    }
+}
+
+/// Converts an error for use with APIs expecting `io::Result`s.  An
+/// `Error::Io` is unwrapped into the original i/o error; any other
+/// error is wrapped as the inner error of an `io::Error` (retaining
+/// its display message) of a kind approximating its cause, e.g.
+/// `io::ErrorKind::TimedOut` for `KafkaCode::RequestTimedOut`.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::ArcSelf(e) => match Arc::try_unwrap(e) {
+                Ok(e) => e.into(),
+                Err(e) => io::Error::new(e.io_error_kind(), Error::ArcSelf(e)),
+            },
+            e => io::Error::new(e.io_error_kind(), e),
+        }
+    }
+}
+
+impl Error {
+    // ~ the kind of i/o error best describing this error; see
+    // `From<Error> for io::Error`
+    fn io_error_kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::Io(ref e) => e.kind(),
+            Error::ArcSelf(ref e) => e.io_error_kind(),
+            Error::UnexpectedEOF => io::ErrorKind::UnexpectedEof,
+            Error::UnsupportedProtocol
+            | Error::UnsupportedCompression
+            | Error::CodecError
+            | Error::StringDecodeError => io::ErrorKind::InvalidData,
+            Error::NoTopicsAssigned
+            | Error::InvalidDuration
+            | Error::InvalidRequiredAcks(_)
+            | Error::MessageTooLarge { .. } => io::ErrorKind::InvalidInput,
+            Error::NoHostReachable => io::ErrorKind::NotConnected,
+            _ => match self.kafka_code() {
+                Some(KafkaCode::RequestTimedOut) => io::ErrorKind::TimedOut,
+                _ => io::ErrorKind::Other,
+            },
+        }
+    }
+}
+
+/// Combinators for results failing with errors reported by Kafka
+/// brokers, e.g. to treat specific codes as success in idempotent
+/// operations.  Codes are matched as determined by
+/// `Error::kafka_code`; results failing with other errors are
+/// passed through untouched.
+///
+/// # Example
+///
+/// ```no_run
+/// use kafka::client::KafkaClient;
+/// use kafka::error::{KafkaCode, KafkaResultExt};
+///
+/// let mut client = KafkaClient::new(vec!["localhost:9092".to_owned()]);
+/// client
+///     .load_metadata(&["my-topic"])
+///     .ignore_kafka_code(KafkaCode::UnknownTopicOrPartition)
+///     .unwrap();
+/// ```
+pub trait KafkaResultExt<T> {
+    /// Delivers `Ok(T::default())` in place of an error with the
+    /// given code.
+    fn ignore_kafka_code(self, code: KafkaCode) -> Result<T>
+    where
+        T: Default;
+
+    /// Delivers `Ok` with the value produced by `f` in place of an
+    /// error with the given code.
+    fn on_kafka_code<F>(self, code: KafkaCode, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T;
+
+    /// Hands the code of an error reported by Kafka to `f`,
+    /// delivering `Ok` with the value it produces, if any, in place
+    /// of the error.  If `f` produces `None`, the error is kept.
+    fn map_kafka_code<F>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(KafkaCode) -> Option<T>;
+
+    /// Hands an error with the given code to `f`, e.g. for logging,
+    /// delivering the result unchanged.
+    fn inspect_kafka_code<F>(self, code: KafkaCode, f: F) -> Result<T>
+    where
+        F: FnOnce(&Error);
+}
+
+impl<T> KafkaResultExt<T> for Result<T> {
+    fn ignore_kafka_code(self, code: KafkaCode) -> Result<T>
+    where
+        T: Default,
+    {
+        self.on_kafka_code(code, T::default)
+    }
+
+    fn on_kafka_code<F>(self, code: KafkaCode, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        self.map_kafka_code(|c| if c == code { Some(f()) } else { None })
+    }
+
+    fn map_kafka_code<F>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(KafkaCode) -> Option<T>,
+    {
+        match self {
+            Err(e) => match e.kafka_code().and_then(f) {
+                Some(v) => Ok(v),
+                None => Err(e),
+            },
+            ok => ok,
+        }
+    }
+
+    fn inspect_kafka_code<F>(self, code: KafkaCode, f: F) -> Result<T>
+    where
+        F: FnOnce(&Error),
+    {
+        if let Err(ref e) = self {
+            if e.kafka_code() == Some(code) {
+                f(e);
+            }
+        }
+        self
+    }
+}
+
 /// Various errors reported by a remote Kafka server.
 /// See also [Kafka Errors](http://kafka.apache.org/protocol.html)
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum KafkaCode {
     /// An unexpected server error
     Unknown = -1,
@@ -187,4 +383,492 @@ pub enum KafkaCode {
     IllegalSaslState = 34,
     /// The version of API is not supported.
     UnsupportedVersion = 35,
+    /// The topic already exists.
+    TopicAlreadyExists = 36,
+    /// The number of partitions is invalid.
+    InvalidPartitions = 37,
+    /// The replication factor is invalid.
+    InvalidReplicationFactor = 38,
+    /// The replica assignment is invalid.
+    InvalidReplicaAssignment = 39,
+    /// The configuration is invalid.
+    InvalidConfig = 40,
+    /// The request was sent to a broker that is not the controller.
+    NotController = 41,
+    /// The request is malformed, uses an unsupported version, or
+    /// references an unexpected resource; see the broker logs for
+    /// details.
+    InvalidRequest = 42,
+    /// The message format version on the broker does not support the
+    /// request.
+    UnsupportedForMessageFormat = 43,
+    /// The request parameters do not satisfy the configured policy.
+    PolicyViolation = 44,
+    /// The broker received an out of order sequence number.
+    OutOfOrderSequenceNumber = 45,
+    /// The broker received a duplicate sequence number.
+    DuplicateSequenceNumber = 46,
+    /// The producer attempted to produce with an old epoch.
+    InvalidProducerEpoch = 47,
+    /// The producer attempted a transactional operation in an invalid
+    /// state.
+    InvalidTxnState = 48,
+    /// The producer attempted to use a producer id which is not currently
+    /// assigned to its transactional id.
+    InvalidProducerIdMapping = 49,
+    /// The transaction timeout is larger than the maximum value allowed
+    /// by the broker.
+    InvalidTransactionTimeout = 50,
+    /// The producer attempted to update a transaction while another
+    /// concurrent operation on the same transaction was ongoing.
+    ConcurrentTransactions = 51,
+    /// The transaction coordinator sending a `WriteTxnMarker` is no longer
+    /// the current coordinator for the transactional id.
+    TransactionCoordinatorFenced = 52,
+    /// The client is not authorized to use the transactional id.
+    TransactionalIdAuthorizationFailed = 53,
+    /// Security features are disabled.
+    SecurityDisabled = 54,
+    /// The broker did not attempt to execute this operation, e.g. since a
+    /// related operation failed.
+    OperationNotAttempted = 55,
+    /// A disk error occurred when trying to access the log file.
+    KafkaStorageError = 56,
+    /// The user-specified log directory is not found in the broker
+    /// config.
+    LogDirNotFound = 57,
+    /// SASL authentication failed.
+    SaslAuthenticationFailed = 58,
+    /// The broker could not locate the producer metadata associated with
+    /// the producer id.
+    UnknownProducerId = 59,
+    /// A partition reassignment is in progress.
+    ReassignmentInProgress = 60,
+    /// Delegation token feature is not enabled.
+    DelegationTokenAuthDisabled = 61,
+    /// Delegation token is not found on the server.
+    DelegationTokenNotFound = 62,
+    /// The specified principal is not a valid owner or renewer of the
+    /// delegation token.
+    DelegationTokenOwnerMismatch = 63,
+    /// Delegation token requests are not allowed on plaintext or 1-way
+    /// SSL channels and on delegation token authenticated channels.
+    DelegationTokenRequestNotAllowed = 64,
+    /// The delegation token authorization failed.
+    DelegationTokenAuthorizationFailed = 65,
+    /// The delegation token is expired.
+    DelegationTokenExpired = 66,
+    /// The supplied principal type is not supported.
+    InvalidPrincipalType = 67,
+    /// The group is not empty.
+    NonEmptyGroup = 68,
+    /// The group id does not exist.
+    GroupIdNotFound = 69,
+    /// The fetch session id was not found.
+    FetchSessionIdNotFound = 70,
+    /// The fetch session epoch is invalid.
+    InvalidFetchSessionEpoch = 71,
+    /// There is no listener on the leader broker that matches the
+    /// listener on which the metadata request was processed.
+    ListenerNotFound = 72,
+    /// Topic deletion is disabled.
+    TopicDeletionDisabled = 73,
+    /// The leader epoch in the request is older than the epoch on the
+    /// broker.
+    FencedLeaderEpoch = 74,
+    /// The leader epoch in the request is newer than the epoch on the
+    /// broker.
+    UnknownLeaderEpoch = 75,
+    /// The requesting client does not support the compression type of the
+    /// given partition.
+    UnsupportedCompressionType = 76,
+    /// The broker epoch has changed.
+    StaleBrokerEpoch = 77,
+    /// The leader high watermark has not caught up from a recent leader
+    /// election so the offsets cannot be guaranteed to be monotonically
+    /// increasing.
+    OffsetNotAvailable = 78,
+    /// The group member needs to have a valid member id before actually
+    /// entering a consumer group.
+    MemberIdRequired = 79,
+    /// The preferred leader was not available.
+    PreferredLeaderNotAvailable = 80,
+    /// The consumer group has reached its max size.
+    GroupMaxSizeReached = 81,
+    /// The broker rejected this static consumer since another consumer
+    /// with the same group instance id has registered with a different
+    /// member id.
+    FencedInstanceId = 82,
+    /// Eligible topic partition leaders are not available.
+    EligibleLeadersNotAvailable = 83,
+    /// The leader election is not needed for the topic partition.
+    ElectionNotNeeded = 84,
+    /// No partition reassignment is in progress.
+    NoReassignmentInProgress = 85,
+    /// Deleting offsets of a topic is forbidden while the consumer group
+    /// is actively subscribed to it.
+    GroupSubscribedToTopic = 86,
+    /// The record failed the broker's validation.
+    InvalidRecord = 87,
+    /// There are unstable offsets that need to be cleared.
+    UnstableOffsetCommit = 88,
+    /// The throttling quota has been exceeded.
+    ThrottlingQuotaExceeded = 89,
+    /// There is a newer producer with the same transactional id which
+    /// fences the current one.
+    ProducerFenced = 90,
+    /// A request illegally referred to a resource that does not
+    /// exist.
+    ResourceNotFound = 91,
+    /// A request illegally referred to the same resource twice.
+    DuplicateResource = 92,
+    /// The requested credential would not meet the criteria for
+    /// acceptability.
+    UnacceptableCredential = 93,
+}
+
+impl KafkaCode {
+    /// Retrieves the numeric code identifying this error on the wire
+    /// as documented by the [Kafka
+    /// protocol](http://kafka.apache.org/protocol.html#protocol_error_codes).
+    pub fn code(&self) -> i16 {
+        *self as i16
+    }
+
+    /// Determines whether an operation failing with this error may
+    /// succeed when retried, e.g. after refreshing metadata or
+    /// waiting for a leader election to complete.  This follows the
+    /// Java client's notion of "retriable" errors.
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            *self,
+            KafkaCode::CorruptMessage
+                | KafkaCode::UnknownTopicOrPartition
+                | KafkaCode::LeaderNotAvailable
+                | KafkaCode::NotLeaderForPartition
+                | KafkaCode::RequestTimedOut
+                | KafkaCode::ReplicaNotAvailable
+                | KafkaCode::NetworkException
+                | KafkaCode::GroupLoadInProgress
+                | KafkaCode::GroupCoordinatorNotAvailable
+                | KafkaCode::NotCoordinatorForGroup
+                | KafkaCode::NotEnoughReplicas
+                | KafkaCode::NotEnoughReplicasAfterAppend
+                | KafkaCode::NotController
+                | KafkaCode::ConcurrentTransactions
+                | KafkaCode::KafkaStorageError
+                | KafkaCode::FetchSessionIdNotFound
+                | KafkaCode::InvalidFetchSessionEpoch
+                | KafkaCode::ListenerNotFound
+                | KafkaCode::FencedLeaderEpoch
+                | KafkaCode::UnknownLeaderEpoch
+                | KafkaCode::OffsetNotAvailable
+                | KafkaCode::PreferredLeaderNotAvailable
+                | KafkaCode::EligibleLeadersNotAvailable
+                | KafkaCode::UnstableOffsetCommit
+                | KafkaCode::ThrottlingQuotaExceeded
+        )
+    }
+
+    /// Retrieves a short, human readable description of this error.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            KafkaCode::Unknown => "An unexpected server error.",
+            KafkaCode::OffsetOutOfRange => "The requested offset is outside the range of offsets maintained by the server for the given topic/partition.",
+            KafkaCode::CorruptMessage => "The message contents does not match its CRC.",
+            KafkaCode::UnknownTopicOrPartition => "The topic or partition does not exist on this broker.",
+            KafkaCode::InvalidMessageSize => "The message has a negative size.",
+            KafkaCode::LeaderNotAvailable => "There is currently no leader for this partition due to an ongoing leadership election.",
+            KafkaCode::NotLeaderForPartition => "The broker is not the leader for this partition; the client's metadata is out of date.",
+            KafkaCode::RequestTimedOut => "The request exceeded the user-specified time limit.",
+            KafkaCode::BrokerNotAvailable => "The broker is not alive.",
+            KafkaCode::ReplicaNotAvailable => "The replica is expected on this broker but is not.",
+            KafkaCode::MessageSizeTooLarge => "The message is larger than the maximum the server accepts.",
+            KafkaCode::StaleControllerEpoch => "The controller epoch is stale (broker-to-broker communication).",
+            KafkaCode::OffsetMetadataTooLarge => "The offset metadata string is larger than the configured maximum.",
+            KafkaCode::NetworkException => "The server disconnected before a response was received.",
+            KafkaCode::GroupLoadInProgress => "The coordinator is still loading the group's metadata or offsets.",
+            KafkaCode::GroupCoordinatorNotAvailable => "The group coordinator is not active or the offsets topic has not been created yet.",
+            KafkaCode::NotCoordinatorForGroup => "The broker is not the coordinator for this group.",
+            KafkaCode::InvalidTopic => "The topic is invalid or internal and cannot be accessed this way.",
+            KafkaCode::RecordListTooLarge => "The message batch exceeds the maximum configured segment size.",
+            KafkaCode::NotEnoughReplicas => "The number of in-sync replicas is lower than the configured minimum.",
+            KafkaCode::NotEnoughReplicasAfterAppend => "The message was written to the log, but with fewer in-sync replicas than required.",
+            KafkaCode::InvalidRequiredAcks => "The requested required acks is invalid (anything other than -1, 1, or 0).",
+            KafkaCode::IllegalGeneration => "The generation id provided in the request is not the current generation.",
+            KafkaCode::InconsistentGroupProtocol => "The member's protocol type or set of protocols is not compatible with the current group.",
+            KafkaCode::InvalidGroupId => "The group id is empty or null.",
+            KafkaCode::UnknownMemberId => "The member id is not in the current generation.",
+            KafkaCode::InvalidSessionTimeout => "The requested session timeout is outside of the range allowed by the broker.",
+            KafkaCode::RebalanceInProgress => "The coordinator has begun rebalancing the group; the client should rejoin the group.",
+            KafkaCode::InvalidCommitOffsetSize => "The offset commit was rejected because of oversize metadata.",
+            KafkaCode::TopicAuthorizationFailed => "The client is not authorized to access the requested topic.",
+            KafkaCode::GroupAuthorizationFailed => "The client is not authorized to access the requested group.",
+            KafkaCode::ClusterAuthorizationFailed => "The client is not authorized to use an inter-broker or administrative API.",
+            KafkaCode::InvalidTimestamp => "The timestamp of the message is out of acceptable range.",
+            KafkaCode::UnsupportedSaslMechanism => "The broker does not support the requested SASL mechanism.",
+            KafkaCode::IllegalSaslState => "The request is not valid given the current SASL state.",
+            KafkaCode::UnsupportedVersion => "The version of the API is not supported.",
+            KafkaCode::TopicAlreadyExists => "The topic already exists.",
+            KafkaCode::InvalidPartitions => "The number of partitions is invalid.",
+            KafkaCode::InvalidReplicationFactor => "The replication factor is invalid.",
+            KafkaCode::InvalidReplicaAssignment => "The replica assignment is invalid.",
+            KafkaCode::InvalidConfig => "The configuration is invalid.",
+            KafkaCode::NotController => "The request was sent to a broker that is not the controller.",
+            KafkaCode::InvalidRequest => "The request is malformed, uses an unsupported version, or references an unexpected resource; see the broker logs for details.",
+            KafkaCode::UnsupportedForMessageFormat => "The message format version on the broker does not support the request.",
+            KafkaCode::PolicyViolation => "The request parameters do not satisfy the configured policy.",
+            KafkaCode::OutOfOrderSequenceNumber => "The broker received an out of order sequence number.",
+            KafkaCode::DuplicateSequenceNumber => "The broker received a duplicate sequence number.",
+            KafkaCode::InvalidProducerEpoch => "The producer attempted to produce with an old epoch.",
+            KafkaCode::InvalidTxnState => "The producer attempted a transactional operation in an invalid state.",
+            KafkaCode::InvalidProducerIdMapping => "The producer attempted to use a producer id which is not currently assigned to its transactional id.",
+            KafkaCode::InvalidTransactionTimeout => "The transaction timeout is larger than the maximum value allowed by the broker.",
+            KafkaCode::ConcurrentTransactions => "The producer attempted to update a transaction while another concurrent operation on the same transaction was ongoing.",
+            KafkaCode::TransactionCoordinatorFenced => "The transaction coordinator sending a WriteTxnMarker is no longer the current coordinator for the transactional id.",
+            KafkaCode::TransactionalIdAuthorizationFailed => "The client is not authorized to use the transactional id.",
+            KafkaCode::SecurityDisabled => "Security features are disabled.",
+            KafkaCode::OperationNotAttempted => "The broker did not attempt to execute this operation, e.g. since a related operation failed.",
+            KafkaCode::KafkaStorageError => "A disk error occurred when trying to access the log file.",
+            KafkaCode::LogDirNotFound => "The user-specified log directory is not found in the broker config.",
+            KafkaCode::SaslAuthenticationFailed => "SASL authentication failed.",
+            KafkaCode::UnknownProducerId => "The broker could not locate the producer metadata associated with the producer id.",
+            KafkaCode::ReassignmentInProgress => "A partition reassignment is in progress.",
+            KafkaCode::DelegationTokenAuthDisabled => "Delegation token feature is not enabled.",
+            KafkaCode::DelegationTokenNotFound => "Delegation token is not found on the server.",
+            KafkaCode::DelegationTokenOwnerMismatch => "The specified principal is not a valid owner or renewer of the delegation token.",
+            KafkaCode::DelegationTokenRequestNotAllowed => "Delegation token requests are not allowed on plaintext or 1-way SSL channels and on delegation token authenticated channels.",
+            KafkaCode::DelegationTokenAuthorizationFailed => "The delegation token authorization failed.",
+            KafkaCode::DelegationTokenExpired => "The delegation token is expired.",
+            KafkaCode::InvalidPrincipalType => "The supplied principal type is not supported.",
+            KafkaCode::NonEmptyGroup => "The group is not empty.",
+            KafkaCode::GroupIdNotFound => "The group id does not exist.",
+            KafkaCode::FetchSessionIdNotFound => "The fetch session id was not found.",
+            KafkaCode::InvalidFetchSessionEpoch => "The fetch session epoch is invalid.",
+            KafkaCode::ListenerNotFound => "There is no listener on the leader broker that matches the listener on which the metadata request was processed.",
+            KafkaCode::TopicDeletionDisabled => "Topic deletion is disabled.",
+            KafkaCode::FencedLeaderEpoch => "The leader epoch in the request is older than the epoch on the broker.",
+            KafkaCode::UnknownLeaderEpoch => "The leader epoch in the request is newer than the epoch on the broker.",
+            KafkaCode::UnsupportedCompressionType => "The requesting client does not support the compression type of the given partition.",
+            KafkaCode::StaleBrokerEpoch => "The broker epoch has changed.",
+            KafkaCode::OffsetNotAvailable => "The leader high watermark has not caught up from a recent leader election so the offsets cannot be guaranteed to be monotonically increasing.",
+            KafkaCode::MemberIdRequired => "The group member needs to have a valid member id before actually entering a consumer group.",
+            KafkaCode::PreferredLeaderNotAvailable => "The preferred leader was not available.",
+            KafkaCode::GroupMaxSizeReached => "The consumer group has reached its max size.",
+            KafkaCode::FencedInstanceId => "The broker rejected this static consumer since another consumer with the same group instance id has registered with a different member id.",
+            KafkaCode::EligibleLeadersNotAvailable => "Eligible topic partition leaders are not available.",
+            KafkaCode::ElectionNotNeeded => "The leader election is not needed for the topic partition.",
+            KafkaCode::NoReassignmentInProgress => "No partition reassignment is in progress.",
+            KafkaCode::GroupSubscribedToTopic => "Deleting offsets of a topic is forbidden while the consumer group is actively subscribed to it.",
+            KafkaCode::InvalidRecord => "The record failed the broker's validation.",
+            KafkaCode::UnstableOffsetCommit => "There are unstable offsets that need to be cleared.",
+            KafkaCode::ThrottlingQuotaExceeded => "The throttling quota has been exceeded.",
+            KafkaCode::ProducerFenced => "There is a newer producer with the same transactional id which fences the current one.",
+            KafkaCode::ResourceNotFound => "A request illegally referred to a resource that does not exist.",
+            KafkaCode::DuplicateResource => "A request illegally referred to the same resource twice.",
+            KafkaCode::UnacceptableCredential => "The requested credential would not meet the criteria for acceptability.",
+        }
+    }
+}
+
+impl fmt::Display for KafkaCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self, self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+
+    use super::{Error, KafkaCode, KafkaResultExt, Result};
+
+    #[test]
+    fn test_from_kafka_code() {
+        match Error::from(KafkaCode::NotLeaderForPartition) {
+            Error::Kafka(KafkaCode::NotLeaderForPartition) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_kafka_code() {
+        let e = Error::Kafka(KafkaCode::OffsetOutOfRange);
+        assert_eq!(Some(KafkaCode::OffsetOutOfRange), e.kafka_code());
+
+        let e = Error::TopicPartitionError {
+            topic_name: "foo".to_owned(),
+            partition_id: 1,
+            error_code: KafkaCode::UnknownTopicOrPartition,
+        };
+        assert_eq!(Some(KafkaCode::UnknownTopicOrPartition), e.kafka_code());
+
+        let e = Error::ArcSelf(Arc::new(Error::Kafka(KafkaCode::RequestTimedOut)));
+        assert_eq!(Some(KafkaCode::RequestTimedOut), e.kafka_code());
+
+        assert_eq!(None, Error::NoHostReachable.kafka_code());
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        // ~ i/o errors are passed through untouched
+        let e = io::Error::from(Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "gone")));
+        assert_eq!(io::ErrorKind::BrokenPipe, e.kind());
+        assert_eq!("gone", e.to_string());
+        assert!(e.get_ref().unwrap().downcast_ref::<Error>().is_none());
+        let inner = io::Error::from(io::ErrorKind::ConnectionReset);
+        let e = io::Error::from(Error::ArcSelf(Arc::new(Error::Io(inner))));
+        assert_eq!(io::ErrorKind::ConnectionReset, e.kind());
+        assert!(e.get_ref().is_none());
+
+        // ~ others are wrapped keeping their message
+        let cases = [
+            (
+                Error::Kafka(KafkaCode::RequestTimedOut),
+                io::ErrorKind::TimedOut,
+            ),
+            (
+                Error::TopicPartitionError {
+                    topic_name: "foo".to_owned(),
+                    partition_id: 0,
+                    error_code: KafkaCode::RequestTimedOut,
+                },
+                io::ErrorKind::TimedOut,
+            ),
+            (
+                Error::Kafka(KafkaCode::NotLeaderForPartition),
+                io::ErrorKind::Other,
+            ),
+            (Error::UnexpectedEOF, io::ErrorKind::UnexpectedEof),
+            (Error::CodecError, io::ErrorKind::InvalidData),
+            (Error::InvalidDuration, io::ErrorKind::InvalidInput),
+            (Error::NoHostReachable, io::ErrorKind::NotConnected),
+        ];
+        for (err, kind) in cases {
+            let msg = err.to_string();
+            let e = io::Error::from(err);
+            assert_eq!(kind, e.kind(), "{}", msg);
+            assert_eq!(msg, e.to_string());
+            assert!(e.get_ref().unwrap().downcast_ref::<Error>().is_some());
+        }
+
+        // ~ shared errors are wrapped as such
+        let shared = Arc::new(Error::Kafka(KafkaCode::RequestTimedOut));
+        let e = io::Error::from(Error::ArcSelf(Arc::clone(&shared)));
+        assert_eq!(io::ErrorKind::TimedOut, e.kind());
+        assert_eq!(shared.to_string(), e.to_string());
+    }
+
+    #[test]
+    fn test_result_ext() {
+        let absent = || -> Result<u32> {
+            Err(Error::TopicPartitionError {
+                topic_name: "foo".to_owned(),
+                partition_id: 0,
+                error_code: KafkaCode::UnknownTopicOrPartition,
+            })
+        };
+        let code = |r: Result<u32>| r.unwrap_err().kafka_code();
+
+        // ~ ignore
+        assert_eq!(
+            0,
+            absent()
+                .ignore_kafka_code(KafkaCode::UnknownTopicOrPartition)
+                .unwrap()
+        );
+        assert_eq!(
+            Some(KafkaCode::UnknownTopicOrPartition),
+            code(absent().ignore_kafka_code(KafkaCode::OffsetOutOfRange))
+        );
+        assert_eq!(
+            7,
+            Ok(7)
+                .ignore_kafka_code(KafkaCode::UnknownTopicOrPartition)
+                .unwrap()
+        );
+        let r: Result<u32> = Err(Error::NoHostReachable);
+        assert!(matches!(
+            r.ignore_kafka_code(KafkaCode::Unknown),
+            Err(Error::NoHostReachable)
+        ));
+
+        // ~ on / map
+        assert_eq!(
+            5,
+            absent()
+                .on_kafka_code(KafkaCode::UnknownTopicOrPartition, || 5)
+                .unwrap()
+        );
+        let retriable = |c: KafkaCode| c.is_retriable().then_some(1);
+        assert_eq!(
+            1,
+            Err(Error::Kafka(KafkaCode::NotLeaderForPartition))
+                .map_kafka_code(retriable)
+                .unwrap()
+        );
+        assert_eq!(
+            Some(KafkaCode::UnknownTopicOrPartition),
+            code(absent().map_kafka_code(|_| None))
+        );
+
+        // ~ inspect
+        let mut seen = Vec::new();
+        let r = absent()
+            .inspect_kafka_code(KafkaCode::UnknownTopicOrPartition, |e| {
+                seen.push(e.kafka_code())
+            })
+            .inspect_kafka_code(KafkaCode::OffsetOutOfRange, |e| seen.push(e.kafka_code()));
+        assert_eq!(vec![Some(KafkaCode::UnknownTopicOrPartition)], seen);
+        assert_eq!(Some(KafkaCode::UnknownTopicOrPartition), code(r));
+    }
+
+    #[test]
+    fn test_is_fatal_for_connection() {
+        use std::io;
+
+        let io_err = |kind| Error::Io(io::Error::from(kind));
+        assert!(io_err(io::ErrorKind::UnexpectedEof).is_fatal_for_connection());
+        assert!(io_err(io::ErrorKind::TimedOut).is_fatal_for_connection());
+        assert!(!io_err(io::ErrorKind::Interrupted).is_fatal_for_connection());
+        assert!(Error::CodecError.is_fatal_for_connection());
+        assert!(Error::UnsupportedProtocol.is_fatal_for_connection());
+        assert!(Error::ArcSelf(Arc::new(Error::CodecError)).is_fatal_for_connection());
+
+        assert!(!Error::Kafka(KafkaCode::NotLeaderForPartition).is_fatal_for_connection());
+        assert!(!Error::UnsupportedCompression.is_fatal_for_connection());
+        assert!(!Error::NoHostReachable.is_fatal_for_connection());
+        assert!(!Error::MessageTooLarge { size: 2, limit: 1 }.is_fatal_for_connection());
+    }
+
+    #[test]
+    fn test_kafka_code_display() {
+        assert_eq!(
+            "NotLeaderForPartition (6)",
+            KafkaCode::NotLeaderForPartition.to_string()
+        );
+        assert_eq!("Unknown (-1)", KafkaCode::Unknown.to_string());
+        assert_eq!(
+            "UnsupportedVersion (35)",
+            KafkaCode::UnsupportedVersion.to_string()
+        );
+    }
+
+    #[test]
+    fn test_kafka_code_code() {
+        assert_eq!(-1, KafkaCode::Unknown.code());
+        assert_eq!(1, KafkaCode::OffsetOutOfRange.code());
+        assert_eq!(6, KafkaCode::NotLeaderForPartition.code());
+        assert_eq!(35, KafkaCode::UnsupportedVersion.code());
+        assert!(!KafkaCode::RebalanceInProgress.description().is_empty());
+    }
+
+    #[test]
+    fn test_kafka_code_is_retriable() {
+        assert!(KafkaCode::NotLeaderForPartition.is_retriable());
+        assert!(KafkaCode::FencedLeaderEpoch.is_retriable());
+        assert!(!KafkaCode::PolicyViolation.is_retriable());
+        assert!(!KafkaCode::Unknown.is_retriable());
+    }
 }
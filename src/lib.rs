@@ -31,4 +31,4 @@ pub mod producer;
 mod protocol;
 mod utils;
 
-pub use self::error::{Error, Result};
+pub use self::error::{Error, KafkaResultExt, Result};
@@ -55,17 +55,55 @@
 //! client code at the `Producer`'s construction time and defaults to
 //! `DefaultPartitioner`.  See that for more information for its
 //! strategy to find a partition.
+//!
+//! # Ordering
+//!
+//! The messages sent to a partition are appended to it in the order
+//! they were sent in: within a single `send_all`/`flush` in the
+//! order of the given records, across calls in the order of the
+//! calls.  `Producer` sends synchronously, i.e. it awaits the
+//! brokers' response to a request before issuing the next one; there
+//! is never more than one request per partition in flight which a
+//! later one could overtake.  The price is throughput: every call
+//! costs at least one full round trip to the partitions' leaders.
+//! Batching records through `send_all` or `send_queued` amortizes it.
+//!
+//! `Producer` resends records on its own in two cases only, both
+//! preserving the order:
+//!
+//! - `flush` retries the records of partitions failing with a
+//!   retriable error if enabled through
+//!   `Builder::with_retry_max_attempts`.  The records are put back to
+//!   the head of the queue, ahead of any record queued later, and
+//!   sent again as part of the next attempt.
+//! - `send_all` and `flush` split the records of a partition rejected
+//!   as too large into halves sent one after the other.  The second
+//!   half is sent only once the first one has been accepted.
+//!
+//! Otherwise a failed send is not retried.  To preserve the order
+//! when retrying a failed record, client code must do so before
+//! sending any later record to the same partition.
 
 // XXX 1) rethink return values for the send_all() method
-// XXX 2) Handle recoverable errors behind the scenes through retry attempts
+// XXX 2) Handle recoverable errors behind the scenes through retry
+// attempts also for `send`/`send_all` (`flush` does already.)  to
+// preserve the ordering guarantees (see above) a retry must be
+// completed before any later record is sent to the same partition,
+// i.e. at most one request per partition may be in flight
+// unless the brokers deduplicate and order the messages themselves
+// (idempotent producers, which need the v2 record batch format.)
 
 use crate::client::{self, KafkaClient};
-use crate::error::{Error, Result};
+use crate::error::{Error, KafkaCode, Result};
 use std::collections::HashMap;
 use std::fmt;
 use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use std::io;
+use std::mem;
 use std::slice::from_ref;
-use std::time::Duration;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use twox_hash::XxHash32;
 
 #[cfg(feature = "security")]
@@ -82,9 +120,29 @@ pub use crate::client::{Compression, ProduceConfirm, ProducePartitionConfirm, Re
 /// The default value for `Builder::with_ack_timeout`.
 pub const DEFAULT_ACK_TIMEOUT_MILLIS: u64 = 30 * 1000;
 
+/// The smallest ack timeout `Builder::with_ack_timeout` accepts.
+pub const MIN_ACK_TIMEOUT_MILLIS: u64 = 1;
+
+/// The largest ack timeout `Builder::with_ack_timeout` accepts; the
+/// upper limit of the protocol.
+pub const MAX_ACK_TIMEOUT_MILLIS: u64 = i32::MAX as u64;
+
 /// The default value for `Builder::with_required_acks`.
 pub const DEFAULT_REQUIRED_ACKS: RequiredAcks = RequiredAcks::One;
 
+/// The default value for `Builder::with_max_request_size`.
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// The default value for `Builder::with_close_timeout`.
+pub const DEFAULT_CLOSE_TIMEOUT_MILLIS: u64 = 1000;
+
+/// The default value for `Builder::with_retry_max_attempts`; a
+/// single attempt, i.e. no retries.
+pub const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 1;
+
+/// The default value for `Builder::with_retry_backoff_time`.
+pub const DEFAULT_RETRY_BACKOFF_TIME_MILLIS: u64 = client::DEFAULT_RETRY_BACKOFF_TIME_MILLIS;
+
 // --------------------------------------------------------------------
 
 /// A trait used by `Producer` to obtain the bytes `Record::key` and
@@ -92,6 +150,12 @@ pub const DEFAULT_REQUIRED_ACKS: RequiredAcks = RequiredAcks::One;
 /// for `key` and `value` with the client.
 pub trait AsBytes {
     fn as_bytes(&self) -> &[u8];
+
+    /// Retrieves the bytes to be sent, `None` denoting "null".  By
+    /// default, empty data is sent as "null".
+    fn as_nullable_bytes(&self) -> Option<&[u8]> {
+        to_option(self.as_bytes())
+    }
 }
 
 impl AsBytes for () {
@@ -127,6 +191,24 @@ impl<'a> AsBytes for &'a str {
         str::as_bytes(self)
     }
 }
+impl<const N: usize> AsBytes for &[u8; N] {
+    fn as_bytes(&self) -> &[u8] {
+        &self[..]
+    }
+}
+
+/// `None` is sent as "null" while `Some` is sent as the wrapped data
+/// even if it is empty.  This allows distinguishing a "null" key from
+/// an empty one.  See `Record::with_key`.
+impl<T: AsBytes> AsBytes for Option<T> {
+    fn as_bytes(&self) -> &[u8] {
+        self.as_ref().map_or(&[], AsBytes::as_bytes)
+    }
+
+    fn as_nullable_bytes(&self) -> Option<&[u8]> {
+        self.as_ref().map(AsBytes::as_bytes)
+    }
+}
 
 // --------------------------------------------------------------------
 
@@ -148,6 +230,14 @@ pub struct Record<'a, K, V> {
     /// "unspecified".  A `Producer` will then typically try to derive
     /// a partition on its own.
     pub partition: i32,
+
+    /// Data to derive the partition of this record from instead of
+    /// its key.  If present, the `Partitioner` is handed this data as
+    /// the message's key when determining the record's partition,
+    /// while the record is still sent with its actual key.  This is
+    /// ignored for records with an explicit `partition`.  See
+    /// `Record::with_partition_key`.
+    pub partition_key: Option<&'a [u8]>,
 }
 
 impl<'a, K, V> Record<'a, K, V> {
@@ -161,6 +251,7 @@ impl<'a, K, V> Record<'a, K, V> {
             value,
             topic,
             partition: -1,
+            partition_key: None,
         }
     }
 
@@ -170,6 +261,59 @@ impl<'a, K, V> Record<'a, K, V> {
         self.partition = partition;
         self
     }
+
+    /// Convenience method to set the partition key.  See
+    /// `Record::partition_key`.
+    #[inline]
+    pub fn with_partition_key<T: AsRef<[u8]> + ?Sized>(mut self, partition_key: &'a T) -> Self {
+        self.partition_key = Some(partition_key.as_ref());
+        self
+    }
+
+    /// Convenience method to replace the record's key with the given
+    /// one, explicitly distinguishing a "null" key from an empty one.
+    ///
+    /// A record with a `None` key is sent with a "null" key and
+    /// partitioned like a keyless record.  A record with a
+    /// `Some(&[])` key is sent with an empty key which the
+    /// `DefaultPartitioner` hashes like any other key.  Note that
+    /// other key types, e.g. `&str`, send empty keys as "null".
+    #[inline]
+    pub fn with_key<'k>(self, key: Option<&'k [u8]>) -> Record<'a, Option<&'k [u8]>, V> {
+        Record {
+            key,
+            value: self.value,
+            topic: self.topic,
+            partition: self.partition,
+            partition_key: self.partition_key,
+        }
+    }
+}
+
+impl<'a, K: AsBytes, V: AsBytes> Record<'a, K, V> {
+    /// Computes the number of bytes this record occupies on the wire
+    /// when sent uncompressed, including the framing of the message
+    /// within its message set, e.g. to pre-size batches or enforce
+    /// custom limits.  The request and partition overhead shared by
+    /// all records sent together is not accounted for.
+    ///
+    /// Note: this client sends records in the version 0 message
+    /// format which carries neither headers nor timestamps.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kafka::producer::Record;
+    ///
+    /// let r = Record::from_key_value("my-topic", "key", "value");
+    /// assert_eq!(26 + 3 + 5, r.estimated_size());
+    /// ```
+    pub fn estimated_size(&self) -> usize {
+        protocol::produce::message_size(
+            self.key.as_nullable_bytes(),
+            self.value.as_nullable_bytes(),
+        )
+    }
 }
 
 impl<'a, V> Record<'a, (), V> {
@@ -183,29 +327,210 @@ impl<'a, V> Record<'a, (), V> {
             value,
             topic,
             partition: -1,
+            partition_key: None,
         }
     }
 }
 
+#[cfg(feature = "serde")]
+impl<'a> Record<'a, (), Vec<u8>> {
+    /// Convenience function to create a new value only record with an
+    /// "unspecified" partition whose value is the JSON serialization
+    /// of the given value.
+    ///
+    /// Fails with `Error::CodecError` if the value cannot be
+    /// serialized.
+    pub fn from_json<T: serde::Serialize>(
+        topic: &'a str,
+        value: &T,
+    ) -> Result<Record<'a, (), Vec<u8>>> {
+        let value = serde_json::to_vec(value).map_err(|_| Error::CodecError)?;
+        Ok(Record::from_value(topic, value))
+    }
+
+    /// Convenience function to create a new value only record with an
+    /// "unspecified" partition whose value is the CBOR serialization
+    /// of the given value.
+    ///
+    /// Fails with `Error::CodecError` if the value cannot be
+    /// serialized.
+    pub fn from_cbor<T: serde::Serialize>(
+        topic: &'a str,
+        value: &T,
+    ) -> Result<Record<'a, (), Vec<u8>>> {
+        let value = serde_cbor::to_vec(value).map_err(|_| Error::CodecError)?;
+        Ok(Record::from_value(topic, value))
+    }
+}
+
 impl<'a, K: fmt::Debug, V: fmt::Debug> fmt::Debug for Record<'a, K, V> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Record {{ topic: {}, partition: {}, key: {:?}, value: {:?} }}",
-            self.topic, self.partition, self.key, self.value
+            "Record {{ topic: {}, partition: {}, key: {:?}, value: {:?}, \
+             partition_key: {:?} }}",
+            self.topic, self.partition, self.key, self.value, self.partition_key
         )
     }
 }
 
 // --------------------------------------------------------------------
 
+/// A record as handed to `ProduceInterceptor::on_send`.  The key and
+/// value are copies of the original record's data; `None` denotes
+/// "null".
+pub type InterceptedRecord<'a> = Record<'a, Option<Vec<u8>>, Option<Vec<u8>>>;
+
+/// A hook to inspect and modify records on their way to the brokers
+/// and to observe the brokers' acknowledgements, e.g. to tag records
+/// for tracing or to redact sensitive data.  Interceptors are
+/// registered through `Builder::with_interceptor`; multiple
+/// interceptors are invoked in the order they were registered in,
+/// each seeing the record as modified by its predecessors.
+///
+/// Note: record headers require a newer message format than the one
+/// this client speaks; interceptors may modify the record's key or
+/// value instead.
+pub trait ProduceInterceptor: Send + Sync {
+    /// Invoked for every record before it is partitioned and sent,
+    /// or when it is queued through `Producer::send_queued`.  Changes
+    /// to the record are sent in place of the original data.
+    fn on_send(&self, record: &mut InterceptedRecord<'_>);
+
+    /// Invoked for every confirm delivered by the brokers for sent
+    /// records.  Records sent with `RequiredAcks::None` are not
+    /// acknowledged.
+    ///
+    /// The default implementation does nothing.
+    fn on_acknowledgement(&self, _confirm: &ProduceConfirm) {}
+}
+
+// --------------------------------------------------------------------
+
 /// The Kafka Producer
 ///
 /// See module level documentation.
+///
+/// Records queued through `Producer::send_queued` but not flushed yet
+/// are flushed when the producer is dropped.  See
+/// `Producer::close`.
 pub struct Producer<P = DefaultPartitioner> {
     client: KafkaClient,
     state: State<P>,
     config: Config,
+    /// Records queued through `Producer::send_queued` awaiting the
+    /// next `Producer::flush`
+    queued: Vec<QueuedRecord>,
+    // ~ flushes the queued records when dropping the producer;
+    // captured at construction where `P: Partitioner` is known since
+    // a `Drop` impl cannot require more than the struct itself
+    flush_on_drop: fn(&mut Producer<P>),
+}
+
+/// A copy of a record queued for sending along with the slot to
+/// deliver its result to.
+struct QueuedRecord {
+    key: Option<Vec<u8>>,
+    value: Vec<u8>,
+    topic: String,
+    partition: i32,
+    partition_key: Option<Vec<u8>>,
+    slot: Arc<SendSlot>,
+}
+
+#[derive(Default)]
+struct SendSlot {
+    result: Mutex<Option<Result<ProduceConfirm>>>,
+    done: Condvar,
+}
+
+impl SendSlot {
+    fn complete(&self, r: Result<ProduceConfirm>) {
+        *self.result.lock().unwrap() = Some(r);
+        self.done.notify_all();
+    }
+}
+
+/// A handle to the outcome of sending a record queued through
+/// `Producer::send_queued`.  The outcome becomes available once the
+/// record has been sent through `Producer::flush`, possibly on
+/// another thread than the one holding the handle.
+pub struct SendHandle {
+    slot: Arc<SendSlot>,
+}
+
+impl SendHandle {
+    /// Determines whether the outcome of the corresponding record is
+    /// available, i.e. whether `SendHandle::wait` will return
+    /// immediately.
+    pub fn is_done(&self) -> bool {
+        self.slot.result.lock().unwrap().is_some()
+    }
+
+    /// Blocks until the outcome of the corresponding record is
+    /// available and returns it.  The delivered confirm refers to
+    /// the record only, i.e. it carries exactly one partition
+    /// confirm stating the partition the record was sent to and the
+    /// offset assigned to it.  If the record was sent without
+    /// requiring any acknowledgements, the confirm carries no
+    /// partition confirm at all.
+    ///
+    /// Fails with an `io::ErrorKind::TimedOut` error if the outcome
+    /// is not available within the given timeout.
+    pub fn wait(self, timeout: Duration) -> Result<ProduceConfirm> {
+        let deadline = Instant::now() + timeout;
+        let mut result = self.slot.result.lock().unwrap();
+        loop {
+            if let Some(r) = result.take() {
+                return r;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "queued record not sent in time",
+                )));
+            }
+            result = self
+                .slot
+                .done
+                .wait_timeout(result, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl fmt::Debug for SendHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SendHandle {{ done: {} }}", self.is_done())
+    }
+}
+
+/// Aggregate outcome of `Producer::produce_stream`.
+#[derive(Debug, Default)]
+pub struct ProduceStats {
+    /// The number of records successfully delivered.  With
+    /// `RequiredAcks::None` this is the number of records sent.
+    pub records_sent: u64,
+
+    /// The number of records which failed to be delivered by the
+    /// error reported for them.  Records exceeding
+    /// `Builder::with_max_request_size` are reported as
+    /// `KafkaCode::MessageSizeTooLarge`.
+    pub failures: HashMap<KafkaCode, u64>,
+}
+
+impl ProduceStats {
+    /// Retrieves the total number of records which failed to be
+    /// delivered.
+    pub fn records_failed(&self) -> u64 {
+        self.failures.values().sum()
+    }
+
+    fn add_failures(&mut self, code: KafkaCode, n: u64) {
+        *self.failures.entry(code).or_default() += n;
+    }
 }
 
 struct State<P> {
@@ -222,6 +547,24 @@ struct Config {
     /// The number of acks to request. See
     /// `KafkaClient::produce_messages`.
     required_acks: i16,
+    /// The maximum size of a single message to be sent. See
+    /// `Builder::with_max_request_size`.
+    max_request_size: usize,
+    /// The partition to send keyless records without an explicit
+    /// partition to. See `Builder::with_default_partition`.
+    default_partition: Option<i32>,
+    /// The maximum time to spend flushing queued records when
+    /// closing the producer. See `Builder::with_close_timeout`.
+    close_timeout: Duration,
+    /// The interceptors to hand records and confirms to. See
+    /// `Builder::with_interceptor`.
+    interceptors: Vec<Box<dyn ProduceInterceptor>>,
+    /// The maximum number of attempts to send a queued record. See
+    /// `Builder::with_retry_max_attempts`.
+    retry_max_attempts: u32,
+    /// The time to wait before retrying to send queued records. See
+    /// `Builder::with_retry_backoff_time`.
+    retry_backoff_time: Duration,
 }
 
 impl Producer {
@@ -247,8 +590,10 @@ impl Producer {
     }
 
     /// Destroys this producer returning the underlying kafka client.
-    pub fn into_client(self) -> KafkaClient {
-        self.client
+    /// Queued records are flushed as when dropping the producer.
+    pub fn into_client(mut self) -> KafkaClient {
+        self.flush_on_close();
+        mem::replace(&mut self.client, KafkaClient::new(Vec::new()))
     }
 }
 
@@ -259,9 +604,37 @@ impl<P: Partitioner> Producer<P> {
         K: AsBytes,
         V: AsBytes,
     {
-        let mut rs = self.send_all(from_ref(rec))?;
+        let acks = self.config.required_acks;
+        self.send_acks(rec, acks)
+    }
+
+    /// Synchronously send the specified message to Kafka requiring
+    /// the given acknowledgements instead of the ones this producer
+    /// was configured with.  See `Builder::with_required_acks`.
+    ///
+    /// This allows one producer to serve e.g. fire-and-forget
+    /// messages next to messages requiring an acknowledgement by all
+    /// in-sync replicas.
+    pub fn send_with_acks<'a, K, V>(
+        &mut self,
+        rec: &Record<'a, K, V>,
+        acks: RequiredAcks,
+    ) -> Result<()>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        self.send_acks(rec, acks as i16)
+    }
 
-        if self.config.required_acks == 0 {
+    fn send_acks<'a, K, V>(&mut self, rec: &Record<'a, K, V>, acks: i16) -> Result<()>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let mut rs = self.send_all_acks(from_ref(rec), acks)?;
+
+        if acks == 0 {
             // ~ with no required_acks we get no response and
             // consider the send-data request blindly as successful
             Ok(())
@@ -283,33 +656,747 @@ impl<P: Partitioner> Producer<P> {
     /// Synchronously send all of the specified messages to Kafka. To validate
     /// that all of the specified records have been successfully delivered,
     /// inspection of the offsets on the returned confirms is necessary.
+    ///
+    /// If a broker rejects the records sent to a partition with
+    /// `KafkaCode::RecordListTooLarge` or
+    /// `KafkaCode::MessageSizeTooLarge`, the records are split into
+    /// halves which are sent one after the other, recursively, until
+    /// they are accepted; the partition's confirm then carries the
+    /// offset of its first record.  If a single record is rejected
+    /// this way, the partition's confirm carries the error and the
+    /// records following it are not sent; records split off before
+    /// it may have been delivered already.  The confirms of the other
+    /// partitions are not affected.
     pub fn send_all<'a, K, V>(&mut self, recs: &[Record<'a, K, V>]) -> Result<Vec<ProduceConfirm>>
     where
         K: AsBytes,
         V: AsBytes,
     {
+        let acks = self.config.required_acks;
+        self.send_all_acks(recs, acks)
+    }
+
+    /// Synchronously send all of the specified messages to Kafka
+    /// requiring the given acknowledgements instead of the ones this
+    /// producer was configured with.  With `RequiredAcks::None` the
+    /// returned confirms are always empty.  See
+    /// `Producer::send_all` and `Producer::send_with_acks`.
+    pub fn send_all_with_acks<'a, K, V>(
+        &mut self,
+        recs: &[Record<'a, K, V>],
+        acks: RequiredAcks,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        self.send_all_acks(recs, acks as i16)
+    }
+
+    fn send_all_acks<'a, K, V>(
+        &mut self,
+        recs: &[Record<'a, K, V>],
+        acks: i16,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        if self.config.interceptors.is_empty() {
+            self.produce_all(recs, acks)
+        } else {
+            let recs = self.intercept(recs);
+            self.produce_all(&recs, acks)
+        }
+    }
+
+    fn produce_all<K, V>(
+        &mut self,
+        recs: &[Record<'_, K, V>],
+        acks: i16,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        // ~ reject oversized messages before hitting the brokers
+        for r in recs {
+            self.check_size(r)?;
+        }
+        let msgs = self.partition_all(recs)?;
+        self.produce_msgs(msgs, acks)
+    }
+
+    fn produce_msgs(
+        &mut self,
+        msgs: Vec<client::ProduceMessage<'_, '_>>,
+        acks: i16,
+    ) -> Result<Vec<ProduceConfirm>> {
+        let mut confirms =
+            self.client
+                .internal_produce_messages(acks, self.config.ack_timeout, &msgs)?;
+        self.split_rejected(&msgs, &mut confirms, acks);
+        self.acknowledge(&confirms);
+        Ok(confirms)
+    }
+
+    // ~ splits the batches of multiple messages the brokers rejected
+    // as too large (see `produce_split`) and updates their confirms:
+    // to the offset of the batch's first message if all of its
+    // messages got delivered, to the error of the first failed one
+    // otherwise.  returns the offsets of the messages of the split
+    // batches by partition.
+    fn split_rejected<'a>(
+        &mut self,
+        msgs: &[client::ProduceMessage<'a, '_>],
+        confirms: &mut [ProduceConfirm],
+        acks: i16,
+    ) -> HashMap<(&'a str, i32), Vec<std::result::Result<i64, KafkaCode>>> {
+        let mut split = HashMap::new();
+        for c in confirms {
+            for pc in &mut c.partition_confirms {
+                let code = match pc.offset {
+                    Err(code) if is_too_large(code) => code,
+                    _ => continue,
+                };
+                let batch: Vec<_> = msgs
+                    .iter()
+                    .filter(|m| m.topic == c.topic && m.partition == pc.partition)
+                    .collect();
+                if batch.len() < 2 {
+                    continue;
+                }
+                let offsets = self.produce_split(&batch, acks, code);
+                pc.offset = match offsets.last() {
+                    Some(&Err(code)) => Err(code),
+                    _ => offsets[0],
+                };
+                split.insert((batch[0].topic, pc.partition), offsets);
+            }
+        }
+        split
+    }
+
+    // ~ produces the given messages of a single partition whose batch
+    // the broker rejected with `code` being too large by recursively
+    // halving it; returns the offset of each message.  a message
+    // rejected as too large on its own fails with `code`.  to keep
+    // the messages in order, the ones following a failed message are
+    // not sent and fail with the same error; a network error fails
+    // them with `KafkaCode::NetworkException`.
+    fn produce_split(
+        &mut self,
+        batch: &[&client::ProduceMessage<'_, '_>],
+        acks: i16,
+        code: KafkaCode,
+    ) -> Vec<std::result::Result<i64, KafkaCode>> {
+        if batch.len() <= 1 {
+            return vec![Err(code); batch.len()];
+        }
+        debug!(
+            "produce: splitting batch of {} messages rejected with {:?}",
+            batch.len(),
+            code
+        );
+        let (first, second) = batch.split_at(batch.len() / 2);
+        let mut offsets = Vec::with_capacity(batch.len());
+        for half in [first, second] {
+            let base =
+                match self
+                    .client
+                    .internal_produce_messages(acks, self.config.ack_timeout, half)
+                {
+                    Ok(confirms) => confirms
+                        .into_iter()
+                        .flat_map(|c| c.partition_confirms)
+                        .next()
+                        .map_or(Err(KafkaCode::Unknown), |pc| pc.offset),
+                    Err(e) => {
+                        debug!("produce: failed to send split batch: {}", e);
+                        Err(KafkaCode::NetworkException)
+                    }
+                };
+            match base {
+                Ok(base) => offsets.extend((0..half.len() as i64).map(|n| Ok(base + n))),
+                Err(code) if is_too_large(code) => {
+                    offsets.extend(self.produce_split(half, acks, code));
+                }
+                Err(code) => offsets.push(Err(code)),
+            }
+            if let Some(&Err(code)) = offsets.last() {
+                offsets.resize(batch.len(), Err(code));
+                break;
+            }
+        }
+        offsets
+    }
+
+    /// Synchronously sends the given records to the partitions they
+    /// are paired with, bypassing this producer's partitioner, e.g.
+    /// for batches whose partitions have been determined upstream.
+    /// The records' own partitions are ignored.  To validate that all
+    /// of the records have been successfully delivered, inspection of
+    /// the offsets on the returned confirms is necessary.
+    ///
+    /// The partitions are validated against the loaded metadata
+    /// before anything is sent: a partition not existing for its
+    /// record's topic results in `Error::TopicPartitionError` with
+    /// `KafkaCode::UnknownTopicOrPartition`.
+    pub fn produce_assigned<K, V>(
+        &mut self,
+        recs: Vec<(i32, Record<'_, K, V>)>,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let recs: Vec<_> = recs
+            .into_iter()
+            .map(|(partition, r)| r.with_partition(partition))
+            .collect();
+        let acks = self.config.required_acks;
+        if self.config.interceptors.is_empty() {
+            self.produce_assigned_all(&recs, acks)
+        } else {
+            let recs = self.intercept(&recs);
+            self.produce_assigned_all(&recs, acks)
+        }
+    }
+
+    fn produce_assigned_all<K, V>(
+        &mut self,
+        recs: &[Record<'_, K, V>],
+        acks: i16,
+    ) -> Result<Vec<ProduceConfirm>>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let mut msgs = Vec::with_capacity(recs.len());
+        for r in recs {
+            self.check_size(r)?;
+            check_partition(&self.state.partitions, r.topic, r.partition)?;
+            msgs.push(client::ProduceMessage {
+                key: r.key.as_nullable_bytes(),
+                value: r.value.as_nullable_bytes(),
+                topic: r.topic,
+                partition: r.partition,
+            });
+        }
+        self.produce_msgs(msgs, acks)
+    }
+
+    // ~ copies the given records and passes them through the
+    // registered interceptors
+    fn intercept<'a, K, V>(&self, recs: &[Record<'a, K, V>]) -> Vec<InterceptedRecord<'a>>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        recs.iter().map(|r| self.intercept_one(r)).collect()
+    }
+
+    fn intercept_one<'a, K, V>(&self, r: &Record<'a, K, V>) -> InterceptedRecord<'a>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let mut r = Record {
+            key: r.key.as_nullable_bytes().map(<[u8]>::to_vec),
+            value: r.value.as_nullable_bytes().map(<[u8]>::to_vec),
+            topic: r.topic,
+            partition: r.partition,
+            partition_key: r.partition_key,
+        };
+        for i in &self.config.interceptors {
+            i.on_send(&mut r);
+        }
+        r
+    }
+
+    // ~ hands the given confirms to the registered interceptors
+    fn acknowledge(&self, confirms: &[ProduceConfirm]) {
+        for i in &self.config.interceptors {
+            for c in confirms {
+                i.on_acknowledgement(c);
+            }
+        }
+    }
+
+    /// Synchronously sends all records pulled from the given
+    /// iterator without materializing them all in memory at once.
+    ///
+    /// Records are pulled lazily in batches of at most `batch`
+    /// records whose data do not exceed
+    /// `Builder::with_max_request_size` in total.  A batch is pulled
+    /// only after the previous one has been acknowledged, such that
+    /// a slow cluster slows down the consumption of the iterator
+    /// rather than letting buffered records pile up.
+    ///
+    /// Records rejected by the brokers or being too large to be sent
+    /// do not stop the stream but are accounted for in the returned
+    /// statistics.  Other errors, e.g. network errors, abort the
+    /// stream and are returned; records pulled before may or may
+    /// not have been delivered then.
+    pub fn produce_stream<'a, K, V, I>(&mut self, recs: I, batch: usize) -> Result<ProduceStats>
+    where
+        K: AsBytes,
+        V: AsBytes,
+        I: IntoIterator<Item = Record<'a, K, V>>,
+    {
+        let limit = self.config.max_request_size;
+        let batch = batch.max(1);
+        let mut stats = ProduceStats::default();
+        let mut recs = recs.into_iter().peekable();
+        let mut buf = Vec::with_capacity(batch);
+        while recs.peek().is_some() {
+            // ~ pull records until the batch is full or the next one
+            // would exceed the request size limit
+            let mut size = 0;
+            while buf.len() < batch {
+                let Some(r) =
+                    recs.next_if(|r| buf.is_empty() || size + r.estimated_size() <= limit)
+                else {
+                    break;
+                };
+                let n = r.estimated_size();
+                if n > limit {
+                    stats.add_failures(KafkaCode::MessageSizeTooLarge, 1);
+                } else {
+                    size += n;
+                    buf.push(r);
+                }
+            }
+            if !buf.is_empty() {
+                self.send_stream_batch(&buf, &mut stats)?;
+                buf.clear();
+            }
+        }
+        Ok(stats)
+    }
+
+    fn send_stream_batch<K, V>(
+        &mut self,
+        recs: &[Record<'_, K, V>],
+        stats: &mut ProduceStats,
+    ) -> Result<()>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        if self.config.interceptors.is_empty() {
+            self.produce_stream_batch(recs, stats)
+        } else {
+            let recs = self.intercept(recs);
+            self.produce_stream_batch(&recs, stats)
+        }
+    }
+
+    fn produce_stream_batch<K, V>(
+        &mut self,
+        recs: &[Record<'_, K, V>],
+        stats: &mut ProduceStats,
+    ) -> Result<()>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let acks = self.config.required_acks;
+        let msgs = self.partition_all(recs)?;
+        // ~ confirms are reported per partition; remember how many
+        // records each of them stands for
+        let mut counts: HashMap<(&str, i32), u64> = HashMap::new();
+        for m in &msgs {
+            *counts.entry((m.topic, m.partition)).or_default() += 1;
+        }
+        let confirms =
+            self.client
+                .internal_produce_messages(acks, self.config.ack_timeout, msgs)?;
+        if acks == 0 {
+            stats.records_sent += recs.len() as u64;
+            return Ok(());
+        }
+        self.acknowledge(&confirms);
+        for c in confirms {
+            for pc in c.partition_confirms {
+                let n = counts
+                    .get(&(&c.topic[..], pc.partition))
+                    .copied()
+                    .unwrap_or(0);
+                match pc.offset {
+                    Ok(_) => stats.records_sent += n,
+                    Err(code) => stats.add_failures(code, n),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues the specified message for sending through the next
+    /// call to `Producer::flush` and returns a handle to the outcome
+    /// of sending it.  The record's key and value are copied.
+    ///
+    /// This allows producing many records at once while collecting
+    /// the outcome for each of them individually, possibly on other
+    /// threads.
+    pub fn send_queued<'a, K, V>(&mut self, rec: &Record<'a, K, V>) -> SendHandle
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let slot = Arc::new(SendSlot::default());
+        let queued = if self.config.interceptors.is_empty() {
+            QueuedRecord {
+                key: rec.key.as_nullable_bytes().map(<[u8]>::to_owned),
+                value: rec.value.as_bytes().to_owned(),
+                topic: rec.topic.to_owned(),
+                partition: rec.partition,
+                partition_key: rec.partition_key.map(<[u8]>::to_owned),
+                slot: Arc::clone(&slot),
+            }
+        } else {
+            let rec = self.intercept_one(rec);
+            QueuedRecord {
+                key: rec.key,
+                value: rec.value.unwrap_or_default(),
+                topic: rec.topic.to_owned(),
+                partition: rec.partition,
+                partition_key: rec.partition_key.map(<[u8]>::to_owned),
+                slot: Arc::clone(&slot),
+            }
+        };
+        self.queued.push(queued);
+        SendHandle { slot }
+    }
+
+    /// Retrieves the number of records queued through
+    /// `Producer::send_queued` but not sent yet.
+    pub fn num_queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Synchronously sends all records queued through
+    /// `Producer::send_queued` so far and delivers the outcome for
+    /// each of them to its `SendHandle`.
+    ///
+    /// If a partition rejects the records sent to it with a
+    /// retriable error, e.g. `KafkaCode::NotLeaderForPartition`, the
+    /// records are put back to the head of the queue - ahead of any
+    /// record queued later - and sent again after reloading the
+    /// metadata, up to `Builder::with_retry_max_attempts` times.
+    /// The error of the last attempt is delivered to the records'
+    /// handles once the attempts are exhausted.
+    ///
+    /// Records rejected by a broker as too large in total are split
+    /// like in `Producer::send_all`, each record receiving its own
+    /// offset or error.  Records exceeding
+    /// `Builder::with_max_request_size` fail individually without
+    /// affecting the others.  If sending the
+    /// remaining records fails as a whole, e.g. due to a network
+    /// error, the error is delivered to all of their handles as well
+    /// as returned from this method.
+    pub fn flush(&mut self) -> Result<()> {
+        self.flush_until(None).map(|_| ())
+    }
+
+    /// Flushes all queued records like `Producer::flush` but bounded
+    /// by `Builder::with_close_timeout` and consumes the producer.
+    ///
+    /// Unlike dropping the producer, which logs records that could
+    /// not be delivered, this surfaces the first error encountered
+    /// if any of the queued records failed to be delivered.
+    pub fn close(mut self) -> Result<()> {
+        let deadline = Instant::now() + self.config.close_timeout;
+        match self.flush_until(Some(deadline))?.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // ~ best-effort flush of the queued records when closing the
+    // producer implicitly; failures are logged
+    fn flush_on_close(&mut self) {
+        let n = self.queued.len();
+        if n == 0 {
+            return;
+        }
+        let deadline = Instant::now() + self.config.close_timeout;
+        match self.flush_until(Some(deadline)) {
+            Ok(errors) if errors.is_empty() => {}
+            Ok(errors) => warn!(
+                "close: {} of {} queued record(s) could not be sent: {}",
+                errors.len(),
+                n,
+                errors[0]
+            ),
+            Err(e) => warn!("close: {} queued record(s) could not be sent: {}", n, e),
+        }
+    }
+
+    // ~ sends all queued records bounded by the given deadline, if
+    // any, retrying partitions failing with a retriable error as
+    // configured; returns the errors of the records which failed
+    // individually
+    fn flush_until(&mut self, deadline: Option<Instant>) -> Result<Vec<Error>> {
+        let mut errors = Vec::new();
+        let mut attempt = 1;
+        loop {
+            let backoff = self.config.retry_backoff_time;
+            let may_retry = attempt < self.config.retry_max_attempts
+                && deadline
+                    .map(|d| Instant::now() + backoff < d)
+                    .unwrap_or(true);
+            if !self.flush_attempt(deadline, may_retry, &mut errors)? {
+                return Ok(errors);
+            }
+            attempt += 1;
+            debug!(
+                "flush: retrying {} record(s) after attempt {}",
+                self.queued.len(),
+                attempt - 1
+            );
+            thread::sleep(backoff);
+            if let Err(e) = self.refresh_metadata() {
+                debug!("flush: failed to refresh metadata: {}", e);
+            }
+        }
+    }
+
+    // ~ sends all queued records once; the records of partitions
+    // failing with a retriable error are put back to the head of the
+    // queue if `may_retry`, in which case true is returned
+    fn flush_attempt(
+        &mut self,
+        deadline: Option<Instant>,
+        may_retry: bool,
+        errors: &mut Vec<Error>,
+    ) -> Result<bool> {
+        let queued = mem::take(&mut self.queued);
+        let mut recs = Vec::with_capacity(queued.len());
+        let mut slots = Vec::with_capacity(queued.len());
+        // ~ the indices of the records in `recs` within `queued`
+        let mut indices = Vec::with_capacity(queued.len());
+        for (i, q) in queued.iter().enumerate() {
+            let r = Record {
+                key: q.key.as_deref(),
+                value: &q.value[..],
+                topic: &q.topic,
+                partition: q.partition,
+                partition_key: q.partition_key.as_deref(),
+            };
+            let size = r.estimated_size();
+            let limit = self.config.max_request_size;
+            if size > limit {
+                // ~ the same error as delivered to the record's handle
+                q.slot.complete(Err(Error::MessageTooLarge { size, limit }));
+                errors.push(Error::MessageTooLarge { size, limit });
+            } else {
+                recs.push(r);
+                slots.push(&q.slot);
+                indices.push(i);
+            }
+        }
+        if recs.is_empty() {
+            return Ok(false);
+        }
+
+        let acks = self.config.required_acks;
+        let msgs = match self.partition_all(&recs) {
+            Ok(msgs) => msgs,
+            Err(e) => {
+                let e = Arc::new(e);
+                for slot in slots {
+                    slot.complete(Err(Error::ArcSelf(Arc::clone(&e))));
+                }
+                return Err(Error::ArcSelf(e));
+            }
+        };
+        let ack_timeout = self.config.ack_timeout;
+        let confirms = match deadline {
+            None => self
+                .client
+                .internal_produce_messages(acks, ack_timeout, &msgs),
+            Some(deadline) => {
+                self.client
+                    .internal_produce_messages_deadline(deadline, acks, ack_timeout, &msgs)
+            }
+        };
+        let mut confirms = match confirms {
+            Ok(confirms) => confirms,
+            Err(e) => {
+                let e = Arc::new(e);
+                for slot in slots {
+                    slot.complete(Err(Error::ArcSelf(Arc::clone(&e))));
+                }
+                return Err(Error::ArcSelf(e));
+            }
+        };
+        let split = if acks == 0 {
+            HashMap::new()
+        } else {
+            self.split_rejected(&msgs, &mut confirms, acks)
+        };
+        self.acknowledge(&confirms);
+
+        // ~ messages to the same partition get consecutive offsets
+        // in the order they were sent in
+        let mut nth_in_partition: HashMap<(&str, i32), i64> = HashMap::new();
+        // ~ the (index within `queued`, partition) of the records to
+        // retry in the order they were sent in
+        let mut retries = Vec::new();
+        for ((m, slot), &i) in msgs.iter().zip(slots).zip(&indices) {
+            let partition_confirms = if acks == 0 {
+                vec![]
+            } else {
+                let nth = nth_in_partition.entry((m.topic, m.partition)).or_insert(0);
+                let offset = match split.get(&(m.topic, m.partition)) {
+                    Some(offsets) => offsets[*nth as usize],
+                    None => confirms
+                        .iter()
+                        .filter(|c| c.topic == m.topic)
+                        .flat_map(|c| &c.partition_confirms)
+                        .find(|p| p.partition == m.partition)
+                        .map_or(Err(KafkaCode::Unknown), |p| {
+                            p.offset.as_ref().map(|base| base + *nth).map_err(|e| *e)
+                        }),
+                };
+                *nth += 1;
+                match offset {
+                    Err(code) if may_retry && code.is_retriable() => {
+                        retries.push((i, m.partition));
+                        continue;
+                    }
+                    Err(code) => errors.push(Error::Kafka(code)),
+                    Ok(_) => {}
+                }
+                vec![ProducePartitionConfirm {
+                    offset,
+                    partition: m.partition,
+                }]
+            };
+            slot.complete(Ok(ProduceConfirm {
+                topic: m.topic.to_owned(),
+                partition_confirms,
+            }));
+        }
+        if retries.is_empty() {
+            return Ok(false);
+        }
+        // ~ put the records to retry back to the head of the queue
+        // keeping the partitions they were assigned to
+        let mut queued: Vec<_> = queued.into_iter().map(Some).collect();
+        let mut requeued = Vec::with_capacity(retries.len() + self.queued.len());
+        for (i, partition) in retries {
+            let mut q = queued[i].take().expect("record retried twice");
+            q.partition = partition;
+            requeued.push(q);
+        }
+        requeued.append(&mut self.queued);
+        self.queued = requeued;
+        Ok(true)
+    }
+
+    /// Reloads the metadata of all topics from the brokers and
+    /// updates this producer's view of the topics' partitions
+    /// accordingly.  The partitioner is notified about every topic
+    /// whose number of partitions changed through
+    /// `Partitioner::partitions_changed`.
+    ///
+    /// Note: records partitioned by key are dispatched based on the
+    /// total number of partitions of their topic.  Once the number
+    /// changes, e.g. due to a topic being expanded, records with the
+    /// same key as before will generally end up in a different
+    /// partition.
+    pub fn refresh_metadata(&mut self) -> Result<()> {
+        self.client.load_metadata_all()?;
+        self.state.update(&self.client);
+        Ok(())
+    }
+
+    fn check_size<'a, K, V>(&self, r: &Record<'a, K, V>) -> Result<()>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let size = r.estimated_size();
+        if size > self.config.max_request_size {
+            Err(Error::MessageTooLarge {
+                size,
+                limit: self.config.max_request_size,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    // ~ turns the given records into messages with their partition
+    // determined
+    fn partition_all<'a, 'b, K, V>(
+        &mut self,
+        recs: &'b [Record<'a, K, V>],
+    ) -> Result<Vec<client::ProduceMessage<'a, 'b>>>
+    where
+        K: AsBytes,
+        V: AsBytes,
+    {
+        let default_partition = self.config.default_partition;
         let partitioner = &mut self.state.partitioner;
         let partitions = &self.state.partitions;
-        let client = &mut self.client;
-        let config = &self.config;
-
-        client.internal_produce_messages(
-            config.required_acks,
-            config.ack_timeout,
-            recs.iter().map(|r| {
+        recs.iter()
+            .map(|r| {
+                // ~ the partitioner sees the partition key - if any -
+                // in place of the actual key
+                let key = r.key.as_nullable_bytes();
                 let mut m = client::ProduceMessage {
-                    key: to_option(r.key.as_bytes()),
-                    value: to_option(r.value.as_bytes()),
+                    key: r.partition_key.or(key),
+                    value: r.value.as_nullable_bytes(),
                     topic: r.topic,
                     partition: r.partition,
                 };
-                partitioner.partition(Topics::new(partitions), &mut m);
-                m
-            }),
-        )
+                match default_partition {
+                    // ~ keyless records without an explicit partition
+                    // bypass the partitioner if there's a default
+                    Some(p) if m.partition < 0 && m.key.is_none() => {
+                        check_partition(partitions, m.topic, p)?;
+                        m.partition = p;
+                    }
+                    _ => partitioner.partition(Topics::new(partitions), &mut m),
+                }
+                m.key = key;
+                Ok(m)
+            })
+            .collect()
     }
 }
 
+// ~ verifies the given partition exists for the given topic
+fn check_partition(
+    partitions: &HashMap<String, Partitions>,
+    topic: &str,
+    partition: i32,
+) -> Result<()> {
+    let exists = partitions
+        .get(topic)
+        .is_some_and(|ps| u32::try_from(partition).is_ok_and(|p| p < ps.num_all_partitions));
+    if exists {
+        Ok(())
+    } else {
+        Err(Error::TopicPartitionError {
+            topic_name: topic.to_owned(),
+            partition_id: partition,
+            error_code: KafkaCode::UnknownTopicOrPartition,
+        })
+    }
+}
+
+// ~ whether a broker rejected a batch of messages for its size
+fn is_too_large(code: KafkaCode) -> bool {
+    matches!(
+        code,
+        KafkaCode::RecordListTooLarge | KafkaCode::MessageSizeTooLarge
+    )
+}
+
 fn to_option(data: &[u8]) -> Option<&[u8]> {
     if data.is_empty() {
         None
@@ -322,6 +1409,13 @@ fn to_option(data: &[u8]) -> Option<&[u8]> {
 
 impl<P> State<P> {
     fn new(client: &mut KafkaClient, partitioner: P) -> Result<State<P>> {
+        Ok(State {
+            partitions: Self::load_partitions(client),
+            partitioner,
+        })
+    }
+
+    fn load_partitions(client: &KafkaClient) -> HashMap<String, Partitions> {
         let ts = client.topics();
         let mut ids = HashMap::with_capacity(ts.len());
         for t in ts {
@@ -334,10 +1428,37 @@ impl<P> State<P> {
                 },
             );
         }
-        Ok(State {
-            partitions: ids,
-            partitioner,
-        })
+        ids
+    }
+}
+
+impl<P: Partitioner> State<P> {
+    // ~ reloads the partitions from the client's metadata notifying
+    // the partitioner about topics with a changed number of
+    // partitions
+    fn update(&mut self, client: &KafkaClient) {
+        let partitions = Self::load_partitions(client);
+        for (topic, ps) in &partitions {
+            match self.partitions.get(topic) {
+                Some(prev) if prev.num_all_partitions != ps.num_all_partitions => {
+                    warn!(
+                        "number of partitions of topic '{}' changed from {} to {}; \
+                         records partitioned by key will be dispatched differently",
+                        topic, prev.num_all_partitions, ps.num_all_partitions
+                    );
+                    self.partitioner
+                        .partitions_changed(topic, ps.num_all_partitions);
+                }
+                _ => {}
+            }
+        }
+        self.partitions = partitions;
+    }
+}
+
+impl<P> Drop for Producer<P> {
+    fn drop(&mut self) {
+        (self.flush_on_drop)(self);
     }
 }
 
@@ -349,12 +1470,19 @@ pub struct Builder<P = DefaultPartitioner> {
     client: Option<KafkaClient>,
     hosts: Vec<String>,
     compression: Compression,
+    compression_min_size: usize,
     ack_timeout: Duration,
     conn_idle_timeout: Duration,
     required_acks: RequiredAcks,
+    max_request_size: usize,
     partitioner: P,
     security_config: Option<SecurityConfig>,
     client_id: Option<String>,
+    default_partition: Option<i32>,
+    close_timeout: Duration,
+    interceptors: Vec<Box<dyn ProduceInterceptor>>,
+    retry_max_attempts: u32,
+    retry_backoff_time: Duration,
 }
 
 impl Builder {
@@ -363,17 +1491,25 @@ impl Builder {
             client,
             hosts,
             compression: client::DEFAULT_COMPRESSION,
+            compression_min_size: client::DEFAULT_COMPRESSION_MIN_SIZE,
             ack_timeout: Duration::from_millis(DEFAULT_ACK_TIMEOUT_MILLIS),
             conn_idle_timeout: Duration::from_millis(
                 client::DEFAULT_CONNECTION_IDLE_TIMEOUT_MILLIS,
             ),
             required_acks: DEFAULT_REQUIRED_ACKS,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
             partitioner: DefaultPartitioner::default(),
             security_config: None,
             client_id: None,
+            default_partition: None,
+            close_timeout: Duration::from_millis(DEFAULT_CLOSE_TIMEOUT_MILLIS),
+            interceptors: Vec::new(),
+            retry_max_attempts: DEFAULT_RETRY_MAX_ATTEMPTS,
+            retry_backoff_time: Duration::from_millis(DEFAULT_RETRY_BACKOFF_TIME_MILLIS),
         };
         if let Some(ref c) = b.client {
             b.compression = c.compression();
+            b.compression_min_size = c.compression_min_size();
             b.conn_idle_timeout = c.connection_idle_timeout();
         }
         b
@@ -395,10 +1531,28 @@ impl Builder {
         self
     }
 
+    /// Sets the uncompressed size in bytes a partition's batch of
+    /// messages must reach to be compressed.  Smaller batches are
+    /// sent uncompressed regardless of `Builder::with_compression`.
+    ///
+    /// See `KafkaClient::set_compression_min_size`.
+    pub fn with_compression_min_size(mut self, min_size: usize) -> Self {
+        self.compression_min_size = min_size;
+        self
+    }
+
     /// Sets the maximum time the kafka brokers can await the receipt
     /// of required acknowledgements (which is specified through
     /// `Builder::with_required_acks`.)  Note that Kafka explicitly
     /// documents this not to be a hard limit.
+    ///
+    /// This is the timeout sent along with every produce request and
+    /// is unrelated to the time the client waits for a response from
+    /// the brokers.  Values outside of `MIN_ACK_TIMEOUT_MILLIS` and
+    /// `MAX_ACK_TIMEOUT_MILLIS` are clamped to that range.  If the
+    /// brokers fail to receive the required acknowledgements in time,
+    /// sending fails with `KafkaCode::RequestTimedOut` for the
+    /// affected partitions.
     pub fn with_ack_timeout(mut self, timeout: Duration) -> Self {
         self.ack_timeout = timeout;
         self
@@ -414,21 +1568,84 @@ impl Builder {
     /// Sets how many acknowledgements the kafka brokers should
     /// receive before responding to sent messages.
     ///
-    /// See `RequiredAcks`.
+    /// See `RequiredAcks`.  A numeric setting, e.g. read from a
+    /// configuration file, is validated by converting it through
+    /// `RequiredAcks::try_from` which rejects anything other than
+    /// -1, 0 or 1 with `Error::InvalidRequiredAcks`.
     pub fn with_required_acks(mut self, acks: RequiredAcks) -> Self {
         self.required_acks = acks;
         self
     }
 
+    /// Sets the maximum size in bytes of a single message (including
+    /// its key and the protocol's per message overhead) the producer
+    /// will attempt to send.  Larger messages are rejected with
+    /// `Error::MessageTooLarge` without contacting the brokers.
+    ///
+    /// This should be kept in sync with the brokers'
+    /// `message.max.bytes` setting.
+    pub fn with_max_request_size(mut self, max_request_size: usize) -> Self {
+        self.max_request_size = max_request_size;
+        self
+    }
+
     /// Specifies a client_id to be sent along every request to Kafka
     /// brokers. See `KafkaClient::set_client_id`.
     pub fn with_client_id(mut self, client_id: String) -> Self {
         self.client_id = Some(client_id);
         self
     }
+
+    /// Sets the partition to send records to which carry neither a
+    /// key (nor a partition key) nor an explicit partition.  Such
+    /// records bypass the
+    /// partitioner entirely; all other records are still dispatched
+    /// through it.  This is useful for keeping a stream of keyless
+    /// records strictly ordered.
+    ///
+    /// Sending a record to a topic which doesn't have the specified
+    /// partition fails with `Error::TopicPartitionError`.
+    pub fn with_default_partition(mut self, partition: i32) -> Self {
+        self.default_partition = Some(partition);
+        self
+    }
+
+    /// Sets the maximum number of attempts to send records queued
+    /// through `Producer::send_queued` to a partition which rejects
+    /// them with a retriable error.  Retried records are sent ahead
+    /// of any record queued later, preserving their order within
+    /// the partition.  See `Producer::flush`.
+    ///
+    /// The default value for this setting is
+    /// `DEFAULT_RETRY_MAX_ATTEMPTS`, i.e. records are not retried.
+    pub fn with_retry_max_attempts(mut self, attempts: u32) -> Self {
+        self.retry_max_attempts = attempts;
+        self
+    }
+
+    /// Sets the time to wait before retrying to send queued records.
+    /// See `Builder::with_retry_max_attempts`.
+    ///
+    /// The default value for this setting is
+    /// `DEFAULT_RETRY_BACKOFF_TIME_MILLIS`.
+    pub fn with_retry_backoff_time(mut self, time: Duration) -> Self {
+        self.retry_backoff_time = time;
+        self
+    }
+
+    /// Sets the maximum time to spend flushing records queued through
+    /// `Producer::send_queued` when the producer is closed or
+    /// dropped.  Records not sent within this time are failed.
+    ///
+    /// The default value for this setting is
+    /// `DEFAULT_CLOSE_TIMEOUT_MILLIS`.
+    pub fn with_close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = timeout;
+        self
+    }
 }
 
-impl<P> Builder<P> {
+impl<P: Partitioner> Builder<P> {
     /// Sets the partitioner to dispatch when sending messages without
     /// an explicit partition assignment.
     pub fn with_partitioner<Q: Partitioner>(self, partitioner: Q) -> Builder<Q> {
@@ -436,15 +1653,34 @@ impl<P> Builder<P> {
             client: self.client,
             hosts: self.hosts,
             compression: self.compression,
+            compression_min_size: self.compression_min_size,
             ack_timeout: self.ack_timeout,
             conn_idle_timeout: self.conn_idle_timeout,
             required_acks: self.required_acks,
+            max_request_size: self.max_request_size,
             partitioner,
             security_config: None,
             client_id: None,
+            default_partition: self.default_partition,
+            close_timeout: self.close_timeout,
+            interceptors: self.interceptors,
+            retry_max_attempts: self.retry_max_attempts,
+            retry_backoff_time: self.retry_backoff_time,
         }
     }
 
+    /// Registers an interceptor to inspect and modify records before
+    /// they are sent and to observe their acknowledgements.  Multiple
+    /// interceptors are invoked in the order of their registration.
+    /// See `ProduceInterceptor`.
+    ///
+    /// Note: with interceptors registered, the key and value of every
+    /// sent record are copied before being handed to them.
+    pub fn with_interceptor<I: ProduceInterceptor + 'static>(mut self, interceptor: I) -> Self {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
     #[cfg(not(feature = "security"))]
     fn new_kafka_client(hosts: Vec<String>, _: Option<SecurityConfig>) -> KafkaClient {
         KafkaClient::new(hosts)
@@ -472,13 +1708,23 @@ impl<P> Builder<P> {
         };
         // ~ apply configuration settings
         client.set_compression(self.compression);
+        client.set_compression_min_size(self.compression_min_size);
         client.set_connection_idle_timeout(self.conn_idle_timeout);
         if let Some(client_id) = self.client_id {
             client.set_client_id(client_id);
         }
         let producer_config = Config {
-            ack_timeout: protocol::to_millis_i32(self.ack_timeout)?,
+            ack_timeout: protocol::to_millis_i32(self.ack_timeout.clamp(
+                Duration::from_millis(MIN_ACK_TIMEOUT_MILLIS),
+                Duration::from_millis(MAX_ACK_TIMEOUT_MILLIS),
+            ))?,
             required_acks: self.required_acks as i16,
+            max_request_size: self.max_request_size,
+            default_partition: self.default_partition,
+            close_timeout: self.close_timeout,
+            interceptors: self.interceptors,
+            retry_max_attempts: self.retry_max_attempts,
+            retry_backoff_time: self.retry_backoff_time,
         };
         // ~ load metadata if necessary
         if need_metadata {
@@ -490,6 +1736,8 @@ impl<P> Builder<P> {
             client,
             state,
             config: producer_config,
+            queued: Vec::new(),
+            flush_on_drop: Producer::flush_on_close,
         })
     }
 }
@@ -527,6 +1775,14 @@ impl Partitions {
         self.available_ids.len() as u32
     }
 
+    /// Determines whether the specified partition is currently
+    /// "available", i.e. has a leader broker assigned.  See
+    /// `Partitions::available_ids`.
+    #[inline]
+    pub fn is_available(&self, partition: i32) -> bool {
+        self.available_ids.contains(&partition)
+    }
+
     /// The total number of partitions of the underlygin topic.  This
     /// number includes also partitions without a current leader
     /// assignment.
@@ -563,6 +1819,15 @@ pub trait Partitioner {
     /// `msg` the message whose partition assignment potentially to
     /// change.
     fn partition(&mut self, topics: Topics<'_>, msg: &mut client::ProduceMessage<'_, '_>);
+
+    /// Notifies the partitioner that the total number of partitions
+    /// of the given topic changed to `num_partitions`, e.g. due to
+    /// the topic having been expanded.  Stateful partitioners are
+    /// supposed to reset any state derived from the previous number
+    /// of partitions.  See `Producer::refresh_metadata`.
+    ///
+    /// The default implementation does nothing.
+    fn partitions_changed(&mut self, _topic: &str, _num_partitions: u32) {}
 }
 
 /// The default hasher implementation used of `DefaultPartitioner`.
@@ -592,6 +1857,14 @@ pub type DefaultHasher = XxHash32;
 /// fashion.  "Available" it this context means partitions with a
 /// known leader.
 ///
+/// By default, keyed messages are dispatched to their partition no
+/// matter whether it is currently available or not.  A partitioner
+/// created with `DefaultPartitioner::with_prefer_available(true)`
+/// instead re-hashes keyed messages whose partition lacks a leader
+/// onto the available partitions; this trades the consistency of the
+/// key to partition mapping for not stalling on leaderless
+/// partitions.
+///
 /// This behavior may not suffice every workload.  If your application
 /// is dependent on a particular distribution scheme different from
 /// the one outlined above, you want to provide your own partioner to
@@ -605,6 +1878,9 @@ pub struct DefaultPartitioner<H = BuildHasherDefault<DefaultHasher>> {
     // ~ a counter incremented with each partitioned message to
     // achieve a different partition assignment for each message
     cntr: u32,
+    // ~ whether to dispatch keyed messages to available partitions
+    // only
+    prefer_available: bool,
 }
 
 impl DefaultPartitioner {
@@ -614,6 +1890,7 @@ impl DefaultPartitioner {
         DefaultPartitioner {
             hash_builder,
             cntr: 0,
+            prefer_available: false,
         }
     }
 
@@ -624,10 +1901,22 @@ impl DefaultPartitioner {
         DefaultPartitioner {
             hash_builder: BuildHasherDefault::<B>::default(),
             cntr: 0,
+            prefer_available: false,
         }
     }
 }
 
+impl<H> DefaultPartitioner<H> {
+    /// Specifies whether to dispatch messages with a key to an
+    /// available partition if the partition derived from the key is
+    /// currently unavailable.  See `DefaultPartitioner`.  By default,
+    /// keyed messages are dispatched regardless of availability.
+    pub fn with_prefer_available(mut self, prefer_available: bool) -> Self {
+        self.prefer_available = prefer_available;
+        self
+    }
+}
+
 impl<H: BuildHasher> Partitioner for DefaultPartitioner<H> {
     #[allow(unused_variables)]
     fn partition(&mut self, topics: Topics<'_>, rec: &mut client::ProduceMessage<'_, '_>) {
@@ -661,6 +1950,12 @@ impl<H: BuildHasher> Partitioner for DefaultPartitioner<H> {
                 // negative partition ... such a partition count is very
                 // unlikely though
                 rec.partition = (hash % num_partitions) as i32;
+                if self.prefer_available && !partitions.is_available(rec.partition) {
+                    let avail = partitions.available_ids();
+                    if !avail.is_empty() {
+                        rec.partition = avail[hash as usize % avail.len()];
+                    }
+                }
             }
             None => {
                 // ~ no key available, determine a partition from the
@@ -675,6 +1970,11 @@ impl<H: BuildHasher> Partitioner for DefaultPartitioner<H> {
             }
         }
     }
+
+    fn partitions_changed(&mut self, _topic: &str, _num_partitions: u32) {
+        // ~ start over distributing keyless messages
+        self.cntr = 0;
+    }
 }
 
 // --------------------------------------------------------------------
@@ -795,4 +2095,851 @@ mod default_partitioner_tests {
         let p2 = assert_partitioning(&h, &mut p, "contents", "B" /* ascii: 66 */);
         assert_eq!(6, p2);
     }
+
+    /// Validate keyed messages avoid leaderless partitions only if
+    /// asked to
+    #[test]
+    fn test_key_partitioning_prefer_available() {
+        let h = topics_map(vec![(
+            "contents",
+            Partitions {
+                available_ids: vec![0, 1, 9],
+                num_all_partitions: 10,
+            },
+        )]);
+
+        // ~ partition 6 is leaderless but chosen by default
+        let mut p = DefaultPartitioner::with_default_hasher::<MyCustomHasher>();
+        assert_eq!(6, assert_partitioning(&h, &mut p, "contents", "B"));
+
+        // ~ 66 % 3 picks the first available partition instead
+        let mut p = p.with_prefer_available(true);
+        assert_eq!(0, assert_partitioning(&h, &mut p, "contents", "B"));
+        // ~ available partitions are kept
+        assert_eq!(
+            9,
+            assert_partitioning(&h, &mut p, "contents", "c" /* ascii: 99 */)
+        );
+    }
+}
+
+#[cfg(test)]
+mod producer_tests {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{
+        InterceptedRecord, Partitioner, ProduceInterceptor, Producer, Record, RequiredAcks,
+        SendHandle, Topics,
+    };
+    use crate::client::fake_broker::{serve, serve_with_topics, MockBroker};
+    use crate::client::{self, KafkaClient};
+    use crate::error::{Error, KafkaCode};
+
+    #[test]
+    fn test_send_with_acks() {
+        let (host, num_requests) = serve(&[("foo", 1)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![host])
+            .with_required_acks(RequiredAcks::One)
+            .create()
+            .unwrap();
+        let rec = Record::from_value("foo", "bar").with_partition(0);
+
+        // ~ fire-and-forget: no response is awaited
+        let confirms = producer
+            .send_all_with_acks(&[rec], RequiredAcks::None)
+            .unwrap();
+        assert!(confirms.is_empty());
+
+        // ~ all in-sync replicas: the confirmed offset is delivered
+        let rec = Record::from_value("foo", "bar").with_partition(0);
+        let confirms = producer
+            .send_all_with_acks(&[rec], RequiredAcks::All)
+            .unwrap();
+        assert_eq!(1, confirms.len());
+        assert_eq!(Ok(11), confirms[0].partition_confirms[0].offset);
+
+        let rec = Record::from_value("foo", "bar").with_partition(0);
+        producer.send_with_acks(&rec, RequiredAcks::None).unwrap();
+        producer.send(&rec).unwrap();
+        // ~ metadata, four produce requests
+        assert_eq!(5, num_requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_ack_timeout() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let create = |timeout| {
+            Producer::from_hosts(vec![broker.host().to_owned()])
+                .with_ack_timeout(timeout)
+                .create()
+                .unwrap()
+        };
+        let rec = Record::from_value("foo", "bar");
+        create(Duration::from_millis(1500)).send(&rec).unwrap();
+        // ~ out of range timeouts get clamped
+        create(Duration::ZERO).send(&rec).unwrap();
+        create(Duration::from_secs(u64::MAX)).send(&rec).unwrap();
+        assert_eq!(vec![1500, 1, i32::MAX], broker.ack_timeouts());
+
+        // ~ brokers failing to collect the acknowledgements in time
+        broker.set_partition_error("foo", 0, Some(KafkaCode::RequestTimedOut));
+        let r = create(Duration::from_millis(10)).send(&rec);
+        assert!(matches!(r, Err(Error::Kafka(KafkaCode::RequestTimedOut))));
+    }
+
+    #[test]
+    fn test_ordering_across_retries() {
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_retry_max_attempts(3)
+            .with_retry_backoff_time(Duration::from_millis(10))
+            .create()
+            .unwrap();
+        let values = || {
+            broker
+                .produced_values("foo", 0)
+                .into_iter()
+                .map(|v| String::from_utf8(v).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // ~ the first attempt fails without appending anything and
+        // is retried before any later record reaches the partition
+        broker.fail_produce_requests("foo", 0, KafkaCode::NotLeaderForPartition, 1);
+        let handles: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|v| producer.send_queued(&Record::from_value("foo", *v).with_partition(0)))
+            .collect();
+        producer.flush().unwrap();
+        // ~ metadata, the failed produce request, the metadata reload
+        // following the leadership error, and the retried produce
+        // request
+        assert_eq!(vec![3, 0, 3, 0], broker.requests());
+        assert_eq!(vec!["a", "b"], values());
+
+        let h = producer.send_queued(&Record::from_value("foo", "c").with_partition(0));
+        producer.flush().unwrap();
+        assert_eq!(vec!["a", "b", "c"], values());
+        let offsets: Vec<_> = handles
+            .into_iter()
+            .chain(Some(h))
+            .map(|h| {
+                let confirm = h.wait(Duration::from_secs(1)).unwrap();
+                confirm.partition_confirms[0].offset
+            })
+            .collect();
+        assert_eq!(vec![Ok(10), Ok(11), Ok(12)], offsets);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_send_serialized_records() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Event {
+            id: u32,
+            name: String,
+        }
+
+        let (host, _) = serve(&[("foo", 1)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![host]).create().unwrap();
+        let event = Event {
+            id: 42,
+            name: "created".to_owned(),
+        };
+
+        let rec = Record::from_json("foo", &event).unwrap().with_partition(0);
+        producer.send(&rec).unwrap();
+        assert_eq!(event, serde_json::from_slice::<Event>(&rec.value).unwrap());
+
+        let rec = Record::from_cbor("foo", &event).unwrap().with_partition(0);
+        producer.send(&rec).unwrap();
+        assert_eq!(event, serde_cbor::from_slice::<Event>(&rec.value).unwrap());
+    }
+
+    #[test]
+    fn test_send_queued() {
+        let (host, num_requests) = serve(&[("foo", 2)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![host])
+            .with_required_acks(RequiredAcks::One)
+            .create()
+            .unwrap();
+
+        let handles: Vec<_> = [0, 1, 0, 0]
+            .iter()
+            .map(|&p| producer.send_queued(&Record::from_value("foo", "bar").with_partition(p)))
+            .collect();
+        assert_eq!(4, producer.num_queued());
+        assert!(handles.iter().all(|h| !h.is_done()));
+
+        producer.flush().unwrap();
+        assert_eq!(0, producer.num_queued());
+        assert!(handles.iter().all(SendHandle::is_done));
+
+        let offsets: Vec<_> = handles
+            .into_iter()
+            .map(|h| {
+                let confirm = h.wait(Duration::from_secs(1)).unwrap();
+                assert_eq!("foo", confirm.topic);
+                assert_eq!(1, confirm.partition_confirms.len());
+                let p = &confirm.partition_confirms[0];
+                (p.partition, p.offset.unwrap())
+            })
+            .collect();
+        assert_eq!(vec![(0, 10), (1, 10), (0, 11), (0, 12)], offsets);
+        // ~ metadata, a single produce request
+        assert_eq!(2, num_requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_send_queued_wait_times_out() {
+        let mut producer = Producer::from_client(KafkaClient::new(vec![]))
+            .create()
+            .unwrap();
+        let h = producer.send_queued(&Record::from_value("foo", "bar"));
+        match h.wait(Duration::from_millis(10)) {
+            Err(Error::Io(e)) => assert_eq!(io::ErrorKind::TimedOut, e.kind()),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    // ~ collects the messages of warnings logged on the current thread
+    // while running `f`
+    fn capture_warnings<F: FnOnce()>(f: F) -> Vec<String> {
+        use std::fmt::{self, Write};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record as SpanRecord};
+        use tracing::{Event, Level, Metadata, Subscriber};
+
+        struct Warnings(Arc<Mutex<Vec<String>>>);
+        struct Message(String);
+
+        impl Visit for Message {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                if field.name() == "message" {
+                    write!(self.0, "{:?}", value).unwrap();
+                }
+            }
+        }
+
+        impl Subscriber for Warnings {
+            fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+                *metadata.level() == Level::WARN
+            }
+            fn new_span(&self, _: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &SpanRecord<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut msg = Message(String::new());
+                event.record(&mut msg);
+                self.0.lock().unwrap().push(msg.0);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Warnings(Arc::clone(&warnings)), f);
+        let warnings = warnings.lock().unwrap();
+        warnings.clone()
+    }
+
+    #[test]
+    fn test_drop_flushes_queued() {
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        broker.set_partition_error("foo", 1, Some(KafkaCode::MessageSizeTooLarge));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .create()
+            .unwrap();
+
+        let handles: Vec<_> = [0, 1, 0]
+            .iter()
+            .map(|&p| producer.send_queued(&Record::from_value("foo", "bar").with_partition(p)))
+            .collect();
+        let warnings = capture_warnings(|| drop(producer));
+        assert_eq!(2, broker.num_produced("foo", 0));
+        assert!(handles.iter().all(SendHandle::is_done));
+        assert_eq!(1, warnings.len(), "{:?}", warnings);
+        assert!(
+            warnings[0].contains("1 of 3 queued record(s)"),
+            "{}",
+            warnings[0]
+        );
+
+        // ~ an unresponsive broker does not block dropping for long
+        broker.set_response_delay(Duration::from_secs(2));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_close_timeout(Duration::from_millis(100))
+            .create()
+            .unwrap();
+        producer.send_queued(&Record::from_value("foo", "bar").with_partition(0));
+        let start = Instant::now();
+        let warnings = capture_warnings(|| drop(producer));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(1, warnings.len(), "{:?}", warnings);
+        assert!(
+            warnings[0].contains("1 queued record(s)"),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn test_flush_retries_failed_partitions() {
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_retry_max_attempts(3)
+            .with_retry_backoff_time(Duration::from_millis(10))
+            .create()
+            .unwrap();
+
+        // ~ partition 0 fails transiently as a whole
+        broker.fail_produce_requests("foo", 0, KafkaCode::NotLeaderForPartition, 2);
+        let handles: Vec<_> = [(0, "a"), (1, "b"), (0, "c"), (0, "d")]
+            .iter()
+            .map(|&(p, v)| producer.send_queued(&Record::from_value("foo", v).with_partition(p)))
+            .collect();
+        producer.flush().unwrap();
+        assert_eq!(0, producer.num_queued());
+        let values = |p| {
+            broker
+                .produced_values("foo", p)
+                .into_iter()
+                .map(|v| String::from_utf8(v).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["a", "c", "d"], values(0));
+        assert_eq!(vec!["b"], values(1));
+        let offsets: Vec<_> = handles
+            .into_iter()
+            .map(|h| {
+                let confirm = h.wait(Duration::from_secs(1)).unwrap();
+                confirm.partition_confirms[0].offset
+            })
+            .collect();
+        assert_eq!(vec![Ok(10), Ok(10), Ok(11), Ok(12)], offsets);
+
+        // ~ the error surfaces once the attempts are exhausted
+        broker.fail_produce_requests("foo", 1, KafkaCode::NotLeaderForPartition, 3);
+        let h = producer.send_queued(&Record::from_value("foo", "e").with_partition(1));
+        producer.flush().unwrap();
+        let confirm = h.wait(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            Err(KafkaCode::NotLeaderForPartition),
+            confirm.partition_confirms[0].offset
+        );
+        assert_eq!(vec!["b"], values(1));
+
+        // ~ non-retriable errors are not retried
+        broker.fail_produce_requests("foo", 1, KafkaCode::MessageSizeTooLarge, 1);
+        let h = producer.send_queued(&Record::from_value("foo", "f").with_partition(1));
+        producer.flush().unwrap();
+        let confirm = h.wait(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            Err(KafkaCode::MessageSizeTooLarge),
+            confirm.partition_confirms[0].offset
+        );
+        assert_eq!(vec!["b"], values(1));
+    }
+
+    #[test]
+    fn test_split_too_large_batches() {
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .create()
+            .unwrap();
+        // ~ each message takes 36 bytes in a message set such that
+        // at most two of them fit
+        broker.set_max_message_set_size(100);
+        let values: Vec<_> = (0..7).map(|i| format!("value-{:04}", i)).collect();
+        let mut recs: Vec<_> = values
+            .iter()
+            .map(|v| Record::from_value("foo", &v[..]).with_partition(0))
+            .collect();
+        recs.push(Record::from_value("foo", "small").with_partition(1));
+        let confirms = producer.send_all(&recs).unwrap();
+        let mut offsets: Vec<_> = confirms[0]
+            .partition_confirms
+            .iter()
+            .map(|p| (p.partition, p.offset))
+            .collect();
+        offsets.sort_unstable_by_key(|p| p.0);
+        assert_eq!(vec![(0, Ok(10)), (1, Ok(10))], offsets);
+        let produced: Vec<_> = broker
+            .produced_values("foo", 0)
+            .into_iter()
+            .map(|v| String::from_utf8(v).unwrap())
+            .collect();
+        assert_eq!(values, produced);
+        // ~ 7 => 3 (=> 1, 2) + 4 (=> 2, 2): three rejected and four
+        // accepted produce requests
+        assert_eq!(7, broker.ack_timeouts().len());
+
+        // ~ a single record too large is reported through its
+        // partition's confirm; the records following it are not sent
+        // and the other partitions are not affected
+        let big = vec![0u8; 200];
+        let recs = [
+            Record::from_value("foo", &b"a"[..]).with_partition(0),
+            Record::from_value("foo", &big[..]).with_partition(0),
+            Record::from_value("foo", &b"b"[..]).with_partition(0),
+            Record::from_value("foo", &b"c"[..]).with_partition(1),
+        ];
+        let offsets = |confirms: Vec<super::ProduceConfirm>| {
+            let mut offsets: Vec<_> = confirms
+                .into_iter()
+                .flat_map(|c| c.partition_confirms)
+                .map(|p| (p.partition, p.offset))
+                .collect();
+            offsets.sort_unstable_by_key(|p| p.0);
+            offsets
+        };
+        assert_eq!(
+            vec![(0, Err(KafkaCode::RecordListTooLarge)), (1, Ok(11))],
+            offsets(producer.send_all(&recs).unwrap())
+        );
+        assert_eq!(8, broker.num_produced("foo", 0));
+        assert_eq!(2, broker.num_produced("foo", 1));
+
+        // ~ as is a batch of a single record rejected right away
+        assert_eq!(
+            vec![(0, Err(KafkaCode::RecordListTooLarge))],
+            offsets(producer.send_all(&recs[1..2]).unwrap())
+        );
+
+        // ~ queued records are split alike, each one receiving its
+        // own outcome
+        let handles: Vec<_> = values
+            .iter()
+            .map(|v| &v.as_bytes()[..])
+            .chain([&big[..], &b"d"[..]])
+            .map(|v| producer.send_queued(&Record::from_value("foo", v).with_partition(0)))
+            .collect();
+        assert!(producer.flush().is_ok());
+        let outcomes: Vec<_> = handles
+            .into_iter()
+            .map(|h| h.wait(Duration::ZERO).unwrap().partition_confirms[0].offset)
+            .collect();
+        let mut expected: Vec<_> = (18..25).map(Ok).collect();
+        expected.extend([Err(KafkaCode::RecordListTooLarge); 2]);
+        assert_eq!(expected, outcomes);
+    }
+
+    #[test]
+    fn test_close() {
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .create()
+            .unwrap();
+        producer.send_queued(&Record::from_value("foo", "bar").with_partition(0));
+        producer.close().unwrap();
+        assert_eq!(1, broker.num_produced("foo", 0));
+
+        broker.set_partition_error("foo", 1, Some(KafkaCode::MessageSizeTooLarge));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .create()
+            .unwrap();
+        producer.send_queued(&Record::from_value("foo", "bar").with_partition(1));
+        match producer.close() {
+            Err(Error::Kafka(KafkaCode::MessageSizeTooLarge)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // ~ a record exceeding the request size limit fails with the
+        // same error on its handle and from `close`
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_max_request_size(100)
+            .create()
+            .unwrap();
+        let h = producer.send_queued(&Record::from_value("foo", vec![0u8; 100]).with_partition(0));
+        let expected = match producer.close() {
+            Err(Error::MessageTooLarge { size, limit }) => (size, limit),
+            r => panic!("unexpected result: {:?}", r),
+        };
+        match h.wait(Duration::ZERO) {
+            Err(Error::MessageTooLarge { size, limit }) => assert_eq!(expected, (size, limit)),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_compression_min_size() {
+        let broker = MockBroker::start(&[("foo", 2)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_compression(client::Compression::GZIP)
+            .with_compression_min_size(500)
+            .create()
+            .unwrap();
+
+        // ~ a compressed batch reaches the broker as a single wrapper
+        // message
+        let value = vec![b'x'; 100];
+        let mut recs: Vec<_> = (0..2)
+            .map(|_| Record::from_value("foo", &value[..10]).with_partition(0))
+            .collect();
+        recs.extend((0..10).map(|_| Record::from_value("foo", &value[..]).with_partition(1)));
+        producer.send_all(&recs).unwrap();
+        assert_eq!(2, broker.num_produced("foo", 0));
+        assert_eq!(1, broker.num_produced("foo", 1));
+
+        // ~ without a threshold even the tiny batch is compressed
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_compression(client::Compression::GZIP)
+            .create()
+            .unwrap();
+        let recs: Vec<_> = (0..2)
+            .map(|_| Record::from_value("foo", &value[..10]).with_partition(0))
+            .collect();
+        producer.send_all(&recs).unwrap();
+        assert_eq!(3, broker.num_produced("foo", 0));
+    }
+
+    #[test]
+    fn test_send_to_default_partition() {
+        let (host, _) = serve(&[("foo", 3)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![host])
+            .with_default_partition(2)
+            .create()
+            .unwrap();
+
+        // ~ keyless records land on the default partition
+        let recs = [
+            Record::from_value("foo", "a"),
+            Record::from_value("foo", "b"),
+            Record::from_value("foo", "c"),
+        ];
+        let confirms = producer.send_all(&recs).unwrap();
+        assert_eq!(1, confirms.len());
+        let ps = &confirms[0].partition_confirms;
+        assert_eq!(1, ps.len());
+        assert_eq!(2, ps[0].partition);
+
+        // ~ an explicit partition takes precedence
+        let confirms = producer
+            .send_all(&[Record::from_value("foo", "d").with_partition(1)])
+            .unwrap();
+        assert_eq!(1, confirms[0].partition_confirms[0].partition);
+    }
+
+    #[test]
+    fn test_produce_assigned() {
+        let broker = MockBroker::start(&[("foo", 3)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_partitioner(RecordingPartitioner::default())
+            .create()
+            .unwrap();
+
+        // ~ the partitioner (assigning partition 0) is bypassed
+        let recs = vec![
+            (2, Record::from_value("foo", "a")),
+            (0, Record::from_value("foo", "b").with_partition(1)),
+            (2, Record::from_value("foo", "c")),
+        ];
+        let confirms = producer.produce_assigned(recs).unwrap();
+        let mut landed: Vec<_> = confirms[0]
+            .partition_confirms
+            .iter()
+            .map(|p| p.partition)
+            .collect();
+        landed.sort_unstable();
+        assert_eq!(vec![0, 2], landed);
+        assert_eq!(2, broker.num_produced("foo", 2));
+        assert_eq!(1, broker.num_produced("foo", 0));
+
+        // ~ out-of-range partitions fail the whole batch up front
+        let n = broker.num_requests();
+        let recs = vec![
+            (1, Record::from_value("foo", "d")),
+            (3, Record::from_value("foo", "e")),
+        ];
+        match producer.produce_assigned(recs) {
+            Err(Error::TopicPartitionError {
+                partition_id,
+                error_code,
+                ..
+            }) => {
+                assert_eq!(3, partition_id);
+                assert_eq!(KafkaCode::UnknownTopicOrPartition, error_code);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(n, broker.num_requests());
+        assert_eq!(0, broker.num_produced("foo", 1));
+    }
+
+    #[test]
+    fn test_send_to_unknown_default_partition() {
+        let (host, _) = serve(&[("foo", 2)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![host])
+            .with_default_partition(5)
+            .create()
+            .unwrap();
+        match producer.send(&Record::from_value("foo", "a")) {
+            Err(Error::TopicPartitionError {
+                topic_name,
+                partition_id,
+                error_code,
+            }) => {
+                assert_eq!("foo", topic_name);
+                assert_eq!(5, partition_id);
+                assert_eq!(KafkaCode::UnknownTopicOrPartition, error_code);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    // ~ a partitioner recording the number of partitions it sees
+    // and the changes it is notified about
+    #[derive(Default, Clone)]
+    struct RecordingPartitioner {
+        seen: Arc<Mutex<Vec<u32>>>,
+        changes: Arc<Mutex<Vec<(String, u32)>>>,
+    }
+
+    impl Partitioner for RecordingPartitioner {
+        fn partition(&mut self, topics: Topics<'_>, msg: &mut client::ProduceMessage<'_, '_>) {
+            let n = topics.partitions(msg.topic).unwrap().num_all();
+            self.seen.lock().unwrap().push(n);
+            msg.partition = 0;
+        }
+
+        fn partitions_changed(&mut self, topic: &str, num_partitions: u32) {
+            self.changes
+                .lock()
+                .unwrap()
+                .push((topic.to_owned(), num_partitions));
+        }
+    }
+
+    #[test]
+    fn test_partition_count_change() {
+        let (host, topics) = serve_with_topics(&[("foo", 2), ("bar", 1)], (0, 10));
+        let partitioner = RecordingPartitioner::default();
+        let mut producer = Producer::from_hosts(vec![host])
+            .with_partitioner(partitioner.clone())
+            .create()
+            .unwrap();
+        producer.send(&Record::from_value("foo", "a")).unwrap();
+
+        // ~ unchanged metadata goes unnoticed
+        producer.refresh_metadata().unwrap();
+        assert!(partitioner.changes.lock().unwrap().is_empty());
+
+        // ~ "foo" gets expanded
+        *topics.lock().unwrap() = &[("foo", 4), ("bar", 1)];
+        producer.refresh_metadata().unwrap();
+        assert_eq!(
+            vec![("foo".to_owned(), 4)],
+            *partitioner.changes.lock().unwrap()
+        );
+        producer.send(&Record::from_value("foo", "b")).unwrap();
+        assert_eq!(vec![2, 4], *partitioner.seen.lock().unwrap());
+    }
+
+    #[test]
+    fn test_null_vs_empty_key() {
+        let (host, _) = serve(&[("foo", 16)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![host]).create().unwrap();
+
+        // ~ empty keys are sent as such and hashed like any other key
+        let recs: Vec<_> = (0..8)
+            .map(|_| Record::from_value("foo", "a").with_key(Some(&[])))
+            .collect();
+        let msgs = producer.partition_all(&recs).unwrap();
+        for m in &msgs {
+            assert_eq!(Some(&b""[..]), m.key);
+            assert_eq!(msgs[0].partition, m.partition);
+        }
+
+        // ~ null keys are sent as such and spread like keyless records
+        let recs: Vec<_> = (0..8)
+            .map(|_| Record::from_value("foo", "a").with_key(None))
+            .collect();
+        let msgs = producer.partition_all(&recs).unwrap();
+        assert!(msgs.iter().all(|m| m.key.is_none()));
+        assert!(msgs.iter().any(|m| m.partition != msgs[0].partition));
+
+        // ~ other key types keep sending empty keys as null
+        let recs = [Record::from_key_value("foo", "", "a")];
+        assert_eq!(None, producer.partition_all(&recs).unwrap()[0].key);
+    }
+
+    #[test]
+    fn test_interceptors() {
+        struct Tag(&'static str, Arc<AtomicUsize>);
+        impl ProduceInterceptor for Tag {
+            fn on_send(&self, rec: &mut InterceptedRecord<'_>) {
+                let value = rec.value.get_or_insert_with(Vec::new);
+                value.extend_from_slice(self.0.as_bytes());
+            }
+            fn on_acknowledgement(&self, confirm: &client::ProduceConfirm) {
+                assert!(confirm.partition_confirms.iter().all(|p| p.offset.is_ok()));
+                self.1.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let broker = MockBroker::start(&[("foo", 1)], (0, 10));
+        let acks = Arc::new(AtomicUsize::new(0));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_interceptor(Tag(";trace=1", Arc::clone(&acks)))
+            .with_interceptor(Tag(";redacted", Arc::clone(&acks)))
+            .create()
+            .unwrap();
+
+        producer.send(&Record::from_value("foo", "a")).unwrap();
+        let h = producer.send_queued(&Record::from_value("foo", "b"));
+        producer.flush().unwrap();
+        h.wait(Duration::from_secs(1)).unwrap();
+        producer
+            .produce_stream(vec![Record::from_value("foo", "c")], 1)
+            .unwrap();
+        // ~ each confirm is seen by both interceptors
+        assert_eq!(6, acks.load(Ordering::SeqCst));
+
+        // ~ the interceptors applied in order of their registration
+        let mut client = producer.into_client();
+        let mut values = Vec::new();
+        for offset in 10..13 {
+            let resps = client
+                .fetch_messages(&[client::FetchPartition::new("foo", 0, offset)])
+                .unwrap();
+            let data = resps[0].topics()[0].partitions()[0].data().unwrap();
+            values.push(String::from_utf8(data.messages()[0].value.to_vec()).unwrap());
+        }
+        assert_eq!(
+            vec![
+                "a;trace=1;redacted",
+                "b;trace=1;redacted",
+                "c;trace=1;redacted"
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn test_produce_stream() {
+        let broker = MockBroker::start(&[("foo", 4)], (0, 10));
+        broker.set_partition_error("foo", 3, Some(KafkaCode::CorruptMessage));
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .create()
+            .unwrap();
+
+        let recs = (0..100_000).map(|i| Record::from_key_value("foo", i.to_string(), "v"));
+        let stats = producer.produce_stream(recs, 64).unwrap();
+        let delivered: usize = (0..4).map(|p| broker.num_produced("foo", p)).sum();
+        assert_eq!(delivered as u64, stats.records_sent);
+        assert_eq!(100_000, stats.records_sent + stats.records_failed());
+        assert!(stats.failures[&KafkaCode::CorruptMessage] > 0);
+
+        // ~ oversized records are accounted for without aborting
+        let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+            .with_max_request_size(100)
+            .create()
+            .unwrap();
+        let big = vec![0u8; 100];
+        let recs = vec![
+            Record::from_value("foo", &b"a"[..]).with_partition(0),
+            Record::from_value("foo", &big[..]).with_partition(0),
+            Record::from_value("foo", &b"b"[..]).with_partition(0),
+        ];
+        let stats = producer.produce_stream(recs, 10).unwrap();
+        assert_eq!(2, stats.records_sent);
+        assert_eq!(1, stats.failures[&KafkaCode::MessageSizeTooLarge]);
+    }
+
+    #[test]
+    fn test_partition_key() {
+        let (host, _) = serve(&[("foo", 16)], (0, 10));
+        let mut producer = Producer::from_hosts(vec![host]).create().unwrap();
+
+        // ~ where records keyed by "tenant-1" go to
+        let keyed = [Record::from_key_value("foo", "tenant-1", "a")];
+        let expected = producer.partition_all(&keyed).unwrap()[0].partition;
+
+        let recs: Vec<_> = (0..8)
+            .map(|i| {
+                Record::from_key_value("foo", format!("key-{}", i), "a")
+                    .with_partition_key("tenant-1")
+            })
+            .collect();
+        let msgs = producer.partition_all(&recs).unwrap();
+        for (i, m) in msgs.iter().enumerate() {
+            assert_eq!(expected, m.partition);
+            assert_eq!(Some(format!("key-{}", i).as_bytes()), m.key);
+        }
+        // ~ the keys on their own would have been spread
+        let unkeyed: Vec<_> = recs
+            .iter()
+            .map(|r| Record::from_key_value("foo", &r.key[..], "a"))
+            .collect();
+        let msgs = producer.partition_all(&unkeyed).unwrap();
+        assert!(msgs.iter().any(|m| m.partition != expected));
+
+        // ~ an explicit partition takes precedence
+        let rec = Record::from_value("foo", "a")
+            .with_partition_key("tenant-1")
+            .with_partition((expected + 1) % 16);
+        let msgs = producer.partition_all(std::slice::from_ref(&rec)).unwrap();
+        assert_eq!((expected + 1) % 16, msgs[0].partition);
+        assert_eq!(None, msgs[0].key);
+        producer.send(&rec).unwrap();
+    }
+
+    #[test]
+    fn test_send_rejects_too_large_message() {
+        let mut producer = Producer::from_client(KafkaClient::new(vec![]))
+            .with_max_request_size(1024 * 1024)
+            .create()
+            .unwrap();
+
+        let value = vec![0u8; 2 * 1024 * 1024];
+        match producer.send(&Record::from_value("foo", value)) {
+            Err(Error::MessageTooLarge { size, limit }) => {
+                assert!(size > 2 * 1024 * 1024);
+                assert_eq!(1024 * 1024, limit);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_estimated_size() {
+        use super::{AsBytes, Compression};
+        use crate::codecs::ToByte;
+        use crate::protocol::ProduceRequest;
+
+        // ~ the number of bytes the given key/value pair adds to an
+        // (uncompressed) produce request already carrying a message
+        // for the same partition
+        fn encoded_size(key: Option<&[u8]>, value: Option<&[u8]>) -> usize {
+            let encode = |msgs: &[(Option<&[u8]>, Option<&[u8]>)]| {
+                let mut req = ProduceRequest::new(1, 1000, 0, "", Compression::NONE, 0);
+                for &(k, v) in msgs {
+                    req.add("foo", 0, k, v);
+                }
+                let mut buf = Vec::new();
+                req.encode(&mut buf).unwrap();
+                buf.len()
+            };
+            let first = (Some(&b"x"[..]), Some(&b"y"[..]));
+            encode(&[first, (key, value)]) - encode(&[first])
+        }
+
+        let big = vec![7u8; 10_000];
+        for r in [
+            Record::from_key_value("foo", &b"key"[..], &b"value"[..]),
+            Record::from_key_value("foo", &b""[..], &b""[..]),
+            Record::from_key_value("foo", &b"k"[..], &big[..]),
+        ] {
+            let expected = encoded_size(r.key.as_nullable_bytes(), r.value.as_nullable_bytes());
+            assert_eq!(expected, r.estimated_size());
+        }
+        let r = Record::from_value("foo", "value").with_key(None);
+        assert_eq!(encoded_size(None, Some(b"value")), r.estimated_size());
+    }
 }
@@ -0,0 +1,184 @@
+use std::io::{Read, Write};
+
+use crate::codecs::{self, FromByte, ToByte};
+use crate::error::{Error, Result};
+
+use super::{HeaderRequest, HeaderResponse, API_KEY_API_VERSIONS};
+
+// ~ version three is the first to carry the client's software name
+// and version (KIP-511.)  it is a "flexible version"; the request
+// header and payload carry tagged fields and compact strings, while
+// the response header - unlike those of all other flexible apis -
+// does not, such that clients can always parse the error code.
+const API_VERSION_API_VERSIONS: i16 = 3;
+
+// --------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct ApiVersionsRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    pub client_software_name: &'a str,
+    pub client_software_version: &'a str,
+}
+
+impl<'a> ApiVersionsRequest<'a> {
+    pub fn new(
+        correlation_id: i32,
+        client_id: &'a str,
+        client_software_name: &'a str,
+        client_software_version: &'a str,
+    ) -> ApiVersionsRequest<'a> {
+        ApiVersionsRequest {
+            header: HeaderRequest::new(
+                API_KEY_API_VERSIONS,
+                API_VERSION_API_VERSIONS,
+                correlation_id,
+                client_id,
+            ),
+            client_software_name,
+            client_software_version,
+        }
+    }
+}
+
+impl<'a> ToByte for ApiVersionsRequest<'a> {
+    fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
+        // ~ the request header (v2) and the body are each followed
+        // by an empty set of tagged fields
+        try_multi!(
+            self.header.encode(buffer),
+            0i8.encode(buffer),
+            codecs::encode_compact_string(buffer, self.client_software_name),
+            codecs::encode_compact_string(buffer, self.client_software_version),
+            0i8.encode(buffer)
+        )
+    }
+}
+
+// --------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct ApiVersionsResponse {
+    pub header: HeaderResponse,
+    pub error: i16,
+    pub api_keys: Vec<ApiVersionsKey>,
+    pub throttle_time_ms: i32,
+}
+
+#[derive(Debug, Default)]
+pub struct ApiVersionsKey {
+    pub api_key: i16,
+    pub min_version: i16,
+    pub max_version: i16,
+}
+
+impl ApiVersionsResponse {
+    pub fn into_result(self) -> Result<Self> {
+        match Error::from_protocol(self.error) {
+            Some(e) => Err(e),
+            None => Ok(self),
+        }
+    }
+}
+
+impl FromByte for ApiVersionsResponse {
+    type R = ApiVersionsResponse;
+
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        // ~ response header (v0)
+        self.header.decode(buffer)?;
+        self.error.decode(buffer)?;
+        if self.error != 0 {
+            // ~ brokers not supporting the requested version answer
+            // in the v0 format; we don't bother parsing it
+            return Ok(());
+        }
+        let n = codecs::decode_compact_array_len(buffer)?;
+        self.api_keys = Vec::with_capacity(n);
+        for _ in 0..n {
+            self.api_keys.push(ApiVersionsKey::decode_new(buffer)?);
+        }
+        self.throttle_time_ms.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)
+    }
+}
+
+impl FromByte for ApiVersionsKey {
+    type R = ApiVersionsKey;
+
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        self.api_key.decode(buffer)?;
+        self.min_version.decode(buffer)?;
+        self.max_version.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{ApiVersionsRequest, ApiVersionsResponse};
+    use crate::codecs::{FromByte, ToByte};
+    use crate::error::{Error, KafkaCode};
+
+    #[test]
+    fn test_encode_request() {
+        let mut buf = Vec::new();
+        ApiVersionsRequest::new(7, "me", "kafka-rust", "0.1")
+            .encode(&mut buf)
+            .unwrap();
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            // header: api key, api version, correlation id, client id,
+            // tagged fields
+            0, 18, 0, 3, 0, 0, 0, 7, 0, 2, b'm', b'e', 0,
+            // client software name
+            11, b'k', b'a', b'f', b'k', b'a', b'-', b'r', b'u', b's', b't',
+            // client software version
+            4, b'0', b'.', b'1',
+            // tagged fields
+            0,
+        ];
+        assert_eq!(expected, buf);
+    }
+
+    #[test]
+    fn test_decode_response() {
+        #[rustfmt::skip]
+        let raw: Vec<u8> = vec![
+            // header: correlation id (no tagged fields)
+            0, 0, 0, 7,
+            // error code
+            0, 0,
+            // two api keys
+            3,
+            0, 0, 0, 0, 0, 9, 0,
+            0, 18, 0, 0, 0, 3, 1, 0, 1, 0xff,
+            // throttle time, tagged fields
+            0, 0, 0, 0, 0,
+        ];
+        let r = ApiVersionsResponse::decode_new(&mut Cursor::new(raw))
+            .unwrap()
+            .into_result()
+            .unwrap();
+        assert_eq!(7, r.header.correlation);
+        let keys: Vec<_> = r
+            .api_keys
+            .iter()
+            .map(|k| (k.api_key, k.min_version, k.max_version))
+            .collect();
+        assert_eq!(vec![(0, 0, 9), (18, 0, 3)], keys);
+    }
+
+    #[test]
+    fn test_decode_unsupported_version() {
+        // ~ a v0 response rejecting the version of the request
+        let raw: Vec<u8> = vec![0, 0, 0, 7, 0, 35, 0, 0, 0, 1, 0, 18, 0, 0, 0, 2];
+        let r = ApiVersionsResponse::decode_new(&mut Cursor::new(raw)).unwrap();
+        assert!(matches!(
+            r.into_result(),
+            Err(Error::Kafka(KafkaCode::UnsupportedVersion))
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::{Read, Write};
+
+use crate::codecs::{self, FromByte, ToByte};
+use crate::error::{Error, Result};
+
+use super::{HeaderRequest, HeaderResponse, API_KEY_DESCRIBE_CLUSTER};
+
+// ~ `DescribeCluster` (KIP-700) exists only as a "flexible version"
+// api; the request and response headers carry tagged fields and the
+// payload uses compact strings and arrays.  We support only version
+// zero of it.
+const API_VERSION_DESCRIBE_CLUSTER: i16 = 0;
+
+// ~ the authorized operations value reported if the client did not
+// ask for them
+const AUTHORIZED_OPERATIONS_OMITTED: i32 = i32::MIN;
+
+// --------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct DescribeClusterRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    pub include_cluster_authorized_operations: bool,
+}
+
+impl<'a> DescribeClusterRequest<'a> {
+    pub fn new(correlation_id: i32, client_id: &'a str) -> DescribeClusterRequest<'a> {
+        DescribeClusterRequest {
+            header: HeaderRequest::new(
+                API_KEY_DESCRIBE_CLUSTER,
+                API_VERSION_DESCRIBE_CLUSTER,
+                correlation_id,
+                client_id,
+            ),
+            include_cluster_authorized_operations: true,
+        }
+    }
+}
+
+impl<'a> ToByte for DescribeClusterRequest<'a> {
+    fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
+        // ~ the request header (v2) and the body are each followed
+        // by an empty set of tagged fields
+        try_multi!(
+            self.header.encode(buffer),
+            0i8.encode(buffer),
+            i8::from(self.include_cluster_authorized_operations).encode(buffer),
+            0i8.encode(buffer)
+        )
+    }
+}
+
+// --------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct DescribeClusterResponse {
+    pub header: HeaderResponse,
+    pub throttle_time_ms: i32,
+    pub error: i16,
+    pub error_message: Option<String>,
+    pub cluster_id: String,
+    pub controller_id: i32,
+    pub brokers: Vec<DescribeClusterBroker>,
+    pub cluster_authorized_operations: i32,
+}
+
+#[derive(Debug, Default)]
+pub struct DescribeClusterBroker {
+    pub broker_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub rack: Option<String>,
+}
+
+impl DescribeClusterResponse {
+    pub fn into_result(self) -> Result<Self> {
+        match Error::from_protocol(self.error) {
+            Some(e) => Err(e),
+            None => Ok(self),
+        }
+    }
+
+    /// Retrieves the authorized operations bit field unless the
+    /// broker omitted it.
+    pub fn authorized_operations(&self) -> Option<i32> {
+        if self.cluster_authorized_operations == AUTHORIZED_OPERATIONS_OMITTED {
+            None
+        } else {
+            Some(self.cluster_authorized_operations)
+        }
+    }
+}
+
+impl FromByte for DescribeClusterResponse {
+    type R = DescribeClusterResponse;
+
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        // ~ response header (v1)
+        self.header.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)?;
+
+        self.throttle_time_ms.decode(buffer)?;
+        self.error.decode(buffer)?;
+        self.error_message = codecs::decode_compact_nullable_string(buffer)?;
+        self.cluster_id = codecs::decode_compact_string(buffer)?;
+        self.controller_id.decode(buffer)?;
+        let n = codecs::decode_compact_array_len(buffer)?;
+        self.brokers = Vec::with_capacity(n);
+        for _ in 0..n {
+            self.brokers
+                .push(DescribeClusterBroker::decode_new(buffer)?);
+        }
+        self.cluster_authorized_operations.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)
+    }
+}
+
+impl FromByte for DescribeClusterBroker {
+    type R = DescribeClusterBroker;
+
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        self.broker_id.decode(buffer)?;
+        self.host = codecs::decode_compact_string(buffer)?;
+        self.port.decode(buffer)?;
+        self.rack = codecs::decode_compact_nullable_string(buffer)?;
+        codecs::skip_tagged_fields(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{DescribeClusterRequest, DescribeClusterResponse};
+    use crate::codecs::{FromByte, ToByte};
+
+    #[test]
+    fn test_encode_request() {
+        let mut buf = Vec::new();
+        DescribeClusterRequest::new(7, "me")
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(
+            vec![0, 60, 0, 0, 0, 0, 0, 7, 0, 2, b'm', b'e', 0, 1, 0],
+            buf
+        );
+    }
+
+    #[test]
+    fn test_decode_response() {
+        #[rustfmt::skip]
+        let raw: Vec<u8> = vec![
+            // header: correlation id, tagged fields
+            0, 0, 0, 7, 0,
+            // throttle time, error code, null error message
+            0, 0, 0, 0, 0, 0, 0,
+            // cluster id "abc"
+            4, b'a', b'b', b'c',
+            // controller id
+            0, 0, 0, 2,
+            // two brokers
+            3,
+            0, 0, 0, 1, 3, b'h', b'1', 0, 0, 0x23, 0x84, 0, 0,
+            0, 0, 0, 2, 3, b'h', b'2', 0, 0, 0x23, 0x85, 3, b'r', b'2',
+            // ~ a tagged field to be skipped
+            1, 5, 2, 0xff, 0xff,
+            // authorized operations
+            0, 0, 0x0f, 0xf8,
+            // tagged fields
+            0,
+        ];
+        let r = DescribeClusterResponse::decode_new(&mut Cursor::new(raw))
+            .unwrap()
+            .into_result()
+            .unwrap();
+        assert_eq!(7, r.header.correlation);
+        assert_eq!(None, r.error_message);
+        assert_eq!("abc", r.cluster_id);
+        assert_eq!(2, r.controller_id);
+        assert_eq!(2, r.brokers.len());
+        assert_eq!((1, "h1", 9092, None), {
+            let b = &r.brokers[0];
+            (b.broker_id, &b.host[..], b.port, b.rack.as_deref())
+        });
+        assert_eq!((2, "h2", 9093, Some("r2")), {
+            let b = &r.brokers[1];
+            (b.broker_id, &b.host[..], b.port, b.rack.as_deref())
+        });
+        assert_eq!(Some(0x0ff8), r.authorized_operations());
+    }
+}
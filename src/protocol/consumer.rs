@@ -1,9 +1,11 @@
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
 
 use crate::codecs::{self, FromByte, ToByte};
 use crate::error::{self, Error, KafkaCode, Result};
 use crate::utils::PartitionOffset;
 
+use super::zreader::ZReader;
 use super::{HeaderRequest, HeaderResponse};
 use super::{API_KEY_GROUP_COORDINATOR, API_KEY_OFFSET_COMMIT, API_KEY_OFFSET_FETCH, API_VERSION};
 
@@ -80,6 +82,10 @@ pub enum OffsetFetchVersion {
     /// supported as of kafka 0.8.2, causes the retrieval of the
     /// offsets from kafka itself
     V1 = 1,
+    /// supported as of kafka 2.5, retrieves the offsets from kafka
+    /// itself and allows requiring stable offsets; a "flexible
+    /// version" (KIP-482) of the api
+    V7 = 7,
 }
 
 #[derive(Debug)]
@@ -87,6 +93,10 @@ pub struct OffsetFetchRequest<'a, 'b, 'c> {
     pub header: HeaderRequest<'a>,
     pub group: &'b str,
     pub topic_partitions: Vec<TopicPartitionOffsetFetchRequest<'c>>,
+    /// Available as of `OffsetFetchVersion::V7`; makes the broker
+    /// reject partitions with pending transactional offset commits
+    /// with `KafkaCode::UnstableOffsetCommit`.
+    pub require_stable: bool,
 }
 
 #[derive(Debug)]
@@ -116,6 +126,7 @@ impl<'a, 'b, 'c> OffsetFetchRequest<'a, 'b, 'c> {
             ),
             group,
             topic_partitions: vec![],
+            require_stable: false,
         }
     }
 
@@ -154,11 +165,29 @@ impl PartitionOffsetFetchRequest {
 
 impl<'a, 'b, 'c> ToByte for OffsetFetchRequest<'a, 'b, 'c> {
     fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
-        try_multi!(
-            self.header.encode(buffer),
-            self.group.encode(buffer),
-            self.topic_partitions.encode(buffer)
-        )
+        if self.header.api_version < OffsetFetchVersion::V7 as i16 {
+            return try_multi!(
+                self.header.encode(buffer),
+                self.group.encode(buffer),
+                self.topic_partitions.encode(buffer)
+            );
+        }
+        // ~ the request header (v2), the topics and the body are each
+        // followed by an empty set of tagged fields
+        self.header.encode(buffer)?;
+        0i8.encode(buffer)?;
+        codecs::encode_compact_string(buffer, self.group)?;
+        codecs::encode_compact_array_len(buffer, self.topic_partitions.len())?;
+        for tp in &self.topic_partitions {
+            codecs::encode_compact_string(buffer, tp.topic)?;
+            codecs::encode_compact_array_len(buffer, tp.partitions.len())?;
+            for p in &tp.partitions {
+                p.partition.encode(buffer)?;
+            }
+            0i8.encode(buffer)?;
+        }
+        i8::from(self.require_stable).encode(buffer)?;
+        0i8.encode(buffer)
     }
 }
 
@@ -180,6 +209,9 @@ impl ToByte for PartitionOffsetFetchRequest {
 pub struct OffsetFetchResponse {
     pub header: HeaderResponse,
     pub topic_partitions: Vec<TopicPartitionOffsetFetchResponse>,
+    /// The group level error; available as of
+    /// `OffsetFetchVersion::V7` (among the versions supported here.)
+    pub error: i16,
 }
 
 #[derive(Default, Debug)]
@@ -228,6 +260,64 @@ impl FromByte for OffsetFetchResponse {
     }
 }
 
+/// Parses a raw offset fetch response according to the (api)
+/// version of the corresponding request.
+pub struct OffsetFetchResponseParser {
+    pub version: i16,
+}
+
+impl super::SliceResponseParser for OffsetFetchResponseParser {
+    type T = OffsetFetchResponse;
+    fn parse_slice(&self, response: &[u8]) -> Result<Self::T> {
+        let mut buffer = Cursor::new(response);
+        if self.version < OffsetFetchVersion::V7 as i16 {
+            return OffsetFetchResponse::decode_new(&mut buffer);
+        }
+        let mut r = OffsetFetchResponse::default();
+        r.decode_flexible(&mut buffer)?;
+        Ok(r)
+    }
+}
+
+impl OffsetFetchResponse {
+    pub fn into_result(self) -> Result<Self> {
+        match Error::from_protocol(self.error) {
+            Some(e) => Err(e),
+            None => Ok(self),
+        }
+    }
+
+    fn decode_flexible<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        // ~ response header (v1)
+        self.header.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)?;
+
+        let _throttle_time_ms = i32::decode_new(buffer)?;
+        let n = codecs::decode_compact_array_len(buffer)?;
+        self.topic_partitions = Vec::with_capacity(n);
+        for _ in 0..n {
+            let topic = codecs::decode_compact_string(buffer)?;
+            let n = codecs::decode_compact_array_len(buffer)?;
+            let mut partitions = Vec::with_capacity(n);
+            for _ in 0..n {
+                let mut p = PartitionOffsetFetchResponse::default();
+                p.partition.decode(buffer)?;
+                p.offset.decode(buffer)?;
+                let _leader_epoch = i32::decode_new(buffer)?;
+                p.metadata = codecs::decode_compact_nullable_string(buffer)?.unwrap_or_default();
+                p.error.decode(buffer)?;
+                codecs::skip_tagged_fields(buffer)?;
+                partitions.push(p);
+            }
+            codecs::skip_tagged_fields(buffer)?;
+            self.topic_partitions
+                .push(TopicPartitionOffsetFetchResponse { topic, partitions });
+        }
+        self.error.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)
+    }
+}
+
 impl FromByte for TopicPartitionOffsetFetchResponse {
     type R = TopicPartitionOffsetFetchResponse;
 
@@ -438,3 +528,182 @@ impl FromByte for PartitionOffsetCommitResponse {
         try_multi!(self.partition.decode(buffer), self.error.decode(buffer))
     }
 }
+
+// --------------------------------------------------------------------
+
+/// The partitions assigned to a single member of a consumer group as
+/// exchanged through the member assignment bytes of the `SyncGroup`
+/// api, following the schema of the Java client's
+/// "ConsumerProtocol": `version [topic [partition]] user_data`.
+/// The group leader encodes an assignment for each member; every
+/// other member decodes its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemberAssignment {
+    /// The version of the assignment schema.
+    pub version: i16,
+    /// The assigned partitions by topic.
+    pub partitions: HashMap<String, Vec<i32>>,
+    /// Opaque data of the assignment strategy, if any.
+    pub user_data: Option<Vec<u8>>,
+}
+
+impl MemberAssignment {
+    /// Decodes an assignment from the given member assignment bytes.
+    /// Empty bytes, as delivered to members the leader assigned
+    /// nothing at all, decode to an empty assignment.  Fields added
+    /// by later versions of the schema are ignored.
+    pub fn from_slice(data: &[u8]) -> Result<MemberAssignment> {
+        if data.is_empty() {
+            return Ok(MemberAssignment::default());
+        }
+        let mut r = ZReader::new(data);
+        let version = r.read_i16()?;
+        if version < 0 {
+            return Err(Error::UnsupportedProtocol);
+        }
+        let n = r.read_array_len()?;
+        let mut partitions = HashMap::with_capacity(n);
+        for _ in 0..n {
+            let topic = r.read_str()?.to_owned();
+            let m = r.read_array_len()?;
+            let mut ps = Vec::with_capacity(m);
+            for _ in 0..m {
+                ps.push(r.read_i32()?);
+            }
+            partitions.entry(topic).or_insert_with(Vec::new).extend(ps);
+        }
+        // ~ version 0 assignments of some clients end right here
+        let user_data = if r.is_empty() {
+            None
+        } else {
+            r.read_nullable_bytes()?.map(<[u8]>::to_vec)
+        };
+        Ok(MemberAssignment {
+            version,
+            partitions,
+            user_data,
+        })
+    }
+}
+
+impl ToByte for MemberAssignment {
+    fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
+        self.version.encode(buffer)?;
+        // ~ render topics in a deterministic order
+        let mut topics: Vec<_> = self.partitions.iter().collect();
+        topics.sort_by(|a, b| a.0.cmp(b.0));
+        codecs::encode_as_array(buffer, &topics, |buffer, &(topic, ps)| {
+            try_multi!(topic.encode(buffer), ps.encode(buffer))
+        })?;
+        match self.user_data {
+            Some(ref d) => d.encode(buffer),
+            None => (-1i32).encode(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::SliceResponseParser;
+    use super::{
+        MemberAssignment, OffsetFetchRequest, OffsetFetchResponseParser, OffsetFetchVersion,
+    };
+    use crate::codecs::ToByte;
+    use crate::error::{Error, KafkaCode};
+
+    #[test]
+    fn test_encode_offset_fetch_v7() {
+        let mut req = OffsetFetchRequest::new("g", OffsetFetchVersion::V7, 1, "me");
+        req.add("t", 0);
+        req.add("t", 1);
+        req.require_stable = true;
+        let mut buf = Vec::new();
+        req.encode(&mut buf).unwrap();
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            // header: api key, version, correlation id, client id,
+            // tagged fields
+            0, 9, 0, 7, 0, 0, 0, 1, 0, 2, b'm', b'e', 0,
+            // group
+            2, b'g',
+            // one topic with two partitions, tagged fields
+            2, 2, b't', 3, 0, 0, 0, 0, 0, 0, 0, 1, 0,
+            // require stable, tagged fields
+            1, 0,
+        ];
+        assert_eq!(expected, buf);
+    }
+
+    #[test]
+    fn test_decode_offset_fetch_v7() {
+        #[rustfmt::skip]
+        let raw: Vec<u8> = vec![
+            // header: correlation id, tagged fields
+            0, 0, 0, 1, 0,
+            // throttle time
+            0, 0, 0, 0,
+            // one topic with two partitions
+            2, 2, b't', 3,
+            // partition, offset, leader epoch, null metadata, error
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0,
+            0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 1, 0, 88, 0,
+            // topic tagged fields
+            0,
+            // group error, tagged fields
+            0, 0, 0,
+        ];
+        let p = OffsetFetchResponseParser {
+            version: OffsetFetchVersion::V7 as i16,
+        };
+        let r = p.parse_slice(&raw).unwrap();
+        assert_eq!(1, r.header.correlation);
+        assert_eq!(0, r.error);
+        assert_eq!(1, r.topic_partitions.len());
+        let tp = &r.topic_partitions[0];
+        assert_eq!("t", tp.topic);
+        assert_eq!(5, tp.partitions[0].get_offsets().unwrap().offset);
+        assert!(matches!(
+            tp.partitions[1].get_offsets(),
+            Err(Error::Kafka(KafkaCode::UnstableOffsetCommit))
+        ));
+    }
+
+    #[test]
+    fn test_decode_member_assignment() {
+        #[rustfmt::skip]
+        let raw = vec![
+            // version
+            0, 1,
+            // two topics
+            0, 0, 0, 2,
+            // "foo": partitions 0, 2, 4
+            0, 3, b'f', b'o', b'o', 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 4,
+            // "bar": partition 1
+            0, 3, b'b', b'a', b'r', 0, 0, 0, 1, 0, 0, 0, 1,
+            // user data
+            0, 0, 0, 2, 7, 8,
+            // fields of a later version
+            0xff, 0xff, 0xff, 0xff,
+        ];
+        let a = MemberAssignment::from_slice(&raw).unwrap();
+        assert_eq!(1, a.version);
+        assert_eq!(2, a.partitions.len());
+        assert_eq!(vec![0, 2, 4], a.partitions["foo"]);
+        assert_eq!(vec![1], a.partitions["bar"]);
+        assert_eq!(Some(vec![7, 8]), a.user_data);
+
+        // ~ round trip
+        let mut buf = Vec::new();
+        a.encode(&mut buf).unwrap();
+        assert_eq!(a, MemberAssignment::from_slice(&buf).unwrap());
+
+        // ~ members assigned nothing may receive no bytes at all
+        assert_eq!(
+            MemberAssignment::default(),
+            MemberAssignment::from_slice(&[]).unwrap()
+        );
+        // ~ truncated data
+        assert!(MemberAssignment::from_slice(&raw[..12]).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::io::{Read, Write};
+
+use super::{HeaderRequest, HeaderResponse};
+use super::{API_KEY_OFFSET_FOR_LEADER_EPOCH, API_VERSION};
+use crate::codecs::{FromByte, ToByte};
+use crate::error::{KafkaCode, Result};
+
+// ~ `OffsetForLeaderEpoch` (KIP-101, as of kafka 0.11.0) in its
+// version zero; later versions additionally deliver the epoch of the
+// end offset and allow fencing the request by the current leader
+// epoch (KIP-320).
+
+#[derive(Debug)]
+pub struct OffsetForLeaderEpochRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    pub topic_partitions: Vec<TopicPartitionEpochRequest<'a>>,
+}
+
+#[derive(Debug)]
+pub struct TopicPartitionEpochRequest<'a> {
+    pub topic: &'a str,
+    // ~ (partition, leader_epoch)
+    pub partitions: Vec<(i32, i32)>,
+}
+
+impl<'a> OffsetForLeaderEpochRequest<'a> {
+    pub fn new(correlation_id: i32, client_id: &'a str) -> OffsetForLeaderEpochRequest<'a> {
+        OffsetForLeaderEpochRequest {
+            header: HeaderRequest::new(
+                API_KEY_OFFSET_FOR_LEADER_EPOCH,
+                API_VERSION,
+                correlation_id,
+                client_id,
+            ),
+            topic_partitions: vec![],
+        }
+    }
+
+    pub fn add(&mut self, topic: &'a str, partition: i32, leader_epoch: i32) {
+        for tp in &mut self.topic_partitions {
+            if tp.topic == topic {
+                tp.partitions.push((partition, leader_epoch));
+                return;
+            }
+        }
+        self.topic_partitions.push(TopicPartitionEpochRequest {
+            topic,
+            partitions: vec![(partition, leader_epoch)],
+        });
+    }
+}
+
+impl<'a> ToByte for OffsetForLeaderEpochRequest<'a> {
+    fn encode<T: Write>(&self, buffer: &mut T) -> Result<()> {
+        try_multi!(
+            self.header.encode(buffer),
+            self.topic_partitions.encode(buffer)
+        )
+    }
+}
+
+impl<'a> ToByte for TopicPartitionEpochRequest<'a> {
+    fn encode<T: Write>(&self, buffer: &mut T) -> Result<()> {
+        self.topic.encode(buffer)?;
+        (self.partitions.len() as i32).encode(buffer)?;
+        for &(partition, leader_epoch) in &self.partitions {
+            partition.encode(buffer)?;
+            leader_epoch.encode(buffer)?;
+        }
+        Ok(())
+    }
+}
+
+// --------------------------------------------------------------------
+
+#[derive(Default, Debug)]
+pub struct OffsetForLeaderEpochResponse {
+    pub header: HeaderResponse,
+    pub topic_partitions: Vec<TopicPartitionEpochResponse>,
+}
+
+#[derive(Default, Debug)]
+pub struct TopicPartitionEpochResponse {
+    pub topic: String,
+    pub partitions: Vec<PartitionEpochResponse>,
+}
+
+#[derive(Default, Debug)]
+pub struct PartitionEpochResponse {
+    pub error: i16,
+    pub partition: i32,
+    pub end_offset: i64,
+}
+
+impl PartitionEpochResponse {
+    /// Delivers the end offset of the requested epoch, i.e. the
+    /// offset of the first message of the subsequent epoch.
+    pub fn to_end_offset(&self) -> std::result::Result<i64, KafkaCode> {
+        match KafkaCode::from_protocol(self.error) {
+            Some(code) => Err(code),
+            None => Ok(self.end_offset),
+        }
+    }
+}
+
+impl FromByte for OffsetForLeaderEpochResponse {
+    type R = OffsetForLeaderEpochResponse;
+
+    #[allow(unused_must_use)]
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        try_multi!(
+            self.header.decode(buffer),
+            self.topic_partitions.decode(buffer)
+        )
+    }
+}
+
+impl FromByte for TopicPartitionEpochResponse {
+    type R = TopicPartitionEpochResponse;
+
+    #[allow(unused_must_use)]
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        try_multi!(self.topic.decode(buffer), self.partitions.decode(buffer))
+    }
+}
+
+impl FromByte for PartitionEpochResponse {
+    type R = PartitionEpochResponse;
+
+    #[allow(unused_must_use)]
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        try_multi!(
+            self.error.decode(buffer),
+            self.partition.decode(buffer),
+            self.end_offset.decode(buffer)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{OffsetForLeaderEpochRequest, OffsetForLeaderEpochResponse};
+    use crate::codecs::{FromByte, ToByte};
+    use crate::error::KafkaCode;
+
+    #[test]
+    fn test_encode_request() {
+        let mut req = OffsetForLeaderEpochRequest::new(7, "me");
+        req.add("t", 0, 3);
+        req.add("t", 1, 4);
+        let mut buf = Vec::new();
+        req.encode(&mut buf).unwrap();
+        #[rustfmt::skip]
+        let expected = vec![
+            // header
+            0, 23, 0, 0, 0, 0, 0, 7, 0, 2, b'm', b'e',
+            // one topic with two partitions
+            0, 0, 0, 1, 0, 1, b't', 0, 0, 0, 2,
+            0, 0, 0, 0, 0, 0, 0, 3,
+            0, 0, 0, 1, 0, 0, 0, 4,
+        ];
+        assert_eq!(expected, buf);
+    }
+
+    #[test]
+    fn test_decode_response() {
+        #[rustfmt::skip]
+        let raw = vec![
+            // correlation id
+            0, 0, 0, 7,
+            // one topic with two partitions
+            0, 0, 0, 1, 0, 1, b't', 0, 0, 0, 2,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 42,
+            0, 75, 0, 0, 0, 1, 255, 255, 255, 255, 255, 255, 255, 255,
+        ];
+        let r = OffsetForLeaderEpochResponse::decode_new(&mut Cursor::new(raw)).unwrap();
+        assert_eq!(7, r.header.correlation);
+        let ps = &r.topic_partitions[0].partitions;
+        assert_eq!(Ok(42), ps[0].to_end_offset());
+        assert_eq!(1, ps[1].partition);
+        assert_eq!(Err(KafkaCode::UnknownLeaderEpoch), ps[1].to_end_offset());
+    }
+}
@@ -7,13 +7,15 @@ use std::io::Write;
 use std::sync::Arc;
 use std::{mem, result};
 
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
 use fnv::FnvHasher;
 
 use crate::codecs::ToByte;
 #[cfg(feature = "gzip")]
 use crate::compression::gzip;
 #[cfg(feature = "snappy")]
-use crate::compression::snappy::SnappyReader;
+use crate::compression::snappy;
 use crate::compression::Compression;
 use crate::error::KafkaCode;
 use crate::{Error, Result};
@@ -24,6 +26,16 @@ use super::{HeaderRequest, API_KEY_FETCH, API_VERSION};
 
 pub type PartitionHasher = BuildHasherDefault<FnvHasher>;
 
+// XXX incremental fetch sessions (KIP-227) need fetch v7+ whose
+// responses carry the v2 record batch format; support them once we
+// can decode record batches.  the same holds for fetching from the
+// closest replica (KIP-392): the request's `rack_id` and the
+// response's `preferred_read_replica` exist only as of fetch v11.
+/// The replica id identifying a fetch request as issued by a
+/// debugging tool; brokers serve such requests even if they don't
+/// lead the requested partitions.
+pub const DEBUGGING_REPLICA_ID: i32 = -2;
+
 #[derive(Debug)]
 pub struct FetchRequest<'a, 'b> {
     pub header: HeaderRequest<'a>,
@@ -138,13 +150,21 @@ impl PartitionFetchRequest {
 
 pub struct ResponseParser<'a, 'b, 'c> {
     pub validate_crc: bool,
+    // ~ the maximum total size of the decompressed message sets of
+    // a response
+    pub max_uncompressed_size: usize,
     pub requests: Option<&'c FetchRequest<'a, 'b>>,
 }
 
 impl<'a, 'b, 'c> super::ResponseParser for ResponseParser<'a, 'b, 'c> {
     type T = Response;
     fn parse(&self, response: Vec<u8>) -> Result<Self::T> {
-        Response::from_vec(response, self.requests, self.validate_crc)
+        Response::from_vec(
+            response,
+            self.requests,
+            self.validate_crc,
+            self.max_uncompressed_size,
+        )
     }
 }
 
@@ -187,13 +207,97 @@ impl Response {
         response: Vec<u8>,
         reqs: Option<&FetchRequest<'_, '_>>,
         validate_crc: bool,
+        max_size: usize,
     ) -> Result<Response> {
         let slice = unsafe { mem::transmute(&response[..]) };
-        let mut r = ZReader::new(slice);
-        let correlation_id = r.read_i32()?;
-        let topics = array_of!(r, Topic::read(&mut r, reqs, validate_crc));
+        let r = BorrowedResponse::from_slice(slice, reqs, validate_crc, max_size)?;
         Ok(Response {
             raw_data: response,
+            correlation_id: r.correlation_id,
+            topics: r.topics,
+        })
+    }
+
+    /// Retrieves the id corresponding to the fetch messages request
+    /// (provided for debugging purposes only).
+    #[inline]
+    pub fn correlation_id(&self) -> i32 {
+        self.correlation_id
+    }
+
+    /// Provides an iterator over all the topics and the fetched data
+    /// relative to these topics.
+    #[inline]
+    pub fn topics<'a>(&'a self) -> &[Topic<'a>] {
+        &self.topics
+    }
+
+    /// Passes the messages of all partitions with successfully
+    /// fetched data through the given function (along with the
+    /// message's topic and partition), replacing the key and value of
+    /// each message with the ones it delivers.  Messages for which
+    /// the function delivers `None` are removed.
+    pub(crate) fn rewrite_messages<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&str, i32, &Message<'_>) -> Option<(Option<Vec<u8>>, Vec<u8>)>,
+    {
+        for t in &mut self.topics {
+            let topic = t.topic;
+            for p in &mut t.partitions {
+                let partition = p.partition;
+                if let Ok(ref mut data) = p.data {
+                    data.message_set.rewrite(|m| f(topic, partition, m));
+                }
+            }
+        }
+    }
+}
+
+/// The result of a "fetch messages" request from a particular Kafka
+/// broker like `Response` but borrowing its data from a buffer owned
+/// by the caller.  See `KafkaClient::fetch_messages_into`.
+///
+/// Messages cannot outlive the response they were taken from, even
+/// though the buffer is still borrowed:
+///
+/// ```compile_fail
+/// use kafka::client::{FetchPartition, KafkaClient};
+///
+/// let mut client = KafkaClient::new(vec!["localhost:9092".to_owned()]);
+/// let mut buf = Vec::new();
+/// let value = {
+///     let resps = client
+///         .fetch_messages_into(&[FetchPartition::new("my-topic", 0, 0)], &mut buf)
+///         .unwrap();
+///     resps[0].topics()[0].partitions()[0].data().unwrap().messages()[0].value
+/// };
+/// println!("{:?}", value);
+/// ```
+#[derive(Debug)]
+pub struct BorrowedResponse<'a> {
+    correlation_id: i32,
+    topics: Vec<Topic<'a>>,
+}
+
+impl<'a> BorrowedResponse<'a> {
+    /// Parses a response from binary data as defined by the Kafka
+    /// Protocol.  Only the data of compressed message sets is copied
+    /// (in its decompressed form), failing with
+    /// `KafkaCode::MessageSizeTooLarge` if the compressed message sets
+    /// of the response decompress to more than `max_size` bytes in
+    /// total.
+    pub(crate) fn from_slice(
+        response: &'a [u8],
+        reqs: Option<&FetchRequest<'_, '_>>,
+        validate_crc: bool,
+        max_size: usize,
+    ) -> Result<BorrowedResponse<'a>> {
+        let mut r = ZReader::new(response);
+        let correlation_id = r.read_i32()?;
+        // ~ the decompression budget shared by all message sets
+        let mut budget = max_size;
+        let topics = array_of!(r, Topic::read(&mut r, reqs, validate_crc, &mut budget));
+        Ok(BorrowedResponse {
             correlation_id,
             topics,
         })
@@ -208,8 +312,12 @@ impl Response {
 
     /// Provides an iterator over all the topics and the fetched data
     /// relative to these topics.
+    ///
+    /// The messages are bound to the lifetime of this response, not
+    /// of the buffer it was read into: the data of compressed message
+    /// sets is owned by the response itself.
     #[inline]
-    pub fn topics<'a>(&'a self) -> &[Topic<'a>] {
+    pub fn topics(&self) -> &[Topic<'_>] {
         &self.topics
     }
 }
@@ -229,10 +337,11 @@ impl<'a> Topic<'a> {
         r: &mut ZReader<'a>,
         reqs: Option<&FetchRequest<'_, '_>>,
         validate_crc: bool,
+        budget: &mut usize,
     ) -> Result<Topic<'a>> {
         let name = r.read_str()?;
         let preqs = reqs.and_then(|reqs| reqs.get(name));
-        let partitions = array_of!(r, Partition::read(r, preqs, validate_crc));
+        let partitions = array_of!(r, Partition::read(r, preqs, validate_crc, budget));
         Ok(Topic {
             topic: name,
             partitions,
@@ -274,6 +383,7 @@ impl<'a> Partition<'a> {
         r: &mut ZReader<'a>,
         preqs: Option<&TopicPartitionFetchRequest>,
         validate_crc: bool,
+        budget: &mut usize,
     ) -> Result<Partition<'a>> {
         let partition = r.read_i32()?;
         let proffs = preqs
@@ -285,7 +395,7 @@ impl<'a> Partition<'a> {
         // we need to parse the rest even if there was an error to
         // consume the input stream (zreader)
         let highwatermark = r.read_i64()?;
-        let msgset = MessageSet::from_slice(r.read_bytes()?, proffs, validate_crc)?;
+        let msgset = MessageSet::from_slice(r.read_bytes()?, proffs, validate_crc, budget)?;
 
         Ok(Partition {
             partition,
@@ -306,7 +416,7 @@ impl<'a> Partition<'a> {
     }
 
     /// Retrieves the data payload for this partition.
-    pub fn data(&'a self) -> result::Result<&'a Data<'a>, Arc<Error>> {
+    pub fn data(&self) -> result::Result<&Data<'a>, Arc<Error>> {
         match self.data.as_ref() {
             Ok(data) => Ok(data),
             Err(err) => Err(err.clone()),
@@ -336,6 +446,154 @@ impl<'a> Data<'a> {
     pub fn messages(&self) -> &[Message<'a>] {
         &self.message_set.messages
     }
+
+    /// Determines whether the fetched data ended in a message cut off
+    /// at the requested `max_bytes`, i.e. whether the message
+    /// following the delivered ones did not fit into the fetch.
+    #[inline]
+    pub(crate) fn is_truncated(&self) -> bool {
+        self.message_set.truncated
+    }
+}
+
+/// A fetched message whose key and value are views into a shared,
+/// reference counted buffer holding the fetched data.  Unlike
+/// `Message`, such a message is not bound to the lifetime of the
+/// `Response` it originates from.  See
+/// `Response::into_bytes_message_sets`.
+#[cfg(feature = "bytes")]
+#[derive(Debug, Clone)]
+pub struct BytesMessage {
+    /// The offset at which this message resides in the remote kafka
+    /// broker topic partition.
+    pub offset: i64,
+
+    /// The "key" data of this message.  `None` if the message was
+    /// sent with a "null" key.
+    pub key: Option<Bytes>,
+
+    /// The value data of this message.  Empty if there is no such
+    /// data for this message.
+    pub value: Bytes,
+}
+
+#[cfg(feature = "bytes")]
+impl BytesMessage {
+    /// Retrieves the key of this message; `None` for a "null" key,
+    /// `Some(&[])` for an empty one.
+    #[inline]
+    pub fn key(&self) -> Option<&[u8]> {
+        self.key.as_deref()
+    }
+}
+
+/// The messages successfully fetched for a particular topic
+/// partition, backed by reference counted buffers.  See
+/// `Response::into_bytes_message_sets`.
+#[cfg(feature = "bytes")]
+#[derive(Debug, Clone)]
+pub struct BytesMessageSet {
+    topic: String,
+    partition: i32,
+    highwatermark_offset: i64,
+    messages: Vec<BytesMessage>,
+}
+
+#[cfg(feature = "bytes")]
+impl BytesMessageSet {
+    /// Retrieves the topic the messages were fetched from.
+    #[inline]
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Retrieves the partition the messages were fetched from.
+    #[inline]
+    pub fn partition(&self) -> i32 {
+        self.partition
+    }
+
+    /// See `Data::highwatermark_offset`.
+    #[inline]
+    pub fn highwatermark_offset(&self) -> i64 {
+        self.highwatermark_offset
+    }
+
+    /// Retrieves the fetched messages.
+    #[inline]
+    pub fn messages(&self) -> &[BytesMessage] {
+        &self.messages
+    }
+
+    /// Takes the fetched messages out of this set.
+    #[inline]
+    pub fn into_messages(self) -> Vec<BytesMessage> {
+        self.messages
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Response {
+    /// Converts this response into the message sets of all
+    /// partitions with successfully fetched data.  The messages' keys
+    /// and values are not copied; they are views into the buffers
+    /// this response was decoded from, which stay alive as long as
+    /// any of the messages does.  Partitions with an error are
+    /// skipped.
+    pub fn into_bytes_message_sets(self) -> Vec<BytesMessageSet> {
+        let Response {
+            raw_data, topics, ..
+        } = self;
+        // ~ moving the vector into `Bytes` keeps its heap allocation
+        // such that the slices decoded from it stay valid
+        let buf = Bytes::from(raw_data);
+        let mut sets = Vec::new();
+        for t in topics {
+            for p in t.partitions {
+                let data = match p.data {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                };
+                // ~ decompressed message sets own their data
+                let set_buf = match data.message_set.raw_data {
+                    Cow::Borrowed(_) => buf.clone(),
+                    Cow::Owned(v) => Bytes::from(v),
+                };
+                let messages = data
+                    .message_set
+                    .messages
+                    .iter()
+                    .map(|m| BytesMessage {
+                        offset: m.offset,
+                        key: m.key.map(|k| slice_of(&set_buf, k)),
+                        value: slice_of(&set_buf, m.value),
+                    })
+                    .collect();
+                sets.push(BytesMessageSet {
+                    topic: t.topic.to_owned(),
+                    partition: p.partition,
+                    highwatermark_offset: data.highwatermark_offset,
+                    messages,
+                });
+            }
+        }
+        sets
+    }
+}
+
+// ~ turns `sub` - a view into `buf` - into a `Bytes` sharing `buf`'s
+// data by slicing at the offsets of `sub` within `buf`.
+// `Bytes::slice_ref` may panic on buffers converted from a `Vec` with
+// some of the versions of `bytes` we accept.
+#[cfg(feature = "bytes")]
+fn slice_of(buf: &Bytes, sub: &[u8]) -> Bytes {
+    if sub.is_empty() {
+        return Bytes::new();
+    }
+    let start = (sub.as_ptr() as usize)
+        .checked_sub(buf.as_ptr() as usize)
+        .expect("slice out of buffer");
+    buf.slice(start..start + sub.len())
 }
 
 #[derive(Debug)]
@@ -343,28 +601,52 @@ struct MessageSet<'a> {
     #[allow(dead_code)]
     raw_data: Cow<'a, [u8]>, // ~ this field is used to potentially "own" the underlying vector
     messages: Vec<Message<'a>>,
+    // ~ whether the data ended in a partial message cut off by the
+    // broker at the requested max_bytes
+    truncated: bool,
 }
 
 /// A fetched message from a remote Kafka broker for a particular
 /// topic partition.
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct Message<'a> {
     /// The offset at which this message resides in the remote kafka
     /// broker topic partition.
     pub offset: i64,
 
-    /// The "key" data of this message.  Empty if there is no such
-    /// data for this message.
-    pub key: &'a [u8],
+    /// The "key" data of this message.  `None` if the message was
+    /// sent with a "null" key as opposed to an empty one.
+    pub key: Option<&'a [u8]>,
 
     /// The value data of this message.  Empty if there is no such
     /// data for this message.
     pub value: &'a [u8],
 }
 
+impl<'a> Message<'a> {
+    /// Retrieves the key of this message; `None` for a "null" key,
+    /// `Some(&[])` for an empty one.
+    #[inline]
+    pub fn key(&self) -> Option<&'a [u8]> {
+        self.key
+    }
+
+    /// Retrieves the key and the value of this message as a pair.
+    /// See `OwnedMessage::pair`.
+    #[inline]
+    pub fn pair(&self) -> (Option<&'a [u8]>, &'a [u8]) {
+        (self.key, self.value)
+    }
+}
+
 impl<'a> MessageSet<'a> {
     #[allow(dead_code)]
-    fn from_vec(data: Vec<u8>, req_offset: i64, validate_crc: bool) -> Result<MessageSet<'a>> {
+    fn from_vec(
+        data: Vec<u8>,
+        req_offset: i64,
+        validate_crc: bool,
+        budget: &mut usize,
+    ) -> Result<MessageSet<'a>> {
         // since we're going to keep the original
         // uncompressed vector around without
         // further modifying it and providing
@@ -374,32 +656,88 @@ impl<'a> MessageSet<'a> {
             unsafe { mem::transmute(&data[..]) },
             req_offset,
             validate_crc,
+            budget,
         )?;
         return Ok(MessageSet {
             raw_data: Cow::Owned(data),
             messages: ms.messages,
+            truncated: ms.truncated,
         });
     }
 
-    fn from_slice(raw_data: &[u8], req_offset: i64, validate_crc: bool) -> Result<MessageSet<'_>> {
+    /// Replaces the messages of this set by the ones delivered by the
+    /// given function, dropping those for which it delivers `None`.
+    /// The new keys and values are copied into a buffer owned by this
+    /// message set.
+    fn rewrite<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Message<'_>) -> Option<(Option<Vec<u8>>, Vec<u8>)>,
+    {
+        // ~ (offset, key range, value range) into `data`
+        let mut spans = Vec::with_capacity(self.messages.len());
+        let mut data = Vec::new();
+        for m in &self.messages {
+            if let Some((key, value)) = f(m) {
+                let key = key.map(|k| {
+                    let start = data.len();
+                    data.extend_from_slice(&k);
+                    start..data.len()
+                });
+                let start = data.len();
+                data.extend_from_slice(&value);
+                spans.push((m.offset, key, start..data.len()));
+            }
+        }
+        // since we're going to keep the vector around without
+        // further modifying it (see `from_vec`) this is safe
+        let buf: &'a [u8] = unsafe { mem::transmute(&data[..]) };
+        self.messages = spans
+            .into_iter()
+            .map(|(offset, key, value)| Message {
+                offset,
+                key: key.map(|k| &buf[k]),
+                value: &buf[value],
+            })
+            .collect();
+        self.raw_data = Cow::Owned(data);
+    }
+
+    // ~ `budget` is consumed by decompressing message sets only
+    #[cfg_attr(
+        not(any(feature = "gzip", feature = "snappy")),
+        allow(unused_variables)
+    )]
+    fn from_slice<'b>(
+        raw_data: &'b [u8],
+        req_offset: i64,
+        validate_crc: bool,
+        budget: &mut usize,
+    ) -> Result<MessageSet<'b>> {
         let mut r = ZReader::new(raw_data);
         let mut msgs = Vec::new();
+        let mut truncated = false;
         while !r.is_empty() {
             match MessageSet::next_message(&mut r, validate_crc) {
-                // this is the last messages which might be
-                // incomplete; a valid case to be handled by
-                // consumers
-                Err(Error::UnexpectedEOF) => {
+                // this is the last message which the broker cut off
+                // at the requested max_bytes boundary; a valid case
+                // to be handled by consumers by fetching it again
+                // starting at the last complete message
+                Ok(None) => {
+                    trace!(
+                        "from_slice: discarding trailing partial message ({} bytes)",
+                        r.rest().len()
+                    );
+                    truncated = true;
                     break;
                 }
                 Err(e) => {
                     return Err(e);
                 }
-                Ok((offset, pmsg)) => {
+                Ok(Some((offset, pmsg))) => {
                     // handle compression (denoted by the last 3 bits
                     // of the attr field)
-                    match pmsg.attr & 0x07 {
-                        c if c == Compression::NONE as i8 => {
+                    match Compression::detect(i16::from(pmsg.attr)).compression()? {
+                        Compression::NONE => {
                             // skip messages with a lower offset
                             // than the request one
                             if offset >= req_offset {
@@ -412,18 +750,20 @@ impl<'a> MessageSet<'a> {
                         }
                         // XXX handle recursive compression in future
                         #[cfg(feature = "gzip")]
-                        c if c == Compression::GZIP as i8 => {
-                            let v = gzip::uncompress(pmsg.value)?;
-                            return MessageSet::from_vec(v, req_offset, validate_crc);
+                        Compression::GZIP => {
+                            // ~ decompress as a stream giving up as soon as
+                            // the data exceeds the remaining budget rather
+                            // than materializing arbitrarily large batches
+                            let v = gzip::uncompress_max(pmsg.value, *budget)?;
+                            *budget -= v.len();
+                            return MessageSet::from_vec(v, req_offset, validate_crc, budget);
                         }
                         #[cfg(feature = "snappy")]
-                        c if c == Compression::SNAPPY as i8 => {
-                            use std::io::Read;
-                            let mut v = Vec::new();
-                            SnappyReader::new(pmsg.value)?.read_to_end(&mut v)?;
-                            return MessageSet::from_vec(v, req_offset, validate_crc);
+                        Compression::SNAPPY => {
+                            let v = snappy::uncompress_max(pmsg.value, *budget)?;
+                            *budget -= v.len();
+                            return MessageSet::from_vec(v, req_offset, validate_crc, budget);
                         }
-                        _ => return Err(Error::UnsupportedCompression),
                     }
                 }
             };
@@ -431,23 +771,36 @@ impl<'a> MessageSet<'a> {
         Ok(MessageSet {
             raw_data: Cow::Borrowed(raw_data),
             messages: msgs,
+            truncated,
         })
     }
 
+    /// Reads the next message from the given reader.  Delivers
+    /// `None` if the rest of the data is a partial message, in which
+    /// case the reader is left untouched.
     fn next_message<'b>(
         r: &mut ZReader<'b>,
         validate_crc: bool,
-    ) -> Result<(i64, ProtocolMessage<'b>)> {
-        let offset = r.read_i64()?;
-        let msg_data = r.read_bytes()?;
-        Ok((offset, ProtocolMessage::from_slice(msg_data, validate_crc)?))
+    ) -> Result<Option<(i64, ProtocolMessage<'b>)>> {
+        let mut peek = r.clone();
+        let (Ok(offset), Ok(msg_data)) = (peek.read_i64(), peek.read_bytes()) else {
+            return Ok(None);
+        };
+        *r = peek;
+        // ~ the message is complete; failing to parse it is not due to
+        // truncation
+        match ProtocolMessage::from_slice(msg_data, validate_crc) {
+            Ok(pmsg) => Ok(Some((offset, pmsg))),
+            Err(Error::UnexpectedEOF) => Err(Error::CodecError),
+            Err(e) => Err(e),
+        }
     }
 }
 
 /// Represents a messages exactly as defined in the protocol.
 struct ProtocolMessage<'a> {
     attr: i8,
-    key: &'a [u8],
+    key: Option<&'a [u8]>,
     value: &'a [u8],
 }
 
@@ -463,13 +816,16 @@ impl<'a> ProtocolMessage<'a> {
             return Err(Error::Kafka(KafkaCode::CorruptMessage));
         }
         // ~ we support parsing only messages with the "zero"
-        // magic_byte; this covers kafka 0.8 and 0.9.
+        // magic_byte; this covers kafka 0.8 and 0.9.  (control batches
+        // carrying transaction markers exist only in the v2 record
+        // batch format; brokers drop them when down-converting for our
+        // v0 fetch requests, hence there's nothing to skip here.)
         let msg_magic = r.read_i8()?;
         if msg_magic != 0 {
             return Err(Error::UnsupportedProtocol);
         }
         let msg_attr = r.read_i8()?;
-        let msg_key = r.read_bytes()?;
+        let msg_key = r.read_nullable_bytes()?;
         let msg_val = r.read_bytes()?;
 
         debug_assert!(r.is_empty());
@@ -488,8 +844,10 @@ impl<'a> ProtocolMessage<'a> {
 mod tests {
     use std::str;
 
-    use super::{FetchRequest, Message, Response};
+    use super::{FetchRequest, Message, MessageSet, Response};
+    use crate::codecs::ToByte;
     use crate::error::{Error, KafkaCode};
+    use crate::protocol::to_crc;
 
     static FETCH1_TXT: &str = include_str!("../../test-data/fetch1.txt");
 
@@ -538,7 +896,7 @@ mod tests {
         requests: Option<&FetchRequest<'_, '_>>,
         validate_crc: bool,
     ) {
-        let resp = Response::from_vec(response, requests, validate_crc);
+        let resp = Response::from_vec(response, requests, validate_crc, usize::MAX);
         let resp = resp.unwrap();
 
         let original: Vec<_> = msg_per_line.lines().collect();
@@ -559,6 +917,20 @@ mod tests {
         for (msg, orig) in msgs.into_iter().zip(original.iter()) {
             assert_eq!(str::from_utf8(msg.value).unwrap(), *orig);
         }
+
+        // ~ the zero-copy view delivers the very same messages
+        #[cfg(feature = "bytes")]
+        {
+            let sets = resp.into_bytes_message_sets();
+            assert_eq!(1, sets.len());
+            assert_eq!("my-topic", sets[0].topic());
+            let values: Vec<_> = sets[0]
+                .messages()
+                .iter()
+                .map(|m| str::from_utf8(&m.value).unwrap())
+                .collect();
+            assert_eq!(original, values);
+        }
     }
 
     fn skip_lines(mut lines: &str, mut n: usize) -> &str {
@@ -604,6 +976,7 @@ mod tests {
             FETCH1_FETCH_RESPONSE_SNAPPY_K0821.to_owned(),
             Some(&req),
             false,
+            usize::MAX,
         );
         assert!(match r {
             return Err(Error::UnsupportedCompression) => true,
@@ -681,6 +1054,114 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_from_slice_gzip_max_size() {
+        let data = FETCH1_FETCH_RESPONSE_GZIP_K0821;
+        // ~ the compressed set decompresses to more than 16 bytes
+        match Response::from_vec(data.to_owned(), None, false, 16) {
+            Err(Error::Kafka(KafkaCode::MessageSizeTooLarge)) => {}
+            r => panic!("Expected MessageSizeTooLarge, but got: {:?}", r),
+        }
+        Response::from_vec(data.to_owned(), None, false, data.len() * 10).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_budget_shared_across_sets() {
+        use crate::compression::{gzip, Compression};
+
+        let inner = message_set(&[(0, "hello"), (1, "world")]);
+        // ~ a single message wrapping the gzip compressed `inner` set
+        let mut msg = vec![0, Compression::GZIP as u8];
+        (-1i32).encode(&mut msg).unwrap();
+        gzip::compress(&inner).unwrap().encode(&mut msg).unwrap();
+        let mut raw = Vec::new();
+        1i64.encode(&mut raw).unwrap();
+        (msg.len() as i32 + 4).encode(&mut raw).unwrap();
+        (to_crc(&msg) as i32).encode(&mut raw).unwrap();
+        raw.extend_from_slice(&msg);
+
+        // ~ each set fits the limit on its own, but not both of them
+        let mut budget = 2 * inner.len() - 1;
+        let ms = MessageSet::from_slice(&raw, 0, true, &mut budget).unwrap();
+        assert_eq!(2, ms.messages.len());
+        assert_eq!(inner.len() - 1, budget);
+        match MessageSet::from_slice(&raw, 0, true, &mut budget) {
+            Err(Error::Kafka(KafkaCode::MessageSizeTooLarge)) => {}
+            r => panic!("Expected MessageSizeTooLarge, but got: {:?}", r),
+        }
+    }
+
+    // ~ renders a message set of the given (offset, value) messages
+    fn message_set(msgs: &[(i64, &str)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for &(offset, value) in msgs {
+            // ~ magic, attributes, null key, value
+            let mut msg = vec![0, 0];
+            (-1i32).encode(&mut msg).unwrap();
+            value.as_bytes().encode(&mut msg).unwrap();
+
+            offset.encode(&mut buf).unwrap();
+            (msg.len() as i32 + 4).encode(&mut buf).unwrap();
+            (to_crc(&msg) as i32).encode(&mut buf).unwrap();
+            buf.extend_from_slice(&msg);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_truncated_message_set() {
+        let raw = message_set(&[(5, "hello"), (6, "world"), (7, "cut off")]);
+        let expected = vec![(5, &b"hello"[..]), (6, &b"world"[..])];
+        let mut budget = usize::MAX;
+
+        // ~ the last message cut off in the middle of its value, its
+        // size, and its offset respectively
+        for cut in [3, 21 + 2, 21 + 4 + 5] {
+            let ms = MessageSet::from_slice(&raw[..raw.len() - cut], 0, true, &mut budget).unwrap();
+            let msgs: Vec<_> = ms.messages.iter().map(|m| (m.offset, m.value)).collect();
+            assert_eq!(expected, msgs, "cut: {}", cut);
+            assert!(ms.truncated, "cut: {}", cut);
+        }
+        let ms = MessageSet::from_slice(&raw, 0, true, &mut budget).unwrap();
+        assert!(!ms.truncated);
+
+        // ~ a complete but malformed message is not mistaken for a
+        // truncated one
+        let mut raw = message_set(&[(5, "hello")]);
+        0i64.encode(&mut raw).unwrap();
+        10i32.encode(&mut raw).unwrap();
+        raw.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 100]);
+        match MessageSet::from_slice(&raw, 0, false, &mut budget) {
+            Err(Error::CodecError) => {}
+            r => panic!("unexpected result: {:?}", r.map(|ms| ms.messages.len())),
+        }
+    }
+
+    #[test]
+    fn test_null_vs_empty_key() {
+        let mut raw = Vec::new();
+        for (offset, key) in [(0i64, None), (1, Some(&b""[..])), (2, Some(&b"k"[..]))] {
+            // ~ magic, attributes, key, null value
+            let mut msg = vec![0, 0];
+            match key {
+                Some(key) => key.encode(&mut msg).unwrap(),
+                None => (-1i32).encode(&mut msg).unwrap(),
+            }
+            (-1i32).encode(&mut msg).unwrap();
+
+            offset.encode(&mut raw).unwrap();
+            (msg.len() as i32 + 4).encode(&mut raw).unwrap();
+            (to_crc(&msg) as i32).encode(&mut raw).unwrap();
+            raw.extend_from_slice(&msg);
+        }
+        let mut budget = usize::MAX;
+        let ms = MessageSet::from_slice(&raw, 0, true, &mut budget).unwrap();
+        let keys: Vec<_> = ms.messages.iter().map(Message::key).collect();
+        assert_eq!(vec![None, Some(&b""[..]), Some(&b"k"[..])], keys);
+    }
+
     #[test]
     fn test_crc_validation() {
         test_decode_new_fetch_response(
@@ -706,6 +1187,7 @@ mod tests {
             FETCH2_FETCH_RESPONSE_NOCOMPRESSION_INVALID_CRC_K0900.to_owned(),
             None,
             true,
+            usize::MAX,
         ) {
             Ok(_) => panic!("Expected error, but got successful response!"),
             Err(Error::Kafka(KafkaCode::CorruptMessage)) => {}
@@ -717,7 +1199,7 @@ mod tests {
     mod benches {
         use test::{black_box, Bencher};
 
-        use super::super::{FetchRequest, Response};
+        use super::super::{BorrowedResponse, FetchRequest, Response};
         use super::into_messages;
 
         fn bench_decode_new_fetch_response(b: &mut Bencher, data: Vec<u8>, validate_crc: bool) {
@@ -726,12 +1208,93 @@ mod tests {
             b.bytes = data.len() as u64;
             b.iter(|| {
                 let data = data.clone();
-                let r = black_box(Response::from_vec(data, Some(&reqs), validate_crc).unwrap());
+                let r = black_box(
+                    Response::from_vec(data, Some(&reqs), validate_crc, usize::MAX).unwrap(),
+                );
                 let v = black_box(into_messages(&r));
                 v.len()
             });
         }
 
+        // ~ decodes the given response and takes the messages out of
+        // it such that they can outlive the response
+        #[cfg(feature = "bytes")]
+        fn bench_owned_messages<T, F>(b: &mut Bencher, data: Vec<u8>, f: F)
+        where
+            F: Fn(Response) -> Vec<T>,
+        {
+            let mut reqs = FetchRequest::new(0, "foo", -1, -1);
+            reqs.add("my-topic", 0, 0, -1);
+            b.bytes = data.len() as u64;
+            b.iter(|| {
+                let data = data.clone();
+                let r =
+                    black_box(Response::from_vec(data, Some(&reqs), false, usize::MAX).unwrap());
+                black_box(f(r)).len()
+            });
+        }
+
+        #[cfg(feature = "bytes")]
+        #[bench]
+        fn bench_owned_messages_copying_nocompression_k0821(b: &mut Bencher) {
+            bench_owned_messages(
+                b,
+                super::FETCH1_FETCH_RESPONSE_NOCOMPRESSION_K0821.to_owned(),
+                |r| {
+                    into_messages(&r)
+                        .into_iter()
+                        .map(|m| (m.offset, m.key.map(<[u8]>::to_vec), m.value.to_vec()))
+                        .collect()
+                },
+            )
+        }
+
+        #[cfg(feature = "bytes")]
+        #[bench]
+        fn bench_owned_messages_bytes_nocompression_k0821(b: &mut Bencher) {
+            bench_owned_messages(
+                b,
+                super::FETCH1_FETCH_RESPONSE_NOCOMPRESSION_K0821.to_owned(),
+                |r| {
+                    r.into_bytes_message_sets()
+                        .into_iter()
+                        .flat_map(|s| s.into_messages())
+                        .collect()
+                },
+            )
+        }
+
+        // ~ decodes the given response from a re-used buffer the way
+        // `KafkaClient::fetch_messages_into` does
+        fn bench_decode_borrowed_fetch_response(b: &mut Bencher, data: Vec<u8>) {
+            let mut reqs = FetchRequest::new(0, "foo", -1, -1);
+            reqs.add("my-topic", 0, 0, -1);
+            b.bytes = data.len() as u64;
+            let mut buf = Vec::new();
+            b.iter(|| {
+                buf.clear();
+                buf.extend_from_slice(&data);
+                let r = black_box(
+                    BorrowedResponse::from_slice(&buf, Some(&reqs), false, usize::MAX).unwrap(),
+                );
+                let n: usize = r
+                    .topics()
+                    .iter()
+                    .flat_map(|t| t.partitions())
+                    .map(|p| black_box(p.data().unwrap().messages()).len())
+                    .sum();
+                n
+            });
+        }
+
+        #[bench]
+        fn bench_decode_borrowed_fetch_response_nocompression_k0821(b: &mut Bencher) {
+            bench_decode_borrowed_fetch_response(
+                b,
+                super::FETCH1_FETCH_RESPONSE_NOCOMPRESSION_K0821.to_owned(),
+            )
+        }
+
         #[bench]
         fn bench_decode_new_fetch_response_nocompression_k0821(b: &mut Bencher) {
             bench_decode_new_fetch_response(
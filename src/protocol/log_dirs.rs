@@ -0,0 +1,223 @@
+use std::io::{Read, Write};
+
+use crate::codecs::{FromByte, ToByte};
+use crate::error::{KafkaCode, Result};
+
+use super::{HeaderRequest, HeaderResponse, API_KEY_DESCRIBE_LOG_DIRS};
+
+// ~ version one is identical to version zero on the wire; brokers
+// merely throttle its responses differently.  (available as of
+// kafka 1.1)
+const API_VERSION_DESCRIBE_LOG_DIRS: i16 = 1;
+
+/// A log directory of a broker as delivered by
+/// `KafkaClient::describe_log_dirs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogDir {
+    /// The id of the broker hosting the log directory.
+    pub broker_id: i32,
+    /// The absolute path of the log directory.
+    pub path: String,
+    /// The partitions stored in the log directory or the error the
+    /// broker reported for it, e.g. `KafkaCode::KafkaStorageError`
+    /// for an offline log directory.
+    pub partitions: std::result::Result<Vec<LogDirPartition>, KafkaCode>,
+}
+
+/// A partition stored in a `LogDir`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogDirPartition {
+    /// The name of the topic.
+    pub topic: String,
+    /// The partition of the topic.
+    pub partition: i32,
+    /// The size of the partition's log segments in bytes.
+    pub size: i64,
+    /// The lag of the log's end offset behind the partition's high
+    /// watermark; for a future replica the lag behind the current
+    /// replica's end offset.
+    pub offset_lag: i64,
+    /// Whether the log is a future replica, i.e. one in the course of
+    /// being moved to the log directory.
+    pub is_future: bool,
+}
+
+// --------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct DescribeLogDirsRequest<'a, 'b> {
+    pub header: HeaderRequest<'a>,
+    // ~ `None` to describe all partitions
+    pub topic_partitions: Option<Vec<(&'b str, Vec<i32>)>>,
+}
+
+impl<'a, 'b> DescribeLogDirsRequest<'a, 'b> {
+    pub fn new(correlation_id: i32, client_id: &'a str) -> DescribeLogDirsRequest<'a, 'b> {
+        DescribeLogDirsRequest {
+            header: HeaderRequest::new(
+                API_KEY_DESCRIBE_LOG_DIRS,
+                API_VERSION_DESCRIBE_LOG_DIRS,
+                correlation_id,
+                client_id,
+            ),
+            topic_partitions: None,
+        }
+    }
+
+    /// Restricts the request to the given topic partition (in
+    /// addition to those added so far.)
+    pub fn add(&mut self, topic: &'b str, partition: i32) {
+        let tps = self.topic_partitions.get_or_insert_with(Vec::new);
+        match tps.iter_mut().find(|tp| tp.0 == topic) {
+            Some(tp) => tp.1.push(partition),
+            None => tps.push((topic, vec![partition])),
+        }
+    }
+}
+
+impl<'a, 'b> ToByte for DescribeLogDirsRequest<'a, 'b> {
+    fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
+        self.header.encode(buffer)?;
+        match self.topic_partitions {
+            // ~ a null array asks for all partitions
+            None => (-1i32).encode(buffer),
+            Some(ref tps) => {
+                (tps.len() as i32).encode(buffer)?;
+                for (topic, partitions) in tps {
+                    topic.encode(buffer)?;
+                    partitions.encode(buffer)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DescribeLogDirsResponse {
+    pub header: HeaderResponse,
+    pub throttle_time_ms: i32,
+    // ~ (error, path, partitions) of each log directory
+    pub log_dirs: Vec<(i16, String, Vec<LogDirPartition>)>,
+}
+
+impl DescribeLogDirsResponse {
+    /// Converts this response into the log directories of the given
+    /// broker.
+    pub fn into_log_dirs(self, broker_id: i32) -> Vec<LogDir> {
+        self.log_dirs
+            .into_iter()
+            .map(|(error, path, partitions)| LogDir {
+                broker_id,
+                path,
+                partitions: match KafkaCode::from_protocol(error) {
+                    Some(e) => Err(e),
+                    None => Ok(partitions),
+                },
+            })
+            .collect()
+    }
+}
+
+impl FromByte for DescribeLogDirsResponse {
+    type R = DescribeLogDirsResponse;
+
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        // ~ response: throttle_time_ms [error log_dir [topic
+        //   [partition size offset_lag is_future]]]
+        self.header.decode(buffer)?;
+        self.throttle_time_ms.decode(buffer)?;
+        let n = i32::decode_new(buffer)?;
+        self.log_dirs = Vec::with_capacity(n.max(0) as usize);
+        for _ in 0..n {
+            let error = i16::decode_new(buffer)?;
+            let path = String::decode_new(buffer)?;
+            let mut partitions = Vec::new();
+            for _ in 0..i32::decode_new(buffer)? {
+                let topic = String::decode_new(buffer)?;
+                for _ in 0..i32::decode_new(buffer)? {
+                    partitions.push(LogDirPartition {
+                        topic: topic.clone(),
+                        partition: i32::decode_new(buffer)?,
+                        size: i64::decode_new(buffer)?,
+                        offset_lag: i64::decode_new(buffer)?,
+                        is_future: i8::decode_new(buffer)? != 0,
+                    });
+                }
+            }
+            self.log_dirs.push((error, path, partitions));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{DescribeLogDirsRequest, DescribeLogDirsResponse, LogDirPartition};
+    use crate::codecs::{FromByte, ToByte};
+    use crate::error::KafkaCode;
+
+    #[test]
+    fn test_encode_request() {
+        let mut req = DescribeLogDirsRequest::new(7, "me");
+        req.add("ab", 0);
+        req.add("c", 1);
+        req.add("ab", 2);
+        let mut buf = Vec::new();
+        req.encode(&mut buf).unwrap();
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            // header
+            0, 35, 0, 1, 0, 0, 0, 7, 0, 2, b'm', b'e',
+            // topics
+            0, 0, 0, 2,
+            0, 2, b'a', b'b', 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2,
+            0, 1, b'c', 0, 0, 0, 1, 0, 0, 0, 1,
+        ];
+        assert_eq!(expected, buf);
+
+        // ~ all partitions
+        let mut buf = Vec::new();
+        DescribeLogDirsRequest::new(7, "me")
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(&[0xff, 0xff, 0xff, 0xff], &buf[buf.len() - 4..]);
+    }
+
+    #[test]
+    fn test_decode_response() {
+        #[rustfmt::skip]
+        let raw: Vec<u8> = vec![
+            // header, throttle time
+            0, 0, 0, 7, 0, 0, 0, 0,
+            // two log dirs
+            0, 0, 0, 2,
+            // an online one hosting a partition
+            0, 0, 0, 2, b'/', b'a',
+            0, 0, 0, 1, 0, 1, b't', 0, 0, 0, 1,
+            0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0,
+            // an offline one
+            0, 56, 0, 2, b'/', b'b', 0, 0, 0, 0,
+        ];
+        let log_dirs = DescribeLogDirsResponse::decode_new(&mut Cursor::new(raw))
+            .unwrap()
+            .into_log_dirs(4);
+        assert_eq!(2, log_dirs.len());
+        assert_eq!(4, log_dirs[0].broker_id);
+        assert_eq!("/a", log_dirs[0].path);
+        assert_eq!(
+            Ok(vec![LogDirPartition {
+                topic: "t".to_owned(),
+                partition: 3,
+                size: 256,
+                offset_lag: 5,
+                is_future: false,
+            }]),
+            log_dirs[0].partitions
+        );
+        assert_eq!("/b", log_dirs[1].path);
+        assert_eq!(Err(KafkaCode::KafkaStorageError), log_dirs[1].partitions);
+    }
+}
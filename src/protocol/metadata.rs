@@ -1,32 +1,77 @@
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 
 use crate::codecs::{AsStrings, FromByte, ToByte};
-use crate::error::Result;
+use crate::error::{KafkaCode, Result};
 
+use super::API_KEY_METADATA;
 use super::{HeaderRequest, HeaderResponse};
-use super::{API_KEY_METADATA, API_VERSION};
+
+/// The versions of the metadata api supported by this library.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MetadataVersion {
+    /// supported by all kafka versions (as of 0.8.0)
+    V0 = 0,
+    /// supported as of kafka 0.10.0; delivers the id of the
+    /// controller broker and the brokers' racks
+    V1 = 1,
+    /// supported as of kafka 0.10.1; additionally delivers the id of
+    /// the cluster
+    V2 = 2,
+    /// supported as of kafka 0.11.0
+    V3 = 3,
+    /// supported as of kafka 1.0.0
+    V4 = 4,
+    /// supported as of kafka 1.0.0; additionally delivers the
+    /// offline replicas of partitions
+    V5 = 5,
+    /// supported as of kafka 2.0.0
+    V6 = 6,
+    /// supported as of kafka 2.1.0; additionally delivers the leader
+    /// epoch of partitions
+    V7 = 7,
+}
 
 #[derive(Debug)]
 pub struct MetadataRequest<'a, T> {
     pub header: HeaderRequest<'a>,
     pub topics: &'a [T],
+    // ~ whether brokers may create requested topics not existing yet
+    // (subject to their configuration); encoded as of v4 only
+    pub allow_auto_topic_creation: bool,
 }
 
 impl<'a, T: AsRef<str>> MetadataRequest<'a, T> {
-    pub fn new(correlation_id: i32, client_id: &'a str, topics: &'a [T]) -> MetadataRequest<'a, T> {
+    pub fn new(
+        version: MetadataVersion,
+        correlation_id: i32,
+        client_id: &'a str,
+        topics: &'a [T],
+    ) -> MetadataRequest<'a, T> {
         MetadataRequest {
-            header: HeaderRequest::new(API_KEY_METADATA, API_VERSION, correlation_id, client_id),
+            header: HeaderRequest::new(API_KEY_METADATA, version as i16, correlation_id, client_id),
             topics,
+            allow_auto_topic_creation: true,
         }
     }
 }
 
 impl<'a, T: AsRef<str> + 'a> ToByte for MetadataRequest<'a, T> {
     fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
-        try_multi!(
-            self.header.encode(buffer),
-            AsStrings(self.topics).encode(buffer)
-        )
+        let version = self.header.api_version;
+        self.header.encode(buffer)?;
+        if version >= MetadataVersion::V1 as i16 && self.topics.is_empty() {
+            // ~ as of v1 an empty array means "no topics"; we need to
+            // send a null array to ask for all topics
+            (-1i32).encode(buffer)?;
+        } else {
+            AsStrings(self.topics).encode(buffer)?;
+        }
+        if version >= MetadataVersion::V4 as i16 {
+            // ~ earlier versions leave the decision to the broker's
+            // configuration
+            i8::from(self.allow_auto_topic_creation).encode(buffer)?;
+        }
+        Ok(())
     }
 }
 
@@ -36,6 +81,10 @@ impl<'a, T: AsRef<str> + 'a> ToByte for MetadataRequest<'a, T> {
 pub struct MetadataResponse {
     pub header: HeaderResponse,
     pub brokers: Vec<BrokerMetadata>,
+    /// Available as of `MetadataVersion::V2`.
+    pub cluster_id: Option<String>,
+    /// Available as of `MetadataVersion::V1`.
+    pub controller_id: Option<i32>,
     pub topics: Vec<TopicMetadata>,
 }
 
@@ -44,74 +93,291 @@ pub struct BrokerMetadata {
     pub node_id: i32,
     pub host: String,
     pub port: i32,
+    /// Available as of `MetadataVersion::V1`.
+    pub rack: Option<String>,
 }
 
 #[derive(Default, Debug)]
 pub struct TopicMetadata {
     pub error: i16,
     pub topic: String,
+    /// Available as of `MetadataVersion::V1`.
+    pub is_internal: bool,
     pub partitions: Vec<PartitionMetadata>,
 }
 
+impl TopicMetadata {
+    /// Retrieves the error the brokers reported for this topic, if
+    /// any; e.g. `KafkaCode::UnknownTopicOrPartition` for a topic
+    /// not known to the cluster.
+    pub fn error_code(&self) -> Option<KafkaCode> {
+        KafkaCode::from_protocol(self.error)
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct PartitionMetadata {
     pub error: i16,
     pub id: i32,
     pub leader: i32,
+    /// Available as of `MetadataVersion::V7`.
+    pub leader_epoch: Option<i32>,
     pub replicas: Vec<i32>,
     pub isr: Vec<i32>,
+    /// Available as of `MetadataVersion::V5`.
+    pub offline_replicas: Vec<i32>,
 }
 
-impl FromByte for MetadataResponse {
-    type R = MetadataResponse;
+/// Parses a raw metadata response according to the version of the
+/// corresponding request.
+pub struct ResponseParser {
+    pub version: MetadataVersion,
+}
 
-    #[allow(unused_must_use)]
-    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
-        try_multi!(
-            self.header.decode(buffer),
-            self.brokers.decode(buffer),
-            self.topics.decode(buffer)
-        )
+impl super::SliceResponseParser for ResponseParser {
+    type T = MetadataResponse;
+    fn parse_slice(&self, response: &[u8]) -> Result<Self::T> {
+        let mut r = MetadataResponse::default();
+        r.decode_version(&mut Cursor::new(response), self.version as i16)?;
+        Ok(r)
     }
 }
 
-impl FromByte for BrokerMetadata {
-    type R = BrokerMetadata;
+// ~ decodes an array of elements through the given closure
+fn decode_array<T, R, F>(buffer: &mut R, mut f: F) -> Result<Vec<T>>
+where
+    T: Default,
+    R: Read,
+    F: FnMut(&mut T, &mut R) -> Result<()>,
+{
+    let n = i32::decode_new(buffer)?;
+    let mut xs = Vec::with_capacity(if n > 0 { n as usize } else { 0 });
+    for _ in 0..n {
+        let mut x = T::default();
+        f(&mut x, buffer)?;
+        xs.push(x);
+    }
+    Ok(xs)
+}
 
-    #[allow(unused_must_use)]
-    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
-        try_multi!(
-            self.node_id.decode(buffer),
-            self.host.decode(buffer),
-            self.port.decode(buffer)
-        )
+impl MetadataResponse {
+    fn decode_version<T: Read>(&mut self, buffer: &mut T, version: i16) -> Result<()> {
+        self.header.decode(buffer)?;
+        if version >= MetadataVersion::V3 as i16 {
+            // ~ throttle_time_ms
+            i32::decode_new(buffer)?;
+        }
+        self.brokers = decode_array(buffer, |b: &mut BrokerMetadata, buffer| {
+            b.decode_version(buffer, version)
+        })?;
+        if version >= MetadataVersion::V2 as i16 {
+            self.cluster_id = Option::<String>::decode_new(buffer)?;
+        }
+        if version >= MetadataVersion::V1 as i16 {
+            self.controller_id = Some(i32::decode_new(buffer)?);
+        }
+        self.topics = decode_array(buffer, |t: &mut TopicMetadata, buffer| {
+            t.decode_version(buffer, version)
+        })?;
+        Ok(())
     }
 }
 
-impl FromByte for TopicMetadata {
-    type R = TopicMetadata;
+impl BrokerMetadata {
+    fn decode_version<T: Read>(&mut self, buffer: &mut T, version: i16) -> Result<()> {
+        self.node_id.decode(buffer)?;
+        self.host.decode(buffer)?;
+        self.port.decode(buffer)?;
+        if version >= MetadataVersion::V1 as i16 {
+            self.rack = Option::<String>::decode_new(buffer)?;
+        }
+        Ok(())
+    }
+}
 
-    #[allow(unused_must_use)]
-    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
-        try_multi!(
-            self.error.decode(buffer),
-            self.topic.decode(buffer),
-            self.partitions.decode(buffer)
-        )
+impl TopicMetadata {
+    fn decode_version<T: Read>(&mut self, buffer: &mut T, version: i16) -> Result<()> {
+        self.error.decode(buffer)?;
+        self.topic.decode(buffer)?;
+        if version >= MetadataVersion::V1 as i16 {
+            self.is_internal = i8::decode_new(buffer)? != 0;
+        }
+        self.partitions = decode_array(buffer, |p: &mut PartitionMetadata, buffer| {
+            p.decode_version(buffer, version)
+        })?;
+        Ok(())
     }
 }
 
-impl FromByte for PartitionMetadata {
-    type R = PartitionMetadata;
+impl PartitionMetadata {
+    fn decode_version<T: Read>(&mut self, buffer: &mut T, version: i16) -> Result<()> {
+        self.error.decode(buffer)?;
+        self.id.decode(buffer)?;
+        self.leader.decode(buffer)?;
+        if version >= MetadataVersion::V7 as i16 {
+            self.leader_epoch = Some(i32::decode_new(buffer)?);
+        }
+        self.replicas.decode(buffer)?;
+        self.isr.decode(buffer)?;
+        if version >= MetadataVersion::V5 as i16 {
+            self.offline_replicas.decode(buffer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetadataRequest, MetadataVersion, ResponseParser};
+    use crate::codecs::ToByte;
+    use crate::protocol::SliceResponseParser as _;
+
+    fn encode_response(version: MetadataVersion) -> Vec<u8> {
+        let v = version as i16;
+        let mut buf = Vec::new();
+        // ~ correlation id
+        7i32.encode(&mut buf).unwrap();
+        if v >= 3 {
+            // ~ throttle_time_ms
+            0i32.encode(&mut buf).unwrap();
+        }
+        // ~ brokers
+        1i32.encode(&mut buf).unwrap();
+        3i32.encode(&mut buf).unwrap();
+        "broker-3".encode(&mut buf).unwrap();
+        9092i32.encode(&mut buf).unwrap();
+        if v >= 1 {
+            "rack-a".encode(&mut buf).unwrap();
+        }
+        if v >= 2 {
+            "my-cluster".encode(&mut buf).unwrap();
+        }
+        if v >= 1 {
+            // ~ controller_id
+            3i32.encode(&mut buf).unwrap();
+        }
+        // ~ topics
+        1i32.encode(&mut buf).unwrap();
+        0i16.encode(&mut buf).unwrap();
+        "foo".encode(&mut buf).unwrap();
+        if v >= 1 {
+            0i8.encode(&mut buf).unwrap();
+        }
+        // ~ partitions
+        1i32.encode(&mut buf).unwrap();
+        0i16.encode(&mut buf).unwrap();
+        0i32.encode(&mut buf).unwrap();
+        3i32.encode(&mut buf).unwrap();
+        if v >= 7 {
+            // ~ leader_epoch
+            12i32.encode(&mut buf).unwrap();
+        }
+        [3i32].encode(&mut buf).unwrap();
+        [3i32].encode(&mut buf).unwrap();
+        if v >= 5 {
+            [0i32; 0].encode(&mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn test_decode_v0() {
+        let parser = ResponseParser {
+            version: MetadataVersion::V0,
+        };
+        let r = parser
+            .parse_slice(&encode_response(MetadataVersion::V0))
+            .unwrap();
+        assert_eq!(7, r.header.correlation);
+        assert_eq!(None, r.cluster_id);
+        assert_eq!(None, r.controller_id);
+        assert_eq!(1, r.brokers.len());
+        assert_eq!("broker-3", r.brokers[0].host);
+        assert_eq!(None, r.brokers[0].rack);
+        assert_eq!(1, r.topics.len());
+        assert_eq!(vec![3], r.topics[0].partitions[0].isr);
+    }
+
+    #[test]
+    fn test_decode_v5() {
+        let parser = ResponseParser {
+            version: MetadataVersion::V5,
+        };
+        let r = parser
+            .parse_slice(&encode_response(MetadataVersion::V5))
+            .unwrap();
+        assert_eq!(7, r.header.correlation);
+        assert_eq!(Some("my-cluster"), r.cluster_id.as_deref());
+        assert_eq!(Some(3), r.controller_id);
+        assert_eq!(1, r.brokers.len());
+        assert_eq!(3, r.brokers[0].node_id);
+        assert_eq!(9092, r.brokers[0].port);
+        assert_eq!(Some("rack-a"), r.brokers[0].rack.as_deref());
+        assert_eq!(1, r.topics.len());
+        assert_eq!("foo", r.topics[0].topic);
+        assert!(!r.topics[0].is_internal);
+        assert_eq!(3, r.topics[0].partitions[0].leader);
+        assert!(r.topics[0].partitions[0].offline_replicas.is_empty());
+        assert_eq!(None, r.topics[0].partitions[0].leader_epoch);
+    }
+
+    #[test]
+    fn test_decode_v7() {
+        let parser = ResponseParser {
+            version: MetadataVersion::V7,
+        };
+        let r = parser
+            .parse_slice(&encode_response(MetadataVersion::V7))
+            .unwrap();
+        assert_eq!(Some("my-cluster"), r.cluster_id.as_deref());
+        assert_eq!(1, r.topics.len());
+        assert_eq!(3, r.topics[0].partitions[0].leader);
+        assert_eq!(Some(12), r.topics[0].partitions[0].leader_epoch);
+        assert_eq!(vec![3], r.topics[0].partitions[0].isr);
+    }
+
+    #[test]
+    fn test_encode_all_topics() {
+        let no_topics: &[&str] = &[];
+
+        let mut buf = Vec::new();
+        MetadataRequest::new(MetadataVersion::V0, 1, "", no_topics)
+            .encode(&mut buf)
+            .unwrap();
+        // ~ header (2 + 2 + 4 + 2) followed by an empty topics array
+        assert_eq!(&[0, 0, 0, 0], &buf[10..]);
+
+        let mut buf = Vec::new();
+        MetadataRequest::new(MetadataVersion::V5, 1, "", no_topics)
+            .encode(&mut buf)
+            .unwrap();
+        // ~ header followed by a null topics array and allow_auto_topic_creation
+        assert_eq!(&[0xff, 0xff, 0xff, 0xff, 1], &buf[10..]);
+    }
 
-    #[allow(unused_must_use)]
-    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
-        try_multi!(
-            self.error.decode(buffer),
-            self.id.decode(buffer),
-            self.leader.decode(buffer),
-            self.replicas.decode(buffer),
-            self.isr.decode(buffer)
-        )
+    #[test]
+    fn test_encode_allow_auto_topic_creation() {
+        let topics = ["a"];
+        let encode = |version, allow| {
+            let mut req = MetadataRequest::new(version, 1, "", &topics);
+            req.allow_auto_topic_creation = allow;
+            let mut buf = Vec::new();
+            req.encode(&mut buf).unwrap();
+            buf
+        };
+        // ~ header followed by the topics array and the flag
+        assert_eq!(
+            &[0, 0, 0, 1, 0, 1, b'a', 0],
+            &encode(MetadataVersion::V4, false)[10..]
+        );
+        assert_eq!(
+            &[0, 0, 0, 1, 0, 1, b'a', 1],
+            &encode(MetadataVersion::V4, true)[10..]
+        );
+        // ~ not available before v4
+        assert_eq!(
+            &[0, 0, 0, 1, 0, 1, b'a'],
+            &encode(MetadataVersion::V3, false)[10..]
+        );
     }
 }
@@ -14,35 +14,59 @@ macro_rules! try_multi {
     })
 }
 
+pub mod api_versions;
+pub mod cluster;
 pub mod consumer;
+pub mod epoch;
 pub mod metadata;
 pub mod offset;
 pub mod produce;
+pub mod scram;
 
 pub mod fetch;
+pub mod log_dirs;
 mod zreader;
 
 // ~ convenient re-exports for request/response types defined in the
 // submodules
+pub use self::api_versions::{ApiVersionsRequest, ApiVersionsResponse};
+pub use self::cluster::{DescribeClusterRequest, DescribeClusterResponse};
 pub use self::consumer::{
-    GroupCoordinatorRequest, GroupCoordinatorResponse, OffsetCommitRequest, OffsetCommitResponse,
-    OffsetCommitVersion, OffsetFetchRequest, OffsetFetchResponse, OffsetFetchVersion,
+    GroupCoordinatorRequest, GroupCoordinatorResponse, MemberAssignment, OffsetCommitRequest,
+    OffsetCommitResponse, OffsetCommitVersion, OffsetFetchRequest, OffsetFetchResponse,
+    OffsetFetchResponseParser, OffsetFetchVersion,
 };
+pub use self::epoch::{OffsetForLeaderEpochRequest, OffsetForLeaderEpochResponse};
 pub use self::fetch::FetchRequest;
-pub use self::metadata::{MetadataRequest, MetadataResponse};
+pub use self::log_dirs::{
+    DescribeLogDirsRequest, DescribeLogDirsResponse, LogDir, LogDirPartition,
+};
+pub use self::metadata::{MetadataRequest, MetadataResponse, MetadataVersion};
 pub use self::offset::{OffsetRequest, OffsetResponse};
-pub use self::produce::{ProduceRequest, ProduceResponse};
+pub use self::produce::{ProduceRequest, ProduceResponse, RawProduceRequest};
+pub use self::scram::{
+    AlterScramCredentialResult, AlterUserScramCredentialsRequest,
+    AlterUserScramCredentialsResponse, DescribeUserScramCredentialsRequest,
+    DescribeUserScramCredentialsResponse, ScramCredentialDeletion, ScramCredentialInfo,
+    ScramCredentialUpsertion, ScramMechanism, UserScramCredentials,
+};
 
 // --------------------------------------------------------------------
 
-const API_KEY_PRODUCE: i16 = 0;
-const API_KEY_FETCH: i16 = 1;
+pub const API_KEY_PRODUCE: i16 = 0;
+pub const API_KEY_FETCH: i16 = 1;
 const API_KEY_OFFSET: i16 = 2;
 const API_KEY_METADATA: i16 = 3;
 // 4-7 reserved for non-public kafka api services
 const API_KEY_OFFSET_COMMIT: i16 = 8;
-const API_KEY_OFFSET_FETCH: i16 = 9;
+pub const API_KEY_OFFSET_FETCH: i16 = 9;
 const API_KEY_GROUP_COORDINATOR: i16 = 10;
+const API_KEY_API_VERSIONS: i16 = 18;
+const API_KEY_OFFSET_FOR_LEADER_EPOCH: i16 = 23;
+const API_KEY_DESCRIBE_LOG_DIRS: i16 = 35;
+const API_KEY_DESCRIBE_USER_SCRAM_CREDENTIALS: i16 = 50;
+const API_KEY_ALTER_USER_SCRAM_CREDENTIALS: i16 = 51;
+pub const API_KEY_DESCRIBE_CLUSTER: i16 = 60;
 
 // the default version of Kafka API we are requesting
 const API_VERSION: i16 = 0;
@@ -56,6 +80,14 @@ pub trait ResponseParser {
     fn parse(&self, response: Vec<u8>) -> Result<Self::T>;
 }
 
+/// Like `ResponseParser` but for response structures not borrowing
+/// from the raw data, which can thus be read into a buffer re-used
+/// across responses.
+pub trait SliceResponseParser {
+    type T;
+    fn parse_slice(&self, response: &[u8]) -> Result<Self::T>;
+}
+
 // --------------------------------------------------------------------
 
 impl KafkaCode {
@@ -63,7 +95,8 @@ impl KafkaCode {
         if n == 0 {
             return None;
         }
-        if n >= KafkaCode::OffsetOutOfRange as i16 && n <= KafkaCode::UnsupportedVersion as i16 {
+        if n >= KafkaCode::OffsetOutOfRange as i16 && n <= KafkaCode::UnacceptableCredential as i16
+        {
             return Some(unsafe { mem::transmute(n as i8) });
         }
         Some(KafkaCode::Unknown)
@@ -97,6 +130,10 @@ fn test_kafka_code_from_protocol() {
         KafkaCode::UnsupportedVersion,
         KafkaCode::UnsupportedVersion as i16
     );
+    assert_kafka_code!(KafkaCode::FencedLeaderEpoch, 74);
+    assert_kafka_code!(KafkaCode::UnknownLeaderEpoch, 75);
+    assert_kafka_code!(KafkaCode::ProducerFenced, 90);
+    assert_kafka_code!(KafkaCode::UnacceptableCredential, 93);
     assert_kafka_code!(KafkaCode::Unknown, KafkaCode::Unknown as i16);
     // ~ test some un mapped non-zero codes; should all map to "unknown"
     assert_kafka_code!(KafkaCode::Unknown, i16::MAX);
@@ -105,6 +142,21 @@ fn test_kafka_code_from_protocol() {
     assert_kafka_code!(KafkaCode::Unknown, 100);
 }
 
+#[test]
+fn test_kafka_code_round_trip() {
+    // ~ all codes are contiguous up to the last one known
+    for n in 1..=KafkaCode::UnacceptableCredential as i16 {
+        let code = KafkaCode::from_protocol(n).unwrap();
+        assert_ne!(KafkaCode::Unknown, code);
+        assert_eq!(n, code.code());
+        assert!(!code.description().is_empty());
+    }
+    assert_eq!(
+        Some(KafkaCode::Unknown),
+        KafkaCode::from_protocol(KafkaCode::UnacceptableCredential as i16 + 1)
+    );
+}
+
 // a (sub-) module private method for error
 impl Error {
     fn from_protocol(n: i16) -> Option<Error> {
@@ -17,6 +17,23 @@ use crate::producer::{ProduceConfirm, ProducePartitionConfirm};
 /// The magic byte (a.k.a version) we use for sent messages.
 const MESSAGE_MAGIC_BYTE: i8 = 0;
 
+// ~ the fixed number of bytes every message occupies in a message set
+// on top of its key and value: Offset (8), MessageSize (4), Crc (4),
+// MagicByte (1), Attributes (1), and the length prefixes of the key
+// (4) and the value (4).
+const MESSAGE_OVERHEAD: usize = 8 + 4 + 4 + 1 + 1 + 4 + 4;
+
+/// Computes the number of bytes the given key/value pair will
+/// occupy when rendered as an uncompressed message within a message
+/// set.  (Version 0 messages carry no headers.)
+pub fn message_size(key: Option<&[u8]>, value: Option<&[u8]>) -> usize {
+    MESSAGE_OVERHEAD + key.map_or(0, <[u8]>::len) + value.map_or(0, <[u8]>::len)
+}
+
+// XXX transactional producing (KIP-98) needs produce v3+ whose
+// requests carry v2 record batches stamped with the producer id and
+// epoch obtained through `InitProducerId`; support transactions (and
+// `init_transactions`) once we can encode record batches.
 #[derive(Debug)]
 pub struct ProduceRequest<'a, 'b> {
     pub header: HeaderRequest<'a>,
@@ -24,6 +41,7 @@ pub struct ProduceRequest<'a, 'b> {
     pub timeout: i32,
     pub topic_partitions: Vec<TopicPartitionProduceRequest<'b>>,
     pub compression: Compression,
+    pub compression_min_size: usize,
 }
 
 #[derive(Debug)]
@@ -31,6 +49,7 @@ pub struct TopicPartitionProduceRequest<'a> {
     pub topic: &'a str,
     pub partitions: Vec<PartitionProduceRequest<'a>>,
     pub compression: Compression,
+    pub compression_min_size: usize,
 }
 
 #[derive(Debug)]
@@ -52,6 +71,7 @@ impl<'a, 'b> ProduceRequest<'a, 'b> {
         correlation_id: i32,
         client_id: &'a str,
         compression: Compression,
+        compression_min_size: usize,
     ) -> ProduceRequest<'a, 'b> {
         ProduceRequest {
             header: HeaderRequest::new(API_KEY_PRODUCE, API_VERSION, correlation_id, client_id),
@@ -59,6 +79,7 @@ impl<'a, 'b> ProduceRequest<'a, 'b> {
             timeout,
             topic_partitions: vec![],
             compression,
+            compression_min_size,
         }
     }
 
@@ -75,18 +96,24 @@ impl<'a, 'b> ProduceRequest<'a, 'b> {
                 return;
             }
         }
-        let mut tp = TopicPartitionProduceRequest::new(topic, self.compression);
+        let mut tp =
+            TopicPartitionProduceRequest::new(topic, self.compression, self.compression_min_size);
         tp.add(partition, key, value);
         self.topic_partitions.push(tp);
     }
 }
 
 impl<'a> TopicPartitionProduceRequest<'a> {
-    pub fn new(topic: &'a str, compression: Compression) -> TopicPartitionProduceRequest<'a> {
+    pub fn new(
+        topic: &'a str,
+        compression: Compression,
+        compression_min_size: usize,
+    ) -> TopicPartitionProduceRequest<'a> {
         TopicPartitionProduceRequest {
             topic,
             partitions: vec![],
             compression,
+            compression_min_size,
         }
     }
 
@@ -138,7 +165,7 @@ impl<'a> ToByte for TopicPartitionProduceRequest<'a> {
         self.topic.encode(buffer)?;
         (self.partitions.len() as i32).encode(buffer)?;
         for e in &self.partitions {
-            e._encode(buffer, self.compression)?
+            e._encode(buffer, self.compression, self.compression_min_size)?
         }
         Ok(())
     }
@@ -149,7 +176,12 @@ impl<'a> PartitionProduceRequest<'a> {
     //
     // MessetSet => [Offset MessageSize Message]
     // MessageSets are not preceded by an int32 like other array elements in the protocol.
-    fn _encode<W: Write>(&self, out: &mut W, compression: Compression) -> Result<()> {
+    fn _encode<W: Write>(
+        &self,
+        out: &mut W,
+        compression: Compression,
+        compression_min_size: usize,
+    ) -> Result<()> {
         self.partition.encode(out)?;
 
         // ~ render the whole MessageSet first to a temporary buffer
@@ -157,6 +189,12 @@ impl<'a> PartitionProduceRequest<'a> {
         for msg in &self.messages {
             msg._encode_to_buf(&mut buf, MESSAGE_MAGIC_BYTE, 0)?;
         }
+        // ~ small message sets are not worth compressing
+        let compression = if buf.len() < compression_min_size {
+            Compression::NONE
+        } else {
+            compression
+        };
         match compression {
             Compression::NONE => {
                 // ~ nothing to do
@@ -176,6 +214,55 @@ impl<'a> PartitionProduceRequest<'a> {
     }
 }
 
+/// A produce request carrying a single, pre-encoded message set for
+/// one topic partition; the message set is sent unchanged.
+#[derive(Debug)]
+pub struct RawProduceRequest<'a, 'b> {
+    pub header: HeaderRequest<'a>,
+    pub required_acks: i16,
+    pub timeout: i32,
+    pub topic: &'b str,
+    pub partition: i32,
+    pub message_set: &'b [u8],
+}
+
+impl<'a, 'b> RawProduceRequest<'a, 'b> {
+    pub fn new(
+        required_acks: i16,
+        timeout: i32,
+        correlation_id: i32,
+        client_id: &'a str,
+        topic: &'b str,
+        partition: i32,
+        message_set: &'b [u8],
+    ) -> RawProduceRequest<'a, 'b> {
+        RawProduceRequest {
+            header: HeaderRequest::new(API_KEY_PRODUCE, API_VERSION, correlation_id, client_id),
+            required_acks,
+            timeout,
+            topic,
+            partition,
+            message_set,
+        }
+    }
+}
+
+impl<'a, 'b> ToByte for RawProduceRequest<'a, 'b> {
+    // render: Header RequiredAcks Timeout [TopicName [Partition MessageSetSize MessageSet]]
+    fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
+        try_multi!(
+            self.header.encode(buffer),
+            self.required_acks.encode(buffer),
+            self.timeout.encode(buffer),
+            1i32.encode(buffer),
+            self.topic.encode(buffer),
+            1i32.encode(buffer),
+            self.partition.encode(buffer),
+            self.message_set.encode(buffer)
+        )
+    }
+}
+
 // ~ A helper method to render `cdata` into `out` as a compressed message.
 // ~ `out` is first cleared and then populated with the rendered message.
 #[cfg(any(feature = "snappy", feature = "gzip"))]
@@ -229,6 +316,57 @@ impl<'a> MessageProduceRequest<'a> {
     }
 }
 
+#[test]
+fn test_message_size() {
+    let key: &[u8] = b"key";
+    let value: &[u8] = b"value";
+    let msg = MessageProduceRequest::new(Some(key), Some(value));
+    let mut buf = Vec::new();
+    msg._encode_to_buf(&mut buf, MESSAGE_MAGIC_BYTE, 0).unwrap();
+    assert_eq!(buf.len(), message_size(Some(key), Some(value)));
+
+    let msg = MessageProduceRequest::new(None, None);
+    buf.clear();
+    msg._encode_to_buf(&mut buf, MESSAGE_MAGIC_BYTE, 0).unwrap();
+    assert_eq!(buf.len(), message_size(None, None));
+}
+
+#[test]
+fn test_raw_produce_request() {
+    let mset = vec![1u8, 2, 3];
+    let mut raw = Vec::new();
+    RawProduceRequest::new(1, 100, 7, "me", "foo", 2, &mset)
+        .encode(&mut raw)
+        .unwrap();
+    // ~ the raw request renders the message set unchanged
+    #[rustfmt::skip]
+    let expected: Vec<u8> = vec![
+        // header: api key, api version, correlation id, client id
+        0, 0, 0, 0, 0, 0, 0, 7, 0, 2, b'm', b'e',
+        // required acks, timeout
+        0, 1, 0, 0, 0, 100,
+        // one topic, one partition
+        0, 0, 0, 1, 0, 3, b'f', b'o', b'o', 0, 0, 0, 1, 0, 0, 0, 2,
+        // message set size, message set
+        0, 0, 0, 3, 1, 2, 3,
+    ];
+    assert_eq!(expected, raw);
+}
+
+#[test]
+fn test_null_vs_empty_key() {
+    let encode_key = |key| {
+        let mut buf = Vec::new();
+        MessageProduceRequest::new(key, None)
+            ._encode_to_buf(&mut buf, MESSAGE_MAGIC_BYTE, 0)
+            .unwrap();
+        // ~ skip offset, size, crc, magic, and attributes
+        buf[8 + 4 + 4 + 1 + 1..8 + 4 + 4 + 1 + 1 + 4].to_vec()
+    };
+    assert_eq!(vec![0xff, 0xff, 0xff, 0xff], encode_key(None));
+    assert_eq!(vec![0, 0, 0, 0], encode_key(Some(&[])));
+}
+
 impl<'a> ToByte for Option<&'a [u8]> {
     fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
         match *self {
@@ -0,0 +1,429 @@
+use std::io::{Read, Write};
+
+use crate::codecs::{self, FromByte, ToByte};
+use crate::error::{Error, KafkaCode, Result};
+
+use super::{HeaderRequest, HeaderResponse};
+use super::{API_KEY_ALTER_USER_SCRAM_CREDENTIALS, API_KEY_DESCRIBE_USER_SCRAM_CREDENTIALS};
+
+// ~ `DescribeUserScramCredentials` and `AlterUserScramCredentials`
+// (KIP-554) exist only as "flexible version" apis; see
+// `super::cluster`.  We support only version zero of them.
+const API_VERSION_SCRAM_CREDENTIALS: i16 = 0;
+
+/// The SCRAM mechanisms (RFC 5802) Kafka supports for SASL
+/// authentication.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ScramMechanism {
+    /// SCRAM-SHA-256
+    Sha256 = 1,
+    /// SCRAM-SHA-512
+    Sha512 = 2,
+}
+
+impl ScramMechanism {
+    fn from_protocol(n: i8) -> Option<ScramMechanism> {
+        match n {
+            1 => Some(ScramMechanism::Sha256),
+            2 => Some(ScramMechanism::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// Describes a SCRAM credential of a user without revealing any of
+/// its secrets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScramCredentialInfo {
+    /// The mechanism the credential is usable with.
+    pub mechanism: ScramMechanism,
+    /// The number of iterations used to derive the salted password.
+    pub iterations: i32,
+}
+
+/// The SCRAM credentials of a single user as delivered by
+/// `KafkaClient::describe_scram_credentials`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserScramCredentials {
+    /// The name of the user.
+    pub user: String,
+    /// The user's credentials or the error the brokers reported for
+    /// the user, e.g. `KafkaCode::ResourceNotFound` for a user
+    /// without any credentials.
+    pub credentials: std::result::Result<Vec<ScramCredentialInfo>, KafkaCode>,
+}
+
+/// Creates or replaces a user's SCRAM credential for a particular
+/// mechanism.  See `KafkaClient::alter_scram_credentials`.
+#[derive(Debug, Clone)]
+pub struct ScramCredentialUpsertion {
+    /// The name of the user.
+    pub user: String,
+    /// The mechanism the credential is to be usable with.
+    pub mechanism: ScramMechanism,
+    /// The number of iterations used to derive `salted_password`.
+    pub iterations: i32,
+    /// A random salt.
+    pub salt: Vec<u8>,
+    /// The salted password, i.e. `Hi(password, salt, iterations)`
+    /// as defined by RFC 5802 using the mechanism's hash function.
+    pub salted_password: Vec<u8>,
+}
+
+#[cfg(feature = "security")]
+impl ScramCredentialUpsertion {
+    /// Creates an upsertion for the given plain text password,
+    /// deriving the salted password from a fresh random salt.
+    pub fn new(
+        user: &str,
+        mechanism: ScramMechanism,
+        iterations: i32,
+        password: &str,
+    ) -> Result<ScramCredentialUpsertion> {
+        use openssl::hash::MessageDigest;
+        use openssl::ssl;
+
+        let digest = match mechanism {
+            ScramMechanism::Sha256 => MessageDigest::sha256(),
+            ScramMechanism::Sha512 => MessageDigest::sha512(),
+        };
+        let rounds = usize::try_from(iterations).map_err(|_| Error::CodecError)?;
+        let mut salt = vec![0u8; 32];
+        openssl::rand::rand_bytes(&mut salt).map_err(ssl::Error::from)?;
+        let mut salted_password = vec![0u8; digest.size()];
+        openssl::pkcs5::pbkdf2_hmac(
+            password.as_bytes(),
+            &salt,
+            rounds,
+            digest,
+            &mut salted_password,
+        )
+        .map_err(ssl::Error::from)?;
+        Ok(ScramCredentialUpsertion {
+            user: user.to_owned(),
+            mechanism,
+            iterations,
+            salt,
+            salted_password,
+        })
+    }
+}
+
+/// Removes a user's SCRAM credential for a particular mechanism.  See
+/// `KafkaClient::alter_scram_credentials`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramCredentialDeletion {
+    /// The name of the user.
+    pub user: String,
+    /// The mechanism of the credential to remove.
+    pub mechanism: ScramMechanism,
+}
+
+/// The outcome of altering the SCRAM credentials of a single user.
+/// See `KafkaClient::alter_scram_credentials`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlterScramCredentialResult {
+    /// The name of the user.
+    pub user: String,
+    /// The error the brokers reported for the user, if any.
+    pub result: std::result::Result<(), KafkaCode>,
+}
+
+// ~ encodes compact bytes, i.e. prefixed by their length plus one
+fn encode_compact_bytes<W: Write>(buffer: &mut W, bytes: &[u8]) -> Result<()> {
+    codecs::encode_compact_array_len(buffer, bytes.len())?;
+    buffer.write_all(bytes).map_err(From::from)
+}
+
+// ~ converts a per user error code into a result
+fn to_result(error: i16) -> std::result::Result<(), KafkaCode> {
+    match KafkaCode::from_protocol(error) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// --------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct DescribeUserScramCredentialsRequest<'a, 'b, T> {
+    pub header: HeaderRequest<'a>,
+    // ~ empty to describe all users
+    pub users: &'b [T],
+}
+
+impl<'a, 'b, T: AsRef<str>> DescribeUserScramCredentialsRequest<'a, 'b, T> {
+    pub fn new(
+        users: &'b [T],
+        correlation_id: i32,
+        client_id: &'a str,
+    ) -> DescribeUserScramCredentialsRequest<'a, 'b, T> {
+        DescribeUserScramCredentialsRequest {
+            header: HeaderRequest::new(
+                API_KEY_DESCRIBE_USER_SCRAM_CREDENTIALS,
+                API_VERSION_SCRAM_CREDENTIALS,
+                correlation_id,
+                client_id,
+            ),
+            users,
+        }
+    }
+}
+
+impl<'a, 'b, T: AsRef<str>> ToByte for DescribeUserScramCredentialsRequest<'a, 'b, T> {
+    fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
+        // ~ request header (v2)
+        self.header.encode(buffer)?;
+        0i8.encode(buffer)?;
+        if self.users.is_empty() {
+            // ~ a null array asks for all users
+            codecs::encode_uvarint(buffer, 0)?;
+        } else {
+            codecs::encode_compact_array_len(buffer, self.users.len())?;
+            for user in self.users {
+                codecs::encode_compact_string(buffer, user.as_ref())?;
+                0i8.encode(buffer)?;
+            }
+        }
+        0i8.encode(buffer)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DescribeUserScramCredentialsResponse {
+    pub header: HeaderResponse,
+    pub throttle_time_ms: i32,
+    pub error: i16,
+    pub error_message: Option<String>,
+    pub results: Vec<UserScramCredentials>,
+}
+
+impl DescribeUserScramCredentialsResponse {
+    pub fn into_result(self) -> Result<Self> {
+        match Error::from_protocol(self.error) {
+            Some(e) => Err(e),
+            None => Ok(self),
+        }
+    }
+}
+
+impl FromByte for DescribeUserScramCredentialsResponse {
+    type R = DescribeUserScramCredentialsResponse;
+
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        // ~ response header (v1)
+        self.header.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)?;
+
+        self.throttle_time_ms.decode(buffer)?;
+        self.error.decode(buffer)?;
+        self.error_message = codecs::decode_compact_nullable_string(buffer)?;
+        let n = codecs::decode_compact_array_len(buffer)?;
+        self.results = Vec::with_capacity(n);
+        for _ in 0..n {
+            let user = codecs::decode_compact_string(buffer)?;
+            let error = i16::decode_new(buffer)?;
+            let _error_message = codecs::decode_compact_nullable_string(buffer)?;
+            let m = codecs::decode_compact_array_len(buffer)?;
+            let mut infos = Vec::with_capacity(m);
+            for _ in 0..m {
+                let mechanism = i8::decode_new(buffer)?;
+                let iterations = i32::decode_new(buffer)?;
+                codecs::skip_tagged_fields(buffer)?;
+                // ~ skip mechanisms unknown to us
+                if let Some(mechanism) = ScramMechanism::from_protocol(mechanism) {
+                    infos.push(ScramCredentialInfo {
+                        mechanism,
+                        iterations,
+                    });
+                }
+            }
+            codecs::skip_tagged_fields(buffer)?;
+            self.results.push(UserScramCredentials {
+                user,
+                credentials: to_result(error).map(|()| infos),
+            });
+        }
+        codecs::skip_tagged_fields(buffer)
+    }
+}
+
+// --------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct AlterUserScramCredentialsRequest<'a, 'b> {
+    pub header: HeaderRequest<'a>,
+    pub deletions: &'b [ScramCredentialDeletion],
+    pub upsertions: &'b [ScramCredentialUpsertion],
+}
+
+impl<'a, 'b> AlterUserScramCredentialsRequest<'a, 'b> {
+    pub fn new(
+        upsertions: &'b [ScramCredentialUpsertion],
+        deletions: &'b [ScramCredentialDeletion],
+        correlation_id: i32,
+        client_id: &'a str,
+    ) -> AlterUserScramCredentialsRequest<'a, 'b> {
+        AlterUserScramCredentialsRequest {
+            header: HeaderRequest::new(
+                API_KEY_ALTER_USER_SCRAM_CREDENTIALS,
+                API_VERSION_SCRAM_CREDENTIALS,
+                correlation_id,
+                client_id,
+            ),
+            deletions,
+            upsertions,
+        }
+    }
+}
+
+impl<'a, 'b> ToByte for AlterUserScramCredentialsRequest<'a, 'b> {
+    fn encode<W: Write>(&self, buffer: &mut W) -> Result<()> {
+        // ~ request header (v2)
+        self.header.encode(buffer)?;
+        0i8.encode(buffer)?;
+        codecs::encode_compact_array_len(buffer, self.deletions.len())?;
+        for d in self.deletions {
+            codecs::encode_compact_string(buffer, &d.user)?;
+            (d.mechanism as i8).encode(buffer)?;
+            0i8.encode(buffer)?;
+        }
+        codecs::encode_compact_array_len(buffer, self.upsertions.len())?;
+        for u in self.upsertions {
+            codecs::encode_compact_string(buffer, &u.user)?;
+            (u.mechanism as i8).encode(buffer)?;
+            u.iterations.encode(buffer)?;
+            encode_compact_bytes(buffer, &u.salt)?;
+            encode_compact_bytes(buffer, &u.salted_password)?;
+            0i8.encode(buffer)?;
+        }
+        0i8.encode(buffer)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AlterUserScramCredentialsResponse {
+    pub header: HeaderResponse,
+    pub throttle_time_ms: i32,
+    pub results: Vec<AlterScramCredentialResult>,
+}
+
+impl FromByte for AlterUserScramCredentialsResponse {
+    type R = AlterUserScramCredentialsResponse;
+
+    fn decode<T: Read>(&mut self, buffer: &mut T) -> Result<()> {
+        // ~ response header (v1)
+        self.header.decode(buffer)?;
+        codecs::skip_tagged_fields(buffer)?;
+
+        self.throttle_time_ms.decode(buffer)?;
+        let n = codecs::decode_compact_array_len(buffer)?;
+        self.results = Vec::with_capacity(n);
+        for _ in 0..n {
+            let user = codecs::decode_compact_string(buffer)?;
+            let error = i16::decode_new(buffer)?;
+            let _error_message = codecs::decode_compact_nullable_string(buffer)?;
+            codecs::skip_tagged_fields(buffer)?;
+            self.results.push(AlterScramCredentialResult {
+                user,
+                result: to_result(error),
+            });
+        }
+        codecs::skip_tagged_fields(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{
+        AlterUserScramCredentialsRequest, DescribeUserScramCredentialsRequest,
+        DescribeUserScramCredentialsResponse, ScramCredentialDeletion, ScramCredentialInfo,
+        ScramCredentialUpsertion, ScramMechanism,
+    };
+    use crate::codecs::{FromByte, ToByte};
+    use crate::error::KafkaCode;
+
+    #[test]
+    fn test_encode_describe_request() {
+        let mut buf = Vec::new();
+        DescribeUserScramCredentialsRequest::new(&["al"], 7, "me")
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(
+            vec![0, 50, 0, 0, 0, 0, 0, 7, 0, 2, b'm', b'e', 0, 2, 3, b'a', b'l', 0, 0],
+            buf
+        );
+
+        // ~ all users
+        let mut buf = Vec::new();
+        DescribeUserScramCredentialsRequest::<&str>::new(&[], 7, "me")
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(&[0, 0, 0], &buf[buf.len() - 3..]);
+    }
+
+    #[test]
+    fn test_encode_alter_request() {
+        let upsertions = [ScramCredentialUpsertion {
+            user: "al".to_owned(),
+            mechanism: ScramMechanism::Sha512,
+            iterations: 4096,
+            salt: vec![1, 2],
+            salted_password: vec![3],
+        }];
+        let deletions = [ScramCredentialDeletion {
+            user: "bo".to_owned(),
+            mechanism: ScramMechanism::Sha256,
+        }];
+        let mut buf = Vec::new();
+        AlterUserScramCredentialsRequest::new(&upsertions, &deletions, 7, "me")
+            .encode(&mut buf)
+            .unwrap();
+        #[rustfmt::skip]
+        let expected = vec![
+            // header
+            0, 51, 0, 0, 0, 0, 0, 7, 0, 2, b'm', b'e', 0,
+            // deletions
+            2, 3, b'b', b'o', 1, 0,
+            // upsertions
+            2, 3, b'a', b'l', 2, 0, 0, 16, 0, 3, 1, 2, 2, 3, 0,
+            // tagged fields
+            0,
+        ];
+        assert_eq!(expected, buf);
+    }
+
+    #[test]
+    fn test_decode_describe_response() {
+        #[rustfmt::skip]
+        let raw = vec![
+            // correlation id, tagged fields
+            0, 0, 0, 7, 0,
+            // throttle time, error, null error message
+            0, 0, 0, 0, 0, 0, 0,
+            // two results
+            3,
+            // "al": two credentials, one of an unknown mechanism
+            3, b'a', b'l', 0, 0, 0, 3, 1, 0, 0, 16, 0, 0, 9, 0, 0, 0, 1, 0, 0,
+            // "bo": resource not found
+            3, b'b', b'o', 0, 91, 1, 1, 0,
+            // tagged fields
+            0,
+        ];
+        let r = DescribeUserScramCredentialsResponse::decode_new(&mut Cursor::new(raw)).unwrap();
+        assert_eq!(7, r.header.correlation);
+        assert_eq!(2, r.results.len());
+        assert_eq!("al", r.results[0].user);
+        assert_eq!(
+            Ok(vec![ScramCredentialInfo {
+                mechanism: ScramMechanism::Sha256,
+                iterations: 4096,
+            }]),
+            r.results[0].credentials
+        );
+        assert_eq!("bo", r.results[1].user);
+        assert_eq!(Err(KafkaCode::ResourceNotFound), r.results[1].credentials);
+    }
+}
@@ -5,6 +5,7 @@ use byteorder::{BigEndian, ByteOrder};
 
 static EMPTY_STR: &str = "";
 
+#[derive(Clone)]
 pub struct ZReader<'a> {
     data: &'a [u8],
 }
@@ -89,6 +90,17 @@ impl<'a> ZReader<'a> {
         }
     }
 
+    /// Reads 'bytes' as defined by the Kafka Protocol distinguishing
+    /// 'null' bytes, delivered as `None`, from empty ones.
+    pub fn read_nullable_bytes<'b>(&'b mut self) -> Result<Option<&'a [u8]>> {
+        let len = self.read_i32()?;
+        if len < 0 {
+            Ok(None)
+        } else {
+            self.read(len as usize).map(Some)
+        }
+    }
+
     /// Reads the size of an array as defined by the Kafka
     /// Protocol. The size of 'null' array will be returned as the
     /// size an array of an empty array.
@@ -12,6 +12,7 @@ use env_logger;
 use kafka::client::fetch::Response;
 use kafka::client::{
     CommitOffset, FetchOffset, FetchPartition, PartitionOffset, ProduceMessage, RequiredAcks,
+    TopicPartition,
 };
 
 fn flatten_fetched_messages(resps: &Vec<Response>) -> Vec<(&str, i32, &[u8])> {
@@ -257,3 +258,37 @@ fn test_commit_offsets() {
         assert_eq!(correct_partition_offsets, partition_offsets);
     }
 }
+
+/// Tests:
+///
+/// * KafkaClient::describe_log_dirs
+#[test]
+fn test_describe_log_dirs() {
+    let _ = env_logger::try_init();
+    let mut client = new_ready_kafka_client();
+
+    let req = vec![ProduceMessage::new(
+        TEST_TOPIC_NAME,
+        0,
+        None,
+        Some("a".as_bytes()),
+    )];
+    client
+        .produce_messages(RequiredAcks::All, Duration::from_millis(1000), req)
+        .unwrap();
+
+    // ~ all brokers; the partition's replicas report its size
+    let tps = [TopicPartition::new(TEST_TOPIC_NAME, 0)];
+    let log_dirs = client.describe_log_dirs(&[], Some(&tps)).unwrap();
+    assert!(!log_dirs.is_empty());
+
+    let sizes: Vec<i64> = log_dirs
+        .iter()
+        .filter_map(|d| d.partitions.as_ref().ok())
+        .flatten()
+        .filter(|p| p.topic == TEST_TOPIC_NAME && p.partition == 0)
+        .map(|p| p.size)
+        .collect();
+    assert!(!sizes.is_empty());
+    assert!(sizes.iter().all(|&size| size > 0));
+}
@@ -0,0 +1,79 @@
+//! Exercises the client against the in-memory `MockBroker`.  Run
+//! through `cargo test --features test-util`.
+
+use kafka::client::{FetchOffset, KafkaClient, MockBroker};
+use kafka::consumer::Consumer;
+use kafka::error::{Error, KafkaCode};
+use kafka::producer::{Producer, Record};
+
+const API_KEY_PRODUCE: i16 = 0;
+const API_KEY_METADATA: i16 = 3;
+
+#[test]
+fn test_produce_and_consume() {
+    let broker = MockBroker::start(&[("my-topic", 1)], (0, 0));
+
+    let mut producer = Producer::from_hosts(vec![broker.host().to_owned()])
+        .create()
+        .unwrap();
+    for value in ["a", "b", "c"] {
+        producer
+            .send(&Record::from_key_value("my-topic", "key", value).with_partition(0))
+            .unwrap();
+    }
+    assert_eq!(3, broker.num_produced("my-topic", 0));
+    assert_eq!(
+        vec![
+            API_KEY_METADATA,
+            API_KEY_PRODUCE,
+            API_KEY_PRODUCE,
+            API_KEY_PRODUCE
+        ],
+        broker.requests()
+    );
+
+    let mut consumer = Consumer::from_hosts(vec![broker.host().to_owned()])
+        .with_topic("my-topic".to_owned())
+        .with_group("my-group".to_owned())
+        .with_fallback_offset(FetchOffset::Earliest)
+        .create()
+        .unwrap();
+    let mut consumed = Vec::new();
+    while consumed.len() < 3 {
+        for ms in &consumer.poll().unwrap() {
+            for m in &ms {
+                assert_eq!(Some(&b"key"[..]), m.key());
+                consumed.push(String::from_utf8(m.value.to_owned()).unwrap());
+            }
+            consumer.consume_messageset(ms).unwrap();
+        }
+    }
+    assert_eq!(vec!["a", "b", "c"], consumed);
+
+    consumer.commit_consumed().unwrap();
+    assert_eq!(Some(3), broker.committed_offset("my-topic", 0));
+}
+
+#[test]
+fn test_scripted_partition_error() {
+    let broker = MockBroker::start(&[("my-topic", 2)], (0, 0));
+    broker.set_partition_error("my-topic", 1, Some(KafkaCode::NotLeaderForPartition));
+
+    let mut client = KafkaClient::new(vec![broker.host().to_owned()]);
+    client.load_metadata_all().unwrap();
+    let mut producer = Producer::from_client(client).create().unwrap();
+
+    producer
+        .send(&Record::from_value("my-topic", "a").with_partition(0))
+        .unwrap();
+    match producer.send(&Record::from_value("my-topic", "a").with_partition(1)) {
+        Err(Error::Kafka(KafkaCode::NotLeaderForPartition)) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    broker.set_partition_error("my-topic", 1, None);
+    producer
+        .send(&Record::from_value("my-topic", "a").with_partition(1))
+        .unwrap();
+    assert_eq!(1, broker.num_produced("my-topic", 1));
+}